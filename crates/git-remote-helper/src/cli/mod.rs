@@ -1,4 +1,8 @@
-use clap::Parser;
+use clap::error::ErrorKind;
+use clap::{CommandFactory as _, Parser};
+
+#[cfg(test)]
+mod tests;
 
 #[derive(Debug, Parser)]
 #[command(about, version)]
@@ -8,4 +12,114 @@ pub struct Args {
 
     /// A URL of the form icp://<address> or icp::<transport>://<address>
     pub url: String,
+
+    /// Whether pushing is refused.
+    #[arg(skip = crate::commands::push::readonly())]
+    pub readonly: bool,
+}
+
+/// A hidden invocation for tooling that wants structured ref data rather than
+/// the output of the `list` command.
+#[derive(Debug, Parser)]
+#[command(about, version)]
+pub struct ListJsonArgs {
+    /// A URL of the form icp://<address> or icp::<transport>://<address>
+    #[arg(long = "list-json", value_name = "URL", hide = true)]
+    pub url: String,
+}
+
+/// A hidden invocation that only does the handshake with a remote and lists
+/// its refs, then prints what was negotiated, for diagnosing connectivity.
+#[derive(Debug, Parser)]
+#[command(about, version)]
+pub struct DiagnoseArgs {
+    /// A URL of the form icp://<address> or icp::<transport>://<address>
+    #[arg(long = "diagnose", value_name = "URL", hide = true)]
+    pub url: String,
+}
+
+/// The environment variables every helper reads.
+const ENV: &[(&str, &str)] = &[
+    (
+        crate::GIT_DIR,
+        "The repository to fetch into and push from, which Git sets",
+    ),
+    (
+        crate::git::protocol::GIT_PROTOCOL,
+        "Protocol parameters such as version=2, which Git sets",
+    ),
+    (
+        crate::verbosity::RUST_LOG,
+        "Log filters, such as trace, in place of Git's verbosity",
+    ),
+    (
+        crate::debug::DEBUG_DIR,
+        "A directory to write protocol artifacts to for bug reports",
+    ),
+    (
+        crate::stats::STATS_JSON,
+        "A file to write statistics to as JSON",
+    ),
+    (
+        crate::commands::push::READONLY,
+        "Set to 1 to refuse to push",
+    ),
+    (
+        crate::error::ERROR_FORMAT,
+        "Set to json to print fatal errors as JSON",
+    ),
+];
+
+/// What a helper's `--help` and `--version` describe, since Git runs helpers
+/// indirectly and users otherwise only find out about one when it fails.
+pub struct Usage {
+    /// The name of the helper's binary.
+    pub name: &'static str,
+    pub version: &'static str,
+    /// What the helper is for and the URLs it's given.
+    pub about: &'static str,
+    /// The environment variables the helper reads in addition to those that
+    /// every helper reads, each with what it's for.
+    pub env: &'static [(&'static str, &'static str)],
+}
+
+impl Usage {
+    pub fn command(&self) -> clap::Command {
+        Args::command()
+            .name(self.name)
+            .version(self.version)
+            .about(self.about)
+            .after_help(self.environment())
+    }
+
+    /// Prints the help or the version and exits if either was asked for, so
+    /// that they work before the helper reads any of its configuration.
+    pub fn exit_if_requested(&self) {
+        if let Err(err) = self.command().try_get_matches() {
+            if matches!(
+                err.kind(),
+                ErrorKind::DisplayHelp | ErrorKind::DisplayVersion
+            ) {
+                err.exit();
+            }
+        }
+    }
+
+    fn environment(&self) -> String {
+        let vars = ENV.iter().chain(self.env.iter()).collect::<Vec<_>>();
+        let width = vars.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+
+        let mut help = "Environment:".to_string();
+
+        for (name, description) in vars {
+            help.push_str(&format!(
+                "\n  {:width$}  {}",
+                name,
+                description,
+                width = width
+            ));
+        }
+
+        help
+    }
 }
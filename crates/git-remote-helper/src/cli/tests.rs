@@ -0,0 +1,76 @@
+use super::*;
+
+const USAGE: Usage = Usage {
+    name: "git-remote-test",
+    version: "1.2.3",
+    about: "A Git remote helper for testing.",
+    env: &[("TEST_TOKEN", "A token to authenticate with")],
+};
+
+fn output_for(arg: &str) -> (ErrorKind, String) {
+    let err = USAGE
+        .command()
+        .try_get_matches_from(["git-remote-test", arg])
+        .expect_err("help or version to be displayed");
+    (err.kind(), err.to_string())
+}
+
+#[test]
+fn test_version() {
+    let (kind, output) = output_for("--version");
+
+    assert_eq!(kind, ErrorKind::DisplayVersion, "kind");
+    assert_eq!(output, "git-remote-test 1.2.3\n", "output");
+}
+
+#[test]
+fn test_help_describes_environment() {
+    let (kind, output) = output_for("--help");
+
+    assert_eq!(kind, ErrorKind::DisplayHelp, "kind");
+    assert!(
+        output.contains("A Git remote helper for testing."),
+        "about: {}",
+        output
+    );
+    assert!(
+        output.contains("Usage: git-remote-test <REPOSITORY> <URL>"),
+        "usage: {}",
+        output
+    );
+    assert!(
+        output.contains("TEST_TOKEN") && output.contains("A token to authenticate with"),
+        "environment: {}",
+        output
+    );
+}
+
+#[test]
+fn test_environment_aligns_descriptions() {
+    let environment = USAGE.environment();
+    let mut lines = environment.lines();
+
+    assert_eq!(lines.next(), Some("Environment:"), "heading");
+
+    let columns = lines
+        .map(|line| {
+            let name_len = line.trim_start().find(' ').expect("a description");
+            let padding = line[2 + name_len..]
+                .find(|c| c != ' ')
+                .expect("a description");
+            2 + name_len + padding
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(columns.len(), ENV.len() + 1, "lines");
+    assert!(
+        columns.iter().all(|column| *column == columns[0]),
+        "columns: {:?}",
+        columns
+    );
+    assert!(
+        environment.ends_with("  A token to authenticate with"),
+        "end: {}",
+        environment
+    );
+}
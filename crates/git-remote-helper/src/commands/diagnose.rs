@@ -0,0 +1,82 @@
+use crate::commands::list;
+use crate::git::protocol::Connection;
+use git::protocol::transport::client::Capabilities;
+use git::protocol::transport::Protocol;
+use git_repository as git;
+use log::trace;
+use maybe_async::maybe_async;
+use std::io::{BufWriter, Write};
+
+#[cfg(test)]
+mod tests;
+
+/// Does the handshake with the remote and lists its refs, then writes what
+/// was negotiated to `out` without fetching any objects, for bug reports
+/// about reaching a remote.
+#[maybe_async]
+pub async fn execute<AuthFn, T>(
+    mut transport: T,
+    mut authenticate: AuthFn,
+    extra_parameters: &[(String, Option<String>)],
+    out: &mut impl Write,
+) -> anyhow::Result<()>
+where
+    AuthFn: FnMut(git::credentials::helper::Action) -> git::credentials::protocol::Result,
+    T: git::protocol::transport::client::Transport,
+{
+    trace!("diagnose");
+
+    let mut progress = git::progress::Discard;
+
+    let outcome = git::protocol::fetch::handshake(
+        &mut transport,
+        &mut authenticate,
+        extra_parameters.to_vec(),
+        &mut progress,
+    )
+    .await?;
+
+    let version = outcome.server_protocol_version;
+
+    // Protocol v1 advertises refs in the handshake, but v2 needs `ls-refs`,
+    // which can reuse the connection.
+    let num_refs = match outcome.refs {
+        Some(refs) => refs.len(),
+        None => {
+            let mut connection = Connection {
+                transport,
+                capabilities: Some(outcome.capabilities.clone()),
+            };
+
+            let (refs, _capabilities) =
+                list::list_refs(&mut connection, authenticate, extra_parameters, &[]).await?;
+
+            refs.len()
+        }
+    };
+
+    let mut out = BufWriter::new(out);
+    write_report(&mut out, version, &outcome.capabilities, num_refs)?;
+    out.flush()?;
+
+    Ok(())
+}
+
+fn write_report(
+    mut writer: impl Write,
+    version: Protocol,
+    capabilities: &Capabilities,
+    num_refs: usize,
+) -> std::io::Result<()> {
+    writeln!(writer, "protocol: {:?}", version)?;
+    writeln!(writer, "capabilities:")?;
+
+    for capability in capabilities.iter() {
+        match capability.value() {
+            Some(value) => writeln!(writer, "  {}={}", capability.name(), value)?,
+            None => writeln!(writer, "  {}", capability.name())?,
+        }
+    }
+
+    writeln!(writer, "refs: {}", num_refs)
+}
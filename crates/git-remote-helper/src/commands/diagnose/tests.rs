@@ -0,0 +1,53 @@
+use super::*;
+
+#[test]
+fn test_write_report() {
+    let (capabilities, _delimiter_position) =
+        Capabilities::from_bytes(b"\0ls-refs agent=git/2.39.0").expect("valid capabilities");
+    let mut output = Vec::new();
+    write_report(&mut output, Protocol::V1, &capabilities, 2).expect("write to succeed");
+    assert_eq!(
+        String::from_utf8(output).expect("valid utf-8"),
+        "protocol: V1\ncapabilities:\n  ls-refs\n  agent=git/2.39.0\nrefs: 2\n",
+        "output"
+    )
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_execute() {
+    use crate::test_support::git;
+    use git::protocol::transport;
+
+    let dir = tempfile::tempdir().expect("tempdir");
+    let work = dir.path().join("work");
+    git(dir.path(), ["init", "--bare", "remote.git"]);
+    git(dir.path(), ["init", work.to_str().unwrap()]);
+    git(&work, ["commit", "--allow-empty", "-m", "initial"]);
+    git(&work, ["push", "../remote.git", "main"]);
+    let url = format!("file://{}", dir.path().join("remote.git").display());
+
+    let transport = transport::connect::<_, std::convert::Infallible>(
+        url.as_str(),
+        transport::client::connect::Options {
+            version: transport::Protocol::V2,
+            ssh: Default::default(),
+        },
+    )
+    .expect("transport to connect");
+
+    let mut output = Vec::new();
+    execute(transport, |_| Ok(None), &[], &mut output).expect("diagnosis to succeed");
+
+    let output = String::from_utf8(output).expect("valid utf-8");
+    let lines = output.lines().collect::<Vec<_>>();
+
+    assert_eq!(lines.first(), Some(&"protocol: V2"), "protocol");
+    assert!(
+        lines.iter().any(|line| line.starts_with("  ls-refs")),
+        "capabilities: {:?}",
+        lines
+    );
+    // HEAD and main
+    assert_eq!(lines.last(), Some(&"refs: 2"), "refs");
+}
@@ -1,50 +1,1021 @@
+use crate::commands::option::Options;
+use crate::commands::push::write_remote_message;
+use crate::debug;
+use crate::event;
+use crate::git::service::upload_pack::response::shallow_info::{self, ShallowUpdate};
+use crate::stats::{self, FetchStats};
+use anyhow::{anyhow, Context as _};
+use git::bstr::BString;
+use git::protocol::transport::client::{Capabilities, ExtendedBufRead, MessageKind, WriteMode};
+use git::protocol::transport::Protocol;
+use git::refs::transaction::{Change, LogChange, PreviousValue, RefEdit, RefLog};
 use git_repository as git;
-use log::trace;
-use std::collections::BTreeSet;
+use log::{info, trace};
 use maybe_async::maybe_async;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
-pub type Batch = BTreeSet<(String, String)>;
+#[cfg(feature = "blocking-network-client")]
+use std::io::Read as _;
+
+#[cfg(feature = "async-network-client")]
+use git::protocol::futures_lite::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+#[cfg(test)]
+mod tests;
+
+pub type Batch = BTreeSet<(git::hash::ObjectId, String)>;
 
 #[maybe_async]
-pub async fn process<T>(
-    transport: T,
+#[allow(clippy::too_many_arguments)]
+pub async fn process<AuthFn, T>(
+    connection: crate::git::protocol::Connection<T>,
     repo: &git::Repository,
     url: &str,
+    authenticate: AuthFn,
+    options: &Options,
+    extra_parameters: &[(String, Option<String>)],
+    should_interrupt: &AtomicBool,
+    sink: Option<&mut dyn Write>,
     batch: &mut Batch,
-) -> anyhow::Result<()>
+    out: &mut impl Write,
+) -> anyhow::Result<Option<FetchStats>>
 where
+    AuthFn: FnMut(git::credentials::helper::Action) -> git::credentials::protocol::Result,
     T: git::protocol::transport::client::Transport,
 {
     if !batch.is_empty() {
         trace!("process fetch: {:#?}", batch);
 
+        if let Some(sink) = sink {
+            let (fetch_stats, shallow_updates) = receive_into(
+                connection,
+                repo.object_hash(),
+                authenticate,
+                extra_parameters,
+                options,
+                should_interrupt,
+                batch,
+                &Negotiation {
+                    haves: Vec::new(),
+                    shallow: shallow_commits(repo)?,
+                },
+                sink,
+            )
+            .await?;
+
+            // The pack may be complete by the time it's noticed, but Git
+            // mustn't be told the fetch succeeded.
+            if should_interrupt.load(Ordering::Relaxed) {
+                return Err(anyhow!("fetch was interrupted"));
+            }
+
+            update_shallow(repo, &shallow_updates, options)?;
+
+            batch.clear();
+            writeln!(out)?;
+
+            return Ok(Some(fetch_stats));
+        }
+
+        // gitoxide can't send a filter, so the pack is indexed by Git, which
+        // also marks it as from a promisor remote so that the objects it
+        // leaves out are fetched once they're needed. gitoxide also leaves
+        // out the shallow boundary, both the local one and what the remote
+        // sends, so a fetch into a shallow repository or that may move the
+        // boundary is sent this way as well. So is a fetch into a repository
+        // with alternates, whose refs gitoxide doesn't send as haves, so
+        // that objects the alternates have aren't stored twice, and a clone,
+        // which gitoxide would list the refs for again, since then the fetch
+        // is the only request after the refs Git listed.
+        if options.cloning
+            || options.filter.is_some()
+            || options.update_shallow
+            || shallow_path(repo).exists()
+            || !alternates(repo)?.is_empty()
+        {
+            let (fetch_stats, shallow_updates, pack) = receive_pack(
+                connection,
+                repo,
+                authenticate,
+                extra_parameters,
+                options,
+                should_interrupt,
+                batch,
+            )
+            .await?;
+
+            // Git would otherwise update its refs to objects it doesn't
+            // have, or that an interrupted fetch wasn't meant to leave, and
+            // a boundary Git didn't allow to move would leave the pack's
+            // history cut off without its commits being shallow.
+            let verified = if should_interrupt.load(Ordering::Relaxed) {
+                Err(anyhow!("fetch was interrupted"))
+            } else {
+                shallow_boundary(repo, &shallow_updates)
+                    .and_then(|shallow| verify(repo, batch, options, &shallow))
+                    .and_then(|()| update_shallow(repo, &shallow_updates, options))
+            };
+
+            if let Err(err) = verified {
+                remove_pack(repo, &pack)?;
+                return Err(err);
+            }
+
+            batch.clear();
+            write_connectivity_ok(out, options)?;
+            writeln!(out)?;
+
+            return Ok(Some(fetch_stats));
+        }
+
+        // gitoxide does its own handshake, even over a connection that's
+        // had one, since it lists refs as part of the fetch. The refspecs
+        // have no local refs for it to send as haves.
         let mut remote = repo.remote_at(url)?;
 
-        for (hash, _name) in batch.iter() {
-            remote = remote.with_refspecs(Some(hash.as_bytes()), git::remote::Direction::Fetch)?;
+        for refspec in refspecs(batch) {
+            remote = remote.with_refspecs(Some(refspec), git::remote::Direction::Fetch)?;
         }
 
-        // Implement once option capability is supported
+        // gitoxide turns the remote's progress into calls on this instead of
+        // passing it on as sent, so it isn't shown here even with progress
+        // enabled. Only the negotiations done here show it.
         let progress = git::progress::Discard;
 
-        let outcome = remote
-            .to_connection_with_transport(transport, progress)
+        let start = Instant::now();
+
+        let prepare = remote
+            .to_connection_with_transport(connection.transport, progress)
             .prepare_fetch(git::remote::ref_map::Options {
                 prefix_from_spec_as_filter_on_remote: true,
-                handshake_parameters: vec![],
+                handshake_parameters: extra_parameters.to_vec(),
                 extra_refspecs: vec![],
             })
-            .await?
-            .receive(&git::interrupt::IS_INTERRUPTED)
             .await?;
 
+        check_object_format(
+            repo.object_hash(),
+            &prepare.ref_map().handshake.capabilities,
+        )?;
+
+        let prepare_ms = stats::millis(start.elapsed());
+        let start = Instant::now();
+
+        let refs_before = snapshot_refs(repo)?;
+
+        // Any tempfiles and lock files written so far are removed when
+        // they're dropped, so only what was already persisted needs to be
+        // rolled back.
+        let outcome = match prepare.receive(should_interrupt).await {
+            Ok(outcome) if should_interrupt.load(Ordering::Relaxed) => {
+                remove_pack_bundle(&outcome.status)?;
+                rollback_refs(repo, &refs_before)?;
+                return Err(anyhow!("fetch was interrupted"));
+            }
+            Ok(outcome) => outcome,
+            Err(err) if should_interrupt.load(Ordering::Relaxed) => {
+                rollback_refs(repo, &refs_before)?;
+                return Err(anyhow!(err).context("fetch was interrupted"));
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let receive_ms = stats::millis(start.elapsed());
+
         trace!("outcome: {:#?}", outcome);
+        debug::write("fetch-outcome.txt", format!("{:#?}", outcome));
+
+        // Git would otherwise update its refs to objects it doesn't have, so
+        // a partial pack is removed along with any refs it updated.
+        if let Err(err) = verify(repo, batch, options, &[]) {
+            remove_pack_bundle(&outcome.status)?;
+            rollback_refs(repo, &refs_before)?;
+            return Err(err);
+        }
+
+        let (objects, bytes): (u64, u64) = match &outcome.status {
+            git::remote::fetch::Status::Change {
+                write_pack_bundle, ..
+            } => (
+                write_pack_bundle.index.num_objects.into(),
+                write_pack_bundle
+                    .data_path
+                    .as_ref()
+                    .and_then(|path| std::fs::metadata(path).ok())
+                    .map_or(0, |metadata| metadata.len()),
+            ),
+            _ => (0, 0),
+        };
+
+        event::log(
+            "fetch",
+            &[
+                ("refs", &batch.len()),
+                ("objects", &objects),
+                ("bytes", &bytes),
+                ("receive_ms", &receive_ms),
+            ],
+        );
 
         // TODO: delete .keep files by outputting: lock <file>
         // TODO: determine if gitoxide handles this for us yet
 
         batch.clear();
-        println!();
+        write_connectivity_ok(out, options)?;
+        writeln!(out)?;
+
+        return Ok(Some(FetchStats {
+            prepare_ms,
+            receive_ms,
+            objects,
+            bytes,
+        }));
+    }
+
+    Ok(None)
+}
+
+/// Fetches the objects in `batch` that `repo` doesn't have, with
+/// `git index-pack` indexing the pack into `repo`. The pack is from a
+/// promisor remote when it's limited by the filter in `options`.
+///
+/// Along with what `receive_into` returns, this returns the name of the pack
+/// for `remove_pack`.
+#[maybe_async]
+async fn receive_pack<AuthFn, T>(
+    connection: crate::git::protocol::Connection<T>,
+    repo: &git::Repository,
+    authenticate: AuthFn,
+    extra_parameters: &[(String, Option<String>)],
+    options: &Options,
+    should_interrupt: &AtomicBool,
+    batch: &Batch,
+) -> anyhow::Result<(FetchStats, Vec<ShallowUpdate>, String)>
+where
+    AuthFn: FnMut(git::credentials::helper::Action) -> git::credentials::protocol::Result,
+    T: git::protocol::transport::client::Transport,
+{
+    let negotiation = Negotiation::new(repo, options)?;
+
+    let mut args = vec!["index-pack", "--stdin", "--fix-thin"];
+    if options.filter.is_some() {
+        args.push("--promisor");
+    }
+
+    let tmp_packs_before = tmp_packs(repo);
+
+    // Git reads the helper's stdout, so what index-pack prints is kept
+    // from it.
+    let mut index_pack = Command::new("git")
+        .args(args)
+        .env("GIT_DIR", repo.git_dir())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("failed to run git index-pack")?;
+
+    let mut stdin = index_pack.stdin.take().expect("stdin to be piped");
+
+    let received = receive_into(
+        connection,
+        repo.object_hash(),
+        authenticate,
+        extra_parameters,
+        options,
+        should_interrupt,
+        batch,
+        &negotiation,
+        &mut stdin,
+    )
+    .await;
+
+    // Closing stdin is what tells index-pack the pack has ended.
+    drop(stdin);
+    let output = index_pack.wait_with_output()?;
+
+    if received.is_err() || !output.status.success() {
+        // index-pack leaves what it wrote of a pack that didn't arrive in
+        // full.
+        for path in tmp_packs(repo).difference(&tmp_packs_before) {
+            trace!("removing {}", path.display());
+            std::fs::remove_file(path)?;
+        }
+    }
+
+    let (fetch_stats, shallow_updates) = received?;
+
+    if !output.status.success() {
+        return Err(anyhow!("git index-pack failed with {}", output.status));
+    }
+
+    // index-pack prints `pack` and the pack's hash, or `keep` if it's kept.
+    let pack = String::from_utf8(output.stdout)?
+        .split_whitespace()
+        .nth(1)
+        .ok_or_else(|| anyhow!("git index-pack didn't print the pack it wrote"))?
+        .to_string();
+
+    Ok((fetch_stats, shallow_updates, pack))
+}
+
+/// The temporary files in `repo` that `git index-pack` writes packs to
+/// before they're complete.
+fn tmp_packs(repo: &git::Repository) -> BTreeSet<PathBuf> {
+    std::fs::read_dir(repo.git_dir().join("objects").join("pack"))
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.file_name().map_or(false, |name| {
+                        name.to_string_lossy().starts_with("tmp_pack_")
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Removes the pack named `pack` that `receive_pack` indexed into `repo`,
+/// along with its index and the files that mark it.
+fn remove_pack(repo: &git::Repository, pack: &str) -> anyhow::Result<()> {
+    let pack_dir = repo.git_dir().join("objects").join("pack");
+
+    for extension in ["keep", "promisor", "rev", "idx", "pack"] {
+        let path = pack_dir.join(format!("pack-{}.{}", pack, extension));
+
+        if path.exists() {
+            trace!("removing {}", path.display());
+            std::fs::remove_file(path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Fetches the objects in `batch` as a pack that is written to `sink` rather
+/// than indexed into a repository, for tools that store packs elsewhere. The
+/// remote leaves out what `negotiation` says is already there.
+/// Along with the pack, the remote may send updates to the shallow boundary,
+/// which are returned to be applied once the pack is in place.
+#[maybe_async]
+#[allow(clippy::too_many_arguments)]
+async fn receive_into<AuthFn, T>(
+    mut connection: crate::git::protocol::Connection<T>,
+    object_hash: git::hash::Kind,
+    authenticate: AuthFn,
+    extra_parameters: &[(String, Option<String>)],
+    options: &Options,
+    should_interrupt: &AtomicBool,
+    batch: &Batch,
+    negotiation: &Negotiation,
+    sink: &mut dyn Write,
+) -> anyhow::Result<(FetchStats, Vec<ShallowUpdate>)>
+where
+    AuthFn: FnMut(git::credentials::helper::Action) -> git::credentials::protocol::Result,
+    T: git::protocol::transport::client::Transport,
+{
+    let start = Instant::now();
+
+    // The handshake is skipped if `connection` already did one.
+    let (version, capabilities, _refs) =
+        crate::git::protocol::handshake(&mut connection, authenticate, extra_parameters).await?;
+
+    check_object_format(object_hash, &capabilities)?;
+
+    let prepare_ms = stats::millis(start.elapsed());
+    let start = Instant::now();
+
+    let mut reader = send_request(
+        &mut connection.transport,
+        version,
+        &capabilities,
+        options,
+        batch,
+        negotiation,
+    )
+    .await?;
+    let response = git::protocol::fetch::Response::from_line_reader(version, &mut reader).await?;
+
+    trace!("response: {:#?}", response);
+
+    if !response.has_pack() {
+        return Err(anyhow!("the remote didn't send a pack"));
+    }
+
+    let progress = options.progress;
+    let mut stderr = std::io::stderr();
+    reader.set_progress_handler(Some(Box::new(move |is_err: bool, data: &[u8]| {
+        write_remote_message(&mut stderr, progress, is_err, data)
+    })));
+
+    let shallow_updates = response
+        .shallow_updates()
+        .iter()
+        .map(|update| match update {
+            git::protocol::fetch::response::ShallowUpdate::Shallow(id) => {
+                ShallowUpdate::Shallow(*id)
+            }
+            git::protocol::fetch::response::ShallowUpdate::Unshallow(id) => {
+                ShallowUpdate::Unshallow(*id)
+            }
+        })
+        .collect();
+
+    // The header is the signature, version, and number of objects.
+    let mut header = Vec::with_capacity(12);
+    let mut bytes = 0;
+    let mut buf = vec![0; 64 * 1024];
+
+    loop {
+        if should_interrupt.load(Ordering::Relaxed) {
+            return Err(anyhow!("fetch was interrupted"));
+        }
+
+        let len = reader.read(&mut buf).await?;
+        if len == 0 {
+            break;
+        }
+
+        let header_len = (12 - header.len()).min(len);
+        header.extend_from_slice(&buf[..header_len]);
+
+        sink.write_all(&buf[..len])?;
+        bytes += u64::try_from(len)?;
+    }
+
+    sink.flush()?;
+
+    let receive_ms = stats::millis(start.elapsed());
+
+    let objects = match header.as_slice() {
+        [b'P', b'A', b'C', b'K', _, _, _, _, objects @ ..] => {
+            u32::from_be_bytes(objects.try_into()?).into()
+        }
+        _ => return Err(anyhow!("the remote sent an invalid pack header")),
+    };
+
+    Ok((
+        FetchStats {
+            prepare_ms,
+            receive_ms,
+            objects,
+            bytes,
+        },
+        shallow_updates,
+    ))
+}
+
+/// Sends the request for the objects in `batch`, returning the response.
+/// Rather than negotiating, the request is done with whatever haves there
+/// are, which saves the round-trips that canister calls make costly.
+///
+/// With protocol v2 the request is written here rather than by gitoxide,
+/// since `no-progress` is an argument it has no way to send.
+#[maybe_async]
+async fn send_request<'a, T>(
+    transport: &'a mut T,
+    version: Protocol,
+    capabilities: &Capabilities,
+    options: &Options,
+    batch: &Batch,
+    negotiation: &Negotiation,
+) -> anyhow::Result<Box<dyn ExtendedBufRead + Unpin + 'a>>
+where
+    T: git::protocol::transport::client::Transport,
+{
+    let features = features(version, capabilities, options);
+
+    if version == Protocol::V2 {
+        let arguments = arguments_v2(&features, options, batch, negotiation)?;
+        trace!("fetch arguments: {:#?}", arguments);
+
+        let mut writer = transport.request(
+            WriteMode::OneLfTerminatedLinePerWriteCall,
+            MessageKind::Flush,
+        )?;
+
+        writer.write_all(b"command=fetch").await?;
+
+        // As gitoxide sends them, only the features with a value, like
+        // `agent`, are capabilities of the request.
+        for (name, value) in features.iter() {
+            if let Some(value) = value {
+                writer
+                    .write_all(format!("{}={}", name, value).as_bytes())
+                    .await?;
+            }
+        }
+
+        writer.write_message(MessageKind::Delimiter).await?;
+
+        for argument in arguments {
+            writer.write_all(&argument).await?;
+        }
+
+        return Ok(writer.into_read().await?);
+    }
+
+    let mut arguments = git::protocol::fetch::Arguments::new(version, features);
+
+    for (id, _name) in batch.iter() {
+        arguments.want(id);
+    }
+
+    if !negotiation.shallow.is_empty() && !arguments.can_use_shallow() {
+        return Err(unsupported_shallow());
+    }
+
+    for id in negotiation.shallow.iter() {
+        arguments.shallow(id);
+    }
+
+    for id in negotiation.haves.iter() {
+        arguments.have(id);
+    }
+
+    add_filter(&mut arguments, options.filter.as_deref())?;
+
+    // Otherwise Git fetches the tags it follows once it has seen what they
+    // point to, which takes another round-trip.
+    if options.follow_tags && arguments.can_use_include_tag() {
+        arguments.use_include_tag();
+    }
+
+    Ok(arguments.send(transport, true).await?)
+}
+
+/// The arguments of a protocol v2 `fetch` of `batch` that's done in one
+/// request, as `git fetch` sends them.
+fn arguments_v2(
+    features: &[git::protocol::command::Feature],
+    options: &Options,
+    batch: &Batch,
+    negotiation: &Negotiation,
+) -> anyhow::Result<Vec<BString>> {
+    let mut arguments: Vec<BString> = vec!["thin-pack".into(), "ofs-delta".into()];
+
+    if !options.progress {
+        arguments.push("no-progress".into());
+    }
+
+    // Otherwise Git fetches the tags it follows once it has seen what they
+    // point to, which takes another round-trip.
+    if options.follow_tags {
+        arguments.push("include-tag".into());
+    }
+
+    for (id, _name) in batch.iter() {
+        arguments.push(format!("want {}", id).into());
+    }
+
+    if !negotiation.shallow.is_empty() && !features.iter().any(|(name, _)| *name == "shallow") {
+        return Err(unsupported_shallow());
+    }
+
+    for id in negotiation.shallow.iter() {
+        arguments.push(format!("shallow {}", id).into());
+    }
+
+    for id in negotiation.haves.iter() {
+        arguments.push(format!("have {}", id).into());
+    }
+
+    if let Some(filter) = options.filter.as_deref() {
+        if !features.iter().any(|(name, _)| *name == "filter") {
+            return Err(unsupported_filter(filter));
+        }
+
+        trace!("filter: {}", filter);
+        arguments.push(format!("filter {}", filter).into());
+    }
+
+    arguments.push("done".into());
+
+    Ok(arguments)
+}
+
+/// What the remote is told about the repository being fetched into, so that
+/// the pack leaves out what's already there.
+#[derive(Debug)]
+struct Negotiation {
+    haves: Vec<git::hash::ObjectId>,
+    /// The commits the repository's history is cut off at, past which the
+    /// remote mustn't assume it has anything.
+    shallow: Vec<git::hash::ObjectId>,
+}
+
+impl Negotiation {
+    /// What to tell the remote about `repo`. A clone sends no haves, since
+    /// there's nothing local to negotiate with.
+    fn new(repo: &git::Repository, options: &Options) -> anyhow::Result<Self> {
+        let haves = if options.cloning {
+            Vec::new()
+        } else {
+            haves(repo)?
+        };
+
+        Ok(Self {
+            haves,
+            shallow: shallow_commits(repo)?,
+        })
+    }
+}
+
+/// The commits the refs of `repo` and its alternates point to, for the
+/// remote to leave out of the pack along with their history.
+fn haves(repo: &git::Repository) -> anyhow::Result<Vec<git::hash::ObjectId>> {
+    let mut haves = BTreeSet::new();
+    add_haves(repo, repo, &mut haves)?;
+
+    for alternate in alternates(repo)? {
+        // An alternate that's only an object store has no refs to send.
+        if let Ok(alternate) = git::open(&alternate) {
+            add_haves(repo, &alternate, &mut haves)?;
+        }
+    }
+
+    trace!("haves: {:#?}", haves);
+
+    Ok(haves.into_iter().collect())
+}
+
+/// Adds the commits the refs of `refs_repo` point to that `repo` has.
+fn add_haves(
+    repo: &git::Repository,
+    refs_repo: &git::Repository,
+    haves: &mut BTreeSet<git::hash::ObjectId>,
+) -> anyhow::Result<()> {
+    for reference in refs_repo.references()?.all()? {
+        let mut reference = reference.map_err(|err| anyhow!(err))?;
+
+        // A symbolic ref may point to a ref that doesn't exist, and a ref may
+        // point to an object the repository doesn't have, like a blob that a
+        // partial clone left out.
+        let id = match reference.peel_to_id_in_place() {
+            Ok(id) => id.detach(),
+            Err(_) => continue,
+        };
+        let is_commit = repo
+            .find_object(id)
+            .map_or(false, |object| object.kind == git::objs::Kind::Commit);
+
+        if is_commit {
+            haves.insert(id);
+        }
+    }
+
+    Ok(())
+}
+
+/// The git dirs of the repositories whose objects `repo` borrows, listed in
+/// `objects/info/alternates` by their objects directories. As Git resolves
+/// them, relative paths are relative to the objects directory of `repo`.
+fn alternates(repo: &git::Repository) -> anyhow::Result<Vec<PathBuf>> {
+    let objects = repo.git_dir().join("objects");
+
+    let contents = match std::fs::read_to_string(objects.join("info").join("alternates")) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(anyhow!(err).context("failed to read the alternates")),
+    };
+
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| objects.join(line).parent().map(PathBuf::from))
+        .collect())
+}
+
+/// Moves the shallow boundary of `repo` as the remote sent, which Git only
+/// allows once it's sent `option update-shallow`, since it would otherwise
+/// have commits grafted that it doesn't expect.
+fn update_shallow(
+    repo: &git::Repository,
+    shallow_updates: &[ShallowUpdate],
+    options: &Options,
+) -> anyhow::Result<()> {
+    if shallow_updates.is_empty() {
+        return Ok(());
+    }
+
+    if !options.update_shallow {
+        return Err(anyhow!(
+            "the remote sent updates to the shallow boundary, but Git didn't allow them with update-shallow"
+        ));
+    }
+
+    let lines = shallow_lines(shallow_updates);
+
+    // Shown with `git fetch -v`, as Git shows what it updates.
+    for line in lines.iter() {
+        info!("{}", line);
+    }
+
+    debug::write("shallow-updates.txt", lines.join("\n"));
+
+    shallow_info::apply(&shallow_path(repo), shallow_updates)
+        .context("failed to update the shallow boundary")
+}
+
+/// Where Git lists the commits the history of `repo` is cut off at, which
+/// only exists while it's shallow.
+fn shallow_path(repo: &git::Repository) -> PathBuf {
+    repo.git_dir().join("shallow")
+}
+
+fn shallow_commits(repo: &git::Repository) -> anyhow::Result<Vec<git::hash::ObjectId>> {
+    shallow_info::read(&shallow_path(repo)).context("failed to read the shallow boundary")
+}
+
+/// The commits the history of `repo` is cut off at once `shallow_updates`
+/// are applied, which the fetched pack is only complete up to.
+fn shallow_boundary(
+    repo: &git::Repository,
+    shallow_updates: &[ShallowUpdate],
+) -> anyhow::Result<Vec<git::hash::ObjectId>> {
+    let mut shallow = shallow_commits(repo)?.into_iter().collect::<BTreeSet<_>>();
+
+    for shallow_update in shallow_updates {
+        match shallow_update {
+            ShallowUpdate::Shallow(id) => shallow.insert(*id),
+            ShallowUpdate::Unshallow(id) => shallow.remove(id),
+        };
+    }
+
+    Ok(shallow.into_iter().collect())
+}
+
+/// The updates as the `shallow` and `unshallow` lines they're sent as.
+fn shallow_lines(shallow_updates: &[ShallowUpdate]) -> Vec<String> {
+    shallow_updates
+        .iter()
+        .map(|update| match update {
+            ShallowUpdate::Shallow(id) => format!("shallow {}", id),
+            ShallowUpdate::Unshallow(id) => format!("unshallow {}", id),
+        })
+        .collect()
+}
+
+/// The features to fetch with, which ask the remote not to send progress
+/// when it's disabled, as for `git fetch -q`.
+fn features(
+    version: Protocol,
+    capabilities: &Capabilities,
+    options: &Options,
+) -> Vec<git::protocol::command::Feature> {
+    let mut features = git::protocol::Command::Fetch.default_features(version, capabilities);
+
+    // With protocol v2 `no-progress` is an argument rather than a feature.
+    if !options.progress && version != Protocol::V2 && capabilities.contains("no-progress") {
+        features.push(("no-progress", None));
+    }
+
+    features
+}
+
+/// Asks the remote to leave out the objects `filter` excludes, failing if the
+/// remote doesn't advertise `filter` rather than fetching everything.
+fn add_filter(
+    arguments: &mut git::protocol::fetch::Arguments,
+    filter: Option<&str>,
+) -> anyhow::Result<()> {
+    let filter = match filter {
+        Some(filter) => filter,
+        None => return Ok(()),
+    };
+
+    if !arguments.can_use_filter() {
+        return Err(unsupported_filter(filter));
+    }
+
+    trace!("filter: {}", filter);
+    arguments.filter(filter);
+
+    Ok(())
+}
+
+fn unsupported_shallow() -> anyhow::Error {
+    anyhow!("the repository is shallow, but the remote doesn't support shallow clients")
+}
+
+fn unsupported_filter(filter: &str) -> anyhow::Error {
+    anyhow!(
+        "the remote doesn't support partial clone filters, but `{}` was requested",
+        filter
+    )
+}
+
+/// Fails if the remote's advertised `object-format` differs from the local
+/// repository's, since the fetched objects would be unreadable. Remotes that
+/// don't advertise one use SHA-1.
+fn check_object_format(local: git::hash::Kind, capabilities: &Capabilities) -> anyhow::Result<()> {
+    let local = local.to_string().to_ascii_lowercase();
+    let remote = crate::git::protocol::object_format(capabilities);
+
+    if local != remote {
+        return Err(anyhow!(
+            "object-format mismatch: local {}, remote {}",
+            local,
+            remote
+        ));
+    }
+
+    Ok(())
+}
+
+/// Fails if what was fetched for `batch` is incomplete, which with
+/// `option check-connectivity` includes everything reachable from it short
+/// of the `shallow` commits' parents.
+fn verify(
+    repo: &git::Repository,
+    batch: &Batch,
+    options: &Options,
+    shallow: &[git::hash::ObjectId],
+) -> anyhow::Result<()> {
+    check_received(repo, batch)?;
+
+    if checks_connectivity(options) {
+        check_connectivity(repo, batch, shallow)?;
+    }
+
+    Ok(())
+}
+
+/// Whether `verify` checks everything reachable from what's fetched, which
+/// a partial clone leaves objects out of on purpose.
+fn checks_connectivity(options: &Options) -> bool {
+    options.check_connectivity && options.filter.is_none()
+}
+
+/// Tells Git that a verified fetch is connected, so that it doesn't walk
+/// the objects again itself.
+fn write_connectivity_ok(out: &mut impl Write, options: &Options) -> std::io::Result<()> {
+    if checks_connectivity(options) {
+        writeln!(out, "connectivity-ok")?;
+    }
+
+    Ok(())
+}
+
+/// Fails naming an object that's reachable from a ref in `batch` but
+/// missing, such as a parent commit that a misbehaving remote left out of
+/// the pack, which `check_received` doesn't look far enough to notice. The
+/// history is cut off at the `shallow` commits, whose parents aren't there.
+fn check_connectivity(
+    repo: &git::Repository,
+    batch: &Batch,
+    shallow: &[git::hash::ObjectId],
+) -> anyhow::Result<()> {
+    let mut seen = HashSet::new();
+
+    for (id, name) in batch.iter() {
+        let mut pending = vec![*id];
+
+        while let Some(id) = pending.pop() {
+            if !seen.insert(id) {
+                continue;
+            }
+
+            let object = repo.find_object(id).with_context(|| {
+                format!(
+                    "the fetched objects are incomplete: {} is missing, which is reachable from {}",
+                    id, name
+                )
+            })?;
+
+            match git::objs::ObjectRef::from_bytes(object.kind, &object.data)? {
+                git::objs::ObjectRef::Commit(commit) => {
+                    pending.push(commit.tree());
+
+                    if !shallow.contains(&id) {
+                        pending.extend(commit.parents());
+                    }
+                }
+                git::objs::ObjectRef::Tree(tree) => pending.extend(
+                    tree.entries
+                        .iter()
+                        // Submodule commits are in other repositories.
+                        .filter(|entry| entry.mode != git::objs::tree::EntryMode::Commit)
+                        .map(|entry| entry.oid.to_owned()),
+                ),
+                git::objs::ObjectRef::Tag(tag) => pending.push(tag.target()),
+                git::objs::ObjectRef::Blob(_) => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Fails naming each ref whose object the remote didn't send, which Git
+/// would otherwise only discover when it reads the object.
+fn check_received(repo: &git::Repository, batch: &Batch) -> anyhow::Result<()> {
+    let missing = batch
+        .iter()
+        .filter(|(id, _name)| repo.find_object(*id).is_err())
+        .map(|(id, name)| format!("{} {}", id, name))
+        .collect::<Vec<_>>();
+
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "the remote didn't send the objects for: {}",
+            missing.join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
+/// Refspecs to fetch each object by id without a destination, so that no
+/// local refs are created. This is what we want even when the object isn't
+/// the tip of any remote ref, as with a detached `HEAD`, since Git records
+/// what was fetched in `FETCH_HEAD` and updates refs itself.
+fn refspecs(batch: &Batch) -> impl Iterator<Item = BString> + '_ {
+    batch
+        .iter()
+        .map(|(id, _name)| id.to_hex().to_string().into())
+}
+
+type RefSnapshot = BTreeMap<git::refs::FullName, git::refs::Target>;
+
+fn snapshot_refs(repo: &git::Repository) -> anyhow::Result<RefSnapshot> {
+    let mut snapshot = BTreeMap::new();
+
+    for reference in repo.references()?.all()? {
+        let reference = reference.map_err(|err| anyhow!(err))?;
+        snapshot.insert(reference.inner.name, reference.inner.target);
+    }
+
+    Ok(snapshot)
+}
+
+/// Restores refs to how they were in `snapshot`, undoing any updates that
+/// were applied before an interrupt.
+fn rollback_refs(repo: &git::Repository, snapshot: &RefSnapshot) -> anyhow::Result<()> {
+    let current = snapshot_refs(repo)?;
+
+    let updates = snapshot
+        .iter()
+        .filter(|(name, target)| current.get(*name) != Some(*target))
+        .map(|(name, target)| RefEdit {
+            change: Change::Update {
+                log: LogChange {
+                    message: "fetch: rollback after interrupt".into(),
+                    ..Default::default()
+                },
+                expected: PreviousValue::Any,
+                new: target.clone(),
+            },
+            name: name.clone(),
+            deref: false,
+        });
+
+    let deletes = current
+        .keys()
+        .filter(|name| !snapshot.contains_key(*name))
+        .map(|name| RefEdit {
+            change: Change::Delete {
+                expected: PreviousValue::Any,
+                log: RefLog::AndReference,
+            },
+            name: name.clone(),
+            deref: false,
+        });
+
+    let edits = updates.chain(deletes).collect::<Vec<_>>();
+
+    trace!("rollback edits: {:#?}", edits);
+
+    if !edits.is_empty() {
+        repo.edit_references(edits)?;
+    }
+
+    Ok(())
+}
+
+fn remove_pack_bundle(status: &git::remote::fetch::Status) -> anyhow::Result<()> {
+    if let git::remote::fetch::Status::Change {
+        write_pack_bundle, ..
+    } = status
+    {
+        for path in [
+            &write_pack_bundle.keep_path,
+            &write_pack_bundle.index_path,
+            &write_pack_bundle.data_path,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            trace!("removing {}", path.display());
+            std::fs::remove_file(path)?;
+        }
     }
 
     Ok(())
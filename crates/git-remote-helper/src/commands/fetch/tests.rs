@@ -0,0 +1,1459 @@
+use super::*;
+use crate::test_support::git;
+use std::path::{Path, PathBuf};
+
+/// Creates `local` with `main` and `topic` branches, returning its path.
+fn local(dir: &Path) -> PathBuf {
+    git(dir, ["init", "local"]);
+    let local = dir.join("local");
+    git(&local, ["commit", "--allow-empty", "-m", "Initial commit"]);
+    git(&local, ["branch", "topic"]);
+    local
+}
+
+fn id(hash: &str) -> git::hash::ObjectId {
+    git::hash::ObjectId::from_hex(hash.as_bytes()).expect("valid hash")
+}
+
+fn packs(repo: &git::Repository) -> Vec<PathBuf> {
+    let mut packs = std::fs::read_dir(repo.git_dir().join("objects").join("pack"))
+        .map(|entries| {
+            entries
+                .map(|entry| entry.expect("dir entry").path())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    packs.sort();
+    packs
+}
+
+#[test]
+fn test_rollback_refs() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let local = local(dir.path());
+    let repo = git::open(&local).expect("repository to open");
+
+    let before = snapshot_refs(&repo).expect("refs to be read");
+
+    // Simulate an interrupt part way through applying ref updates
+    git(&local, ["commit", "--allow-empty", "-m", "Partial update"]);
+    git(&local, ["branch", "new"]);
+    git(&local, ["branch", "-D", "topic"]);
+
+    rollback_refs(&repo, &before).expect("rollback to succeed");
+
+    assert_eq!(
+        snapshot_refs(&repo).expect("refs to be read"),
+        before,
+        "refs"
+    )
+}
+
+/// Creates `remote.git` with two commits on `main`, returning its URL and the
+/// ids of the commits, oldest first.
+fn remote(dir: &Path) -> (String, Vec<String>) {
+    git(dir, ["init", "--bare", "remote.git"]);
+    git(
+        &dir.join("remote.git"),
+        ["config", "uploadpack.allowReachableSHA1InWant", "true"],
+    );
+    git(dir, ["clone", "remote.git", "remote"]);
+    let remote = dir.join("remote");
+    git(
+        &remote,
+        ["commit", "--allow-empty", "-m", "First remote commit"],
+    );
+    let first = git(&remote, ["rev-parse", "HEAD"]);
+    git(
+        &remote,
+        ["commit", "--allow-empty", "-m", "Second remote commit"],
+    );
+    let second = git(&remote, ["rev-parse", "HEAD"]);
+    git(&remote, ["push", "origin", "main"]);
+
+    let url = format!("file://{}", dir.join("remote.git").display());
+    (url, vec![first, second])
+}
+
+#[cfg(feature = "blocking-network-client")]
+fn connect(
+    url: &str,
+) -> crate::git::protocol::Connection<Box<dyn git::protocol::transport::client::Transport + Send>> {
+    use git::protocol::transport;
+
+    crate::git::protocol::Connection::new(
+        transport::connect::<_, std::convert::Infallible>(
+            url,
+            transport::client::connect::Options {
+                version: transport::Protocol::V2,
+                ssh: Default::default(),
+            },
+        )
+        .expect("transport to connect"),
+    )
+}
+
+/// A transport that keeps a copy of every request sent over it, so that
+/// tests can check what the remote was asked for.
+#[cfg(feature = "blocking-network-client")]
+struct Recording<T> {
+    inner: T,
+    requests: std::sync::Arc<std::sync::Mutex<Vec<u8>>>,
+}
+
+#[cfg(feature = "blocking-network-client")]
+impl<T> Recording<T> {
+    fn new(inner: T) -> Self {
+        Self {
+            inner,
+            requests: Default::default(),
+        }
+    }
+
+    /// The packet lines of the requests sent so far.
+    fn requests(&self) -> std::sync::Arc<std::sync::Mutex<Vec<u8>>> {
+        std::sync::Arc::clone(&self.requests)
+    }
+}
+
+#[cfg(feature = "blocking-network-client")]
+struct Tee<'a> {
+    inner: Box<dyn Write + 'a>,
+    copy: std::sync::Arc<std::sync::Mutex<Vec<u8>>>,
+}
+
+#[cfg(feature = "blocking-network-client")]
+impl Write for Tee<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.copy
+            .lock()
+            .expect("lock")
+            .extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(feature = "blocking-network-client")]
+impl<T> git::protocol::transport::client::TransportWithoutIO for Recording<T>
+where
+    T: git::protocol::transport::client::Transport,
+{
+    fn request(
+        &mut self,
+        write_mode: WriteMode,
+        on_into_read: MessageKind,
+    ) -> Result<
+        git::protocol::transport::client::RequestWriter<'_>,
+        git::protocol::transport::client::Error,
+    > {
+        let copy = std::sync::Arc::clone(&self.requests);
+        let (writer, reader) = self.inner.request(write_mode, on_into_read)?.into_parts();
+
+        Ok(
+            git::protocol::transport::client::RequestWriter::new_from_bufread(
+                Tee {
+                    inner: writer,
+                    copy,
+                },
+                reader,
+                write_mode,
+                on_into_read,
+            ),
+        )
+    }
+
+    fn to_url(&self) -> std::borrow::Cow<'_, git::bstr::BStr> {
+        self.inner.to_url()
+    }
+
+    fn connection_persistent_by_default(&self) -> bool {
+        self.inner.connection_persistent_by_default()
+    }
+
+    fn configure(
+        &mut self,
+        config: &dyn std::any::Any,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+        self.inner.configure(config)
+    }
+}
+
+#[cfg(feature = "blocking-network-client")]
+impl<T> git::protocol::transport::client::Transport for Recording<T>
+where
+    T: git::protocol::transport::client::Transport,
+{
+    fn handshake<'a>(
+        &mut self,
+        service: git::protocol::transport::Service,
+        extra_parameters: &'a [(&'a str, Option<&'a str>)],
+    ) -> Result<
+        git::protocol::transport::client::SetServiceResponse<'_>,
+        git::protocol::transport::client::Error,
+    > {
+        self.inner.handshake(service, extra_parameters)
+    }
+}
+
+/// Connects to `url` over a transport that records the requests, which are
+/// returned along with the connection.
+#[cfg(feature = "blocking-network-client")]
+fn connect_recording(
+    url: &str,
+) -> (
+    crate::git::protocol::Connection<
+        Recording<Box<dyn git::protocol::transport::client::Transport + Send>>,
+    >,
+    std::sync::Arc<std::sync::Mutex<Vec<u8>>>,
+) {
+    let transport = Recording::new(connect(url).transport);
+    let requests = transport.requests();
+    (crate::git::protocol::Connection::new(transport), requests)
+}
+
+/// Whether the recorded `requests` have a packet line of `line`.
+#[cfg(feature = "blocking-network-client")]
+fn sent_line(requests: &std::sync::Mutex<Vec<u8>>, line: &str) -> bool {
+    use git::bstr::ByteSlice as _;
+
+    let requests = requests.lock().expect("lock");
+    let packet = format!("{:04x}{}\n", line.len() + 5, line);
+    requests.find(packet.as_bytes()).is_some()
+}
+
+#[test]
+fn test_refspecs() {
+    let batch = Batch::from([
+        (
+            id("1111111111111111111111111111111111111111"),
+            "HEAD".to_string(),
+        ),
+        (
+            id("2222222222222222222222222222222222222222"),
+            "refs/heads/main".to_string(),
+        ),
+    ]);
+    assert_eq!(
+        refspecs(&batch).collect::<Vec<_>>(),
+        vec![
+            BString::from("1111111111111111111111111111111111111111"),
+            BString::from("2222222222222222222222222222222222222222"),
+        ],
+        "refspecs"
+    )
+}
+
+fn has_feature(features: &[git::protocol::command::Feature], name: &str) -> bool {
+    features.iter().any(|(feature, _)| *feature == name)
+}
+
+#[test]
+fn test_features_no_progress_when_progress_off() {
+    let capabilities = Capabilities::from_bytes(b"\0multi_ack_detailed side-band-64k no-progress")
+        .expect("valid capabilities")
+        .0;
+    let options = Options {
+        progress: false,
+        ..Default::default()
+    };
+
+    assert!(
+        has_feature(
+            &features(
+                git::protocol::transport::Protocol::V1,
+                &capabilities,
+                &options
+            ),
+            "no-progress"
+        ),
+        "progress off"
+    );
+    assert!(
+        !has_feature(
+            &features(
+                git::protocol::transport::Protocol::V1,
+                &capabilities,
+                &Options::default()
+            ),
+            "no-progress"
+        ),
+        "progress on"
+    );
+}
+
+#[test]
+fn test_features_no_progress_when_unsupported() {
+    let capabilities = Capabilities::from_bytes(b"\0multi_ack_detailed side-band-64k")
+        .expect("valid capabilities")
+        .0;
+    let options = Options {
+        progress: false,
+        ..Default::default()
+    };
+
+    assert!(
+        !has_feature(
+            &features(
+                git::protocol::transport::Protocol::V1,
+                &capabilities,
+                &options
+            ),
+            "no-progress"
+        ),
+        "features"
+    );
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_fetch_without_progress_sends_no_progress() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let local = local(dir.path());
+    let (url, hashes) = remote(dir.path());
+    let repo = git::open(&local).expect("repository to open");
+
+    for progress in [false, true] {
+        let (connection, requests) = connect_recording(&url);
+        let mut batch: Batch = BTreeSet::from([(id(&hashes[1]), "refs/heads/main".to_string())]);
+
+        process(
+            connection,
+            &repo,
+            &url,
+            |_| Ok(None),
+            &Options {
+                progress,
+                ..Default::default()
+            },
+            &[],
+            &AtomicBool::new(false),
+            Some(&mut Vec::new()),
+            &mut batch,
+            &mut std::io::sink(),
+        )
+        .expect("fetch to succeed");
+
+        assert!(sent_line(&requests, "command=fetch"), "fetch request");
+        assert_eq!(
+            sent_line(&requests, "no-progress"),
+            !progress,
+            "no-progress with progress {}",
+            progress
+        );
+    }
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_fetch_detached_commit() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let local = local(dir.path());
+    let (url, hashes) = remote(dir.path());
+    // Not the tip of any remote ref
+    let hash = hashes[0].clone();
+
+    let repo = git::open(&local).expect("repository to open");
+    let refs_before = snapshot_refs(&repo).expect("refs to be read");
+
+    let mut batch: Batch = BTreeSet::from([(id(&hash), hash.clone())]);
+
+    process(
+        connect(&url),
+        &repo,
+        &url,
+        |_| Ok(None),
+        &Options::default(),
+        &[],
+        &AtomicBool::new(false),
+        None,
+        &mut batch,
+        &mut std::io::sink(),
+    )
+    .expect("fetch to succeed");
+
+    assert!(
+        repo.find_object(id(&hash)).is_ok(),
+        "fetched commit should be in the object store"
+    );
+    assert_eq!(
+        snapshot_refs(&repo).expect("refs to be read"),
+        refs_before,
+        "refs"
+    );
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_fetch_with_alternate() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let local = local(dir.path());
+    let (url, hashes) = remote(dir.path());
+
+    // Borrow the objects of a copy of the remote like a shared object store,
+    // with a path relative to the objects directory as Git resolves it.
+    git(dir.path(), ["clone", "--bare", url.as_str(), "store.git"]);
+    let alternates = local.join(".git/objects/info/alternates");
+    std::fs::write(alternates, "../../../store.git/objects\n").expect("alternates to be written");
+
+    let remote = dir.path().join("remote");
+    std::fs::write(remote.join("README.md"), "# Remote\n").expect("file to be written");
+    git(&remote, ["add", "README.md"]);
+    git(&remote, ["commit", "-m", "Add README"]);
+    let third = git(&remote, ["rev-parse", "HEAD"]);
+    git(&remote, ["push", "origin", "main"]);
+
+    let repo = git::open(&local).expect("repository to open");
+    let store = dir.path().join("store.git");
+    let store_objects = git(&store, ["count-objects", "-v"]);
+
+    let (connection, requests) = connect_recording(&url);
+    let mut batch: Batch = BTreeSet::from([(id(&third), "refs/heads/main".to_string())]);
+
+    let fetch_stats = process(
+        connection,
+        &repo,
+        &url,
+        |_| Ok(None),
+        &Options {
+            check_connectivity: true,
+            ..Default::default()
+        },
+        &[],
+        &AtomicBool::new(false),
+        None,
+        &mut batch,
+        &mut std::io::sink(),
+    )
+    .expect("fetch to succeed")
+    .expect("fetch stats");
+
+    assert!(
+        sent_line(&requests, &format!("have {}", hashes[1])),
+        "the alternate's main is a have"
+    );
+    // The new commit, its tree and the README, without what the alternate has
+    assert_eq!(fetch_stats.objects, 3, "objects");
+    assert!(
+        packs(&repo)
+            .iter()
+            .any(|pack| pack.extension() == Some("pack".as_ref())),
+        "pack in the repository's own objects"
+    );
+    assert_eq!(
+        git(&store, ["count-objects", "-v"]),
+        store_objects,
+        "alternate's objects"
+    );
+    assert!(repo.find_object(id(&third)).is_ok(), "third commit");
+    assert!(
+        repo.find_object(id(&hashes[0])).is_ok(),
+        "first commit should be found in the alternate"
+    );
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_fetch_several_refs() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let local = local(dir.path());
+    let (url, hashes) = remote(dir.path());
+
+    let remote = dir.path().join("remote");
+    git(&remote, ["checkout", "-b", "topic", &hashes[0]]);
+    git(&remote, ["commit", "--allow-empty", "-m", "Topic commit"]);
+    let topic = git(&remote, ["rev-parse", "HEAD"]);
+    git(&remote, ["tag", "-a", "v1", "-m", "Version 1", &hashes[0]]);
+    let tag = git(&remote, ["rev-parse", "v1"]);
+    git(&remote, ["push", "origin", "topic", "v1"]);
+
+    let repo = git::open(&local).expect("repository to open");
+
+    let mut batch: Batch = BTreeSet::from([
+        (id(&hashes[1]), "refs/heads/main".to_string()),
+        (id(&topic), "refs/heads/topic".to_string()),
+        (id(&tag), "refs/tags/v1".to_string()),
+    ]);
+
+    process(
+        connect(&url),
+        &repo,
+        &url,
+        |_| Ok(None),
+        &Options::default(),
+        &[],
+        &AtomicBool::new(false),
+        None,
+        &mut batch,
+        &mut std::io::sink(),
+    )
+    .expect("fetch to succeed");
+
+    for hash in [&hashes[1], &topic, &tag] {
+        assert!(
+            repo.find_object(id(hash)).is_ok(),
+            "{} should be fetched",
+            hash
+        );
+    }
+    assert!(batch.is_empty(), "batch");
+}
+
+#[test]
+fn test_check_received() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let local = local(dir.path());
+    let repo = git::open(&local).expect("repository to open");
+
+    let head = (
+        id(&git(&local, ["rev-parse", "HEAD"])),
+        "refs/heads/main".to_string(),
+    );
+    let missing = (
+        id("1111111111111111111111111111111111111111"),
+        "refs/tags/v1".to_string(),
+    );
+
+    assert!(
+        check_received(&repo, &Batch::from([head.clone()])).is_ok(),
+        "received"
+    );
+    assert_eq!(
+        check_received(&repo, &Batch::from([head, missing]))
+            .expect_err("missing object")
+            .to_string(),
+        "the remote didn't send the objects for: \
+         1111111111111111111111111111111111111111 refs/tags/v1",
+        "error"
+    );
+}
+
+/// Indexes a pack of `objects`, given as `git pack-objects` reads them, from
+/// `source` into `local`, the way a remote's pack would be.
+fn copy_objects(source: &Path, local: &Path, objects: &str) {
+    let pack = Command::new("git")
+        .current_dir(source)
+        .args(["pack-objects", "--stdout", "-q"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            child
+                .stdin
+                .take()
+                .expect("stdin to be piped")
+                .write_all(format!("{}\n", objects).as_bytes())?;
+            child.wait_with_output()
+        })
+        .expect("git pack-objects to run");
+    assert!(pack.status.success(), "{:?}", pack);
+
+    let mut index_pack = Command::new("git")
+        .current_dir(local)
+        .args(["index-pack", "--stdin"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("git index-pack to run");
+    index_pack
+        .stdin
+        .take()
+        .expect("stdin to be piped")
+        .write_all(&pack.stdout)
+        .expect("pack to be written");
+    assert!(
+        index_pack
+            .wait()
+            .expect("git index-pack to finish")
+            .success(),
+        "git index-pack"
+    );
+}
+
+#[test]
+fn test_check_connectivity() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let local = local(dir.path());
+    let repo = git::open(&local).expect("repository to open");
+
+    let batch = Batch::from([(
+        id(&git(&local, ["rev-parse", "HEAD"])),
+        "refs/heads/main".to_string(),
+    )]);
+
+    assert!(check_connectivity(&repo, &batch, &[]).is_ok(), "connected");
+}
+
+#[test]
+fn test_check_connectivity_with_missing_parent() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    git(dir.path(), ["init", "source"]);
+    let source = dir.path().join("source");
+
+    std::fs::write(source.join("first.txt"), "first").expect("file to be written");
+    git(&source, ["add", "first.txt"]);
+    git(&source, ["commit", "-m", "First commit"]);
+    let parent = git(&source, ["rev-parse", "HEAD"]);
+
+    git(&source, ["rm", "-q", "first.txt"]);
+    std::fs::write(source.join("second.txt"), "second").expect("file to be written");
+    git(&source, ["add", "second.txt"]);
+    git(&source, ["commit", "-m", "Second commit"]);
+    let tip = git(&source, ["rev-parse", "HEAD"]);
+
+    // Everything the tip has that its parent doesn't, which leaves out the
+    // parent itself.
+    let objects = git(
+        &source,
+        [
+            "rev-list",
+            "--objects",
+            tip.as_str(),
+            "--not",
+            parent.as_str(),
+        ],
+    );
+
+    git(dir.path(), ["init", "local"]);
+    let local = dir.path().join("local");
+    copy_objects(&source, &local, &objects);
+
+    let repo = git::open(&local).expect("repository to open");
+    let batch = Batch::from([(id(&tip), "refs/heads/main".to_string())]);
+
+    assert!(check_received(&repo, &batch).is_ok(), "the tip is received");
+    assert_eq!(
+        check_connectivity(&repo, &batch, &[])
+            .expect_err("missing parent")
+            .to_string(),
+        format!(
+            "the fetched objects are incomplete: {} is missing, which is reachable from refs/heads/main",
+            parent
+        ),
+        "error"
+    );
+    assert!(
+        verify(&repo, &batch, &Options::default(), &[]).is_ok(),
+        "only checked when Git asks"
+    );
+}
+
+/// A shallow remote leaves out the history past its boundary, which Git
+/// would otherwise find missing after the fetch.
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_fetch_with_missing_object_removes_pack() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let local = local(dir.path());
+    let (url, hashes) = remote(dir.path());
+
+    git(
+        dir.path(),
+        ["clone", "--bare", "--depth", "1", &url, "shallow.git"],
+    );
+    git(
+        &dir.path().join("shallow.git"),
+        ["config", "uploadpack.allowReachableSHA1InWant", "true"],
+    );
+    let url = format!("file://{}", dir.path().join("shallow.git").display());
+
+    let repo = git::open(&local).expect("repository to open");
+    let options = Options {
+        check_connectivity: true,
+        ..Default::default()
+    };
+
+    let refs_before = snapshot_refs(&repo).expect("refs to be read");
+    let packs_before = packs(&repo);
+
+    let mut batch: Batch = BTreeSet::from([(id(&hashes[1]), "refs/heads/main".to_string())]);
+
+    let err = process(
+        connect(&url),
+        &repo,
+        &url,
+        |_| Ok(None),
+        &options,
+        &[],
+        &AtomicBool::new(false),
+        None,
+        &mut batch,
+        &mut std::io::sink(),
+    )
+    .expect_err("fetch to fail");
+
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "the fetched objects are incomplete: {} is missing, which is reachable from refs/heads/main",
+            hashes[0]
+        ),
+        "error"
+    );
+    assert_eq!(
+        snapshot_refs(&repo).expect("refs to be read"),
+        refs_before,
+        "refs"
+    );
+    assert_eq!(packs(&repo), packs_before, "packs");
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_interrupted_fetch_leaves_refs_unchanged() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let local = local(dir.path());
+    let (url, hashes) = remote(dir.path());
+    let hash = hashes[1].clone();
+
+    let repo = git::open(&local).expect("repository to open");
+
+    let refs_before = snapshot_refs(&repo).expect("refs to be read");
+    let packs_before = packs(&repo);
+
+    let mut batch: Batch = BTreeSet::from([(id(&hash), "refs/heads/main".to_string())]);
+    let should_interrupt = AtomicBool::new(true);
+
+    let result = process(
+        connect(&url),
+        &repo,
+        &url,
+        |_| Ok(None),
+        &Options::default(),
+        &[],
+        &should_interrupt,
+        None,
+        &mut batch,
+        &mut std::io::sink(),
+    );
+
+    assert!(result.is_err(), "fetch should fail when interrupted");
+    assert_eq!(
+        snapshot_refs(&repo).expect("refs to be read"),
+        refs_before,
+        "refs"
+    );
+    assert_eq!(packs(&repo), packs_before, "packs");
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_fetch_writes_debug_dir() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let local = local(dir.path());
+    let (url, hashes) = remote(dir.path());
+    let debug_dir = dir.path().join("debug");
+
+    let repo = git::open(&local).expect("repository to open");
+    let mut batch: Batch = BTreeSet::from([(id(&hashes[1]), "refs/heads/main".to_string())]);
+
+    debug::with_dir(&debug_dir, || {
+        process(
+            connect(&url),
+            &repo,
+            &url,
+            |_| Ok(None),
+            &Options::default(),
+            &[],
+            &AtomicBool::new(false),
+            None,
+            &mut batch,
+            &mut std::io::sink(),
+        )
+    })
+    .expect("fetch to succeed");
+
+    let file_names = std::fs::read_dir(&debug_dir)
+        .expect("debug dir to exist")
+        .map(|entry| {
+            entry
+                .expect("dir entry")
+                .file_name()
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect::<Vec<_>>();
+
+    assert!(
+        file_names
+            .iter()
+            .any(|name| name.ends_with("-fetch-outcome.txt")),
+        "expected fetch outcome in {:?}",
+        file_names
+    );
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_fetch_into_sink() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let local = local(dir.path());
+    let (url, hashes) = remote(dir.path());
+
+    let repo = git::open(&local).expect("repository to open");
+    let packs_before = packs(&repo);
+
+    let mut batch: Batch = BTreeSet::from([(id(&hashes[1]), "refs/heads/main".to_string())]);
+    let mut sink = Vec::new();
+
+    let fetch_stats = process(
+        connect(&url),
+        &repo,
+        &url,
+        |_| Ok(None),
+        &Options::default(),
+        &[],
+        &AtomicBool::new(false),
+        Some(&mut sink),
+        &mut batch,
+        &mut std::io::sink(),
+    )
+    .expect("fetch to succeed")
+    .expect("fetch stats");
+
+    assert!(sink.starts_with(b"PACK"), "pack signature");
+    assert_eq!(fetch_stats.bytes, sink.len() as u64, "bytes");
+    // Two commits and the empty tree
+    assert_eq!(fetch_stats.objects, 3, "objects");
+    assert_eq!(packs(&repo), packs_before, "packs");
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_interrupted_fetch_into_sink() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let local = local(dir.path());
+    let (url, hashes) = remote(dir.path());
+
+    let repo = git::open(&local).expect("repository to open");
+    let mut batch: Batch = BTreeSet::from([(id(&hashes[1]), "refs/heads/main".to_string())]);
+    let mut sink = Vec::new();
+    let mut out = Vec::new();
+
+    let result = process(
+        connect(&url),
+        &repo,
+        &url,
+        |_| Ok(None),
+        &Options::default(),
+        &[],
+        &AtomicBool::new(true),
+        Some(&mut sink),
+        &mut batch,
+        &mut out,
+    );
+
+    assert_eq!(
+        result.expect_err("fetch to be interrupted").to_string(),
+        "fetch was interrupted",
+        "error"
+    );
+    assert!(out.is_empty(), "nothing reported to Git");
+    assert!(!batch.is_empty(), "batch kept");
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_fetch_into_sink_reuses_handshake() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let local = local(dir.path());
+    let (url, hashes) = remote(dir.path());
+
+    let repo = git::open(&local).expect("repository to open");
+    let mut connection = connect(&url);
+
+    crate::commands::list::list_refs(&mut connection, |_| Ok(None), &[], &[])
+        .expect("refs to be listed");
+    assert!(connection.is_reusable(), "reusable");
+
+    let mut batch: Batch = BTreeSet::from([(id(&hashes[1]), "refs/heads/main".to_string())]);
+    let mut sink = Vec::new();
+
+    process(
+        connection,
+        &repo,
+        &url,
+        |_| Ok(None),
+        &Options::default(),
+        &[],
+        &AtomicBool::new(false),
+        Some(&mut sink),
+        &mut batch,
+        &mut std::io::sink(),
+    )
+    .expect("fetch to succeed");
+
+    assert!(sink.starts_with(b"PACK"), "pack signature");
+}
+
+/// A connection that listed refs can still be fetched over by gitoxide.
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_fetch_after_listing_refs() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let local = local(dir.path());
+    let (url, hashes) = remote(dir.path());
+
+    let repo = git::open(&local).expect("repository to open");
+    let mut connection = connect(&url);
+
+    crate::commands::list::list_refs(&mut connection, |_| Ok(None), &[], &[])
+        .expect("refs to be listed");
+
+    let mut batch: Batch = BTreeSet::from([(id(&hashes[1]), "refs/heads/main".to_string())]);
+
+    process(
+        connection,
+        &repo,
+        &url,
+        |_| Ok(None),
+        &Options::default(),
+        &[],
+        &AtomicBool::new(false),
+        None,
+        &mut batch,
+        &mut std::io::sink(),
+    )
+    .expect("fetch to succeed");
+
+    assert!(
+        repo.find_object(id(&hashes[1])).is_ok(),
+        "fetched commit should be in the object store"
+    );
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_fetch_into_sink_follows_tags() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let local = local(dir.path());
+    let (url, hashes) = remote(dir.path());
+    let remote = dir.path().join("remote");
+    git(&remote, ["tag", "-a", "v1.0", "-m", "Version 1.0"]);
+    git(&remote, ["push", "origin", "v1.0"]);
+
+    let repo = git::open(&local).expect("repository to open");
+    let options = Options {
+        follow_tags: true,
+        ..Default::default()
+    };
+
+    let mut batch: Batch = BTreeSet::from([(id(&hashes[1]), "refs/heads/main".to_string())]);
+    let mut sink = Vec::new();
+
+    let fetch_stats = process(
+        connect(&url),
+        &repo,
+        &url,
+        |_| Ok(None),
+        &options,
+        &[],
+        &AtomicBool::new(false),
+        Some(&mut sink),
+        &mut batch,
+        &mut std::io::sink(),
+    )
+    .expect("fetch to succeed")
+    .expect("fetch stats");
+
+    // Two commits, the empty tree, and the tag
+    assert_eq!(fetch_stats.objects, 4, "objects");
+}
+
+fn filter(filter: &str) -> Options {
+    Options {
+        filter: Some(filter.to_string()),
+        ..Default::default()
+    }
+}
+
+/// Creates `filtered.git` with a commit of one file, returning its URL and
+/// the id of the commit. Filters are only allowed if `allow_filter` is set.
+fn remote_with_blob(dir: &Path, allow_filter: bool) -> (String, String) {
+    git(dir, ["init", "--bare", "filtered.git"]);
+    git(
+        &dir.join("filtered.git"),
+        [
+            "config",
+            "uploadpack.allowFilter",
+            if allow_filter { "true" } else { "false" },
+        ],
+    );
+    git(dir, ["clone", "filtered.git", "filtered"]);
+    let remote = dir.join("filtered");
+    std::fs::write(remote.join("README.md"), "# Filtered\n").expect("file to be written");
+    git(&remote, ["add", "README.md"]);
+    git(&remote, ["commit", "-m", "Add README"]);
+    let hash = git(&remote, ["rev-parse", "HEAD"]);
+    git(&remote, ["push", "origin", "main"]);
+
+    let url = format!("file://{}", dir.join("filtered.git").display());
+    (url, hash)
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_fetch_into_sink_with_filter() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let local = local(dir.path());
+    let (url, hash) = remote_with_blob(dir.path(), true);
+
+    let repo = git::open(&local).expect("repository to open");
+    let mut batch: Batch = BTreeSet::from([(id(&hash), "refs/heads/main".to_string())]);
+    let mut sink = Vec::new();
+
+    let fetch_stats = process(
+        connect(&url),
+        &repo,
+        &url,
+        |_| Ok(None),
+        &filter("blob:none"),
+        &[],
+        &AtomicBool::new(false),
+        Some(&mut sink),
+        &mut batch,
+        &mut std::io::sink(),
+    )
+    .expect("fetch to succeed")
+    .expect("fetch stats");
+
+    // The commit and its tree, without the blob
+    assert_eq!(fetch_stats.objects, 2, "objects");
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_fetch_with_unsupported_filter() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let local = local(dir.path());
+    let (url, hash) = remote_with_blob(dir.path(), false);
+
+    let repo = git::open(&local).expect("repository to open");
+    let mut batch: Batch = BTreeSet::from([(id(&hash), "refs/heads/main".to_string())]);
+
+    let result = process(
+        connect(&url),
+        &repo,
+        &url,
+        |_| Ok(None),
+        &filter("blob:none"),
+        &[],
+        &AtomicBool::new(false),
+        Some(&mut std::io::sink()),
+        &mut batch,
+        &mut std::io::sink(),
+    );
+
+    assert_eq!(
+        result.expect_err("filter to be refused").to_string(),
+        "the remote doesn't support partial clone filters, but `blob:none` was requested",
+        "error"
+    );
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_fetch_promisor_pack() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let local = local(dir.path());
+    let (url, hash) = remote_with_blob(dir.path(), true);
+    let blob = git(
+        &dir.path().join("filtered"),
+        ["rev-parse", "HEAD:README.md"],
+    );
+
+    let repo = git::open(&local).expect("repository to open");
+    let mut batch: Batch = BTreeSet::from([(id(&hash), "refs/heads/main".to_string())]);
+
+    process(
+        connect(&url),
+        &repo,
+        &url,
+        |_| Ok(None),
+        &filter("blob:none"),
+        &[],
+        &AtomicBool::new(false),
+        None,
+        &mut batch,
+        &mut std::io::sink(),
+    )
+    .expect("fetch to succeed");
+
+    assert!(repo.find_object(id(&hash)).is_ok(), "commit");
+    assert!(repo.find_object(id(&blob)).is_err(), "blob");
+    assert!(
+        packs(&repo)
+            .iter()
+            .any(|path| path.extension().map_or(false, |ext| ext == "promisor")),
+        "promisor pack"
+    );
+}
+
+/// Once a filtered fetch has updated a ref, the next one only receives
+/// what's new, rather than everything again.
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_fetch_with_filter_sends_haves() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let local = local(dir.path());
+    let (url, first) = remote_with_blob(dir.path(), true);
+    let repo = git::open(&local).expect("repository to open");
+
+    let mut batch: Batch = BTreeSet::from([(id(&first), "refs/heads/main".to_string())]);
+
+    process(
+        connect(&url),
+        &repo,
+        &url,
+        |_| Ok(None),
+        &filter("blob:none"),
+        &[],
+        &AtomicBool::new(false),
+        None,
+        &mut batch,
+        &mut std::io::sink(),
+    )
+    .expect("first fetch to succeed");
+
+    // As Git does with what was fetched
+    git(&local, ["update-ref", "refs/remotes/origin/main", &first]);
+
+    let remote = dir.path().join("filtered");
+    std::fs::write(remote.join("CHANGELOG.md"), "# Changelog\n").expect("file to be written");
+    git(&remote, ["add", "CHANGELOG.md"]);
+    git(&remote, ["commit", "-m", "Add CHANGELOG"]);
+    let second = git(&remote, ["rev-parse", "HEAD"]);
+    git(&remote, ["push", "origin", "main"]);
+
+    let (connection, requests) = connect_recording(&url);
+    let mut batch: Batch = BTreeSet::from([(id(&second), "refs/heads/main".to_string())]);
+
+    let fetch_stats = process(
+        connection,
+        &repo,
+        &url,
+        |_| Ok(None),
+        &filter("blob:none"),
+        &[],
+        &AtomicBool::new(false),
+        None,
+        &mut batch,
+        &mut std::io::sink(),
+    )
+    .expect("second fetch to succeed")
+    .expect("fetch stats");
+
+    assert!(
+        sent_line(&requests, &format!("have {}", first)),
+        "the first commit is a have"
+    );
+    assert!(repo.find_object(id(&second)).is_ok(), "second commit");
+    // The new commit and its tree, without the first commit or its tree
+    assert_eq!(fetch_stats.objects, 2, "objects");
+}
+
+/// Whether the recorded `requests` have any `have` lines.
+#[cfg(feature = "blocking-network-client")]
+fn sent_haves(requests: &std::sync::Mutex<Vec<u8>>) -> bool {
+    use git::bstr::ByteSlice as _;
+
+    requests.lock().expect("lock").find(b"have ").is_some()
+}
+
+/// How many requests were recorded, each of which names its command.
+#[cfg(feature = "blocking-network-client")]
+fn request_count(requests: &std::sync::Mutex<Vec<u8>>) -> usize {
+    use git::bstr::ByteSlice as _;
+
+    requests
+        .lock()
+        .expect("lock")
+        .find_iter(b"command=")
+        .count()
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_fetch_when_cloning_sends_no_haves() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let (url, hashes) = remote(dir.path());
+
+    // Already has the first commit, which the remote would leave out of the
+    // pack if it were sent as a have.
+    git(dir.path(), ["init", "cloned"]);
+    let cloned = dir.path().join("cloned");
+    git(&cloned, ["fetch", url.as_str(), hashes[0].as_str()]);
+    git(&cloned, ["update-ref", "HEAD", "FETCH_HEAD"]);
+
+    let repo = git::open(&cloned).expect("repository to open");
+    let mut batch: Batch = BTreeSet::from([(id(&hashes[1]), "refs/heads/main".to_string())]);
+
+    let (mut connection, requests) = connect_recording(&url);
+    crate::commands::list::list_refs(&mut connection, |_| Ok(None), &[], &[])
+        .expect("refs to be listed");
+
+    let fetch_stats = process(
+        connection,
+        &repo,
+        &url,
+        |_| Ok(None),
+        &Options {
+            cloning: true,
+            ..Default::default()
+        },
+        &[],
+        &AtomicBool::new(false),
+        None,
+        &mut batch,
+        &mut std::io::sink(),
+    )
+    .expect("fetch to succeed")
+    .expect("fetch stats");
+
+    assert!(!sent_haves(&requests), "haves");
+    // Listing the refs and the fetch, without listing them again
+    assert_eq!(request_count(&requests), 2, "requests");
+    assert!(repo.find_object(id(&hashes[1])).is_ok(), "second commit");
+    // Both commits and their empty tree
+    assert_eq!(fetch_stats.objects, 3, "objects");
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_interrupted_clone_leaves_no_pack() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let (url, hashes) = remote(dir.path());
+    git(dir.path(), ["init", "cloned"]);
+    let repo = git::open(dir.path().join("cloned")).expect("repository to open");
+
+    let packs_before = packs(&repo);
+    let mut batch: Batch = BTreeSet::from([(id(&hashes[1]), "refs/heads/main".to_string())]);
+
+    let result = process(
+        connect(&url),
+        &repo,
+        &url,
+        |_| Ok(None),
+        &Options {
+            cloning: true,
+            ..Default::default()
+        },
+        &[],
+        &AtomicBool::new(true),
+        None,
+        &mut batch,
+        &mut std::io::sink(),
+    );
+
+    assert_eq!(
+        result.expect_err("fetch to be interrupted").to_string(),
+        "fetch was interrupted",
+        "error"
+    );
+    assert_eq!(packs(&repo), packs_before, "packs");
+}
+
+#[test]
+fn test_check_object_format() {
+    let sha1 = Capabilities::from_bytes(b"\0fetch object-format=sha1").expect("valid capabilities");
+    assert!(
+        check_object_format(git::hash::Kind::Sha1, &sha1).is_ok(),
+        "matching formats"
+    );
+
+    let unadvertised = Capabilities::from_bytes(b"\0fetch").expect("valid capabilities");
+    assert!(
+        check_object_format(git::hash::Kind::Sha1, &unadvertised).is_ok(),
+        "sha1 is the default"
+    );
+
+    let sha256 =
+        Capabilities::from_bytes(b"\0fetch object-format=sha256").expect("valid capabilities");
+    assert_eq!(
+        check_object_format(git::hash::Kind::Sha1, &sha256)
+            .expect_err("mismatched formats")
+            .to_string(),
+        "object-format mismatch: local sha1, remote sha256",
+        "error"
+    );
+}
+
+const SHALLOW: &str = "1111111111111111111111111111111111111111";
+const UNSHALLOW: &str = "2222222222222222222222222222222222222222";
+
+#[test]
+fn test_shallow_lines() {
+    assert_eq!(
+        shallow_lines(&[
+            ShallowUpdate::Shallow(id(SHALLOW)),
+            ShallowUpdate::Unshallow(id(UNSHALLOW)),
+        ]),
+        vec![
+            format!("shallow {}", SHALLOW),
+            format!("unshallow {}", UNSHALLOW)
+        ],
+        "lines"
+    )
+}
+
+/// Creates `shallow`, a clone of `remote.git` cut off at its second commit,
+/// then pushes a third commit to `remote.git` and clones that into
+/// `shallow.git`, which is cut off at the third commit. Returns the URLs of
+/// `remote.git` and `shallow.git` along with the ids of the commits, oldest
+/// first.
+#[cfg(feature = "blocking-network-client")]
+fn shallow_clone(dir: &Path) -> (String, String, Vec<String>) {
+    let (url, mut hashes) = remote(dir);
+    git(dir, ["clone", "--depth", "1", url.as_str(), "shallow"]);
+
+    let remote = dir.join("remote");
+    git(
+        &remote,
+        ["commit", "--allow-empty", "-m", "Third remote commit"],
+    );
+    hashes.push(git(&remote, ["rev-parse", "HEAD"]));
+    git(&remote, ["push", "origin", "main"]);
+
+    git(
+        dir,
+        [
+            "clone",
+            "--bare",
+            "--depth",
+            "1",
+            url.as_str(),
+            "shallow.git",
+        ],
+    );
+    let shallow_url = format!("file://{}", dir.join("shallow.git").display());
+
+    (url, shallow_url, hashes)
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_fetch_into_shallow_clone() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let (url, _, hashes) = shallow_clone(dir.path());
+    let repo = git::open(dir.path().join("shallow")).expect("repository to open");
+
+    let (connection, requests) = connect_recording(&url);
+    let mut batch: Batch = BTreeSet::from([(id(&hashes[2]), "refs/heads/main".to_string())]);
+
+    let fetch_stats = process(
+        connection,
+        &repo,
+        &url,
+        |_| Ok(None),
+        &Options {
+            check_connectivity: true,
+            ..Default::default()
+        },
+        &[],
+        &AtomicBool::new(false),
+        None,
+        &mut batch,
+        &mut std::io::sink(),
+    )
+    .expect("fetch to succeed")
+    .expect("fetch stats");
+
+    assert!(
+        sent_line(&requests, &format!("shallow {}", hashes[1])),
+        "the second commit is shallow"
+    );
+    assert!(
+        sent_line(&requests, &format!("have {}", hashes[1])),
+        "the second commit is a have"
+    );
+    assert!(repo.find_object(id(&hashes[2])).is_ok(), "third commit");
+    // The new commit, without the empty tree the second commit has
+    assert_eq!(fetch_stats.objects, 1, "objects");
+    assert_eq!(
+        std::fs::read_to_string(repo.git_dir().join("shallow")).expect("shallow file to exist"),
+        format!("{}\n", hashes[1]),
+        "shallow file"
+    );
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_fetch_from_shallow_remote_without_update_shallow() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let (_, shallow_url, hashes) = shallow_clone(dir.path());
+    let repo = git::open(dir.path().join("shallow")).expect("repository to open");
+
+    let packs_before = packs(&repo);
+    let mut batch: Batch = BTreeSet::from([(id(&hashes[2]), "refs/heads/main".to_string())]);
+
+    let err = process(
+        connect(&shallow_url),
+        &repo,
+        &shallow_url,
+        |_| Ok(None),
+        &Options::default(),
+        &[],
+        &AtomicBool::new(false),
+        None,
+        &mut batch,
+        &mut std::io::sink(),
+    )
+    .expect_err("fetch to be refused");
+
+    assert!(
+        err.to_string().contains("update-shallow"),
+        "message: {}",
+        err
+    );
+    assert_eq!(packs(&repo), packs_before, "packs");
+    assert_eq!(
+        std::fs::read_to_string(repo.git_dir().join("shallow")).expect("shallow file to exist"),
+        format!("{}\n", hashes[1]),
+        "shallow file"
+    );
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_fetch_from_shallow_remote_with_update_shallow() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let (_, shallow_url, hashes) = shallow_clone(dir.path());
+    let repo = git::open(dir.path().join("shallow")).expect("repository to open");
+
+    let mut batch: Batch = BTreeSet::from([(id(&hashes[2]), "refs/heads/main".to_string())]);
+
+    process(
+        connect(&shallow_url),
+        &repo,
+        &shallow_url,
+        |_| Ok(None),
+        &Options {
+            update_shallow: true,
+            check_connectivity: true,
+            ..Default::default()
+        },
+        &[],
+        &AtomicBool::new(false),
+        None,
+        &mut batch,
+        &mut std::io::sink(),
+    )
+    .expect("fetch to succeed");
+
+    assert!(repo.find_object(id(&hashes[2])).is_ok(), "third commit");
+
+    let mut shallow = vec![hashes[1].clone(), hashes[2].clone()];
+    shallow.sort();
+    assert_eq!(
+        std::fs::read_to_string(repo.git_dir().join("shallow")).expect("shallow file to exist"),
+        shallow
+            .iter()
+            .map(|id| format!("{}\n", id))
+            .collect::<String>(),
+        "shallow file"
+    );
+}
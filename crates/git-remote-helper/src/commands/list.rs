@@ -1,7 +1,17 @@
+use crate::debug;
+use crate::event;
+use crate::interrupt;
 use clap::ValueEnum;
+use git::bstr::BString;
+use git::protocol::transport::client::Capabilities;
 use git_repository as git;
 use log::trace;
 use maybe_async::maybe_async;
+use std::collections::BTreeSet;
+use std::io::{BufWriter, Write};
+
+#[cfg(test)]
+mod tests;
 
 #[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd, ValueEnum)]
 pub enum ListVariant {
@@ -10,9 +20,13 @@ pub enum ListVariant {
 
 #[maybe_async]
 pub async fn execute<AuthFn, T>(
-    mut transport: T,
+    connection: &mut crate::git::protocol::Connection<T>,
     authenticate: AuthFn,
     variant: &Option<ListVariant>,
+    extra_parameters: &[(String, Option<String>)],
+    ref_prefixes: &[BString],
+    object_format: bool,
+    out: &mut impl Write,
 ) -> anyhow::Result<()>
 where
     AuthFn: FnMut(git::credentials::helper::Action) -> git::credentials::protocol::Result,
@@ -27,48 +41,270 @@ where
         }
     }
 
-    // Implement once option capability is supported
+    let (refs, capabilities) =
+        list_refs(connection, authenticate, extra_parameters, ref_prefixes).await?;
+
+    let mut out = BufWriter::new(out);
+
+    // Git otherwise assumes SHA-1 once it has asked for the object format.
+    if object_format {
+        writeln!(
+            out,
+            ":object-format {}",
+            crate::git::protocol::object_format(&capabilities)
+        )?;
+    }
+
+    write_refs(&mut out, &refs)?;
+    out.flush()?;
+
+    Ok(())
+}
+
+/// Prints each ref as a JSON object on its own line, for tooling that would
+/// otherwise need to parse the output of `list`.
+#[maybe_async]
+pub async fn execute_json<AuthFn, T>(
+    transport: T,
+    authenticate: AuthFn,
+    extra_parameters: &[(String, Option<String>)],
+) -> anyhow::Result<()>
+where
+    AuthFn: FnMut(git::credentials::helper::Action) -> git::credentials::protocol::Result,
+    T: git::protocol::transport::client::Transport,
+{
+    trace!("list json");
+
+    let mut connection = crate::git::protocol::Connection::new(transport);
+    let (refs, _capabilities) =
+        list_refs(&mut connection, authenticate, extra_parameters, &[]).await?;
+
+    let mut stdout = BufWriter::new(std::io::stdout().lock());
+
+    for r in refs.iter() {
+        writeln!(stdout, "{}", ref_to_json(r)?)?;
+    }
+
+    stdout.flush()?;
+
+    Ok(())
+}
+
+/// Lists refs with `ls-refs`, limited to those that start with any of
+/// `ref_prefixes` unless it's empty, along with the capabilities the remote
+/// advertised. The handshake is skipped if `connection` already did one.
+#[maybe_async]
+pub async fn list_refs<AuthFn, T>(
+    connection: &mut crate::git::protocol::Connection<T>,
+    authenticate: AuthFn,
+    extra_parameters: &[(String, Option<String>)],
+    ref_prefixes: &[BString],
+) -> anyhow::Result<(Vec<git::protocol::handshake::Ref>, Capabilities)>
+where
+    AuthFn: FnMut(git::credentials::helper::Action) -> git::credentials::protocol::Result,
+    T: git::protocol::transport::client::Transport,
+{
     let mut progress = git::progress::Discard;
-    let extra_parameters = vec![];
 
-    let outcome = git::protocol::fetch::handshake(
-        &mut transport,
-        authenticate,
-        extra_parameters,
-        &mut progress,
-    )
-    .await?;
+    let (version, capabilities, advertised_refs) =
+        crate::git::protocol::handshake(connection, authenticate, extra_parameters).await?;
+
+    interrupt::check(&git::interrupt::IS_INTERRUPTED)?;
+
+    // Protocol v1 has no `ls-refs`, and advertises every ref instead.
+    if let (git::protocol::transport::Protocol::V1, Some(refs)) = (version, advertised_refs) {
+        trace!("refs: {:#?}", refs);
+        debug::write("refs.txt", format!("{:#?}", refs));
+        event::log("list", &[("refs", &refs.len())]);
+
+        return Ok((refs, capabilities));
+    }
+
+    let ref_prefix_arguments = ref_prefix_arguments(ref_prefixes);
+
+    trace!("ref-prefix arguments: {:#?}", ref_prefix_arguments);
 
     let refs = git::protocol::ls_refs(
-        &mut transport,
-        &outcome.capabilities,
+        &mut connection.transport,
+        &capabilities,
         // TODO: gain a better understanding of
         // https://github.com/Byron/gitoxide/blob/da5f63cbc7506990f46d310f8064678decb86928/git-repository/src/remote/connection/ref_map.rs#L153-L168
-        |_capabilities, _arguments, _features| Ok(git::protocol::ls_refs::Action::Continue),
+        |capabilities, arguments, _features| {
+            request_symrefs_and_peel(capabilities, arguments);
+            arguments.extend(ref_prefix_arguments);
+            Ok(git::protocol::ls_refs::Action::Continue)
+        },
         &mut progress,
     )
     .await?;
 
     trace!("refs: {:#?}", refs);
+    debug::write("refs.txt", format!("{:#?}", refs));
+    event::log("list", &[("refs", &refs.len())]);
 
-    // TODO: buffer and flush
-    refs.iter().for_each(|r| println!("{}", ref_to_string(r)));
-    println!();
+    Ok((refs, capabilities))
+}
 
-    Ok(())
+/// The prefixes of the remote refs matched by the fetch refspecs configured
+/// for `remote`.
+///
+/// This is empty when listing for a push, if `remote` is a URL rather than
+/// the name of a configured remote, or if any refspec could match every ref.
+pub fn ref_prefixes(
+    repo: &git::Repository,
+    remote: &str,
+    variant: &Option<ListVariant>,
+) -> Vec<BString> {
+    if variant.is_some() {
+        return Vec::new();
+    }
+
+    match repo.find_remote(remote) {
+        Ok(remote) => prefixes_from_refspecs(remote.refspecs(git::remote::Direction::Fetch)),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn prefixes_from_refspecs(refspecs: &[git::refspec::RefSpec]) -> Vec<BString> {
+    let prefixes = refspecs
+        .iter()
+        .map(|refspec| refspec.to_ref().prefix().map(ToOwned::to_owned))
+        .collect::<Option<BTreeSet<_>>>();
+
+    prefixes.map_or_else(Vec::new, |prefixes| prefixes.into_iter().collect())
+}
+
+/// Asks for the targets of symbolic refs and what annotated tags peel to,
+/// without which `HEAD` and tags can't be listed correctly.
+fn request_symrefs_and_peel(capabilities: &Capabilities, arguments: &mut Vec<BString>) {
+    if !capabilities.contains("ls-refs") {
+        return;
+    }
+
+    for argument in ["symrefs", "peel"] {
+        if !arguments.iter().any(|existing| existing == argument) {
+            arguments.push(argument.into());
+        }
+    }
+}
+
+fn ref_prefix_arguments(ref_prefixes: &[BString]) -> Vec<BString> {
+    if ref_prefixes.is_empty() {
+        return Vec::new();
+    }
+
+    // Git relies on `HEAD` to determine the default branch, which none of
+    // the prefixes would match.
+    std::iter::once(BString::from("HEAD"))
+        .chain(ref_prefixes.iter().cloned())
+        .map(|prefix| {
+            let mut argument = BString::from("ref-prefix ");
+            argument.extend_from_slice(&prefix);
+            argument
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct JsonRef {
+    pub name: String,
+    /// The object the ref points to, which is the tag object for peeled refs
+    /// and absent for unborn refs.
+    pub target: Option<String>,
+    /// The object an annotated tag points to.
+    pub peeled: Option<String>,
+    /// The ref a symbolic ref points to.
+    pub symref_target: Option<String>,
+}
+
+impl From<&git::protocol::handshake::Ref> for JsonRef {
+    fn from(r: &git::protocol::handshake::Ref) -> Self {
+        use git::protocol::handshake::Ref;
+
+        match r {
+            Ref::Peeled {
+                full_ref_name,
+                tag,
+                object,
+            } => Self {
+                name: full_ref_name.to_string(),
+                target: Some(tag.to_string()),
+                peeled: Some(object.to_string()),
+                symref_target: None,
+            },
+            Ref::Direct {
+                full_ref_name,
+                object,
+            } => Self {
+                name: full_ref_name.to_string(),
+                target: Some(object.to_string()),
+                peeled: None,
+                symref_target: None,
+            },
+            Ref::Symbolic {
+                full_ref_name,
+                target,
+                object,
+            } => Self {
+                name: full_ref_name.to_string(),
+                target: Some(object.to_string()),
+                peeled: None,
+                symref_target: Some(target.to_string()),
+            },
+            Ref::Unborn {
+                full_ref_name,
+                target,
+            } => Self {
+                name: full_ref_name.to_string(),
+                target: None,
+                peeled: None,
+                symref_target: Some(target.to_string()),
+            },
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+fn ref_to_json(r: &git::protocol::handshake::Ref) -> anyhow::Result<String> {
+    Ok(serde_json::to_string(&JsonRef::from(r))?)
+}
+
+#[cfg(not(feature = "serde"))]
+fn ref_to_json(_r: &git::protocol::handshake::Ref) -> anyhow::Result<String> {
+    anyhow::bail!("--list-json requires git-remote-helper to be built with the `serde` feature")
+}
+
+/// Writes refs in the format Git expects from `list`, terminated by a blank
+/// line.
+fn write_refs(
+    mut writer: impl Write,
+    refs: &[git::protocol::handshake::Ref],
+) -> std::io::Result<()> {
+    for r in refs.iter() {
+        writeln!(writer, "{}", ref_to_string(r))?;
+    }
+
+    writeln!(writer)
 }
 
 fn ref_to_string(r: &git::protocol::handshake::Ref) -> String {
     use git::protocol::handshake::Ref;
 
     match r {
+        // Git follows tags by what they peel to, so annotated tags are
+        // listed with the object they point to as well.
         Ref::Peeled {
             full_ref_name,
-            tag: _,
-            object: _,
+            tag,
+            object,
         } => {
-            // FIXME: not sure how to handle peeled refs yet
-            format!("? {}", full_ref_name)
+            // 1111111111111111111111111111111111111111 refs/tags/v1.0
+            // 91536083cdb16ef3c29638054642b50a34ea8c25 refs/tags/v1.0^{}
+            format!(
+                "{} {}\n{} {}^{{}}",
+                tag, full_ref_name, object, full_ref_name
+            )
         }
         Ref::Direct {
             full_ref_name,
@@ -85,7 +321,8 @@ fn ref_to_string(r: &git::protocol::handshake::Ref) -> String {
             // @refs/heads/main HEAD
             format!("@{} {}", target, full_ref_name)
         }
-        // TODO: determine if this is the correct way to handle unborn symrefs
+        // An empty remote advertises `HEAD` as unborn. Git expects a symref
+        // without an object so that `git clone` creates the initial branch.
         Ref::Unborn {
             full_ref_name,
             target,
@@ -0,0 +1,308 @@
+use super::*;
+use git::protocol::handshake::Ref;
+
+const TAG: &str = "1111111111111111111111111111111111111111";
+const OBJECT: &str = "91536083cdb16ef3c29638054642b50a34ea8c25";
+
+fn oid(hex: &str) -> git::hash::ObjectId {
+    git::hash::ObjectId::from_hex(hex.as_bytes()).expect("valid hash")
+}
+
+fn peeled() -> Ref {
+    Ref::Peeled {
+        full_ref_name: "refs/tags/v1.0".into(),
+        tag: oid(TAG),
+        object: oid(OBJECT),
+    }
+}
+
+fn direct() -> Ref {
+    Ref::Direct {
+        full_ref_name: "refs/heads/main".into(),
+        object: oid(OBJECT),
+    }
+}
+
+fn symbolic() -> Ref {
+    Ref::Symbolic {
+        full_ref_name: "HEAD".into(),
+        target: "refs/heads/main".into(),
+        object: oid(OBJECT),
+    }
+}
+
+#[test]
+fn test_json_ref_peeled() {
+    assert_eq!(
+        JsonRef::from(&peeled()),
+        JsonRef {
+            name: "refs/tags/v1.0".to_string(),
+            target: Some(TAG.to_string()),
+            peeled: Some(OBJECT.to_string()),
+            symref_target: None,
+        },
+        "json ref"
+    )
+}
+
+#[test]
+fn test_json_ref_direct() {
+    assert_eq!(
+        JsonRef::from(&direct()),
+        JsonRef {
+            name: "refs/heads/main".to_string(),
+            target: Some(OBJECT.to_string()),
+            peeled: None,
+            symref_target: None,
+        },
+        "json ref"
+    )
+}
+
+#[test]
+fn test_json_ref_symbolic() {
+    assert_eq!(
+        JsonRef::from(&symbolic()),
+        JsonRef {
+            name: "HEAD".to_string(),
+            target: Some(OBJECT.to_string()),
+            peeled: None,
+            symref_target: Some("refs/heads/main".to_string()),
+        },
+        "json ref"
+    )
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_ref_to_json() {
+    let json = [peeled(), direct(), symbolic()]
+        .iter()
+        .map(|r| {
+            let json = ref_to_json(r).expect("valid json");
+            serde_json::from_str::<serde_json::Value>(&json).expect("valid json")
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        json,
+        vec![
+            serde_json::json!({
+                "name": "refs/tags/v1.0",
+                "target": TAG,
+                "peeled": OBJECT,
+                "symref_target": null,
+            }),
+            serde_json::json!({
+                "name": "refs/heads/main",
+                "target": OBJECT,
+                "peeled": null,
+                "symref_target": null,
+            }),
+            serde_json::json!({
+                "name": "HEAD",
+                "target": OBJECT,
+                "peeled": null,
+                "symref_target": "refs/heads/main",
+            }),
+        ],
+        "json"
+    )
+}
+
+#[test]
+fn test_write_refs() {
+    let mut output = Vec::new();
+    write_refs(&mut output, &[direct(), symbolic()]).expect("write to succeed");
+    assert_eq!(
+        String::from_utf8(output).expect("valid utf-8"),
+        format!("{} refs/heads/main\n@refs/heads/main HEAD\n\n", OBJECT),
+        "output"
+    )
+}
+
+#[test]
+fn test_write_refs_peeled() {
+    let mut output = Vec::new();
+    write_refs(&mut output, &[peeled()]).expect("write to succeed");
+    assert_eq!(
+        String::from_utf8(output).expect("valid utf-8"),
+        format!("{} refs/tags/v1.0\n{} refs/tags/v1.0^{{}}\n\n", TAG, OBJECT),
+        "output"
+    )
+}
+
+#[test]
+fn test_write_refs_empty() {
+    let mut output = Vec::new();
+    write_refs(&mut output, &[]).expect("write to succeed");
+    assert_eq!(output, b"\n", "output")
+}
+
+fn refspecs(refspecs: &[&str]) -> Vec<git::refspec::RefSpec> {
+    refspecs
+        .iter()
+        .map(|refspec| {
+            git::refspec::parse((*refspec).into(), git::refspec::parse::Operation::Fetch)
+                .expect("valid refspec")
+                .to_owned()
+        })
+        .collect()
+}
+
+#[test]
+fn test_prefixes_from_refspecs() {
+    assert_eq!(
+        prefixes_from_refspecs(&refspecs(&[
+            "+refs/heads/*:refs/remotes/origin/*",
+            "+refs/tags/*:refs/tags/*",
+            "+refs/heads/*:refs/remotes/mirror/*",
+        ])),
+        vec![BString::from("refs/heads/"), BString::from("refs/tags/")],
+        "prefixes"
+    )
+}
+
+#[test]
+fn test_prefixes_from_refspecs_unknown() {
+    assert_eq!(
+        prefixes_from_refspecs(&refspecs(&["+refs/heads/*:refs/remotes/origin/*", "main"])),
+        Vec::<BString>::new(),
+        "prefixes"
+    )
+}
+
+#[test]
+fn test_ref_prefix_arguments() {
+    assert_eq!(
+        ref_prefix_arguments(&[BString::from("refs/heads/")]),
+        vec![
+            BString::from("ref-prefix HEAD"),
+            BString::from("ref-prefix refs/heads/"),
+        ],
+        "arguments"
+    );
+    assert_eq!(
+        ref_prefix_arguments(&[]),
+        Vec::<BString>::new(),
+        "no arguments"
+    )
+}
+
+fn capabilities(input: &[u8]) -> Capabilities {
+    Capabilities::from_bytes(input)
+        .expect("valid capabilities")
+        .0
+}
+
+#[test]
+fn test_request_symrefs_and_peel_when_advertised() {
+    let mut arguments = Vec::new();
+    request_symrefs_and_peel(&capabilities(b"\0ls-refs=unborn fetch"), &mut arguments);
+    assert_eq!(
+        arguments,
+        vec![BString::from("symrefs"), BString::from("peel")],
+        "arguments"
+    )
+}
+
+#[test]
+fn test_request_symrefs_and_peel_once() {
+    let mut arguments = vec![BString::from("peel")];
+    request_symrefs_and_peel(&capabilities(b"\0ls-refs"), &mut arguments);
+    assert_eq!(
+        arguments,
+        vec![BString::from("peel"), BString::from("symrefs")],
+        "arguments"
+    )
+}
+
+#[test]
+fn test_request_symrefs_and_peel_when_not_advertised() {
+    let mut arguments = Vec::new();
+    request_symrefs_and_peel(&capabilities(b"\0fetch"), &mut arguments);
+    assert_eq!(arguments, Vec::<BString>::new(), "arguments")
+}
+
+#[test]
+fn test_write_refs_unborn_head() {
+    let unborn = Ref::Unborn {
+        full_ref_name: "HEAD".into(),
+        target: "refs/heads/main".into(),
+    };
+    let mut output = Vec::new();
+    write_refs(&mut output, &[unborn]).expect("write to succeed");
+    assert_eq!(output, b"@refs/heads/main HEAD\n\n", "output")
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_list_refs_empty_remote() {
+    use crate::test_support::git;
+    use git::protocol::transport;
+
+    let dir = tempfile::tempdir().expect("tempdir");
+    git(dir.path(), ["init", "--bare", "remote.git"]);
+    let url = format!("file://{}", dir.path().join("remote.git").display());
+
+    let mut transport = transport::connect::<_, std::convert::Infallible>(
+        url.as_str(),
+        transport::client::connect::Options {
+            version: transport::Protocol::V2,
+            ssh: Default::default(),
+        },
+    )
+    .expect("transport to connect");
+
+    let authenticate =
+        |action| panic!("unexpected call to authenticate with action: {:#?}", action);
+
+    let mut connection = crate::git::protocol::Connection::new(&mut transport);
+
+    let (refs, _capabilities) =
+        list_refs(&mut connection, authenticate, &[], &[]).expect("refs to be listed");
+
+    let mut output = Vec::new();
+    write_refs(&mut output, &refs).expect("write to succeed");
+    assert_eq!(
+        String::from_utf8(output).expect("valid utf-8"),
+        "@refs/heads/main HEAD\n\n",
+        "output"
+    )
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_list_refs_reuses_handshake() {
+    use crate::test_support::git;
+    use git::protocol::transport;
+
+    let dir = tempfile::tempdir().expect("tempdir");
+    let work = dir.path().join("work");
+    git(dir.path(), ["init", "--bare", "remote.git"]);
+    git(dir.path(), ["init", work.to_str().unwrap()]);
+    git(&work, ["commit", "--allow-empty", "-m", "initial"]);
+    git(&work, ["push", "../remote.git", "main"]);
+    let url = format!("file://{}", dir.path().join("remote.git").display());
+
+    let transport = transport::connect::<_, std::convert::Infallible>(
+        url.as_str(),
+        transport::client::connect::Options {
+            version: transport::Protocol::V2,
+            ssh: Default::default(),
+        },
+    )
+    .expect("transport to connect");
+
+    let mut connection = crate::git::protocol::Connection::new(transport);
+
+    let (first, _capabilities) =
+        list_refs(&mut connection, |_| Ok(None), &[], &[]).expect("refs to be listed");
+
+    assert!(connection.is_reusable(), "reusable");
+
+    let (second, _capabilities) =
+        list_refs(&mut connection, |_| Ok(None), &[], &[]).expect("refs to be listed again");
+
+    assert_eq!(first, second, "refs");
+}
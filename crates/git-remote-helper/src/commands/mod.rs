@@ -1,25 +1,128 @@
-use clap::Parser;
-use strum::EnumVariantNames;
+use clap::{Command, FromArgMatches as _, Parser, Subcommand as _};
+use git_repository as git;
+use strum::{EnumDiscriminants, EnumIter, EnumVariantNames, IntoEnumIterator as _};
 
+pub mod diagnose;
 pub mod fetch;
 pub mod list;
+pub mod option;
 pub mod push;
+pub mod stateless_connect;
 
 use list::ListVariant;
+use stateless_connect::Service;
 
-#[derive(Debug, EnumVariantNames, Eq, Ord, PartialEq, PartialOrd, Parser)]
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, EnumDiscriminants, EnumVariantNames, Eq, Ord, PartialEq, PartialOrd, Parser)]
 #[strum(serialize_all = "kebab_case")]
+#[strum_discriminants(name(CommandKind), derive(EnumIter))]
 pub enum Commands {
     Capabilities,
     Fetch {
-        hash: String, // TODO: gitoxide::hash::ObjectId?
+        #[arg(value_parser = parse_object_id)]
+        hash: git::hash::ObjectId,
 
         name: String,
     },
     List {
         variant: Option<ListVariant>,
     },
+    Option {
+        name: String,
+
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        value: Vec<String>,
+    },
     Push {
         src_dst: String,
     },
+    StatelessConnect {
+        service: Service,
+    },
+}
+
+/// Whether Git can be told it may send a command, which is what
+/// `capabilities` advertises.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Readiness {
+    /// Git sends the command without it being advertised.
+    Implicit,
+    /// The command is implemented and advertised as the capability.
+    Advertised(&'static str),
+    /// The command is implemented and advertised as the capability, but only
+    /// works when the remote speaks protocol v2, since Git would otherwise
+    /// try it and fail.
+    AdvertisedWithV2(&'static str),
+}
+
+impl CommandKind {
+    /// How ready the command is. Every command has to declare this, so that
+    /// one isn't advertised before it's implemented.
+    pub fn readiness(self) -> Readiness {
+        match self {
+            CommandKind::Capabilities | CommandKind::List => Readiness::Implicit,
+            CommandKind::Fetch => Readiness::Advertised("fetch"),
+            CommandKind::Option => Readiness::Advertised("option"),
+            CommandKind::Push => Readiness::Advertised("push"),
+            // `connect` isn't implemented, since it needs a bidirectional
+            // stream rather than the request/response transports we're
+            // given, and Git never tries `stateless-connect` once `connect`
+            // is advertised.
+            CommandKind::StatelessConnect => Readiness::AdvertisedWithV2("stateless-connect"),
+        }
+    }
+}
+
+/// Capabilities that aren't commands and don't depend on the remote.
+///
+/// `refspec` isn't advertised, since Git only applies it to refs from
+/// `import` and `export`. The refs from `fetch` are mapped into
+/// `refs/remotes/<name>/*` by the fetch refspec that `git clone` configures.
+///
+/// `check-connectivity` lets Git ask for what's fetched to be checked, and
+/// skip its own check once a fetch says `connectivity-ok`.
+const ATTRIBUTES: &[&str] = &["object-format", "check-connectivity"];
+
+/// The lines to write in response to `capabilities`, before the blank line
+/// that ends them. `push` is left out when `readonly` is set, so that Git
+/// doesn't try it.
+pub fn capabilities(remote_supports_v2: bool, readonly: bool) -> Vec<&'static str> {
+    let commands = CommandKind::iter()
+        .filter(|kind| !(readonly && *kind == CommandKind::Push))
+        .map(CommandKind::readiness);
+
+    let advertised = commands.clone().filter_map(|readiness| match readiness {
+        Readiness::Advertised(capability) => Some(capability),
+        _ => None,
+    });
+
+    let advertised_with_v2 = commands.filter_map(|readiness| match readiness {
+        Readiness::AdvertisedWithV2(capability) if remote_supports_v2 => Some(capability),
+        _ => None,
+    });
+
+    advertised
+        .chain(ATTRIBUTES.iter().copied())
+        .chain(advertised_with_v2)
+        .collect()
+}
+
+impl Commands {
+    /// Parses a line of input from Git.
+    pub fn from_line(line: &str) -> Result<Self, clap::Error> {
+        let command = Command::new("git-remote-icp")
+            .multicall(true)
+            .subcommand_required(true);
+
+        let command = Self::augment_subcommands(command);
+        let matches = command.try_get_matches_from(line.split(' '))?;
+        Self::from_arg_matches(&matches)
+    }
+}
+
+fn parse_object_id(hash: &str) -> Result<git::hash::ObjectId, String> {
+    git::hash::ObjectId::from_hex(hash.as_bytes())
+        .map_err(|err| format!("invalid object id {}: {}", hash, err))
 }
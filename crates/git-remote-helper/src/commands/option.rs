@@ -0,0 +1,271 @@
+use crate::verbosity;
+use git_repository as git;
+use log::trace;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+#[cfg(test)]
+mod tests;
+
+/// Options set by Git with `option <name> <value>`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Options {
+    pub progress: bool,
+    /// Set for `git push --dry-run`, where refs are checked but not updated.
+    pub dry_run: bool,
+    pub verbosity: usize,
+    /// Set for `git push --atomic`, where either every ref is updated or
+    /// none are.
+    pub atomic: bool,
+    /// Set once Git knows the helper will report the remote's hash
+    /// algorithm in `list`.
+    pub object_format: bool,
+    /// Strings given with `git push -o`, in order, for the server's hooks.
+    pub push_options: Vec<String>,
+    /// The filter for a partial clone, such as `blob:none` for
+    /// `git clone --filter=blob:none`.
+    pub filter: Option<String>,
+    /// Set when Git follows tags, so that annotated tags pointing into what's
+    /// fetched are fetched as well.
+    pub follow_tags: bool,
+    /// The ids refs are expected to have on the remote for
+    /// `git push --force-with-lease`, by ref name. A null id expects the ref
+    /// not to exist.
+    pub leases: BTreeMap<String, git::hash::ObjectId>,
+    /// Set for the fetch of `git clone`, where there's nothing local to
+    /// negotiate with.
+    pub cloning: bool,
+    /// Set when Git wants everything reachable from the fetched refs to be
+    /// checked for, rather than only the refs themselves.
+    pub check_connectivity: bool,
+    /// Set when Git allows a fetch to move the repository's shallow
+    /// boundary, as for `git fetch --update-shallow`.
+    pub update_shallow: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            progress: true,
+            dry_run: false,
+            verbosity: verbosity::DEFAULT,
+            atomic: false,
+            object_format: false,
+            push_options: Vec::new(),
+            filter: None,
+            follow_tags: false,
+            leases: BTreeMap::new(),
+            cloning: false,
+            check_connectivity: false,
+            update_shallow: false,
+        }
+    }
+}
+
+/// The response to an `option` command.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Response {
+    Ok,
+    Unsupported,
+    Error(String),
+}
+
+impl std::fmt::Display for Response {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ok => write!(f, "ok"),
+            Self::Unsupported => write!(f, "unsupported"),
+            Self::Error(msg) => write!(f, "error {}", msg),
+        }
+    }
+}
+
+impl Options {
+    pub fn set(&mut self, name: &str, value: &str) -> Response {
+        match name {
+            "progress" => match parse_bool(value) {
+                Ok(progress) => {
+                    self.progress = progress;
+                    Response::Ok
+                }
+                Err(response) => response,
+            },
+            "dry-run" => match parse_bool(value) {
+                Ok(dry_run) => {
+                    self.dry_run = dry_run;
+                    Response::Ok
+                }
+                Err(response) => response,
+            },
+            "verbosity" => match value.parse() {
+                Ok(verbosity) => {
+                    self.verbosity = verbosity;
+
+                    // Git sends `progress` separately, but a quiet helper
+                    // shouldn't show progress either way.
+                    if verbosity == 0 {
+                        self.progress = false;
+                    }
+
+                    Response::Ok
+                }
+                Err(_) => Response::Error(format!("expected a number, got {}", value)),
+            },
+            "atomic" => match parse_bool(value) {
+                Ok(atomic) => {
+                    self.atomic = atomic;
+                    Response::Ok
+                }
+                Err(response) => response,
+            },
+            "object-format" => match parse_bool(value) {
+                Ok(object_format) => {
+                    self.object_format = object_format;
+                    Response::Ok
+                }
+                Err(response) => response,
+            },
+            // Sent once per `-o`, so each one is kept rather than replacing
+            // the last.
+            "push-option" => match unquote_c_style(value) {
+                Ok(push_option) => {
+                    self.push_options.push(push_option);
+                    Response::Ok
+                }
+                Err(response) => response,
+            },
+            "followtags" => match parse_bool(value) {
+                Ok(follow_tags) => {
+                    self.follow_tags = follow_tags;
+                    Response::Ok
+                }
+                Err(response) => response,
+            },
+            // Sent once per ref with a lease.
+            "cas" => match unquote_c_style(value).and_then(|lease| parse_lease(&lease)) {
+                Ok((name, id)) => {
+                    self.leases.insert(name, id);
+                    Response::Ok
+                }
+                Err(response) => response,
+            },
+            "cloning" => match parse_bool(value) {
+                Ok(cloning) => {
+                    self.cloning = cloning;
+                    Response::Ok
+                }
+                Err(response) => response,
+            },
+            "check-connectivity" => match parse_bool(value) {
+                Ok(check_connectivity) => {
+                    self.check_connectivity = check_connectivity;
+                    Response::Ok
+                }
+                Err(response) => response,
+            },
+            "update-shallow" => match parse_bool(value) {
+                Ok(update_shallow) => {
+                    self.update_shallow = update_shallow;
+                    Response::Ok
+                }
+                Err(response) => response,
+            },
+            "filter" => match unquote_c_style(value) {
+                Ok(filter) => {
+                    self.filter = Some(filter);
+                    Response::Ok
+                }
+                Err(response) => response,
+            },
+            _ => Response::Unsupported,
+        }
+    }
+}
+
+fn parse_bool(value: &str) -> Result<bool, Response> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(Response::Error(format!(
+            "expected true or false, got {}",
+            value
+        ))),
+    }
+}
+
+/// Parses a lease as Git sends it, which is `<ref>:<expected id>`.
+fn parse_lease(value: &str) -> Result<(String, git::hash::ObjectId), Response> {
+    let invalid = || Response::Error(format!("expected <ref>:<object id>, got {}", value));
+
+    let (name, id) = value.rsplit_once(':').ok_or_else(invalid)?;
+    let id = git::hash::ObjectId::from_hex(id.as_bytes()).map_err(|_| invalid())?;
+
+    Ok((name.to_string(), id))
+}
+
+/// Removes the quoting Git applies to option values containing special
+/// characters, returning other values unchanged.
+fn unquote_c_style(value: &str) -> Result<String, Response> {
+    let invalid = || Response::Error(format!("invalid quoted value {}", value));
+
+    let quoted = match value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        Some(quoted) => quoted,
+        None => return Ok(value.to_string()),
+    };
+
+    let mut bytes = Vec::with_capacity(quoted.len());
+    let mut input = quoted.bytes();
+
+    while let Some(byte) = input.next() {
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+
+        let unescaped = match input.next().ok_or_else(invalid)? {
+            b'a' => 0x07,
+            b'b' => 0x08,
+            b'f' => 0x0c,
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b't' => b'\t',
+            b'v' => 0x0b,
+            escaped @ (b'\\' | b'"') => escaped,
+            first @ b'0'..=b'3' => {
+                let mut octal = first - b'0';
+                for _ in 0..2 {
+                    match input.next() {
+                        Some(digit @ b'0'..=b'7') => octal = octal * 8 + (digit - b'0'),
+                        _ => return Err(invalid()),
+                    }
+                }
+                octal
+            }
+            _ => return Err(invalid()),
+        };
+
+        bytes.push(unescaped);
+    }
+
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+pub fn execute(
+    options: &mut Options,
+    name: &str,
+    value: &[String],
+    out: &mut impl Write,
+) -> std::io::Result<()> {
+    let value = value.join(" ");
+    let response = options.set(name, &value);
+    trace!("option {} {}: {}", name, value, response);
+
+    if name == "verbosity" && response == Response::Ok {
+        verbosity::set(options.verbosity);
+    }
+
+    writeln!(out, "{}", response)
+}
@@ -0,0 +1,164 @@
+use super::*;
+
+#[test]
+fn test_set_dry_run() {
+    let mut options = Options::default();
+    assert_eq!(options.set("dry-run", "true"), Response::Ok, "response");
+    assert!(options.dry_run, "dry run");
+}
+
+#[test]
+fn test_set_verbosity() {
+    let mut options = Options::default();
+    assert_eq!(options.set("verbosity", "2"), Response::Ok, "response");
+    assert_eq!(options.verbosity, 2, "verbosity");
+    assert!(options.progress, "progress");
+}
+
+#[test]
+fn test_set_verbosity_quiet() {
+    let mut options = Options::default();
+    assert_eq!(options.set("verbosity", "0"), Response::Ok, "response");
+    assert_eq!(options.verbosity, 0, "verbosity");
+    assert!(!options.progress, "progress");
+}
+
+#[test]
+fn test_set_verbosity_invalid() {
+    let mut options = Options::default();
+    assert_eq!(
+        options.set("verbosity", "loud"),
+        Response::Error("expected a number, got loud".to_string()),
+        "response"
+    );
+    assert_eq!(options.verbosity, verbosity::DEFAULT, "verbosity");
+}
+
+#[test]
+fn test_set_unsupported() {
+    let mut options = Options::default();
+    assert_eq!(options.set("depth", "1"), Response::Unsupported, "response");
+}
+
+#[test]
+fn test_set_push_options() {
+    let mut options = Options::default();
+    assert_eq!(options.set("push-option", "ci.skip"), Response::Ok, "first");
+    assert_eq!(
+        options.set("push-option", "merge_request.create"),
+        Response::Ok,
+        "second"
+    );
+    assert_eq!(
+        options.set("push-option", "\"title=\\\"a\\\\b\\\"\\tdone\\303\\251\""),
+        Response::Ok,
+        "quoted"
+    );
+    assert_eq!(
+        options.push_options,
+        vec![
+            "ci.skip".to_string(),
+            "merge_request.create".to_string(),
+            "title=\"a\\b\"\tdoneé".to_string(),
+        ],
+        "push options"
+    );
+}
+
+#[test]
+fn test_set_push_option_invalid_quoting() {
+    let mut options = Options::default();
+    assert_eq!(
+        options.set("push-option", "\"trailing\\\""),
+        Response::Error("invalid quoted value \"trailing\\\"".to_string()),
+        "response"
+    );
+    assert!(options.push_options.is_empty(), "push options");
+}
+
+#[test]
+fn test_set_filter() {
+    let mut options = Options::default();
+    assert_eq!(options.set("filter", "blob:none"), Response::Ok, "response");
+    assert_eq!(options.filter.as_deref(), Some("blob:none"), "filter");
+}
+
+#[test]
+fn test_set_follow_tags() {
+    let mut options = Options::default();
+    assert_eq!(options.set("followtags", "true"), Response::Ok, "response");
+    assert!(options.follow_tags, "follow tags");
+}
+
+#[test]
+fn test_set_check_connectivity() {
+    let mut options = Options::default();
+    assert_eq!(
+        options.set("check-connectivity", "true"),
+        Response::Ok,
+        "response"
+    );
+    assert!(options.check_connectivity, "check connectivity");
+}
+
+#[test]
+fn test_set_update_shallow() {
+    let mut options = Options::default();
+    assert_eq!(
+        options.set("update-shallow", "true"),
+        Response::Ok,
+        "response"
+    );
+    assert!(options.update_shallow, "update shallow");
+}
+
+#[test]
+fn test_set_cloning() {
+    let mut options = Options::default();
+    assert_eq!(options.set("cloning", "true"), Response::Ok, "response");
+    assert!(options.cloning, "cloning");
+}
+
+#[test]
+fn test_set_cas() {
+    let mut options = Options::default();
+    assert_eq!(
+        options.set(
+            "cas",
+            "refs/heads/main:91536083cdb16ef3c29638054642b50a34ea8c25"
+        ),
+        Response::Ok,
+        "lease"
+    );
+    assert_eq!(
+        options.set(
+            "cas",
+            "\"refs/heads/new:0000000000000000000000000000000000000000\""
+        ),
+        Response::Ok,
+        "quoted lease"
+    );
+    assert_eq!(
+        options.leases,
+        BTreeMap::from([
+            (
+                "refs/heads/main".to_string(),
+                git::hash::ObjectId::from_hex(b"91536083cdb16ef3c29638054642b50a34ea8c25")
+                    .expect("valid hash")
+            ),
+            ("refs/heads/new".to_string(), git::hash::Kind::Sha1.null()),
+        ]),
+        "leases"
+    );
+}
+
+#[test]
+fn test_set_cas_invalid() {
+    let mut options = Options::default();
+    assert_eq!(
+        options.set("cas", "refs/heads/main"),
+        Response::Error("expected <ref>:<object id>, got refs/heads/main".to_string()),
+        "response"
+    );
+    assert!(options.leases.is_empty(), "leases");
+}
@@ -1,228 +1,552 @@
+use crate::commands::option::Options;
+use crate::debug;
+use crate::event;
 use crate::git::service::receive_pack;
+use crate::git::service::receive_pack::response::{
+    CommandStatusV2, ErrorMsg, ReportStatusV2, ReportStatusVersion, UnpackResult,
+};
+use crate::stats::{self, PushStats};
 use anyhow::anyhow;
-use git::bstr::ByteSlice as _;
+use git::bstr::{BStr, BString, ByteSlice as _};
 use git::odb::pack::data::output::count::objects::ObjectExpansion;
+use git::protocol::transport::client::Capabilities;
+use git::refspec::parse::Operation;
+use git::refspec::{instruction, Instruction};
 use git_repository as git;
 use log::trace;
 use maybe_async::maybe_async;
-use std::collections::BTreeSet;
-
-#[cfg(feature = "blocking-network-client")]
-use std::io::Write as _;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::time::Instant;
 
 #[cfg(feature = "async-network-client")]
 use git::protocol::futures_lite::io::AsyncWriteExt as _;
 
-pub type Batch = BTreeSet<String>;
+#[cfg(test)]
+mod tests;
+
+pub type Batch = BTreeSet<RefSpec>;
+
+/// The refspec of a `push` command, which updates `dst` on the remote to what
+/// `src` is locally, or deletes it if there's no `src`.
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct RefSpec {
+    pub force: bool,
+    pub src: Option<BString>,
+    pub dst: BString,
+}
+
+impl RefSpec {
+    /// Parses a refspec as Git sends them, which is `[+]<src>:<dst>` with an
+    /// empty `<src>` to delete `<dst>`. Git expands patterns and short names
+    /// before sending them, so `<dst>` is always a full ref name.
+    pub fn parse(src_dst: &str) -> anyhow::Result<Self> {
+        let invalid = |reason: &str| anyhow!("invalid push refspec `{}`: {}", src_dst, reason);
+
+        let (force, rest) = match src_dst.strip_prefix('+') {
+            Some(rest) => (true, rest),
+            None => (false, src_dst),
+        };
+
+        let (src, dst) = rest
+            .split_once(':')
+            .ok_or_else(|| invalid("expected <src>:<dst>"))?;
+
+        if dst.is_empty() {
+            return Err(invalid("missing the ref to update"));
+        }
+
+        if src.contains('*') || dst.contains('*') {
+            return Err(invalid("patterns aren't supported"));
+        }
+
+        if !dst.starts_with("refs/") {
+            return Err(invalid(
+                "expected a full ref name like refs/heads/main to update",
+            ));
+        }
+
+        // This checks that both sides are valid ref names.
+        git::refspec::parse(src_dst.as_bytes().as_bstr(), Operation::Push)
+            .map_err(|err| invalid(&err.to_string()))?;
+
+        Ok(Self {
+            force,
+            src: (!src.is_empty()).then(|| src.into()),
+            dst: dst.into(),
+        })
+    }
+
+    fn instruction(&self) -> Instruction<'_> {
+        match &self.src {
+            Some(src) => Instruction::Push(instruction::Push::Matching {
+                src: src.as_bstr(),
+                dst: self.dst.as_bstr(),
+                allow_non_fast_forward: self.force,
+            }),
+            None => Instruction::Push(instruction::Push::Delete {
+                ref_or_pattern: self.dst.as_bstr(),
+            }),
+        }
+    }
+}
+
+/// Whether a ref was updated, with the reason it wasn't if it failed, as the
+/// remote or a reason of our own has it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RefStatus {
+    pub ref_name: String,
+    pub error: Option<String>,
+}
 
+impl RefStatus {
+    fn ok(ref_name: impl Into<String>) -> Self {
+        Self {
+            ref_name: ref_name.into(),
+            error: None,
+        }
+    }
+
+    fn failed(ref_name: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self {
+            ref_name: ref_name.into(),
+            error: Some(reason.into()),
+        }
+    }
+}
+
+/// The line Git expects for the ref in the status report for `push`.
+impl std::fmt::Display for RefStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.error {
+            None => write!(f, "ok {}", self.ref_name),
+            Some(reason) => write!(f, "error {} {}", self.ref_name, reason),
+        }
+    }
+}
+
+/// What happened when pushing a batch of refs.
+#[derive(Debug)]
+pub struct PushOutcome {
+    /// The status report the remote responded with, which is `None` when
+    /// nothing was sent to it, as for a dry run or when every lease is stale.
+    pub report: Option<ReportStatusV2>,
+    /// The status of each ref, in the order they're reported to Git.
+    pub refs: Vec<RefStatus>,
+    /// Why the push as a whole failed, if any ref failed or the remote
+    /// couldn't unpack what was sent.
+    pub error: Option<anyhow::Error>,
+    pub stats: Option<PushStats>,
+}
+
+impl PushOutcome {
+    /// The status of the ref named `ref_name`, if it was part of the push.
+    pub fn status(&self, ref_name: &str) -> Option<&RefStatus> {
+        self.refs.iter().find(|status| status.ref_name == ref_name)
+    }
+
+    /// The status report to respond to `push` with, before the blank line
+    /// that ends it.
+    pub fn lines(&self) -> Vec<String> {
+        self.refs.iter().map(ToString::to_string).collect()
+    }
+}
+
+pub(crate) const READONLY: &str = "ICP_READONLY";
+
+/// Whether pushing is refused, as set with `ICP_READONLY=1` for environments
+/// where credentials that can write shouldn't be used to.
+pub fn readonly() -> bool {
+    std::env::var(READONLY).map_or(false, |value| {
+        matches!(value.to_ascii_lowercase().as_str(), "1" | "true" | "yes")
+    })
+}
+
+/// Pushes `batch` and writes the status report Git expects for it to `out`.
+/// Fails if any ref failed to update, since Git relies on the exit status to
+/// tell whether the push succeeded.
 #[maybe_async]
 pub async fn process<AuthFn, T>(
-    mut transport: T,
+    transport: T,
     repo: &git::Repository,
     authenticate: AuthFn,
+    options: &Options,
+    extra_parameters: &[(String, Option<String>)],
     batch: &mut Batch,
-) -> anyhow::Result<()>
+    out: &mut impl Write,
+) -> anyhow::Result<Option<PushStats>>
 where
     AuthFn: FnMut(git::credentials::helper::Action) -> git::credentials::protocol::Result,
     T: git::protocol::transport::client::Transport,
 {
-    if !batch.is_empty() {
-        trace!("process push: {:#?}", batch);
-
-        use git::refspec::parse::Operation;
-        use git::refspec::{instruction, Instruction};
-
-        // Implement once option capability is supported
-        let mut progress = git::progress::Discard;
-        let extra_parameters = vec![];
-
-        let mut outcome = git::protocol::handshake(
-            &mut transport,
-            git::protocol::transport::Service::ReceivePack,
-            authenticate,
-            extra_parameters,
-            &mut progress,
-        )
-        .await?;
+    if batch.is_empty() {
+        return Ok(None);
+    }
+
+    trace!("process push: {:#?}", batch);
+
+    let outcome = push(
+        transport,
+        repo,
+        authenticate,
+        options,
+        extra_parameters,
+        batch,
+    )
+    .await?;
+
+    for line in outcome.lines() {
+        trace!("output: {}", line);
+        writeln!(out, "{}", line)?;
+    }
 
-        let remote_refs = outcome
-            .refs
-            .take()
-            .ok_or_else(|| anyhow!("failed to take remote refs"))?;
+    batch.clear();
 
-        trace!("remote_refs: {:#?}", remote_refs);
+    // Terminate the status report output
+    writeln!(out)?;
 
-        let mut request_writer = transport.request(
-            git::protocol::transport::client::WriteMode::Binary,
-            // This is currently redundant because we use `.into_parts()`
-            git::protocol::transport::client::MessageKind::Flush,
-        )?;
+    if let Some(error) = outcome.error {
+        return Err(error);
+    }
 
-        let instructions = batch
-            .iter()
-            .map(|unparse_ref_spec| {
-                let ref_spec_ref =
-                    git::refspec::parse(unparse_ref_spec.as_bytes().as_bstr(), Operation::Push)?;
-                Ok(ref_spec_ref.instruction())
-            })
-            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+    Ok(outcome.stats)
+}
+
+/// Pushes the refs in `batch`, which mustn't be empty, returning what
+/// happened to each of them rather than reporting it to Git, for tools that
+/// push with the library.
+///
+/// Refs failing to update doesn't make this fail, since they're part of the
+/// outcome. It only fails when the push can't be made at all, like when the
+/// remote can't be reached or doesn't support what was asked of it.
+#[maybe_async]
+pub async fn push<AuthFn, T>(
+    mut transport: T,
+    repo: &git::Repository,
+    authenticate: AuthFn,
+    options: &Options,
+    extra_parameters: &[(String, Option<String>)],
+    batch: &Batch,
+) -> anyhow::Result<PushOutcome>
+where
+    AuthFn: FnMut(git::credentials::helper::Action) -> git::credentials::protocol::Result,
+    T: git::protocol::transport::client::Transport,
+{
+    let mut progress = git::progress::Discard;
+    let extra_parameters = extra_parameters.to_vec();
+
+    let start = Instant::now();
+
+    let mut outcome = git::protocol::handshake(
+        &mut transport,
+        git::protocol::transport::Service::ReceivePack,
+        authenticate,
+        extra_parameters,
+        &mut progress,
+    )
+    .await?;
+
+    let remote_refs = outcome
+        .refs
+        .take()
+        .ok_or_else(|| anyhow!("failed to take remote refs"))?;
+
+    trace!("remote_refs: {:#?}", remote_refs);
+    debug::write("push-refs.txt", format!("{:#?}", remote_refs));
+
+    let report_status_version = ReportStatusVersion::negotiate(&outcome.capabilities);
+
+    trace!("report_status_version: {:#?}", report_status_version);
+
+    let request_capabilities =
+        request_capabilities(&outcome.capabilities, report_status_version, options);
+
+    trace!("request_capabilities: {:#?}", request_capabilities);
+
+    let handshake_ms = stats::millis(start.elapsed());
+    let start = Instant::now();
 
-        trace!("instructions: {:#?}", instructions);
+    let instructions = instructions(batch);
 
-        let push_instructions = instructions
+    trace!("instructions: {:#?}", instructions);
+
+    // Git leaves checking leases to the helper, as `send-pack` does for
+    // other remotes. Refs that have moved aren't pushed, and the rest
+    // are sent with the ids their leases expect so that the remote
+    // rejects them if they've moved since.
+    let stale = stale_refs(
+        repo.object_hash(),
+        &options.leases,
+        &instructions,
+        &remote_refs,
+    );
+
+    trace!("stale refs: {:#?}", stale);
+
+    let num_destinations = instructions.iter().filter_map(destination).count();
+
+    if !stale.is_empty() && (options.atomic || stale.len() == num_destinations) {
+        return Ok(PushOutcome {
+            report: None,
+            refs: stale_status_report(&instructions, &stale),
+            error: Some(anyhow!("failed to push some refs: {}", stale.join(", "))),
+            stats: None,
+        });
+    }
+
+    let instructions = instructions
+        .into_iter()
+        .filter(|instruction| {
+            destination(instruction).map_or(true, |dst| !stale.iter().any(|s| dst == s.as_str()))
+        })
+        .collect::<Vec<_>>();
+
+    let stale_statuses = stale
+        .iter()
+        .map(|dst| RefStatus::failed(dst, STALE_MSG))
+        .collect::<Vec<_>>();
+
+    let delete_instructions = delete_instructions(&instructions);
+
+    trace!("delete instructions: {:#?}", delete_instructions);
+
+    if !delete_instructions.is_empty() && !outcome.capabilities.contains("delete-refs") {
+        return Err(anyhow!("the remote does not support deleting refs"));
+    }
+
+    if options.atomic && !outcome.capabilities.contains("atomic") {
+        return Err(anyhow!("the remote does not support atomic pushes"));
+    }
+
+    if !options.push_options.is_empty() && !outcome.capabilities.contains("push-options") {
+        return Err(anyhow!("the remote does not support push options"));
+    }
+
+    // Git expects a status for every ref, so we report the ones that
+    // would be updated without sending the remote any commands.
+    if options.dry_run {
+        let refs = dry_run_status_report(repo, &instructions, &remote_refs)?;
+
+        let failed = stale
             .iter()
-            .filter_map(|instruction| match instruction {
-                Instruction::Push(instruction::Push::Matching {
-                    src,
-                    dst,
-                    allow_non_fast_forward,
-                }) => Some((src, dst, allow_non_fast_forward)),
-                _ => None,
-            });
+            .cloned()
+            .chain(
+                refs.iter()
+                    .filter(|status| status.error.is_some())
+                    .map(|status| status.ref_name.clone()),
+            )
+            .collect::<Vec<_>>();
+
+        return Ok(PushOutcome {
+            report: None,
+            refs: stale_statuses.into_iter().chain(refs).collect(),
+            error: (!failed.is_empty())
+                .then(|| anyhow!("failed to push some refs: {}", failed.join(", "))),
+            stats: None,
+        });
+    }
 
-        trace!("push instructions: {:#?}", push_instructions);
+    let mut request_writer = transport.request(
+        git::protocol::transport::client::WriteMode::Binary,
+        // This is currently redundant because we use `.into_parts()`
+        git::protocol::transport::client::MessageKind::Flush,
+    )?;
+
+    let push_instructions = instructions
+        .iter()
+        .filter_map(|instruction| match instruction {
+            Instruction::Push(instruction::Push::Matching {
+                src,
+                dst,
+                allow_non_fast_forward,
+            }) => Some((src, dst, allow_non_fast_forward)),
+            _ => None,
+        });
 
-        // TODO: use Traverse for initial push
-        let input_object_expansion = ObjectExpansion::TreeAdditionsComparedToAncestor;
+    trace!("push instructions: {:#?}", push_instructions);
 
-        let mut entries = vec![];
+    // TODO: use Traverse for initial push
+    let input_object_expansion = ObjectExpansion::TreeAdditionsComparedToAncestor;
 
-        for (src, dst, _allow_non_fast_forward) in push_instructions {
-            // local
-            let mut src_reference = repo.find_reference(*src)?;
-            let src_id = src_reference.peel_to_id_in_place()?;
+    let mut entries = vec![];
 
-            // remote
-            let dst_id = remote_refs
-                .iter()
-                .find_map(|r| {
-                    let (name, target, peeled) = r.unpack();
-                    (name == *dst).then(|| peeled.or(target)).flatten()
-                })
-                .map(|x| x.to_owned())
-                .unwrap_or_else(|| git::hash::Kind::Sha1.null());
+    for (src, dst, _allow_non_fast_forward) in push_instructions {
+        // local
+        let mut src_reference = repo.find_reference(*src)?;
+        let src_id = src_reference.peel_to_id_in_place()?;
+
+        // remote
+        let dst_id = remote_refs
+            .iter()
+            .find_map(|r| {
+                let (name, target, peeled) = r.unpack();
+                (name == *dst).then(|| peeled.or(target)).flatten()
+            })
+            .map(|x| x.to_owned())
+            .unwrap_or_else(|| repo.object_hash().null());
 
-            trace!("dst_id: {:#?}", dst_id);
+        trace!("dst_id: {:#?}", dst_id);
 
+        // A ref the remote doesn't have yet, as when a branch is created,
+        // has no commit to cut the history off at, so all of it is sent.
+        let sorting = if dst_id.is_null() {
+            git::traverse::commit::Sorting::Topological
+        } else {
             let dst_object = repo.find_object(dst_id)?;
             let dst_commit = dst_object.try_into_commit()?;
             let dst_commit_time = dst_commit
                 .committer()
                 .map(|committer| committer.time.seconds_since_unix_epoch)?;
 
-            let ancestors = src_id
-                .ancestors()
-                .sorting(
-                    git::traverse::commit::Sorting::ByCommitTimeNewestFirstCutoffOlderThan {
-                        time_in_seconds_since_epoch: dst_commit_time,
-                    },
-                )
-                // TODO: repo object cache?
-                .all()
-                // NOTE: this is suboptimal but makes debugging easier
-                .map(|ancestor_commits| ancestor_commits.collect::<Vec<_>>());
-
-            trace!("ancestors: {:#?}", ancestors);
-
-            // FIXME: We need to handle fast-forwards and force pushes.
-            // Ideally we'd fail fast but we can't because figuring out
-            // if a fast-forward is possible consumes the
-            // `ancestor_commits` iterator which can't be cloned.
-            //
-            // TODO: Investigate if we can do this after we're otherwise
-            // done with `ancestor_commits`.
-            /*
-            let is_fast_forward = match ancestor_commits {
-                Ok(mut commits) => commits.any(|commit_id| {
-                    commit_id.map_or(false, |commit_id| commit_id == dst_id)
-                }),
-                Err(_) => false,
-            };
+            git::traverse::commit::Sorting::ByCommitTimeNewestFirstCutoffOlderThan {
+                time_in_seconds_since_epoch: dst_commit_time,
+            }
+        };
+
+        let ancestors = src_id
+            .ancestors()
+            .sorting(sorting)
+            // TODO: repo object cache?
+            .all()
+            // NOTE: this is suboptimal but makes debugging easier
+            .map(|ancestor_commits| ancestor_commits.collect::<Vec<_>>());
+
+        trace!("ancestors: {:#?}", ancestors);
+
+        // FIXME: We need to handle fast-forwards and force pushes.
+        // Ideally we'd fail fast but we can't because figuring out
+        // if a fast-forward is possible consumes the
+        // `ancestor_commits` iterator which can't be cloned.
+        //
+        // TODO: Investigate if we can do this after we're otherwise
+        // done with `ancestor_commits`.
+        /*
+        let is_fast_forward = match ancestor_commits {
+            Ok(mut commits) => commits.any(|commit_id| {
+                commit_id.map_or(false, |commit_id| commit_id == dst_id)
+            }),
+            Err(_) => false,
+        };
+
+        trace!("is_fast_forward: {:#?}", is_fast_forward);
+        trace!("allow_non_fast_forward: {:#?}", allow_non_fast_forward);
+
+        if !is_fast_forward && !allow_non_fast_forward {
+            return Err(anyhow!("attempted non fast-forward push without force"));
+        }
+        */
+
+        // TODO: set_pack_cache?
+        // TODO: ignore_replacements?
+        let mut db = repo.objects.clone();
+        db.prevent_pack_unload();
+
+        // NOTE: we don't want to short circuit on this Result
+        // until after we've determined if we can fast-forward.
+        let commits = ancestors?;
+
+        let (mut counts, _count_stats) = git::odb::pack::data::output::count::objects_unthreaded(
+            db.clone(),
+            commits.into_iter(),
+            git::progress::Discard,
+            &git::interrupt::IS_INTERRUPTED,
+            input_object_expansion,
+        )?;
 
-            trace!("is_fast_forward: {:#?}", is_fast_forward);
-            trace!("allow_non_fast_forward: {:#?}", allow_non_fast_forward);
+        counts.shrink_to_fit();
 
-            if !is_fast_forward && !allow_non_fast_forward {
-                return Err(anyhow!("attempted non fast-forward push without force"));
-            }
-            */
-
-            // TODO: set_pack_cache?
-            // TODO: ignore_replacements?
-            let mut db = repo.objects.clone();
-            db.prevent_pack_unload();
-
-            // NOTE: we don't want to short circuit on this Result
-            // until after we've determined if we can fast-forward.
-            let commits = ancestors?;
-
-            let (mut counts, _count_stats) =
-                git::odb::pack::data::output::count::objects_unthreaded(
-                    db.clone(),
-                    commits.into_iter(),
-                    // Implement once option capability is supported
-                    git::progress::Discard,
-                    &git::interrupt::IS_INTERRUPTED,
-                    input_object_expansion,
-                )?;
-
-            counts.shrink_to_fit();
-
-            trace!("counts: {:#?}", counts);
-
-            // TODO: in order iter
-            let mut entries_iter = git::odb::pack::data::output::entry::iter_from_counts(
-                counts,
-                db,
-                git::progress::Discard,
-                git::odb::pack::data::output::entry::iter_from_counts::Options {
-                    allow_thin_pack: false,
-                    ..Default::default()
-                },
-            );
-
-            entries.push(
-                git::parallel::InOrderIter::from(entries_iter.by_ref())
-                    .collect::<Result<Vec<_>, _>>()?
-                    .into_iter()
-                    .flatten()
-                    .collect::<Vec<_>>(),
-            );
-
-            // NOTE
-            //
-            // * We send `report-status-v2` so that we receive a
-            //   response that includes a status report. We parse this
-            //   and write a status report to stdout in the format that
-            //   remote helpers are expected to produce.
-            //
-            // * See comments on reading the `receive-pack` response as
-            //   to why we send the sideband capability.
-            let chunk = format!(
-                "{} {} {}\0 report-status-v2 side-band-64k",
-                dst_id.to_hex(),
-                src_id.to_hex(),
-                dst
-            );
-
-            request_writer.write_all(chunk.as_bytes().as_bstr()).await?;
+        trace!("counts: {:#?}", counts);
+
+        // TODO: in order iter
+        let mut entries_iter = git::odb::pack::data::output::entry::iter_from_counts(
+            counts,
+            db,
+            git::progress::Discard,
+            git::odb::pack::data::output::entry::iter_from_counts::Options {
+                allow_thin_pack: false,
+                ..Default::default()
+            },
+        );
+
+        entries.push(
+            git::parallel::InOrderIter::from(entries_iter.by_ref())
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>(),
+        );
+
+        // NOTE
+        //
+        // * We send `report-status-v2` (or `report-status` if that's
+        //   all the server supports) so that we receive a response
+        //   that includes a status report. We parse this and write a
+        //   status report to stdout in the format that remote helpers
+        //   are expected to produce.
+        //
+        // * See comments on reading the `receive-pack` response as
+        //   to why we send the sideband capability.
+        let chunk = format!(
+            "{} {} {}\0 {}",
+            lease(&options.leases, dst).unwrap_or(dst_id).to_hex(),
+            src_id.to_hex(),
+            dst,
+            request_capabilities.join(" "),
+        );
+
+        request_writer.write_all(chunk.as_bytes().as_bstr()).await?;
+    }
+
+    for dst in delete_instructions.iter() {
+        let dst_id = delete_target(&remote_refs, dst)?;
+        let dst_id = lease(&options.leases, dst).unwrap_or(dst_id);
+
+        trace!("delete dst_id: {:#?}", dst_id);
+
+        let chunk = format!(
+            "{} {} {}\0 {}",
+            dst_id.to_hex(),
+            repo.object_hash().null().to_hex(),
+            dst,
+            request_capabilities.join(" "),
+        );
+
+        request_writer.write_all(chunk.as_bytes().as_bstr()).await?;
+    }
+
+    request_writer
+        .write_message(git::protocol::transport::client::MessageKind::Flush)
+        .await?;
+
+    // Push options follow the commands as their own flush-terminated
+    // list, which is only expected once `push-options` is requested.
+    if !options.push_options.is_empty() {
+        for push_option in options.push_options.iter() {
+            request_writer.write_all(push_option.as_bytes()).await?;
         }
 
         request_writer
             .write_message(git::protocol::transport::client::MessageKind::Flush)
             .await?;
+    }
+
+    // A pack must not be sent when the only commands are deletions.
+    let send_pack = !entries.is_empty();
+
+    let entries = entries.into_iter().flatten().collect::<Vec<_>>();
+    trace!("entries: {:#?}", entries);
 
-        let entries = entries.into_iter().flatten().collect::<Vec<_>>();
-        trace!("entries: {:#?}", entries);
+    let num_entries: u32 = entries.len().try_into()?;
+    trace!("num entries: {:#?}", num_entries);
 
-        let num_entries: u32 = entries.len().try_into()?;
-        trace!("num entries: {:#?}", num_entries);
+    let (mut writer, reader) = request_writer.into_parts();
 
-        let (mut writer, mut reader) = request_writer.into_parts();
+    #[cfg(feature = "async-network-client")]
+    let mut writer = git::protocol::futures_lite::io::BlockOn::new(&mut writer);
 
-        #[cfg(feature = "async-network-client")]
-        let mut writer = git::protocol::futures_lite::io::BlockOn::new(&mut writer);
+    let mut bytes = 0;
 
+    if send_pack {
         let pack_writer = git::odb::pack::data::output::bytes::FromEntriesIter::new(
             std::iter::once(Ok::<
                 _,
@@ -233,65 +557,398 @@ where
             &mut writer,
             num_entries,
             git::odb::pack::data::Version::V2,
-            git::hash::Kind::Sha1,
+            repo.object_hash(),
         );
 
         // The pack writer is lazy, so we need to consume it
         for write_result in pack_writer {
             let bytes_written = write_result?;
             trace!("bytes written: {:#?}", bytes_written);
+            bytes += bytes_written;
         }
 
         trace!("finished writing pack");
+    }
 
-        // If we don't send any sideband capabilities, we get
-        // `Some(Err(Kind(UnexpectedEof)))` in the `AsyncBufRead`
-        // implementation for `WithSidebands` here when trying to read
-        // the `receive-pack` response:
-        // https://github.com/paulyoung/gitoxide/blob/93f2dd8f7db87afc04a523458faaa46f9b33f21a/git-packetline/src/read/sidebands/async_io.rs#L213
-        //
-        // So, we send `side-band-64k` to address that. Even though we
-        // currently don't support reporting any progress, we set a
-        // progress handler to keep the sideband information separate
-        // from the response we care about.
-        use std::ops::Deref as _;
-        use std::sync::{Arc, Mutex};
-        let messages = Arc::new(Mutex::new(Vec::<String>::new()));
-        reader.set_progress_handler(Some(Box::new({
-            move |is_err, data| {
-                assert!(!is_err);
-                messages
-                    .deref()
-                    .lock()
-                    .expect("no panic in other threads")
-                    .push(std::str::from_utf8(data).expect("valid utf8").to_owned())
+    event::log("pack", &[("objects", &num_entries), ("bytes", &bytes)]);
+
+    let pack_ms = stats::millis(start.elapsed());
+    let start = Instant::now();
+
+    // If we don't send any sideband capabilities, we get
+    // `Some(Err(Kind(UnexpectedEof)))` in the `AsyncBufRead`
+    // implementation for `WithSidebands` here when trying to read
+    // the `receive-pack` response:
+    // https://github.com/paulyoung/gitoxide/blob/93f2dd8f7db87afc04a523458faaa46f9b33f21a/git-packetline/src/read/sidebands/async_io.rs#L213
+    //
+    // So, we send `side-band-64k` to address that, and demultiplex the
+    // response ourselves to keep the sideband information separate from
+    // the response we care about.
+    let mut stderr = std::io::stderr();
+    let report = receive_pack::response::read_and_parse_with_sidebands(
+        reader,
+        report_status_version,
+        |is_err, data| write_remote_message(&mut stderr, options.progress, is_err, data),
+    )
+    .await?;
+
+    let report_ms = stats::millis(start.elapsed());
+
+    trace!("report: {:#?}", report);
+    debug::write("report-status.txt", format!("{:#?}", report));
+
+    let (refs, error) = status_report(&report, options.atomic);
+
+    let refs = stale_statuses.into_iter().chain(refs).collect::<Vec<_>>();
+    let error = match error {
+        None if !stale.is_empty() => {
+            Some(anyhow!("failed to push some refs: {}", stale.join(", ")))
+        }
+        error => error,
+    };
+
+    event::log(
+        "push",
+        &[
+            ("refs", &batch.len()),
+            ("failed", &error.is_some()),
+            ("report_ms", &report_ms),
+        ],
+    );
+
+    Ok(PushOutcome {
+        report: Some(report),
+        refs,
+        error,
+        stats: Some(PushStats {
+            handshake_ms,
+            pack_ms,
+            report_ms,
+            objects: num_entries.into(),
+            bytes,
+        }),
+    })
+}
+
+/// Shows a message the remote sent over the sideband the way Git does for its
+/// own transports. Progress is dropped rather than shown when it's disabled,
+/// as for `git push -q` or `git fetch -q`, even if the remote sends it
+/// anyway, while errors are always shown.
+pub(crate) fn write_remote_message(
+    stderr: &mut impl Write,
+    progress: bool,
+    is_err: bool,
+    data: &[u8],
+) {
+    trace!(
+        "remote {}: {}",
+        if is_err { "error" } else { "progress" },
+        data.as_bstr()
+    );
+
+    if !is_err && !progress {
+        return;
+    }
+
+    // Progress is redrawn after `\r`, so each redraw gets the prefix too.
+    for line in data.split_inclusive(|byte| *byte == b'\n' || *byte == b'\r') {
+        if let Err(err) = stderr
+            .write_all(b"remote: ")
+            .and_then(|_| stderr.write_all(line))
+        {
+            trace!("failed to write remote message: {}", err);
+            return;
+        }
+    }
+}
+
+fn instructions(batch: &Batch) -> Vec<Instruction<'_>> {
+    batch.iter().map(RefSpec::instruction).collect()
+}
+
+/// The refs to delete, which are given as refspecs with an empty source like
+/// `:refs/heads/topic`.
+fn delete_instructions<'a>(instructions: &[Instruction<'a>]) -> Vec<&'a BStr> {
+    instructions
+        .iter()
+        .filter_map(|instruction| match instruction {
+            Instruction::Push(instruction::Push::Delete { ref_or_pattern }) => {
+                Some(*ref_or_pattern)
             }
-        })));
-
-        let (_unpack_result, command_statuses) =
-            receive_pack::response::read_and_parse(reader).await?;
-
-        command_statuses.iter().for_each(|command_status| {
-            trace!("{:#?}", command_status);
-            match command_status {
-                receive_pack::response::CommandStatusV2::Ok(ref_name, _option_lines) => {
-                    let output = format!("ok {}", ref_name);
-                    trace!("output: {}", output);
-                    println!("{}", output);
-                }
-                receive_pack::response::CommandStatusV2::Fail(ref_name, error_msg) => {
-                    let output = format!("error {} {}\0", ref_name, error_msg);
-                    trace!("output: {}", output);
-                    println!("{}", output);
-                }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The id a ref to delete currently has on the remote, which must be what
+/// the ref points to rather than what it peels to.
+fn delete_target(
+    remote_refs: &[git::protocol::handshake::Ref],
+    dst: &BStr,
+) -> anyhow::Result<git::hash::ObjectId> {
+    remote_target(remote_refs, dst)
+        .ok_or_else(|| anyhow!("unable to delete {}: remote ref does not exist", dst))
+}
+
+/// The id `dst` points to on the remote, if it exists.
+fn remote_target(
+    remote_refs: &[git::protocol::handshake::Ref],
+    dst: &BStr,
+) -> Option<git::hash::ObjectId> {
+    remote_refs
+        .iter()
+        .find_map(|r| {
+            let (name, target, _peeled) = r.unpack();
+            (name == dst).then_some(target).flatten()
+        })
+        .map(|x| x.to_owned())
+}
+
+/// The ref an instruction updates or deletes on the remote.
+fn destination<'a>(instruction: &Instruction<'a>) -> Option<&'a BStr> {
+    match instruction {
+        Instruction::Push(instruction::Push::Matching { dst, .. }) => Some(*dst),
+        Instruction::Push(instruction::Push::Delete { ref_or_pattern }) => Some(*ref_or_pattern),
+        _ => None,
+    }
+}
+
+/// The id the lease for `dst` expects it to have on the remote, if it has
+/// one.
+fn lease(
+    leases: &BTreeMap<String, git::hash::ObjectId>,
+    dst: &BStr,
+) -> Option<git::hash::ObjectId> {
+    leases.get(&dst.to_string()).copied()
+}
+
+/// The refs whose leases expect an id other than the one the remote
+/// advertised, where a null id means the ref is expected not to exist.
+fn stale_refs(
+    object_hash: git::hash::Kind,
+    leases: &BTreeMap<String, git::hash::ObjectId>,
+    instructions: &[Instruction<'_>],
+    remote_refs: &[git::protocol::handshake::Ref],
+) -> Vec<String> {
+    instructions
+        .iter()
+        .filter_map(destination)
+        .filter(|dst| match lease(leases, dst) {
+            Some(expected) => {
+                let actual = remote_target(remote_refs, dst).unwrap_or_else(|| object_hash.null());
+                actual != expected
             }
-        });
+            None => false,
+        })
+        .map(|dst| dst.to_string())
+        .collect()
+}
+
+/// The status report when nothing is pushed because of stale leases. For an
+/// atomic push, the refs that aren't stale fail as well.
+fn stale_status_report(instructions: &[Instruction<'_>], stale: &[String]) -> Vec<RefStatus> {
+    instructions
+        .iter()
+        .filter_map(destination)
+        .map(|dst| {
+            let reason = if stale.iter().any(|s| dst == s.as_str()) {
+                STALE_MSG
+            } else {
+                ATOMIC_FAILURE_MSGS[0]
+            };
+            RefStatus::failed(dst.to_string(), reason)
+        })
+        .collect()
+}
 
-        batch.clear();
+/// The status report for a dry run, where every ref that could be pushed or
+/// deleted is reported as updated.
+fn dry_run_status_report(
+    repo: &git::Repository,
+    instructions: &[Instruction<'_>],
+    remote_refs: &[git::protocol::handshake::Ref],
+) -> anyhow::Result<Vec<RefStatus>> {
+    instructions
+        .iter()
+        .filter_map(|instruction| match instruction {
+            Instruction::Push(instruction::Push::Matching {
+                src,
+                dst,
+                allow_non_fast_forward,
+            }) => Some(dry_run_push_status(
+                repo,
+                src,
+                dst,
+                *allow_non_fast_forward,
+                remote_refs,
+            )),
+            Instruction::Push(instruction::Push::Delete { ref_or_pattern }) => Some(
+                delete_target(remote_refs, ref_or_pattern)
+                    .map(|_| RefStatus::ok(ref_or_pattern.to_string())),
+            ),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The status of pushing `src` to `dst` in a dry run, which fails as the
+/// remote would unless `dst` is new, forced, or an ancestor of `src`.
+fn dry_run_push_status(
+    repo: &git::Repository,
+    src: &BStr,
+    dst: &BStr,
+    allow_non_fast_forward: bool,
+    remote_refs: &[git::protocol::handshake::Ref],
+) -> anyhow::Result<RefStatus> {
+    let src_id = repo.find_reference(src)?.peel_to_id_in_place()?;
+
+    let is_fast_forward = || match remote_target(remote_refs, dst) {
+        Some(dst_id) => is_ancestor(src_id, dst_id),
+        None => true,
+    };
+
+    if allow_non_fast_forward || is_fast_forward() {
+        Ok(RefStatus::ok(dst.to_string()))
+    } else {
+        Ok(RefStatus::failed(dst.to_string(), "non-fast-forward"))
+    }
+}
+
+/// Whether `ancestor` is `id` or one of its ancestors, which it can't be if
+/// we don't have it.
+fn is_ancestor(id: git::Id<'_>, ancestor: git::hash::ObjectId) -> bool {
+    if id == ancestor {
+        return true;
+    }
+
+    match id.ancestors().all() {
+        Ok(mut commits) => {
+            commits.any(|commit_id| commit_id.map_or(false, |commit_id| commit_id == ancestor))
+        }
+        Err(_) => false,
+    }
+}
 
-        // Terminate the status report output
-        println!();
+fn request_capabilities(
+    capabilities: &Capabilities,
+    report_status_version: ReportStatusVersion,
+    options: &Options,
+) -> Vec<&'static str> {
+    let mut request_capabilities = vec![report_status_version.capability(), "side-band-64k"];
+
+    // Without `quiet` the server sends progress over the sideband even
+    // though we'd discard it.
+    if !options.progress && capabilities.contains("quiet") {
+        request_capabilities.push("quiet");
     }
 
-    Ok(())
+    // Whether the server supports these is checked before the request.
+    if options.atomic {
+        request_capabilities.push("atomic");
+    }
+
+    if !options.push_options.is_empty() {
+        request_capabilities.push("push-options");
+    }
+
+    request_capabilities
+}
+
+/// The status of each ref in the remote's status report, along with an error
+/// if the pack failed to unpack or any ref failed to update.
+///
+/// For an atomic push, any failed ref fails the whole batch, so refs the
+/// remote reported as updated are reported as failed too.
+fn status_report(report: &ReportStatusV2, atomic: bool) -> (Vec<RefStatus>, Option<anyhow::Error>) {
+    let (unpack_result, command_statuses) = report;
+
+    let atomic_failed = atomic
+        && command_statuses
+            .iter()
+            .any(|command_status| matches!(command_status, CommandStatusV2::Fail(_, _)));
+
+    let statuses = command_statuses
+        .iter()
+        .map(|command_status| match command_status {
+            CommandStatusV2::Ok(ref_name, _option_lines) if atomic_failed => (
+                ref_name.to_string(),
+                Some(ATOMIC_FAILURE_MSGS[0].to_string()),
+            ),
+            CommandStatusV2::Ok(ref_name, _option_lines) => (ref_name.to_string(), None),
+            CommandStatusV2::Fail(ref_name, error_msg) => {
+                (ref_name.to_string(), Some(reason(error_msg)))
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let ref_statuses = statuses
+        .iter()
+        .map(|(ref_name, reason)| RefStatus {
+            ref_name: ref_name.clone(),
+            error: reason.clone(),
+        })
+        .collect::<Vec<_>>();
+
+    let failed = statuses
+        .into_iter()
+        .filter_map(|(ref_name, reason)| reason.map(|reason| (ref_name, reason)))
+        .collect::<Vec<_>>();
+
+    let failed_ref_names = failed
+        .iter()
+        .map(|(ref_name, _)| ref_name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let error = match unpack_result {
+        UnpackResult::ErrorMsg(error_msg) => {
+            Some(anyhow!("remote failed to unpack: {}", error_msg))
+        }
+        UnpackResult::Ok if is_atomic_failure(command_statuses.len(), &failed) => {
+            // The ref that caused the atomic push to fail has its own
+            // reason, while the rest share a generic one.
+            let reason = failed
+                .iter()
+                .find(|(_, error_msg)| !is_atomic_failure_msg(error_msg))
+                .map(|(ref_name, error_msg)| format!("{} {}", ref_name, error_msg))
+                .unwrap_or_else(|| "no reason given".to_string());
+
+            Some(anyhow!(
+                "atomic push failed ({}), no refs were updated: {}",
+                reason,
+                failed_ref_names
+            ))
+        }
+        UnpackResult::Ok if !failed.is_empty() => {
+            Some(anyhow!("failed to push some refs: {}", failed_ref_names))
+        }
+        UnpackResult::Ok => None,
+    };
+
+    (ref_statuses, error)
+}
+
+/// The reason a ref failed to update as the server reported it, so that Git
+/// shows users the server's own message. Invalid UTF-8 is replaced, and the
+/// trailing newline is removed since each status is written as one line.
+fn reason(error_msg: &ErrorMsg) -> String {
+    error_msg.to_str_lossy().trim_end().to_string()
+}
+
+// The reason Git gives for a ref whose lease no longer matches.
+const STALE_MSG: &str = "stale info";
+
+// `receive-pack` rejects every other ref with one of these when a ref in an
+// atomic push fails.
+const ATOMIC_FAILURE_MSGS: &[&str] = &["atomic push failure", "atomic transaction failed"];
+
+fn is_atomic_failure_msg(error_msg: &str) -> bool {
+    ATOMIC_FAILURE_MSGS.contains(&error_msg.trim_end())
+}
+
+fn is_atomic_failure(num_command_statuses: usize, failed: &[(String, String)]) -> bool {
+    failed.len() == num_command_statuses
+        && failed
+            .iter()
+            .any(|(_, error_msg)| is_atomic_failure_msg(error_msg))
 }
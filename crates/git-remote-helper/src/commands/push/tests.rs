@@ -0,0 +1,614 @@
+use super::*;
+use crate::commands::option;
+use crate::git::service::receive_pack::response::RefName;
+use git::bstr::BString;
+
+fn capabilities(input: &[u8]) -> Capabilities {
+    Capabilities::from_bytes(input)
+        .expect("valid capabilities")
+        .0
+}
+
+#[test]
+fn test_request_capabilities_quiet_when_progress_off() {
+    let capabilities = capabilities(b"\0report-status-v2 side-band-64k quiet");
+    let mut options = Options::default();
+    assert_eq!(options.set("progress", "false"), option::Response::Ok);
+    let result = request_capabilities(&capabilities, ReportStatusVersion::V2, &options);
+    assert_eq!(
+        result,
+        vec!["report-status-v2", "side-band-64k", "quiet"],
+        "request capabilities"
+    )
+}
+
+#[test]
+fn test_request_capabilities_not_quiet_when_progress_on() {
+    let capabilities = capabilities(b"\0report-status-v2 side-band-64k quiet");
+    let options = Options::default();
+    let result = request_capabilities(&capabilities, ReportStatusVersion::V2, &options);
+    assert_eq!(
+        result,
+        vec!["report-status-v2", "side-band-64k"],
+        "request capabilities"
+    )
+}
+
+#[test]
+fn test_request_capabilities_not_quiet_when_unsupported() {
+    let capabilities = capabilities(b"\0report-status side-band-64k");
+    let mut options = Options::default();
+    assert_eq!(options.set("progress", "false"), option::Response::Ok);
+    let result = request_capabilities(&capabilities, ReportStatusVersion::V1, &options);
+    assert_eq!(
+        result,
+        vec!["report-status", "side-band-64k"],
+        "request capabilities"
+    )
+}
+
+#[test]
+fn test_request_capabilities_push_options() {
+    let capabilities = capabilities(b"\0report-status-v2 side-band-64k push-options");
+    let mut options = Options::default();
+    assert_eq!(options.set("push-option", "ci.skip"), option::Response::Ok);
+    let result = request_capabilities(&capabilities, ReportStatusVersion::V2, &options);
+    assert_eq!(
+        result,
+        vec!["report-status-v2", "side-band-64k", "push-options"],
+        "request capabilities"
+    )
+}
+
+fn lines(statuses: &[RefStatus]) -> Vec<String> {
+    statuses.iter().map(ToString::to_string).collect()
+}
+
+fn ref_name(name: &str) -> RefName {
+    RefName(BString::from(name))
+}
+
+fn error_msg(msg: &str) -> ErrorMsg {
+    ErrorMsg(BString::from(msg))
+}
+
+#[test]
+fn test_status_report_ok() {
+    let report = (
+        UnpackResult::Ok,
+        vec![CommandStatusV2::Ok(ref_name("refs/heads/main"), Vec::new())],
+    );
+    let (statuses, error) = status_report(&report, false);
+    assert_eq!(lines(&statuses), vec!["ok refs/heads/main"], "lines");
+    assert!(error.is_none(), "error")
+}
+
+#[test]
+fn test_status_report_unpack_error() {
+    let report = (
+        UnpackResult::ErrorMsg(error_msg("index-pack abnormal exit")),
+        vec![CommandStatusV2::Fail(
+            ref_name("refs/heads/main"),
+            error_msg("unpacker error"),
+        )],
+    );
+    let (_lines, error) = status_report(&report, false);
+    assert_eq!(
+        error.map(|error| error.to_string()),
+        Some("remote failed to unpack: index-pack abnormal exit".to_string()),
+        "error"
+    )
+}
+
+#[test]
+fn test_status_report_command_fail() {
+    let report = (
+        UnpackResult::Ok,
+        vec![
+            CommandStatusV2::Ok(ref_name("refs/heads/debug"), Vec::new()),
+            CommandStatusV2::Fail(ref_name("refs/heads/main"), error_msg("non-fast-forward")),
+        ],
+    );
+    let (statuses, error) = status_report(&report, false);
+    assert_eq!(statuses.len(), 2, "lines");
+    assert_eq!(
+        error.map(|error| error.to_string()),
+        Some("failed to push some refs: refs/heads/main".to_string()),
+        "error"
+    )
+}
+
+#[test]
+fn test_status_report_reason() {
+    let report = (
+        UnpackResult::Ok,
+        vec![
+            CommandStatusV2::Fail(
+                ref_name("refs/heads/main"),
+                error_msg("pre-receive hook declined\n"),
+            ),
+            CommandStatusV2::Fail(
+                ref_name("refs/heads/topic"),
+                ErrorMsg(BString::from(b"hook declined: caf\xe9".as_slice())),
+            ),
+        ],
+    );
+    let (statuses, _error) = status_report(&report, false);
+    assert_eq!(
+        lines(&statuses),
+        vec![
+            "error refs/heads/main pre-receive hook declined",
+            "error refs/heads/topic hook declined: caf\u{FFFD}",
+        ],
+        "lines"
+    )
+}
+
+#[test]
+fn test_status_report_known_reasons() {
+    // Git recognizes these reasons by their exact text to show its hints.
+    let reasons = [
+        "non-fast-forward",
+        "fetch first",
+        "stale info",
+        "already exists",
+        "needs force",
+    ];
+    let report = (
+        UnpackResult::Ok,
+        reasons
+            .iter()
+            .map(|reason| CommandStatusV2::Fail(ref_name("refs/heads/main"), error_msg(reason)))
+            .collect(),
+    );
+    let (statuses, _error) = status_report(&report, false);
+    assert_eq!(
+        lines(&statuses),
+        reasons
+            .iter()
+            .map(|reason| format!("error refs/heads/main {}", reason))
+            .collect::<Vec<_>>(),
+        "lines"
+    )
+}
+
+#[test]
+fn test_status_report_atomic_failure() {
+    let report = (
+        UnpackResult::Ok,
+        vec![
+            CommandStatusV2::Fail(
+                ref_name("refs/heads/debug"),
+                error_msg("atomic push failure\n"),
+            ),
+            CommandStatusV2::Fail(ref_name("refs/heads/main"), error_msg("non-fast-forward\n")),
+            CommandStatusV2::Fail(
+                ref_name("refs/heads/topic"),
+                error_msg("atomic push failure\n"),
+            ),
+        ],
+    );
+    let (statuses, error) = status_report(&report, false);
+    assert_eq!(statuses.len(), 3, "lines");
+    assert_eq!(
+        error.map(|error| error.to_string()),
+        Some(
+            "atomic push failed (refs/heads/main non-fast-forward), no refs were updated: \
+             refs/heads/debug, refs/heads/main, refs/heads/topic"
+                .to_string()
+        ),
+        "error"
+    )
+}
+
+#[test]
+fn test_status_report_atomic_partial_update() {
+    let report = (
+        UnpackResult::Ok,
+        vec![
+            CommandStatusV2::Ok(ref_name("refs/heads/debug"), Vec::new()),
+            CommandStatusV2::Fail(ref_name("refs/heads/main"), error_msg("non-fast-forward\n")),
+        ],
+    );
+    let (statuses, error) = status_report(&report, true);
+    assert_eq!(
+        lines(&statuses),
+        vec![
+            "error refs/heads/debug atomic push failure",
+            "error refs/heads/main non-fast-forward",
+        ],
+        "lines"
+    );
+    assert_eq!(
+        error.map(|error| error.to_string()),
+        Some(
+            "atomic push failed (refs/heads/main non-fast-forward), no refs were updated: \
+             refs/heads/debug, refs/heads/main"
+                .to_string()
+        ),
+        "error"
+    )
+}
+
+#[test]
+fn test_status_report_atomic_ok() {
+    let report = (
+        UnpackResult::Ok,
+        vec![
+            CommandStatusV2::Ok(ref_name("refs/heads/debug"), Vec::new()),
+            CommandStatusV2::Ok(ref_name("refs/heads/main"), Vec::new()),
+        ],
+    );
+    let (statuses, error) = status_report(&report, true);
+    assert_eq!(
+        lines(&statuses),
+        vec!["ok refs/heads/debug", "ok refs/heads/main"],
+        "lines"
+    );
+    assert!(error.is_none(), "error");
+}
+
+#[test]
+fn test_push_outcome_mixed() {
+    let report = (
+        UnpackResult::Ok,
+        vec![
+            CommandStatusV2::Ok(ref_name("refs/heads/debug"), Vec::new()),
+            CommandStatusV2::Fail(ref_name("refs/heads/main"), error_msg("non-fast-forward\n")),
+        ],
+    );
+    let (refs, error) = status_report(&report, false);
+    let outcome = PushOutcome {
+        report: Some(report),
+        refs,
+        error,
+        stats: None,
+    };
+
+    assert_eq!(
+        outcome.status("refs/heads/debug"),
+        Some(&RefStatus::ok("refs/heads/debug")),
+        "ok ref"
+    );
+    assert_eq!(
+        outcome.status("refs/heads/main"),
+        Some(&RefStatus::failed("refs/heads/main", "non-fast-forward")),
+        "failed ref"
+    );
+    assert_eq!(outcome.status("refs/heads/topic"), None, "ref not pushed");
+    assert_eq!(
+        outcome.lines(),
+        vec![
+            "ok refs/heads/debug",
+            "error refs/heads/main non-fast-forward"
+        ],
+        "lines"
+    );
+    assert_eq!(
+        outcome.error.map(|error| error.to_string()),
+        Some("failed to push some refs: refs/heads/main".to_string()),
+        "error"
+    );
+}
+
+#[test]
+fn test_write_remote_message_without_progress() {
+    let mut stderr = Vec::new();
+    write_remote_message(&mut stderr, false, false, b"Resolving deltas: 100% (2/2)\n");
+    assert_eq!(stderr, b"", "progress");
+
+    write_remote_message(&mut stderr, false, true, b"hook declined\n");
+    assert_eq!(stderr, b"remote: hook declined\n", "error");
+}
+
+#[test]
+fn test_write_remote_message_with_progress() {
+    let mut stderr = Vec::new();
+    write_remote_message(
+        &mut stderr,
+        true,
+        false,
+        b"Resolving deltas:  50% (1/2)\rResolving deltas: 100% (2/2)\n",
+    );
+    assert_eq!(
+        stderr.as_bstr(),
+        b"remote: Resolving deltas:  50% (1/2)\rremote: Resolving deltas: 100% (2/2)\n".as_bstr(),
+        "progress"
+    );
+}
+
+fn batch(refspecs: &[&str]) -> Batch {
+    refspecs
+        .iter()
+        .map(|refspec| RefSpec::parse(refspec).expect("valid refspec"))
+        .collect()
+}
+
+fn parse_error(src_dst: &str) -> String {
+    RefSpec::parse(src_dst)
+        .expect_err("invalid refspec")
+        .to_string()
+}
+
+#[test]
+fn test_parse_refspec() {
+    assert_eq!(
+        RefSpec::parse("refs/heads/main:refs/heads/main").expect("valid refspec"),
+        RefSpec {
+            force: false,
+            src: Some("refs/heads/main".into()),
+            dst: "refs/heads/main".into(),
+        },
+        "refspec"
+    )
+}
+
+#[test]
+fn test_parse_refspec_force() {
+    assert_eq!(
+        RefSpec::parse("+refs/heads/main:refs/heads/main").expect("valid refspec"),
+        RefSpec {
+            force: true,
+            src: Some("refs/heads/main".into()),
+            dst: "refs/heads/main".into(),
+        },
+        "refspec"
+    )
+}
+
+#[test]
+fn test_parse_refspec_delete() {
+    assert_eq!(
+        RefSpec::parse(":refs/heads/topic").expect("valid refspec"),
+        RefSpec {
+            force: false,
+            src: None,
+            dst: "refs/heads/topic".into(),
+        },
+        "refspec"
+    )
+}
+
+#[test]
+fn test_parse_refspec_create() {
+    assert_eq!(
+        RefSpec::parse("refs/heads/main:refs/heads/topic").expect("valid refspec"),
+        RefSpec {
+            force: false,
+            src: Some("refs/heads/main".into()),
+            dst: "refs/heads/topic".into(),
+        },
+        "refspec"
+    )
+}
+
+#[test]
+fn test_parse_refspec_missing_colon() {
+    assert_eq!(
+        parse_error("refs/heads/main"),
+        "invalid push refspec `refs/heads/main`: expected <src>:<dst>",
+        "error"
+    )
+}
+
+#[test]
+fn test_parse_refspec_missing_dst() {
+    assert_eq!(
+        parse_error("refs/heads/main:"),
+        "invalid push refspec `refs/heads/main:`: missing the ref to update",
+        "error"
+    );
+    assert_eq!(
+        parse_error("+:"),
+        "invalid push refspec `+:`: missing the ref to update",
+        "error with force"
+    )
+}
+
+#[test]
+fn test_parse_refspec_pattern() {
+    assert_eq!(
+        parse_error("refs/heads/*:refs/heads/*"),
+        "invalid push refspec `refs/heads/*:refs/heads/*`: patterns aren't supported",
+        "error"
+    )
+}
+
+#[test]
+fn test_parse_refspec_short_dst() {
+    assert_eq!(
+        parse_error("refs/heads/main:main"),
+        "invalid push refspec `refs/heads/main:main`: expected a full ref name like refs/heads/main to update",
+        "error"
+    )
+}
+
+#[test]
+fn test_parse_refspec_invalid_refname() {
+    assert!(
+        parse_error("refs/heads/main:refs/heads/a..b")
+            .starts_with("invalid push refspec `refs/heads/main:refs/heads/a..b`: "),
+        "error"
+    );
+    assert!(
+        parse_error("refs/heads/a b:refs/heads/main")
+            .starts_with("invalid push refspec `refs/heads/a b:refs/heads/main`: "),
+        "error for src"
+    )
+}
+
+#[test]
+fn test_delete_instructions() {
+    let batch = batch(&[":refs/heads/topic", "refs/heads/main:refs/heads/main"]);
+    let instructions = instructions(&batch);
+    assert_eq!(
+        instructions,
+        vec![
+            Instruction::Push(instruction::Push::Delete {
+                ref_or_pattern: "refs/heads/topic".into(),
+            }),
+            Instruction::Push(instruction::Push::Matching {
+                src: "refs/heads/main".into(),
+                dst: "refs/heads/main".into(),
+                allow_non_fast_forward: false,
+            }),
+        ],
+        "instructions"
+    );
+    assert_eq!(
+        delete_instructions(&instructions),
+        vec!["refs/heads/topic"],
+        "delete instructions"
+    )
+}
+
+const REMOTE_ID: &str = "91536083cdb16ef3c29638054642b50a34ea8c25";
+const OTHER_ID: &str = "1111111111111111111111111111111111111111";
+
+fn oid(hex: &str) -> git::hash::ObjectId {
+    git::hash::ObjectId::from_hex(hex.as_bytes()).expect("valid hash")
+}
+
+fn remote_refs() -> Vec<git::protocol::handshake::Ref> {
+    vec![git::protocol::handshake::Ref::Direct {
+        full_ref_name: "refs/heads/main".into(),
+        object: oid(REMOTE_ID),
+    }]
+}
+
+fn stale(leases: &[(&str, git::hash::ObjectId)], refspecs: &[&str]) -> Vec<String> {
+    let leases = leases
+        .iter()
+        .map(|(name, id)| (name.to_string(), *id))
+        .collect();
+    let batch = batch(refspecs);
+    let instructions = instructions(&batch);
+    stale_refs(
+        git::hash::Kind::Sha1,
+        &leases,
+        &instructions,
+        &remote_refs(),
+    )
+}
+
+#[test]
+fn test_stale_refs_matching_lease() {
+    assert_eq!(
+        stale(
+            &[("refs/heads/main", oid(REMOTE_ID))],
+            &["+refs/heads/main:refs/heads/main"]
+        ),
+        Vec::<String>::new(),
+        "stale"
+    )
+}
+
+#[test]
+fn test_stale_refs_moved() {
+    assert_eq!(
+        stale(
+            &[("refs/heads/main", oid(OTHER_ID))],
+            &["+refs/heads/main:refs/heads/main", ":refs/heads/topic"]
+        ),
+        vec!["refs/heads/main"],
+        "stale"
+    )
+}
+
+#[test]
+fn test_stale_refs_must_not_exist() {
+    let null = git::hash::Kind::Sha1.null();
+    assert_eq!(
+        stale(
+            &[("refs/heads/main", null), ("refs/heads/new", null)],
+            &[
+                "refs/heads/main:refs/heads/main",
+                "refs/heads/main:refs/heads/new"
+            ]
+        ),
+        vec!["refs/heads/main"],
+        "stale"
+    )
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_push_new_ref_with_must_not_exist_lease() {
+    use crate::test_support::git;
+    use git::protocol::transport;
+
+    let dir = tempfile::tempdir().expect("tempdir");
+    git(dir.path(), ["init", "--bare", "remote.git"]);
+    git(dir.path(), ["init", "local"]);
+    let local = dir.path().join("local");
+    std::fs::write(local.join("README.md"), "# Local\n").expect("file to be written");
+    git(&local, ["add", "README.md"]);
+    git(&local, ["commit", "-m", "Add README"]);
+    let head = git(&local, ["rev-parse", "HEAD"]);
+
+    let repo = git::open(&local).expect("repository to open");
+    let url = format!("file://{}", dir.path().join("remote.git").display());
+    let transport = transport::connect::<_, std::convert::Infallible>(
+        url.as_str(),
+        transport::client::connect::Options {
+            version: transport::Protocol::V1,
+            ssh: Default::default(),
+        },
+    )
+    .expect("transport to connect");
+
+    let options = Options {
+        leases: BTreeMap::from([("refs/heads/main".to_string(), repo.object_hash().null())]),
+        ..Default::default()
+    };
+
+    let outcome = push(
+        transport,
+        &repo,
+        |_| Ok(None),
+        &options,
+        &[],
+        &batch(&["refs/heads/main:refs/heads/main"]),
+    )
+    .expect("push to be made");
+
+    assert!(outcome.error.is_none(), "error: {:?}", outcome.error);
+    assert_eq!(outcome.lines(), vec!["ok refs/heads/main"], "status");
+    assert_eq!(
+        git(
+            &dir.path().join("remote.git"),
+            ["rev-parse", "refs/heads/main"]
+        ),
+        head,
+        "remote ref"
+    );
+}
+
+#[test]
+fn test_stale_refs_without_lease() {
+    assert_eq!(
+        stale(&[], &["+refs/heads/main:refs/heads/main"]),
+        Vec::<String>::new(),
+        "stale"
+    )
+}
+
+#[test]
+fn test_stale_status_report() {
+    let batch = batch(&[
+        "refs/heads/main:refs/heads/main",
+        "refs/heads/topic:refs/heads/topic",
+    ]);
+    let instructions = instructions(&batch);
+    assert_eq!(
+        lines(&stale_status_report(
+            &instructions,
+            &["refs/heads/main".to_string()]
+        )),
+        vec![
+            "error refs/heads/main stale info",
+            "error refs/heads/topic atomic push failure",
+        ],
+        "lines"
+    )
+}
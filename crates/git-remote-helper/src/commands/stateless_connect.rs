@@ -0,0 +1,200 @@
+use clap::ValueEnum;
+use git::protocol::transport::client::{
+    Capabilities, MessageKind, ReadlineBufRead as _, WriteMode,
+};
+use git::protocol::transport::packetline::PacketLineRef;
+use git::protocol::transport::Protocol;
+use git_repository as git;
+use log::{trace, warn};
+use maybe_async::maybe_async;
+use std::io::{self, BufRead, Write};
+
+#[cfg(feature = "async-network-client")]
+use git::protocol::futures_lite::io::AsyncWriteExt as _;
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd, ValueEnum)]
+pub enum Service {
+    GitUploadPack,
+    GitReceivePack,
+}
+
+/// Responds to `stateless-connect <service>`.
+///
+/// Each request Git writes to `input` is forwarded to the remote as its own
+/// request, which for canisters is a single call, and the response is
+/// written back to `out` followed by a response-end packet.
+///
+/// This is only supported for `git-upload-pack` when the remote speaks
+/// protocol v2. Otherwise, we respond with `fallback` so that Git uses
+/// `fetch` and `push` instead.
+///
+/// Returns whether a connection was made, in which case Git is done with the
+/// helper once it closes stdin.
+#[maybe_async]
+pub async fn execute<AuthFn, T>(
+    mut transport: T,
+    authenticate: AuthFn,
+    service: &Service,
+    extra_parameters: &[(String, Option<String>)],
+    input: &mut impl BufRead,
+    out: &mut impl Write,
+) -> anyhow::Result<bool>
+where
+    AuthFn: FnMut(git::credentials::helper::Action) -> git::credentials::protocol::Result,
+    T: git::protocol::transport::client::Transport,
+{
+    if *service != Service::GitUploadPack {
+        trace!("stateless-connect {:?}: fallback", service);
+        writeln!(out, "fallback")?;
+        return Ok(false);
+    }
+
+    let mut progress = git::progress::Discard;
+
+    let outcome = git::protocol::handshake(
+        &mut transport,
+        git::protocol::transport::Service::UploadPack,
+        authenticate,
+        extra_parameters.to_vec(),
+        &mut progress,
+    )
+    .await?;
+
+    if outcome.server_protocol_version != Protocol::V2 {
+        warn!(
+            "stateless-connect requires protocol v2 but the remote only supports {:?}, falling back to fetch and push",
+            outcome.server_protocol_version
+        );
+        writeln!(out, "fallback")?;
+        return Ok(false);
+    }
+
+    let mut advertisement = Vec::new();
+    // Let Git know we're ready
+    writeln!(advertisement)?;
+    write_advertisement(&mut advertisement, &outcome.capabilities)?;
+    write_flushed(out, &advertisement)?;
+
+    loop {
+        let request = match read_request(input)? {
+            Some(request) => request,
+            None => {
+                trace!("stateless-connect: end of input");
+                break;
+            }
+        };
+
+        trace!("stateless-connect request: {:#?}", request);
+
+        let mut writer = transport.request(WriteMode::Binary, MessageKind::Flush)?;
+
+        for packet in request {
+            match packet {
+                Packet::Data(data) => writer.write_all(&data).await?,
+                Packet::Delimiter => writer.write_message(MessageKind::Delimiter).await?,
+            }
+        }
+
+        let mut reader = writer.into_read().await?;
+        let mut response = Vec::new();
+
+        while let Some(line) = reader.readline().await {
+            encode(&mut response, line??)?;
+        }
+
+        // The reader stops at the flush that ends the response without
+        // returning it.
+        encode(&mut response, PacketLineRef::Flush)?;
+        encode(&mut response, PacketLineRef::ResponseEnd)?;
+        write_flushed(out, &response)?;
+    }
+
+    Ok(true)
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Packet {
+    Data(Vec<u8>),
+    Delimiter,
+}
+
+/// Reads the packets of a request up to and excluding the flush packet that
+/// ends it, or `None` if there's no more input.
+fn read_request(input: &mut impl BufRead) -> io::Result<Option<Vec<Packet>>> {
+    let mut packets = Vec::new();
+
+    loop {
+        let mut len = [0u8; 4];
+
+        match input.read_exact(&mut len) {
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof && packets.is_empty() => {
+                return Ok(None)
+            }
+            result => result?,
+        }
+
+        let len = std::str::from_utf8(&len)
+            .ok()
+            .and_then(|len| usize::from_str_radix(len, 16).ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid packet length: {:?}", len),
+                )
+            })?;
+
+        match len {
+            0 => return Ok(Some(packets)),
+            1 => packets.push(Packet::Delimiter),
+            2 | 3 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected packet in request: {:04x}", len),
+                ))
+            }
+            _ => {
+                let mut data = vec![0; len - 4];
+                input.read_exact(&mut data)?;
+                packets.push(Packet::Data(data));
+            }
+        }
+    }
+}
+
+fn encode(out: &mut impl Write, line: PacketLineRef<'_>) -> io::Result<()> {
+    match line {
+        PacketLineRef::Data(data) => {
+            write!(out, "{:04x}", data.len() + 4)?;
+            out.write_all(data)
+        }
+        PacketLineRef::Flush => out.write_all(b"0000"),
+        PacketLineRef::Delimiter => out.write_all(b"0001"),
+        PacketLineRef::ResponseEnd => out.write_all(b"0002"),
+    }
+}
+
+/// Writes the protocol v2 capability advertisement that Git expects to read
+/// first.
+fn write_advertisement(out: &mut impl Write, capabilities: &Capabilities) -> io::Result<()> {
+    encode(out, PacketLineRef::Data(b"version 2\n"))?;
+
+    for capability in capabilities.iter() {
+        let mut line = capability.name().to_vec();
+        if let Some(value) = capability.value() {
+            line.push(b'=');
+            line.extend_from_slice(value);
+        }
+        line.push(b'\n');
+        encode(out, PacketLineRef::Data(&line))?;
+    }
+
+    encode(out, PacketLineRef::Flush)
+}
+
+fn write_flushed(out: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    out.write_all(data)?;
+    out.flush()
+}
@@ -0,0 +1,53 @@
+use super::*;
+
+#[test]
+fn test_read_request() {
+    let mut input: &[u8] = b"0014command=ls-refs\n00010009peel\n0000";
+    assert_eq!(
+        read_request(&mut input).expect("request to be read"),
+        Some(vec![
+            Packet::Data(b"command=ls-refs\n".to_vec()),
+            Packet::Delimiter,
+            Packet::Data(b"peel\n".to_vec()),
+        ]),
+        "request"
+    );
+    assert_eq!(
+        read_request(&mut input).expect("end of input"),
+        None,
+        "end of input"
+    )
+}
+
+#[test]
+fn test_read_request_invalid_length() {
+    let mut input: &[u8] = b"zzzz";
+    assert_eq!(
+        read_request(&mut input).expect_err("invalid length").kind(),
+        io::ErrorKind::InvalidData,
+        "error kind"
+    )
+}
+
+#[test]
+fn test_encode() {
+    let mut out = Vec::new();
+    encode(&mut out, PacketLineRef::Data(b"ok\n")).expect("encode");
+    encode(&mut out, PacketLineRef::Delimiter).expect("encode");
+    encode(&mut out, PacketLineRef::Flush).expect("encode");
+    encode(&mut out, PacketLineRef::ResponseEnd).expect("encode");
+    assert_eq!(out.as_slice(), b"0007ok\n000100000002", "encoded")
+}
+
+#[test]
+fn test_write_advertisement() {
+    let (capabilities, _) =
+        Capabilities::from_bytes(b"\0ls-refs object-format=sha1").expect("valid capabilities");
+    let mut out = Vec::new();
+    write_advertisement(&mut out, &capabilities).expect("write");
+    assert_eq!(
+        out.as_slice(),
+        b"000eversion 2\n000cls-refs\n0017object-format=sha1\n0000".as_slice(),
+        "advertisement"
+    )
+}
@@ -0,0 +1,89 @@
+use super::*;
+
+#[test]
+fn test_from_line_fetch() {
+    assert_eq!(
+        Commands::from_line("fetch 91536083cdb16ef3c29638054642b50a34ea8c25 refs/heads/main")
+            .expect("valid command"),
+        Commands::Fetch {
+            hash: git::hash::ObjectId::from_hex(b"91536083cdb16ef3c29638054642b50a34ea8c25")
+                .expect("valid hash"),
+            name: "refs/heads/main".to_string(),
+        },
+        "fetch"
+    )
+}
+
+#[test]
+fn test_from_line_fetch_invalid_hash() {
+    let err = Commands::from_line("fetch 9153608 refs/heads/main").expect_err("invalid hash");
+
+    assert_eq!(err.kind(), clap::error::ErrorKind::ValueValidation, "kind");
+    assert!(
+        err.to_string().contains("invalid object id 9153608"),
+        "message: {}",
+        err
+    );
+}
+
+#[test]
+fn test_capabilities_v2() {
+    assert_eq!(
+        capabilities(true, false),
+        vec![
+            "fetch",
+            "option",
+            "push",
+            "object-format",
+            "check-connectivity",
+            "stateless-connect"
+        ],
+        "capabilities"
+    )
+}
+
+#[test]
+fn test_capabilities_readonly() {
+    assert_eq!(
+        capabilities(true, true),
+        vec![
+            "fetch",
+            "option",
+            "object-format",
+            "check-connectivity",
+            "stateless-connect"
+        ],
+        "capabilities"
+    )
+}
+
+#[test]
+fn test_capabilities_v1() {
+    assert_eq!(
+        capabilities(false, false),
+        vec![
+            "fetch",
+            "option",
+            "push",
+            "object-format",
+            "check-connectivity"
+        ],
+        "capabilities"
+    )
+}
+
+#[test]
+fn test_capabilities_only_advertised_commands() {
+    let advertised = capabilities(true, false);
+    for kind in CommandKind::iter() {
+        let name = format!("{:?}", kind);
+        match kind.readiness() {
+            Readiness::Advertised(capability) | Readiness::AdvertisedWithV2(capability) => {
+                assert!(advertised.contains(&capability), "{} advertised", name)
+            }
+            Readiness::Implicit => {}
+        }
+    }
+    assert!(!advertised.contains(&"connect"), "connect advertised");
+    assert!(!advertised.contains(&"list"), "list advertised");
+}
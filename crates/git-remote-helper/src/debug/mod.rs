@@ -0,0 +1,80 @@
+//! Protocol artifacts for bug reports.
+//!
+//! When `GIT_REMOTE_ICP_DEBUG_DIR=<dir>` is set, raw request and response
+//! bodies, parsed refs, and status reports are written to timestamped files
+//! under `<dir>`.
+
+use log::{trace, warn};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(test)]
+mod tests;
+
+pub(crate) const DEBUG_DIR: &str = "GIT_REMOTE_ICP_DEBUG_DIR";
+
+// Keeps files written within the same millisecond in order.
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// Writes `contents` to a file whose name ends with `name` under the
+/// directory in `GIT_REMOTE_ICP_DEBUG_DIR`, if set.
+///
+/// Failing to write is logged rather than returned so that debugging never
+/// causes an operation to fail.
+pub fn write(name: &str, contents: impl AsRef<[u8]>) {
+    if let Some(dir) = dir() {
+        match write_to(&dir, name, contents.as_ref()) {
+            Ok(path) => trace!("wrote {}", path.display()),
+            Err(err) => warn!("failed to write {} to {:?}: {}", name, dir, err),
+        }
+    }
+}
+
+#[cfg(not(test))]
+fn dir() -> Option<PathBuf> {
+    std::env::var_os(DEBUG_DIR).map(PathBuf::from)
+}
+
+// Tests set the directory for their own thread, since setting the variable
+// would change it for every test running alongside.
+#[cfg(test)]
+thread_local! {
+    static TEST_DIR: std::cell::RefCell<Option<PathBuf>> = std::cell::RefCell::new(None);
+}
+
+#[cfg(test)]
+fn dir() -> Option<PathBuf> {
+    TEST_DIR
+        .with(|dir| dir.borrow().clone())
+        .or_else(|| std::env::var_os(DEBUG_DIR).map(PathBuf::from))
+}
+
+/// Runs `f` as if `GIT_REMOTE_ICP_DEBUG_DIR` were set to `dir` for the
+/// current thread alone.
+#[cfg(test)]
+pub(crate) fn with_dir<T>(dir: &Path, f: impl FnOnce() -> T) -> T {
+    TEST_DIR.with(|test_dir| *test_dir.borrow_mut() = Some(dir.to_path_buf()));
+    let result = f();
+    TEST_DIR.with(|test_dir| *test_dir.borrow_mut() = None);
+    result
+}
+
+fn write_to(dir: &Path, name: &str, contents: &[u8]) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis());
+
+    let path = dir.join(format!(
+        "{}-{}-{:04}-{}",
+        millis,
+        std::process::id(),
+        SEQUENCE.fetch_add(1, Ordering::Relaxed),
+        name
+    ));
+
+    std::fs::write(&path, contents)?;
+    Ok(path)
+}
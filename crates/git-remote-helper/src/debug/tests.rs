@@ -0,0 +1,45 @@
+use super::*;
+
+#[test]
+fn test_write_to() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let debug_dir = dir.path().join("debug");
+
+    let first = write_to(&debug_dir, "refs.txt", b"first").expect("write to succeed");
+    let second = write_to(&debug_dir, "refs.txt", b"second").expect("write to succeed");
+
+    assert_ne!(first, second, "paths");
+    assert!(first < second, "paths should sort in the order written");
+    assert!(
+        first
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map_or(false, |name| name.ends_with("-refs.txt")),
+        "file name"
+    );
+    assert_eq!(
+        std::fs::read(&second).expect("file to exist"),
+        b"second",
+        "contents"
+    )
+}
+
+#[test]
+fn test_with_dir() {
+    let temp = tempfile::tempdir().expect("tempdir");
+
+    with_dir(temp.path(), || write("refs.txt", b"refs"));
+
+    assert_eq!(
+        std::fs::read_dir(temp.path())
+            .expect("dir to exist")
+            .count(),
+        1,
+        "files"
+    );
+    assert_eq!(
+        dir(),
+        std::env::var_os(DEBUG_DIR).map(PathBuf::from),
+        "dir after"
+    );
+}
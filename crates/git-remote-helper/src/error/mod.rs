@@ -0,0 +1,207 @@
+//! The error a helper exits with, classified so that tooling doesn't have to
+//! parse the message to tell what failed.
+//!
+//! By default the error is reported the way Rust reports any error returned
+//! from `main`. With `ICP_ERROR_FORMAT=json` and the `serde` feature
+//! enabled, it's instead printed to stderr as a single JSON object like
+//! `{"error": "...", "kind": "fetch", "url": "..."}` before exiting with a
+//! non-zero status.
+
+use git_repository::protocol::transport;
+use std::fmt;
+
+#[cfg(test)]
+mod tests;
+
+pub(crate) const ERROR_FORMAT: &str = "ICP_ERROR_FORMAT";
+
+/// What was being done when a helper failed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Kind {
+    /// The helper's configuration or environment, like `GIT_PROTOCOL` or the
+    /// repository, is invalid.
+    Config,
+    /// The remote couldn't be reached.
+    Connect,
+    /// Git sent something the helper didn't understand or can't act on.
+    Input,
+    /// Listing the remote's refs failed.
+    List,
+    /// Fetching from the remote failed.
+    Fetch,
+    /// Pushing to the remote failed or was refused.
+    Push,
+    /// The helper was interrupted with Ctrl-C.
+    Interrupted,
+    Other,
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Config => "config",
+            Kind::Connect => "connect",
+            Kind::Input => "input",
+            Kind::List => "list",
+            Kind::Fetch => "fetch",
+            Kind::Push => "push",
+            Kind::Interrupted => "interrupted",
+            Kind::Other => "other",
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A fatal error, along with what kind it is and the URL of the remote it
+/// happened with, if any.
+pub struct Error {
+    pub kind: Kind,
+    pub url: Option<String>,
+    source: anyhow::Error,
+}
+
+impl Error {
+    pub fn new(kind: Kind, source: anyhow::Error) -> Self {
+        Self {
+            kind,
+            url: None,
+            source,
+        }
+    }
+
+    /// Sets the URL of the remote, unless one was already set.
+    pub fn with_url(mut self, url: &str) -> Self {
+        self.url.get_or_insert_with(|| url.to_string());
+        self
+    }
+
+    pub fn source(&self) -> &anyhow::Error {
+        &self.source
+    }
+}
+
+// Both defer to the underlying error so that the default report is the same
+// as before there was a kind.
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.source, f)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.source, f)
+    }
+}
+
+impl From<Error> for anyhow::Error {
+    fn from(err: Error) -> Self {
+        err.source
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(err: anyhow::Error) -> Self {
+        Error::new(Kind::Other, err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::new(Kind::Other, err.into())
+    }
+}
+
+impl From<transport::client::connect::Error> for Error {
+    fn from(err: transport::client::connect::Error) -> Self {
+        Error::new(Kind::Connect, err.into())
+    }
+}
+
+/// Classifies the error of a result, like `anyhow::Context` adds context to
+/// it.
+pub trait WithKind<T> {
+    fn with_kind(self, kind: Kind) -> Result<T, Error>;
+}
+
+impl<T> WithKind<T> for anyhow::Result<T> {
+    fn with_kind(self, kind: Kind) -> Result<T, Error> {
+        self.map_err(|err| Error::new(kind, err))
+    }
+}
+
+/// How fatal errors are reported.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    Human,
+    Json,
+}
+
+/// The format from `ICP_ERROR_FORMAT`, which is human-readable unless it's
+/// `json`.
+pub fn format() -> Format {
+    match std::env::var(ERROR_FORMAT) {
+        Ok(value) => parse_format(&value),
+        Err(_) => Format::Human,
+    }
+}
+
+fn parse_format(value: &str) -> Format {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "json" => Format::Json,
+        "" | "human" => Format::Human,
+        _ => {
+            log::warn!(
+                "unsupported {} `{}`, expected json or human",
+                ERROR_FORMAT,
+                value
+            );
+            Format::Human
+        }
+    }
+}
+
+/// Reports the error a helper's `main` failed with, if any, in the format
+/// from `ICP_ERROR_FORMAT`. A JSON report exits the process, and otherwise
+/// the error is returned for Rust to report.
+pub fn report(result: Result<(), Error>) -> anyhow::Result<()> {
+    let err = match result {
+        Ok(()) => return Ok(()),
+        Err(err) => err,
+    };
+
+    if format() == Format::Json {
+        if let Some(json) = to_json(&err) {
+            eprintln!("{}", json);
+            std::process::exit(1);
+        }
+    }
+
+    Err(err.into())
+}
+
+/// The error as a single line of JSON, with the causes of its message joined
+/// the way `{:#}` does.
+#[cfg(feature = "serde")]
+pub fn to_json(err: &Error) -> Option<String> {
+    let json = serde_json::json!({
+        "error": format!("{:#}", err.source),
+        "kind": err.kind.as_str(),
+        "url": err.url,
+    });
+    Some(json.to_string())
+}
+
+#[cfg(not(feature = "serde"))]
+pub fn to_json(_err: &Error) -> Option<String> {
+    log::warn!(
+        "{} is set but git-remote-helper was built without the `serde` feature",
+        ERROR_FORMAT
+    );
+    None
+}
@@ -0,0 +1,82 @@
+use super::*;
+use anyhow::Context as _;
+
+#[test]
+fn test_parse_format() {
+    assert_eq!(parse_format("json"), Format::Json, "json");
+    assert_eq!(parse_format("JSON"), Format::Json, "upper case");
+    assert_eq!(parse_format("human"), Format::Human, "human");
+    assert_eq!(parse_format(""), Format::Human, "empty");
+    assert_eq!(parse_format("yaml"), Format::Human, "unsupported");
+}
+
+#[test]
+fn test_with_kind() {
+    let result: anyhow::Result<()> = Err(anyhow::anyhow!("remote hung up"));
+    let err = result.with_kind(Kind::Fetch).expect_err("an error");
+    assert_eq!(err.kind, Kind::Fetch, "kind");
+    assert_eq!(err.url, None, "url");
+}
+
+#[test]
+fn test_with_url_keeps_first() {
+    let err = Error::new(Kind::Push, anyhow::anyhow!("rejected"))
+        .with_url("icp://first")
+        .with_url("icp://second");
+    assert_eq!(err.url.as_deref(), Some("icp://first"), "url");
+}
+
+#[test]
+fn test_display_is_unchanged() {
+    let source = Err::<(), _>(anyhow::anyhow!("remote hung up"))
+        .context("failed to fetch from icp://example")
+        .expect_err("an error");
+    let expected = format!("{:#}", source);
+    let err = Error::new(Kind::Fetch, source);
+    assert_eq!(
+        err.to_string(),
+        "failed to fetch from icp://example",
+        "display"
+    );
+    assert_eq!(format!("{:#}", err), expected, "alternate display");
+}
+
+#[test]
+fn test_from_connect_error() {
+    let err = Error::from(transport::client::connect::Error::Connection(Box::new(
+        std::io::Error::new(std::io::ErrorKind::Other, "refused"),
+    )));
+    assert_eq!(err.kind, Kind::Connect, "kind");
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_to_json() {
+    let source = Err::<(), _>(anyhow::anyhow!("remote hung up"))
+        .context("failed to fetch")
+        .expect_err("an error");
+    let err = Error::new(Kind::Fetch, source).with_url("icp://example");
+
+    let json = to_json(&err).expect("json");
+    assert!(!json.contains('\n'), "a single line: {}", json);
+
+    let value: serde_json::Value = serde_json::from_str(&json).expect("valid json");
+    assert_eq!(
+        value,
+        serde_json::json!({
+            "error": "failed to fetch: remote hung up",
+            "kind": "fetch",
+            "url": "icp://example",
+        }),
+        "json"
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_to_json_without_url() {
+    let err = Error::new(Kind::Config, anyhow::anyhow!("invalid GIT_PROTOCOL"));
+    let value: serde_json::Value =
+        serde_json::from_str(&to_json(&err).expect("json")).expect("valid json");
+    assert!(value["url"].is_null(), "url");
+}
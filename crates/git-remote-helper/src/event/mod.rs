@@ -0,0 +1,44 @@
+//! Key events logged as `key=value` fields.
+//!
+//! Events are logged at the debug level under a single target, so that
+//! `RUST_LOG=git_remote_icp=debug` shows them without the trace-level dumps
+//! of the structures behind them, and each line can be grepped by its fields.
+
+use std::fmt::{Display, Write as _};
+
+#[cfg(test)]
+mod tests;
+
+/// The target every event is logged under.
+pub const TARGET: &str = "git_remote_icp";
+
+/// Logs the event `name` with `fields`, such as
+/// `event=list refs=3 version=V2`.
+pub fn log(name: &str, fields: &[(&str, &dyn Display)]) {
+    log::debug!(target: TARGET, "{}", format(name, fields));
+}
+
+/// Formats the event `name` with `fields` as `key=value` pairs, quoting
+/// values that are empty or contain whitespace, `=` or `"`.
+pub fn format(name: &str, fields: &[(&str, &dyn Display)]) -> String {
+    let mut line = format!("event={}", quote(name));
+
+    for (key, value) in fields {
+        let _ = write!(line, " {}={}", key, quote(&value.to_string()));
+    }
+
+    line
+}
+
+fn quote(value: &str) -> String {
+    let needs_quotes = value.is_empty()
+        || value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '=' || c == '"');
+
+    if needs_quotes {
+        format!("{:?}", value)
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,40 @@
+use super::*;
+
+#[test]
+fn test_format_without_fields() {
+    assert_eq!(format("handshake", &[]), "event=handshake", "line");
+}
+
+#[test]
+fn test_format_fields() {
+    assert_eq!(
+        format("fetch", &[("refs", &2), ("bytes", &1024)]),
+        "event=fetch refs=2 bytes=1024",
+        "line"
+    );
+}
+
+#[test]
+fn test_format_quotes_values() {
+    assert_eq!(
+        format(
+            "push",
+            &[
+                ("ref", &"refs/heads/main"),
+                ("error", &"non-fast forward"),
+                ("reason", &"")
+            ]
+        ),
+        r#"event=push ref=refs/heads/main error="non-fast forward" reason="""#,
+        "line"
+    );
+}
+
+#[test]
+fn test_format_escapes_quotes() {
+    assert_eq!(
+        format("push", &[("error", &r#"say "hi""#)]),
+        r#"event=push error="say \"hi\"""#,
+        "line"
+    );
+}
@@ -0,0 +1,73 @@
+use git::credentials::helper::Action;
+use git::credentials::protocol;
+use git::protocol::transport::Protocol;
+use git_repository as git;
+use log::trace;
+use std::cell::Cell;
+
+#[cfg(test)]
+mod tests;
+
+/// Obtains credentials for the remote from Git's configured credential
+/// helpers, remembering whether none were available so that the resulting
+/// failure can say why.
+pub struct Credentials<H = fn(Action) -> protocol::Result> {
+    helper: H,
+    declined: Cell<bool>,
+}
+
+impl Default for Credentials {
+    fn default() -> Self {
+        Self::new(git::credentials::builtin)
+    }
+}
+
+impl<H> Credentials<H>
+where
+    H: Fn(Action) -> protocol::Result,
+{
+    pub fn new(helper: H) -> Self {
+        Self {
+            helper,
+            declined: Cell::new(false),
+        }
+    }
+
+    /// Gets credentials on `Action::Get`, and approves or rejects them with
+    /// the helpers on `Action::Store` or `Action::Erase` once they've been
+    /// used.
+    pub fn authenticate(&self, action: Action) -> protocol::Result {
+        trace!("authenticate: {:#?}", action);
+
+        let get = matches!(action, Action::Get(_));
+        let result = (self.helper)(action);
+
+        match &result {
+            Ok(None) | Err(_) if get => self.declined.set(true),
+            _ => {}
+        }
+
+        match result {
+            Ok(None) if get => Err(protocol::Error::Quit),
+            result => result,
+        }
+    }
+
+    pub fn declined(&self) -> bool {
+        self.declined.get()
+    }
+
+    /// Context for an error from trying to `action` `url` with `version`,
+    /// like "failed to list refs from".
+    pub fn context(&self, action: &str, url: &str, version: Protocol) -> String {
+        let mut context = format!("{} {} using protocol {:?}", action, url, version);
+
+        if self.declined() {
+            context.push_str(
+                ": the remote requires credentials, but none were available from Git's credential helpers",
+            );
+        }
+
+        context
+    }
+}
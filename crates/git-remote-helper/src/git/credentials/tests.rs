@@ -0,0 +1,66 @@
+use super::*;
+use git::credentials::protocol::Context;
+
+fn get() -> Action {
+    Action::Get(Context {
+        url: Some("https://example.com/repo.git".into()),
+        ..Default::default()
+    })
+}
+
+#[test]
+fn test_authenticate_declined() {
+    let credentials = Credentials::new(|_| Ok(None));
+    assert!(!credentials.declined(), "not declined yet");
+
+    let result = credentials.authenticate(get());
+
+    assert!(
+        matches!(result, Err(protocol::Error::Quit)),
+        "declined gracefully: {:?}",
+        result
+    );
+    assert!(credentials.declined(), "declined");
+}
+
+#[test]
+fn test_authenticate_helper_error() {
+    let credentials = Credentials::new(|_| Err(protocol::Error::UrlMissing));
+
+    assert!(credentials.authenticate(get()).is_err(), "error");
+    assert!(credentials.declined(), "declined");
+}
+
+#[test]
+fn test_authenticate_store() {
+    let credentials = Credentials::new(|action| {
+        assert!(matches!(action, Action::Store(_)), "store");
+        Ok(None)
+    });
+
+    assert!(
+        matches!(credentials.authenticate(Action::Store("".into())), Ok(None)),
+        "stored"
+    );
+    assert!(!credentials.declined(), "not declined");
+}
+
+#[test]
+fn test_context() {
+    let credentials = Credentials::new(|_| Ok(None));
+    let url = "https://example.com/repo.git";
+
+    assert_eq!(
+        credentials.context("failed to list refs from", url, Protocol::V2),
+        "failed to list refs from https://example.com/repo.git using protocol V2",
+        "without credentials"
+    );
+
+    let _ = credentials.authenticate(get());
+
+    assert_eq!(
+        credentials.context("failed to list refs from", url, Protocol::V2),
+        "failed to list refs from https://example.com/repo.git using protocol V2: the remote requires credentials, but none were available from Git's credential helpers",
+        "with credentials"
+    );
+}
@@ -1,2 +1,5 @@
 pub mod config;
+pub mod credentials;
+pub mod protocol;
+pub mod repository;
 pub mod service;
@@ -0,0 +1,284 @@
+use git::protocol::transport::client::Capabilities;
+use git::protocol::transport::{packetline, Protocol};
+use git_repository as git;
+use log::trace;
+use maybe_async::maybe_async;
+
+#[cfg(test)]
+mod tests;
+
+pub(crate) const GIT_PROTOCOL: &str = "GIT_PROTOCOL";
+
+/// What Git asked for via `GIT_PROTOCOL`, which is a colon-separated list of
+/// `key` or `key=value` parameters such as `version=2`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GitProtocol {
+    pub version: Protocol,
+    /// Parameters other than `version`, to pass on in the handshake.
+    pub extra_parameters: Vec<(String, Option<String>)>,
+}
+
+impl Default for GitProtocol {
+    fn default() -> Self {
+        Self {
+            version: Protocol::V2,
+            extra_parameters: Vec::new(),
+        }
+    }
+}
+
+/// The hash algorithm the remote advertises with `object-format`, in lower
+/// case. Remotes that don't advertise one use SHA-1.
+pub fn object_format(capabilities: &Capabilities) -> String {
+    capabilities
+        .capability("object-format")
+        .and_then(|capability| capability.value().map(|value| value.to_string()))
+        .unwrap_or_else(|| "sha1".to_string())
+        .to_ascii_lowercase()
+}
+
+/// What Git asked for via `GIT_PROTOCOL`, or protocol v2 if it's not set.
+pub fn from_env() -> anyhow::Result<GitProtocol> {
+    let git_protocol = from_value(std::env::var(GIT_PROTOCOL).ok().as_deref())?;
+    trace!("{}: {:#?}", GIT_PROTOCOL, git_protocol);
+    Ok(git_protocol)
+}
+
+/// What Git asked for with `value` as `GIT_PROTOCOL`, or protocol v2 if
+/// there's no value.
+fn from_value(value: Option<&str>) -> anyhow::Result<GitProtocol> {
+    match value {
+        Some(value) => parse(value),
+        None => Ok(GitProtocol::default()),
+    }
+}
+
+/// Parses the value of `GIT_PROTOCOL`, failing on a version other than 0, 1,
+/// or 2 rather than guessing at what a newer Git wants.
+pub fn parse(value: &str) -> anyhow::Result<GitProtocol> {
+    let mut git_protocol = GitProtocol::default();
+
+    for parameter in value.split(':').filter(|parameter| !parameter.is_empty()) {
+        match parameter.split_once('=') {
+            Some(("version", version)) => {
+                git_protocol.version = match version {
+                    // Version 0 is the same as version 1 without the
+                    // `version 1` line, which servers tolerate.
+                    "0" | "1" => Protocol::V1,
+                    "2" => Protocol::V2,
+                    _ => anyhow::bail!(
+                        "unsupported protocol version `{}` in {}, expected 0, 1, or 2",
+                        version,
+                        GIT_PROTOCOL
+                    ),
+                }
+            }
+            Some((key, value)) => git_protocol
+                .extra_parameters
+                .push((key.to_string(), Some(value.to_string()))),
+            None => git_protocol
+                .extra_parameters
+                .push((parameter.to_string(), None)),
+        }
+    }
+
+    Ok(git_protocol)
+}
+
+/// A connection to the remote, along with the capabilities from its
+/// handshake once that's been done with protocol v2.
+///
+/// A protocol v2 handshake only advertises capabilities, so later commands
+/// over the same connection skip it, which saves a round-trip to the remote.
+/// A protocol v1 handshake advertises refs as well, so it's done every time.
+pub struct Connection<T> {
+    pub transport: T,
+    pub capabilities: Option<Capabilities>,
+}
+
+impl<T> Connection<T> {
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            capabilities: None,
+        }
+    }
+
+    /// Whether later commands can reuse this connection without another
+    /// handshake.
+    pub fn is_reusable(&self) -> bool {
+        self.capabilities.is_some()
+    }
+}
+
+/// The protocol version and capabilities the remote responds with, from the
+/// handshake over `connection` unless one was already done. The refs are
+/// only advertised in the handshake with protocol v1.
+#[maybe_async]
+pub async fn handshake<AuthFn, T>(
+    connection: &mut Connection<T>,
+    authenticate: AuthFn,
+    extra_parameters: &[(String, Option<String>)],
+) -> anyhow::Result<(
+    Protocol,
+    Capabilities,
+    Option<Vec<git::protocol::handshake::Ref>>,
+)>
+where
+    AuthFn: FnMut(git::credentials::helper::Action) -> git::credentials::protocol::Result,
+    T: git::protocol::transport::client::Transport,
+{
+    if let Some(capabilities) = &connection.capabilities {
+        trace!("reusing the handshake");
+        return Ok((Protocol::V2, capabilities.clone(), None));
+    }
+
+    let mut progress = git::progress::Discard;
+
+    let outcome = git::protocol::fetch::handshake(
+        &mut connection.transport,
+        authenticate,
+        extra_parameters.to_vec(),
+        &mut progress,
+    )
+    .await?;
+
+    trace!(
+        "server protocol version: {:?}",
+        outcome.server_protocol_version
+    );
+    crate::event::log(
+        "handshake",
+        &[("version", &format!("{:?}", outcome.server_protocol_version))],
+    );
+
+    if outcome.server_protocol_version == Protocol::V2 {
+        connection.capabilities = Some(outcome.capabilities.clone());
+    }
+
+    Ok((
+        outcome.server_protocol_version,
+        outcome.capabilities,
+        outcome.refs,
+    ))
+}
+
+/// The protocol version the remote responds with, found with a handshake
+/// and nothing else.
+#[maybe_async]
+pub async fn probe<AuthFn, T>(
+    connection: &mut Connection<T>,
+    authenticate: AuthFn,
+    extra_parameters: &[(String, Option<String>)],
+) -> anyhow::Result<Protocol>
+where
+    AuthFn: FnMut(git::credentials::helper::Action) -> git::credentials::protocol::Result,
+    T: git::protocol::transport::client::Transport,
+{
+    let (version, _capabilities, _refs) =
+        handshake(connection, authenticate, extra_parameters).await?;
+    Ok(version)
+}
+
+/// The version to retry with when a request made with `version` failed with
+/// `err`. Servers that predate protocol v2 are expected to ignore `version=2`,
+/// but some fail the request with an error about the version instead.
+pub fn fallback(version: Protocol, err: &anyhow::Error) -> Option<Protocol> {
+    if version != Protocol::V2 {
+        return None;
+    }
+
+    let rejects_version = err.chain().any(|cause| {
+        let message = cause.to_string().to_ascii_lowercase();
+        message.contains("version")
+            && ["unsupported", "unknown", "not supported", "unrecognized"]
+                .iter()
+                .any(|rejection| message.contains(rejection))
+    });
+
+    if rejects_version {
+        Some(Protocol::V1)
+    } else {
+        None
+    }
+}
+
+/// The remote aborted with an `ERR` packet line, which is reported in place
+/// of the error it caused.
+#[derive(Debug)]
+pub struct RemoteError {
+    pub message: String,
+    source: anyhow::Error,
+}
+
+impl std::fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the remote responded with an error: {}", self.message)
+    }
+}
+
+impl std::error::Error for RemoteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+/// The message of the `ERR` packet line the remote aborted with, if that's
+/// what caused `err`.
+pub fn remote_error(err: &anyhow::Error) -> Option<String> {
+    err.chain().find_map(|cause| {
+        err_packet_message(cause).or_else(|| err_line_message(&cause.to_string()))
+    })
+}
+
+/// The message of an `ERR` packet line that failed reading it, which is how
+/// packet lines are read when they're told to look out for these.
+fn err_packet_message(cause: &(dyn std::error::Error + 'static)) -> Option<String> {
+    cause
+        .downcast_ref::<std::io::Error>()
+        .and_then(std::io::Error::get_ref)
+        .and_then(|err| err.downcast_ref::<packetline::read::Error>())
+        .map(|err| err.message.to_string())
+}
+
+/// The message of the `ERR` line that `message` starts with or quotes, which
+/// is how gitoxide reports a line it couldn't parse when it isn't expecting
+/// one, such as in place of refs.
+fn err_line_message(message: &str) -> Option<String> {
+    let start = message.find("ERR ")?;
+
+    let quote = match message[..start].chars().next_back() {
+        Some(quote @ ('"' | '\'')) => Some(quote),
+        Some(c) if c.is_whitespace() => None,
+        None => None,
+        Some(_) => return None,
+    };
+
+    let rest = &message[start..];
+    let end = rest
+        .find(|c: char| Some(c) == quote || c == '\n')
+        .unwrap_or(rest.len());
+    let line = rest[..end].trim_end_matches("\\n");
+
+    let err = packetline::PacketLineRef::Data(line.as_bytes()).check_error()?;
+    let text = String::from_utf8_lossy(err.0).trim().to_string();
+
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+/// `err`, or the remote's reason for failing in its place if it aborted with
+/// an `ERR` packet line, so that Git shows that rather than a parse error.
+/// `err` is kept as the source.
+pub fn surface_remote_error(err: anyhow::Error) -> anyhow::Error {
+    match remote_error(&err) {
+        Some(message) => anyhow::Error::new(RemoteError {
+            message,
+            source: err,
+        }),
+        None => err,
+    }
+}
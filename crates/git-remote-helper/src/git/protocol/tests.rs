@@ -0,0 +1,186 @@
+use super::*;
+
+#[test]
+fn test_parse_version_2() {
+    assert_eq!(
+        parse("version=2").expect("valid GIT_PROTOCOL"),
+        GitProtocol {
+            version: Protocol::V2,
+            extra_parameters: Vec::new(),
+        }
+    )
+}
+
+#[test]
+fn test_parse_version_1() {
+    assert_eq!(
+        parse("version=1").expect("valid GIT_PROTOCOL").version,
+        Protocol::V1
+    )
+}
+
+#[test]
+fn test_parse_version_0() {
+    assert_eq!(
+        parse("version=0").expect("valid GIT_PROTOCOL").version,
+        Protocol::V1
+    )
+}
+
+#[test]
+fn test_parse_unsupported_version() {
+    assert_eq!(
+        parse("version=3")
+            .expect_err("version to be rejected")
+            .to_string(),
+        "unsupported protocol version `3` in GIT_PROTOCOL, expected 0, 1, or 2"
+    )
+}
+
+#[test]
+fn test_parse_empty() {
+    assert_eq!(
+        parse("").expect("valid GIT_PROTOCOL"),
+        GitProtocol::default()
+    )
+}
+
+#[test]
+fn test_parse_extra_parameters() {
+    assert_eq!(
+        parse("version=2:foo=bar:baz").expect("valid GIT_PROTOCOL"),
+        GitProtocol {
+            version: Protocol::V2,
+            extra_parameters: vec![
+                ("foo".to_string(), Some("bar".to_string())),
+                ("baz".to_string(), None),
+            ],
+        }
+    )
+}
+
+#[test]
+fn test_from_value() {
+    assert_eq!(
+        from_value(Some("version=2:foo=bar")).expect("valid GIT_PROTOCOL"),
+        GitProtocol {
+            version: Protocol::V2,
+            extra_parameters: vec![("foo".to_string(), Some("bar".to_string()))],
+        },
+        "set"
+    );
+    assert_eq!(
+        from_value(None).expect("valid GIT_PROTOCOL"),
+        GitProtocol::default(),
+        "unset"
+    );
+}
+
+#[test]
+fn test_fallback_on_v2_rejection() {
+    let err = anyhow::Error::new(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "ERR unknown protocol version 2",
+    ))
+    .context("failed to list refs");
+
+    assert_eq!(
+        fallback(Protocol::V2, &err),
+        Some(Protocol::V1),
+        "retries with v1"
+    );
+    assert_eq!(fallback(Protocol::V1, &err), None, "already v1");
+}
+
+#[test]
+fn test_fallback_on_other_errors() {
+    let err = anyhow::anyhow!("Received HTTP status 404");
+
+    assert_eq!(fallback(Protocol::V2, &err), None, "not a v2 rejection")
+}
+
+#[test]
+fn test_remote_error() {
+    let err = anyhow::Error::new(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "ERR repository not found",
+    ))
+    .context("failed to list refs");
+    assert_eq!(
+        remote_error(&err).as_deref(),
+        Some("repository not found"),
+        "unparsed line"
+    );
+
+    let err = anyhow::anyhow!(
+        "\"ERR access denied\\n\" could not be parsed. A V2 ref line should be '<hex> <path>[ (peeled|symref-target):<value>'."
+    );
+    assert_eq!(
+        remote_error(&err).as_deref(),
+        Some("access denied"),
+        "quoted line"
+    );
+
+    let err = anyhow::anyhow!("'ERR read only' is not a valid ref line");
+    assert_eq!(
+        remote_error(&err).as_deref(),
+        Some("read only"),
+        "single-quoted line"
+    );
+
+    let err = anyhow::Error::new(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        packetline::read::Error {
+            message: "quota exceeded".into(),
+        },
+    ))
+    .context("failed to fetch");
+    assert_eq!(
+        remote_error(&err).as_deref(),
+        Some("quota exceeded"),
+        "packet line error"
+    );
+}
+
+#[test]
+fn test_remote_error_other_errors() {
+    assert_eq!(
+        remote_error(&anyhow::anyhow!("Received HTTP status 404")),
+        None,
+        "no ERR line"
+    );
+    assert_eq!(
+        remote_error(&anyhow::anyhow!("ref refs/heads/TERR x is invalid")),
+        None,
+        "not at the start of a line"
+    );
+    assert_eq!(remote_error(&anyhow::anyhow!("ERR ")), None, "no message");
+}
+
+#[test]
+fn test_surface_remote_error() {
+    let err = anyhow::anyhow!("ERR repository not found").context("failed to parse refs");
+    assert_eq!(
+        surface_remote_error(err).to_string(),
+        "the remote responded with an error: repository not found",
+        "remote error"
+    );
+
+    let err = anyhow::anyhow!("ERR repository not found").context("failed to parse refs");
+    assert_eq!(
+        surface_remote_error(err)
+            .chain()
+            .nth(1)
+            .map(ToString::to_string)
+            .as_deref(),
+        Some("failed to parse refs"),
+        "source kept"
+    );
+
+    let err = anyhow::anyhow!("Received HTTP status 404");
+    assert_eq!(
+        surface_remote_error(err).to_string(),
+        "Received HTTP status 404",
+        "other error"
+    );
+}
@@ -0,0 +1,45 @@
+use anyhow::Context as _;
+use git_repository as git;
+use log::trace;
+use std::path::Path;
+
+#[cfg(test)]
+mod tests;
+
+/// Opens the repository that `GIT_DIR` refers to.
+///
+/// `GIT_DIR` is the `.git` directory of a repository with a work tree, the
+/// root of a bare repository, or `.git/worktrees/<name>` for a linked
+/// worktree. In the last case refs specific to the worktree (such as `HEAD`)
+/// are read from `GIT_DIR` while shared refs and the object store are found
+/// through its `commondir` file.
+pub fn open(git_dir: impl AsRef<Path>) -> anyhow::Result<git::Repository> {
+    let git_dir = git_dir.as_ref();
+
+    let repo = git::open(git_dir)
+        .with_context(|| format!("failed to open repository at {}", git_dir.display()))?;
+
+    trace!("git_dir: {}", repo.git_dir().display());
+    trace!("common_dir: {}", repo.common_dir().display());
+
+    Ok(repo)
+}
+
+/// Finds the repository containing `dir`, for when the helper is run directly
+/// rather than by Git, which always sets `GIT_DIR`.
+pub fn discover(dir: impl AsRef<Path>) -> anyhow::Result<git::Repository> {
+    let dir = dir.as_ref();
+
+    let repo = git::discover(dir).with_context(|| {
+        format!(
+            "GIT_DIR is not set and {} is not in a repository; \
+             this is a Git remote helper, which Git runs with GIT_DIR set",
+            dir.display()
+        )
+    })?;
+
+    trace!("git_dir: {}", repo.git_dir().display());
+    trace!("common_dir: {}", repo.common_dir().display());
+
+    Ok(repo)
+}
@@ -0,0 +1,195 @@
+use super::*;
+use crate::test_support::git;
+use std::path::PathBuf;
+
+fn canonicalize(path: impl AsRef<Path>) -> PathBuf {
+    path.as_ref().canonicalize().expect("path to exist")
+}
+
+/// Creates `main` with a single commit and a linked worktree `linked`,
+/// returning the `GIT_DIR` for the linked worktree.
+fn linked_worktree(dir: &Path) -> PathBuf {
+    git(dir, ["init", "main"]);
+    let main = dir.join("main");
+    git(&main, ["commit", "--allow-empty", "-m", "Initial commit"]);
+    git(&main, ["worktree", "add", "../linked"]);
+    main.join(".git").join("worktrees").join("linked")
+}
+
+#[test]
+fn test_open_linked_worktree() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let git_dir = linked_worktree(dir.path());
+
+    let repo = open(&git_dir).expect("repository to open");
+
+    assert_eq!(
+        canonicalize(repo.git_dir()),
+        canonicalize(&git_dir),
+        "git dir"
+    );
+    assert_eq!(
+        canonicalize(repo.common_dir()),
+        canonicalize(dir.path().join("main").join(".git")),
+        "common dir"
+    );
+
+    let head_id = repo.head_id().expect("HEAD to resolve");
+    assert!(
+        repo.find_object(head_id).is_ok(),
+        "HEAD should be found in the shared object store"
+    );
+}
+
+#[test]
+fn test_open_linked_worktree_work_dir() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let git_dir = linked_worktree(dir.path());
+
+    let repo = open(&git_dir).expect("repository to open");
+
+    assert_eq!(
+        repo.work_dir().map(canonicalize),
+        Some(canonicalize(dir.path().join("linked"))),
+        "work dir"
+    );
+}
+
+#[test]
+fn test_open_bare() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    git(dir.path(), ["init", "--bare", "bare.git"]);
+    let git_dir = dir.path().join("bare.git");
+
+    let repo = open(&git_dir).expect("repository to open");
+
+    assert!(repo.is_bare(), "bare");
+    assert_eq!(
+        canonicalize(repo.git_dir()),
+        canonicalize(&git_dir),
+        "git dir"
+    );
+    assert_eq!(repo.work_dir(), None, "work dir");
+}
+
+#[test]
+fn test_open_work_tree() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    git(dir.path(), ["init", "repo"]);
+    let git_dir = dir.path().join("repo").join(".git");
+
+    let repo = open(&git_dir).expect("repository to open");
+
+    assert_eq!(
+        canonicalize(repo.git_dir()),
+        canonicalize(&git_dir),
+        "git dir"
+    );
+    assert_eq!(
+        repo.work_dir().map(canonicalize),
+        Some(canonicalize(dir.path().join("repo"))),
+        "work dir"
+    );
+}
+
+#[test]
+fn test_open_missing() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let git_dir = dir.path().join("missing");
+
+    assert!(
+        open(&git_dir)
+            .expect_err("repository not to open")
+            .to_string()
+            .starts_with("failed to open repository at"),
+        "error"
+    );
+}
+
+#[test]
+fn test_discover_from_subdirectory() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    git(dir.path(), ["init", "repo"]);
+    let subdir = dir.path().join("repo").join("src");
+    std::fs::create_dir(&subdir).expect("subdir to be created");
+
+    let repo = discover(&subdir).expect("repository to be found");
+
+    assert_eq!(
+        canonicalize(repo.git_dir()),
+        canonicalize(dir.path().join("repo").join(".git")),
+        "git dir"
+    );
+}
+
+#[test]
+fn test_discover_outside_repository() {
+    let dir = tempfile::tempdir().expect("tempdir");
+
+    // Discovery continues into parent directories, which could be a
+    // repository when the temporary directory is inside one.
+    if let Ok(repo) = discover(dir.path()) {
+        eprintln!("skipping, {} is in a repository", repo.git_dir().display());
+        return;
+    }
+
+    assert!(
+        discover(dir.path())
+            .expect_err("repository not to be found")
+            .to_string()
+            .contains("this is a Git remote helper, which Git runs with GIT_DIR set"),
+        "error"
+    );
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_fetch_into_linked_worktree() {
+    use crate::commands;
+    use git::protocol::transport;
+    use std::collections::BTreeSet;
+
+    let dir = tempfile::tempdir().expect("tempdir");
+    let git_dir = linked_worktree(dir.path());
+
+    git(dir.path(), ["init", "--bare", "remote.git"]);
+    git(dir.path(), ["clone", "remote.git", "remote"]);
+    let remote = dir.path().join("remote");
+    git(&remote, ["commit", "--allow-empty", "-m", "Remote commit"]);
+    git(&remote, ["push", "origin", "main"]);
+    let hash = git(&remote, ["rev-parse", "HEAD"]);
+    let id = git::hash::ObjectId::from_hex(hash.as_bytes()).expect("valid hash");
+
+    let url = format!("file://{}", dir.path().join("remote.git").display());
+    let repo = open(&git_dir).expect("repository to open");
+
+    let transport = transport::connect::<_, std::convert::Infallible>(
+        url.as_str(),
+        transport::client::connect::Options {
+            version: transport::Protocol::V2,
+            ssh: Default::default(),
+        },
+    )
+    .expect("transport to connect");
+
+    let mut batch: commands::fetch::Batch = BTreeSet::from([(id, "refs/heads/main".to_string())]);
+
+    commands::fetch::process(
+        crate::git::protocol::Connection::new(transport),
+        &repo,
+        &url,
+        |_| Ok(None),
+        &commands::option::Options::default(),
+        &[],
+        &git::interrupt::IS_INTERRUPTED,
+        None,
+        &mut batch,
+        &mut std::io::sink(),
+    )
+    .expect("fetch to succeed");
+
+    assert!(
+        repo.find_object(id).is_ok(),
+        "fetched commit should be in the shared object store"
+    );
+}
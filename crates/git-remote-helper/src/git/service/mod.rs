@@ -1 +1,2 @@
 pub mod receive_pack;
+pub mod upload_pack;
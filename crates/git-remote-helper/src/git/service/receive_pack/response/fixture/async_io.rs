@@ -1,7 +1,6 @@
 use super::Fixture;
 use async_trait::async_trait;
 use core::pin::Pin;
-use git::bstr::{BStr, ByteSlice};
 use git::protocol::transport::packetline;
 use git_repository as git;
 
@@ -40,10 +39,6 @@ impl<'a> git::protocol::transport::client::ReadlineBufRead for Fixture<'a> {
         &mut self,
     ) -> Option<std::io::Result<Result<packetline::PacketLineRef<'_>, packetline::decode::Error>>>
     {
-        let bytes: &BStr = self.0.into();
-        let mut lines = bytes.lines();
-        let res = lines.next()?;
-        self.0 = lines.as_bytes();
-        Some(Ok(Ok(packetline::PacketLineRef::Data(res))))
+        self.next_line()
     }
 }
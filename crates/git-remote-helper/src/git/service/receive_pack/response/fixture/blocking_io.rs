@@ -1,6 +1,5 @@
 use super::Fixture;
 use git_repository as git;
-use git::bstr::{BStr, ByteSlice};
 use git::protocol::transport::packetline;
 
 impl<'a> std::io::Read for Fixture<'a> {
@@ -25,10 +24,6 @@ impl<'a> git::protocol::transport::client::ReadlineBufRead for Fixture<'a> {
     ) -> Option<
         std::io::Result<Result<packetline::PacketLineRef<'_>, packetline::decode::Error>>,
     > {
-        let bytes: &BStr = self.0.into();
-        let mut lines = bytes.lines();
-        let res = lines.next()?;
-        self.0 = lines.as_bytes();
-        Some(Ok(Ok(packetline::PacketLineRef::Data(res))))
+        self.next_line()
     }
 }
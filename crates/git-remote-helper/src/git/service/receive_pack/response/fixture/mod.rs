@@ -0,0 +1,61 @@
+#[cfg(feature = "async-network-client")]
+mod async_io;
+
+#[cfg(feature = "blocking-network-client")]
+mod blocking_io;
+
+use git::protocol::transport::packetline;
+use git_repository as git;
+
+/// Packet-line framed bytes, read either as bytes or a packet line at a
+/// time. Flush packets are returned as lines, and reading ends once every
+/// byte has been read.
+pub struct Fixture<'a>(pub &'a [u8]);
+
+/// Stands in for a flush packet in the lines given to `pkt_lines`.
+pub const FLUSH: &str = "0000";
+
+/// Frames each of `lines` as a packet line, the way a server sends them.
+pub fn pkt_lines(lines: &[&str]) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    for line in lines {
+        if *line != FLUSH {
+            out.extend_from_slice(format!("{:04x}", line.len() + 4).as_bytes());
+        }
+        out.extend_from_slice(line.as_bytes());
+    }
+
+    out
+}
+
+impl<'a> Fixture<'a> {
+    fn next_line(
+        &mut self,
+    ) -> Option<std::io::Result<Result<packetline::PacketLineRef<'a>, packetline::decode::Error>>>
+    {
+        let data = self.0;
+
+        if data.is_empty() {
+            return None;
+        }
+
+        match packetline::decode::streaming(data) {
+            Ok(packetline::decode::Stream::Complete {
+                line,
+                bytes_consumed,
+            }) => {
+                self.0 = &data[bytes_consumed..];
+                Some(Ok(Ok(line)))
+            }
+            Ok(packetline::decode::Stream::Incomplete { .. }) => {
+                self.0 = &[];
+                Some(Err(std::io::ErrorKind::UnexpectedEof.into()))
+            }
+            Err(err) => {
+                self.0 = &[];
+                Some(Ok(Err(err)))
+            }
+        }
+    }
+}
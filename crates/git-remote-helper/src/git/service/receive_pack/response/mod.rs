@@ -1,4 +1,111 @@
+pub mod report_status;
 pub mod report_status_v2;
 
-pub use report_status_v2::read_and_parse;
-pub use report_status_v2::CommandStatusV2;
+#[cfg(test)]
+mod fixture;
+
+#[cfg(test)]
+mod tests;
+
+pub use report_status_v2::{
+    CommandStatusV2, ErrorMsg, OptionLine, ParseError, RefName, ReportStatusV2, UnpackResult,
+};
+
+use git::protocol::transport::client::{Capabilities, ReadlineBufRead};
+use git::protocol::transport::packetline;
+use git_repository as git;
+use maybe_async::maybe_async;
+
+/// The version of the status report sent by `receive-pack`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ReportStatusVersion {
+    V1,
+    V2,
+}
+
+impl ReportStatusVersion {
+    /// Prefers `report-status-v2`, falling back to `report-status` when the
+    /// server doesn't advertise it.
+    pub fn negotiate(capabilities: &Capabilities) -> Self {
+        if capabilities.contains(Self::V2.capability()) {
+            Self::V2
+        } else {
+            Self::V1
+        }
+    }
+
+    /// The capability to request this version of the status report with.
+    pub fn capability(&self) -> &'static str {
+        match self {
+            Self::V1 => "report-status",
+            Self::V2 => "report-status-v2",
+        }
+    }
+}
+
+/// Reads and parses a status report of the given version, representing a
+/// `report-status` as a `report-status-v2` without any `option-line`s.
+#[maybe_async]
+pub async fn read_and_parse<'a, T>(
+    reader: T,
+    version: ReportStatusVersion,
+) -> Result<ReportStatusV2, ParseError>
+where
+    T: ReadlineBufRead + Unpin + 'a,
+{
+    match version {
+        ReportStatusVersion::V1 => report_status::read_and_parse(reader)
+            .await
+            .map(report_status::into_v2),
+        ReportStatusVersion::V2 => report_status_v2::read_and_parse(reader).await,
+    }
+}
+
+/// Reads and parses a status report sent over `side-band-64k`.
+///
+/// The status report is carried in band 1, while progress (band 2) and error
+/// messages (band 3) are passed to `progress` with a flag indicating whether
+/// the message is an error.
+#[maybe_async]
+pub async fn read_and_parse_with_sidebands<'a, T>(
+    mut reader: T,
+    version: ReportStatusVersion,
+    mut progress: impl FnMut(bool, &[u8]),
+) -> Result<ReportStatusV2, ParseError>
+where
+    T: ReadlineBufRead + Unpin + 'a,
+{
+    let mut data = Vec::new();
+
+    while let Some(outcome) = reader.readline().await {
+        if report_status_v2::is_flush(&outcome) {
+            break;
+        }
+
+        let line = report_status_v2::as_slice(outcome)?;
+
+        match line.split_first() {
+            Some((1, rest)) => data.extend_from_slice(rest),
+            Some((2, rest)) => progress(false, rest),
+            Some((3, rest)) => progress(true, rest),
+            Some((band, _)) => return Err(ParseError::UnexpectedSideband(*band)),
+            // A keep-alive
+            None => (),
+        }
+    }
+
+    let mut streaming_peekable_iter = packetline::StreamingPeekableIter::new(
+        data.as_slice(),
+        &[packetline::PacketLineRef::Flush],
+    );
+
+    streaming_peekable_iter.fail_on_err_lines(true);
+    let mut reader = streaming_peekable_iter.as_read();
+
+    match version {
+        ReportStatusVersion::V1 => report_status::parse(&mut reader)
+            .await
+            .map(report_status::into_v2),
+        ReportStatusVersion::V2 => report_status_v2::parse(&mut reader).await,
+    }
+}
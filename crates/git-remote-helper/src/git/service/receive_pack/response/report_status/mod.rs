@@ -0,0 +1,116 @@
+use super::report_status_v2::{
+    as_slice, is_flush, parse_command_fail, parse_command_ok, parse_unpack_status, parse_with,
+    read_data_line_and_parse_with, CommandStatusV2, ErrorMsg, ParseError, RefName, ReportStatusV2,
+    UnpackResult,
+};
+use git::protocol::transport::client::ReadlineBufRead;
+use git_repository as git;
+use maybe_async::maybe_async;
+use nom::branch::alt;
+use nom::error::context;
+use nom::IResult;
+
+#[cfg(test)]
+mod tests;
+
+pub type ReportStatus = (UnpackResult, Vec<CommandStatus>);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CommandStatus {
+    Ok(RefName),
+    Fail(RefName, ErrorMsg),
+}
+
+impl From<CommandStatus> for CommandStatusV2 {
+    fn from(command_status: CommandStatus) -> Self {
+        match command_status {
+            CommandStatus::Ok(ref_name) => CommandStatusV2::Ok(ref_name, Vec::new()),
+            CommandStatus::Fail(ref_name, error_msg) => CommandStatusV2::Fail(ref_name, error_msg),
+        }
+    }
+}
+
+/// Converts a `report-status` into the equivalent `report-status-v2`, which
+/// is the same without any `option-line`s.
+pub fn into_v2((unpack_result, command_statuses): ReportStatus) -> ReportStatusV2 {
+    (
+        unpack_result,
+        command_statuses.into_iter().map(Into::into).collect(),
+    )
+}
+
+#[maybe_async]
+pub async fn read_and_parse<'a, T>(reader: T) -> Result<ReportStatus, ParseError>
+where
+    T: ReadlineBufRead + Unpin + 'a,
+{
+    let mut streaming_peekable_iter =
+        git::protocol::transport::packetline::StreamingPeekableIter::new(
+            reader,
+            &[git::protocol::transport::packetline::PacketLineRef::Flush],
+        );
+
+    streaming_peekable_iter.fail_on_err_lines(true);
+    let mut reader = streaming_peekable_iter.as_read();
+
+    parse(&mut reader).await
+}
+
+/// Parses a `report-status` from a reader that is already positioned at the
+/// `unpack-status` line.
+#[maybe_async]
+pub(super) async fn parse<R>(reader: &mut R) -> Result<ReportStatus, ParseError>
+where
+    R: ReadlineBufRead,
+{
+    let unpack_result = read_data_line_and_parse_with::<_, nom::error::Error<_>>(
+        reader,
+        parse_unpack_status,
+        ParseError::FailedToReadUnpackStatus,
+    )
+    .await?;
+
+    let command_statuses = read_and_parse_command_statuses(reader).await?;
+
+    Ok((unpack_result, command_statuses))
+}
+
+#[maybe_async]
+async fn read_and_parse_command_statuses<'a>(
+    reader: &'a mut (dyn ReadlineBufRead + 'a),
+) -> Result<Vec<CommandStatus>, ParseError> {
+    let mut command_statuses: Vec<CommandStatus> = Vec::new();
+
+    // Unlike `report-status-v2` there are no `option-line`s, so each line is
+    // a complete `command-status`.
+    while let Some(outcome) = reader.readline().await {
+        if is_flush(&outcome) {
+            break;
+        }
+
+        let line = as_slice(outcome)?;
+        let command_status = parse_with(parse_command_status, line)?;
+        command_statuses.push(command_status);
+    }
+
+    if command_statuses.is_empty() {
+        Err(ParseError::ExpectedOneOrMoreCommandStatus)
+    } else {
+        Ok(command_statuses)
+    }
+}
+
+fn parse_command_status<'a, E>(input: &'a [u8]) -> IResult<&'a [u8], CommandStatus, E>
+where
+    E: nom::error::ParseError<&'a [u8]> + nom::error::ContextError<&'a [u8]>,
+{
+    context(
+        "command-status",
+        alt((
+            nom::combinator::map(parse_command_ok, CommandStatus::Ok),
+            nom::combinator::map(parse_command_fail, |(ref_name, error_msg)| {
+                CommandStatus::Fail(ref_name, error_msg)
+            }),
+        )),
+    )(input)
+}
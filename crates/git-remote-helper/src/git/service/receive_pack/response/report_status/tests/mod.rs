@@ -0,0 +1,257 @@
+use super::*;
+use crate::git::service::receive_pack::response::fixture::{pkt_lines, Fixture, FLUSH};
+use git::bstr::BString;
+
+#[maybe_async::test(
+    feature = "blocking-network-client",
+    async(feature = "async-network-client", tokio::test)
+)]
+async fn test_read_and_parse_ok_0_command_status() {
+    let mut input = vec!["000eunpack ok", "0000"].join("\n").into_bytes();
+    let reader = Fixture(&mut input);
+    let result = read_and_parse(reader).await;
+    assert_eq!(
+        result,
+        Err(ParseError::ExpectedOneOrMoreCommandStatus),
+        "report-status"
+    )
+}
+
+#[maybe_async::test(
+    feature = "blocking-network-client",
+    async(feature = "async-network-client", tokio::test)
+)]
+async fn test_read_and_parse_ok_1_command_status_ok() {
+    let mut input = vec!["000eunpack ok", "0017ok refs/heads/main", "0000"]
+        .join("\n")
+        .into_bytes();
+    let reader = Fixture(&mut input);
+    let result = read_and_parse(reader).await;
+    assert_eq!(
+        result,
+        Ok((
+            UnpackResult::Ok,
+            vec![CommandStatus::Ok(RefName(BString::new(
+                b"refs/heads/main".to_vec()
+            )))]
+        )),
+        "report-status"
+    )
+}
+
+#[maybe_async::test(
+    feature = "blocking-network-client",
+    async(feature = "async-network-client", tokio::test)
+)]
+async fn test_read_and_parse_ok_1_command_status_fail() {
+    let mut input = vec![
+        "000eunpack ok",
+        "002ang refs/heads/main some error message",
+        "0000",
+    ]
+    .join("\n")
+    .into_bytes();
+    let reader = Fixture(&mut input);
+    let result = read_and_parse(reader).await;
+    assert_eq!(
+        result,
+        Ok((
+            UnpackResult::Ok,
+            vec![CommandStatus::Fail(
+                RefName(BString::new(b"refs/heads/main".to_vec())),
+                ErrorMsg(BString::new(b"some error message\n".to_vec()))
+            )]
+        )),
+        "report-status"
+    )
+}
+
+#[maybe_async::test(
+    feature = "blocking-network-client",
+    async(feature = "async-network-client", tokio::test)
+)]
+async fn test_read_and_parse_ok_2_command_statuses_ok_fail() {
+    let mut input = vec![
+        "000eunpack ok",
+        "0018ok refs/heads/debug",
+        "0028ng refs/heads/main non-fast-forward",
+        "0000",
+    ]
+    .join("\n")
+    .into_bytes();
+    let reader = Fixture(&mut input);
+    let result = read_and_parse(reader).await;
+    assert_eq!(
+        result,
+        Ok((
+            UnpackResult::Ok,
+            vec![
+                CommandStatus::Ok(RefName(BString::new(b"refs/heads/debug".to_vec()))),
+                CommandStatus::Fail(
+                    RefName(BString::new(b"refs/heads/main".to_vec())),
+                    ErrorMsg(BString::new(b"non-fast-forward\n".to_vec()))
+                ),
+            ]
+        )),
+        "report-status"
+    )
+}
+
+#[maybe_async::test(
+    feature = "blocking-network-client",
+    async(feature = "async-network-client", tokio::test)
+)]
+async fn test_read_and_parse_ok_2_command_statuses_fail_ok() {
+    let mut input = vec![
+        "000eunpack ok",
+        "0028ng refs/heads/main non-fast-forward",
+        "0018ok refs/heads/debug",
+        "0000",
+    ]
+    .join("\n")
+    .into_bytes();
+    let reader = Fixture(&mut input);
+    let result = read_and_parse(reader).await;
+    assert_eq!(
+        result,
+        Ok((
+            UnpackResult::Ok,
+            vec![
+                CommandStatus::Fail(
+                    RefName(BString::new(b"refs/heads/main".to_vec())),
+                    ErrorMsg(BString::new(b"non-fast-forward\n".to_vec()))
+                ),
+                CommandStatus::Ok(RefName(BString::new(b"refs/heads/debug".to_vec()))),
+            ]
+        )),
+        "report-status"
+    )
+}
+
+#[maybe_async::test(
+    feature = "blocking-network-client",
+    async(feature = "async-network-client", tokio::test)
+)]
+async fn test_read_and_parse_error_msg_1_command_status_fail() {
+    let mut input = vec![
+        "0024unpack index-pack abnormal exit",
+        "002ang refs/heads/main some error message",
+        "0000",
+    ]
+    .join("\n")
+    .into_bytes();
+    let reader = Fixture(&mut input);
+    let result = read_and_parse(reader).await;
+    assert_eq!(
+        result,
+        Ok((
+            UnpackResult::ErrorMsg(ErrorMsg(BString::new(
+                b"index-pack abnormal exit\n".to_vec()
+            ))),
+            vec![CommandStatus::Fail(
+                RefName(BString::new(b"refs/heads/main".to_vec())),
+                ErrorMsg(BString::new(b"some error message\n".to_vec()))
+            )]
+        )),
+        "report-status"
+    )
+}
+
+#[maybe_async::test(
+    feature = "blocking-network-client",
+    async(feature = "async-network-client", tokio::test)
+)]
+async fn test_read_and_parse_command_statuses_ok_fail() {
+    let input = pkt_lines(&[
+        "ok refs/heads/debug\n",
+        "ng refs/heads/main non-fast-forward",
+    ]);
+    let mut reader = Fixture(&input);
+    let result = read_and_parse_command_statuses(&mut reader).await;
+    assert_eq!(
+        result,
+        Ok(vec![
+            CommandStatus::Ok(RefName(BString::new(b"refs/heads/debug".to_vec()))),
+            CommandStatus::Fail(
+                RefName(BString::new(b"refs/heads/main".to_vec())),
+                ErrorMsg(BString::new(b"non-fast-forward".to_vec()))
+            ),
+        ]),
+        "command-status"
+    )
+}
+
+#[maybe_async::test(
+    feature = "blocking-network-client",
+    async(feature = "async-network-client", tokio::test)
+)]
+async fn test_read_and_parse_command_statuses_flush_terminated() {
+    let input = pkt_lines(&["ok refs/heads/debug\n", FLUSH]);
+    let mut reader = Fixture(&input);
+    let result = read_and_parse_command_statuses(&mut reader).await;
+    assert_eq!(
+        result,
+        Ok(vec![CommandStatus::Ok(RefName(BString::new(
+            b"refs/heads/debug".to_vec()
+        )))]),
+        "command-status"
+    )
+}
+
+#[test]
+fn test_parse_command_status_ok() {
+    let input = b"ok refs/heads/main";
+    let result = parse_command_status::<nom::error::Error<_>>(input);
+    assert_eq!(
+        result.map(|x| x.1),
+        Ok(CommandStatus::Ok(RefName(BString::new(
+            b"refs/heads/main".to_vec()
+        )))),
+        "command-status"
+    )
+}
+
+#[test]
+fn test_parse_command_status_fail() {
+    let input = b"ng refs/heads/main some error message";
+    let result = parse_command_status::<nom::error::Error<_>>(input);
+    assert_eq!(
+        result.map(|x| x.1),
+        Ok(CommandStatus::Fail(
+            RefName(BString::new(b"refs/heads/main".to_vec())),
+            ErrorMsg(BString::new(b"some error message".to_vec()))
+        )),
+        "command-status"
+    )
+}
+
+#[test]
+fn test_into_v2() {
+    let report_status = (
+        UnpackResult::Ok,
+        vec![
+            CommandStatus::Ok(RefName(BString::new(b"refs/heads/debug".to_vec()))),
+            CommandStatus::Fail(
+                RefName(BString::new(b"refs/heads/main".to_vec())),
+                ErrorMsg(BString::new(b"non-fast-forward".to_vec())),
+            ),
+        ],
+    );
+    assert_eq!(
+        into_v2(report_status),
+        (
+            UnpackResult::Ok,
+            vec![
+                CommandStatusV2::Ok(
+                    RefName(BString::new(b"refs/heads/debug".to_vec())),
+                    Vec::new()
+                ),
+                CommandStatusV2::Fail(
+                    RefName(BString::new(b"refs/heads/main".to_vec())),
+                    ErrorMsg(BString::new(b"non-fast-forward".to_vec())),
+                ),
+            ]
+        ),
+        "report-status-v2"
+    )
+}
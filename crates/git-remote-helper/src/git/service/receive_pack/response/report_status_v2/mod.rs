@@ -1,5 +1,5 @@
 use derive_more::Display;
-use git::bstr::BString;
+use git::bstr::{BStr, BString, ByteSlice as _};
 use git::protocol::transport::client::ReadlineBufRead;
 use git::protocol::transport::packetline;
 use git_repository as git;
@@ -7,9 +7,12 @@ use maybe_async::maybe_async;
 use nom::branch::alt;
 use nom::bytes::complete::{tag, take_while1};
 use nom::character::complete::char;
+use nom::character::is_hex_digit;
 use nom::combinator::{eof, opt};
 use nom::error::context;
+use nom::sequence::preceded;
 use nom::IResult;
+use std::borrow::Cow;
 use std::cell::Cell;
 
 #[cfg(test)]
@@ -44,11 +47,38 @@ pub enum OptionLine {
     OptionForce,
 }
 
-#[derive(Clone, Debug, Display, Eq, PartialEq)]
-pub struct ErrorMsg(BString);
+/// A reason the remote gave for failing. Servers may send localized text
+/// that isn't UTF-8, so it's kept as sent and only converted for display.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ErrorMsg(pub(crate) BString);
+
+impl ErrorMsg {
+    /// The message as the remote sent it.
+    pub fn as_bstr(&self) -> &BStr {
+        self.0.as_ref()
+    }
+
+    /// The message with any bytes that aren't valid UTF-8 replaced, for
+    /// showing to the user.
+    pub fn to_str_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(self.as_bstr())
+    }
+}
+
+impl std::fmt::Display for ErrorMsg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.to_str_lossy())
+    }
+}
 
 #[derive(Clone, Debug, Display, Eq, PartialEq)]
-pub struct RefName(BString);
+pub struct RefName(pub(crate) BString);
+
+impl RefName {
+    pub fn as_bstr(&self) -> &BStr {
+        self.0.as_ref()
+    }
+}
 
 #[maybe_async]
 pub async fn read_and_parse<'a, T>(reader: T) -> Result<ReportStatusV2, ParseError>
@@ -64,20 +94,30 @@ where
     streaming_peekable_iter.fail_on_err_lines(true);
     let mut reader = streaming_peekable_iter.as_read();
 
+    parse(&mut reader).await
+}
+
+/// Parses a `report-status-v2` from a reader that is already positioned at
+/// the `unpack-status` line.
+#[maybe_async]
+pub(super) async fn parse<R>(reader: &mut R) -> Result<ReportStatusV2, ParseError>
+where
+    R: ReadlineBufRead,
+{
     let unpack_result = read_data_line_and_parse_with::<_, nom::error::Error<_>>(
-        &mut reader,
+        reader,
         parse_unpack_status,
         ParseError::FailedToReadUnpackStatus,
     )
     .await?;
 
     let command_statuses_v2 =
-        read_and_parse_command_statuses_v2::<nom::error::Error<_>>(&mut reader).await?;
+        read_and_parse_command_statuses_v2::<nom::error::Error<_>>(reader).await?;
 
     Ok((unpack_result, command_statuses_v2))
 }
 
-fn parse_unpack_status<'a, E>(input: &'a [u8]) -> IResult<&'a [u8], UnpackResult, E>
+pub(super) fn parse_unpack_status<'a, E>(input: &'a [u8]) -> IResult<&'a [u8], UnpackResult, E>
 where
     E: nom::error::ParseError<&'a [u8]> + nom::error::ContextError<&'a [u8]>,
 {
@@ -105,7 +145,7 @@ where
 }
 
 // TODO: send commit without tree to trigger error for test case
-fn parse_error_msg<'a, E>(input: &'a [u8]) -> IResult<&'a [u8], ErrorMsg, E>
+pub(super) fn parse_error_msg<'a, E>(input: &'a [u8]) -> IResult<&'a [u8], ErrorMsg, E>
 where
     E: nom::error::ParseError<&'a [u8]> + nom::error::ContextError<&'a [u8]>,
 {
@@ -135,6 +175,10 @@ where
     let mut command_statuses_v2: Vec<CommandStatusV2> = Vec::new();
 
     while let Some(outcome) = reader.readline().await {
+        if is_flush(&outcome) {
+            break;
+        }
+
         let line = as_slice(outcome)?;
         let command_status_v2_line = parse_with(parse_command_status_v2_line, line)?;
 
@@ -245,7 +289,7 @@ where
     )(input)
 }
 
-fn parse_command_ok<'a, E>(input: &'a [u8]) -> IResult<&'a [u8], RefName, E>
+pub(super) fn parse_command_ok<'a, E>(input: &'a [u8]) -> IResult<&'a [u8], RefName, E>
 where
     E: nom::error::ParseError<&'a [u8]> + nom::error::ContextError<&'a [u8]>,
 {
@@ -259,7 +303,9 @@ where
     })(input)
 }
 
-fn parse_command_fail<'a, E>(input: &'a [u8]) -> IResult<&'a [u8], (RefName, ErrorMsg), E>
+pub(super) fn parse_command_fail<'a, E>(
+    input: &'a [u8],
+) -> IResult<&'a [u8], (RefName, ErrorMsg), E>
 where
     E: nom::error::ParseError<&'a [u8]> + nom::error::ContextError<&'a [u8]>,
 {
@@ -276,11 +322,11 @@ where
 }
 
 // NOTE
-// * This parser is intentionally overly-permissive for now since we treat
-//   refnames as opaque values anyway.
+// * The byte filter only finds where the refname ends, which is then checked
+//   against the rest of the rules in `git check-ref-format`.
 // * `git_validate::refname` doesn't cover all of the validation cases
 //    described in documentation.
-fn parse_refname<'a, E>(input: &'a [u8]) -> IResult<&'a [u8], RefName, E>
+pub(super) fn parse_refname<'a, E>(input: &'a [u8]) -> IResult<&'a [u8], RefName, E>
 where
     E: nom::error::ParseError<&'a [u8]> + nom::error::ContextError<&'a [u8]>,
 {
@@ -290,7 +336,9 @@ where
                 0o040 <= chr
                     && !vec![0o177, b' ', b'~', b'^', b':', b'?', b'*', b'['].contains(&chr)
             }),
-            |refname: &[u8]| git_validate::refname(refname.into()).is_ok(),
+            |refname: &[u8]| {
+                git_validate::refname(refname.into()).is_ok() && is_valid_refname(refname)
+            },
         );
         nom::combinator::map(parser, |refname: &[u8]| {
             RefName(BString::new(refname.to_vec()))
@@ -298,28 +346,79 @@ where
     })(input)
 }
 
+/// Checks the rules from `git check-ref-format` that aren't about which
+/// bytes may appear.
+fn is_valid_refname(refname: &[u8]) -> bool {
+    refname != b"@"
+        && !refname.starts_with(b"/")
+        && !refname.ends_with(b"/")
+        && !refname.ends_with(b".")
+        && !refname.contains_str("..")
+        && !refname.contains_str("//")
+        && !refname.contains_str("@{")
+        && !refname.contains(&b'\\')
+        && refname
+            .split_str("/")
+            .all(|component| !component.starts_with(b".") && !component.ends_with(b".lock"))
+}
+
 fn parse_option_line<'a, E>(input: &'a [u8]) -> IResult<&'a [u8], OptionLine, E>
 where
     E: nom::error::ParseError<&'a [u8]> + nom::error::ContextError<&'a [u8]>,
 {
-    context("option-line", |_input| {
-        // TODO
-        todo!("option-line")
+    context("option-line", |input| {
+        let (next_input, _option) = tag(b"option")(input)?;
+        let (next_input, _space) = char(' ')(next_input)?;
+        let (next_input, option_line) = alt((
+            nom::combinator::map(
+                preceded(tag(b"refname "), parse_refname),
+                OptionLine::OptionRefName,
+            ),
+            nom::combinator::map(
+                preceded(tag(b"old-oid "), parse_obj_id),
+                OptionLine::OptionOldOid,
+            ),
+            nom::combinator::map(
+                preceded(tag(b"new-oid "), parse_obj_id),
+                OptionLine::OptionNewOid,
+            ),
+            nom::combinator::map(tag(b"forced-update"), |_| OptionLine::OptionForce),
+        ))(next_input)?;
+        let (next_input, _newline) = opt(char('\n'))(next_input)?;
+        let (next_input, _) = eof(next_input)?;
+        Ok((next_input, option_line))
     })(input)
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+fn parse_obj_id<'a, E>(input: &'a [u8]) -> IResult<&'a [u8], git::hash::ObjectId, E>
+where
+    E: nom::error::ParseError<&'a [u8]> + nom::error::ContextError<&'a [u8]>,
+{
+    context(
+        "obj-id",
+        nom::combinator::map_opt(take_while1(is_hex_digit), |hex: &[u8]| {
+            git::hash::ObjectId::from_hex(hex).ok()
+        }),
+    )(input)
+}
+
+#[derive(Debug)]
 pub enum ParseError {
     FailedToReadUnpackStatus,
-    Io(String),
+    Io(std::io::Error),
+    ExpectedOneOrMoreCommandStatus,
     ExpectedOneOrMoreCommandStatusV2,
-    Nom(String),
-    PacketLineDecode(String),
+    Nom(nom::Err<nom::error::Error<Vec<u8>>>),
+    PacketLineDecode(packetline::decode::Error),
     UnexpectedCommandFailLine,
     UnexpectedFlush,
     UnexpectedDelimiter,
+    /// The response ended before its flush packet, as when the connection is
+    /// closed early, rather than being complete but malformed.
+    UnexpectedEof,
     UnexpectedOptionLine,
     UnexpectedResponseEnd,
+    UnexpectedSideband(u8),
 }
 
 impl std::fmt::Display for ParseError {
@@ -327,6 +426,9 @@ impl std::fmt::Display for ParseError {
         let msg = match self {
             Self::FailedToReadUnpackStatus => "failed to read unpack status".to_string(),
             Self::Io(err) => format!("IO error: {}", err),
+            Self::ExpectedOneOrMoreCommandStatus => {
+                "expected one or more command status".to_string()
+            }
             Self::ExpectedOneOrMoreCommandStatusV2 => {
                 "expected one or more command status v2".to_string()
             }
@@ -335,17 +437,49 @@ impl std::fmt::Display for ParseError {
             Self::UnexpectedCommandFailLine => "unexpected command fail line".to_string(),
             Self::UnexpectedFlush => "unexpected flush packet".to_string(),
             Self::UnexpectedDelimiter => "unexpected delimiter".to_string(),
+            Self::UnexpectedEof => {
+                "the response ended early; the connection may have been closed".to_string()
+            }
             Self::UnexpectedOptionLine => "unexpected option line".to_string(),
             Self::UnexpectedResponseEnd => "unexpected response end".to_string(),
+            Self::UnexpectedSideband(band) => format!("unexpected sideband {}", band),
         };
         write!(f, "{}", msg)
     }
 }
 
-impl std::error::Error for ParseError {}
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Nom(err) => Some(err),
+            Self::PacketLineDecode(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+// IO and packet line errors can't be compared, so they're equal when their
+// kinds and messages are.
+impl PartialEq for ParseError {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Io(a), Self::Io(b)) => a.kind() == b.kind() && a.to_string() == b.to_string(),
+            (Self::Nom(a), Self::Nom(b)) => a == b,
+            (Self::PacketLineDecode(a), Self::PacketLineDecode(b)) => {
+                a.to_string() == b.to_string()
+            }
+            (Self::UnexpectedSideband(a), Self::UnexpectedSideband(b)) => a == b,
+            // Any other variants have no fields to compare.
+            (a, b) => std::mem::discriminant(a) == std::mem::discriminant(b),
+        }
+    }
+}
+
+impl Eq for ParseError {}
 
 #[maybe_async]
-async fn read_data_line_and_parse_with<'a, Ok, E>(
+pub(super) async fn read_data_line_and_parse_with<'a, Ok, E>(
     input: &'a mut (dyn ReadlineBufRead + 'a),
     parser: impl FnMut(&'a [u8]) -> IResult<&'a [u8], Ok>,
     read_err: ParseError,
@@ -357,13 +491,13 @@ where
     parse_with(parser, line)
 }
 
-fn parse_with<'a, Ok>(
+pub(super) fn parse_with<'a, Ok>(
     mut parser: impl FnMut(&'a [u8]) -> IResult<&'a [u8], Ok>,
     input: &'a [u8],
 ) -> Result<Ok, ParseError> {
     parser(input)
         .map(|x| x.1)
-        .map_err(|err| ParseError::Nom(err.to_string()))
+        .map_err(|err| ParseError::Nom(err.to_owned()))
 }
 
 #[maybe_async]
@@ -377,15 +511,28 @@ async fn read_data_line<'a>(
     }
 }
 
+/// Whether `readline_outcome` is the flush packet that ends a report, which
+/// readers that don't stop at a flush themselves return as a line.
+pub(crate) fn is_flush(
+    readline_outcome: &std::io::Result<
+        Result<packetline::PacketLineRef<'_>, packetline::decode::Error>,
+    >,
+) -> bool {
+    matches!(readline_outcome, Ok(Ok(packetline::PacketLineRef::Flush)))
+}
+
 // Similar to `as_slice()` on `packetline::PacketLineRef`
-fn as_slice(
+pub(super) fn as_slice(
     readline_outcome: std::io::Result<
         Result<packetline::PacketLineRef<'_>, packetline::decode::Error>,
     >,
 ) -> Result<&[u8], ParseError> {
     let packet_line_ref = readline_outcome
-        .map_err(|err| ParseError::Io(err.to_string()))?
-        .map_err(|err| ParseError::PacketLineDecode(err.to_string()))?;
+        .map_err(|err| match err.kind() {
+            std::io::ErrorKind::UnexpectedEof => ParseError::UnexpectedEof,
+            _ => ParseError::Io(err),
+        })?
+        .map_err(ParseError::PacketLineDecode)?;
 
     match packet_line_ref {
         packetline::PacketLineRef::Data(data) => Ok(data),
@@ -1,11 +1,13 @@
-mod fixture;
-
 use super::*;
-use fixture::Fixture;
+use crate::git::service::receive_pack::response::fixture::{pkt_lines, Fixture, FLUSH};
 use git::bstr::ByteSlice;
 use git_repository as git;
 use maybe_async::maybe_async;
 
+fn oid(hex: &str) -> git::hash::ObjectId {
+    git::hash::ObjectId::from_hex(hex.as_bytes()).expect("valid hash")
+}
+
 #[maybe_async::test(
     feature = "blocking-network-client",
     async(feature = "async-network-client", tokio::test)
@@ -210,8 +212,8 @@ fn test_parse_unpack_result_error_msg() {
     async(feature = "async-network-client", tokio::test)
 )]
 async fn test_read_and_parse_command_status_v2_command_ok_v2_0_option_lines() {
-    let input = b"ok refs/heads/main";
-    let mut reader = Fixture(input);
+    let input = pkt_lines(&["ok refs/heads/main"]);
+    let mut reader = Fixture(&input);
     let result = read_and_parse_command_statuses_v2::<nom::error::Error<_>>(&mut reader).await;
     assert_eq!(
         result,
@@ -228,8 +230,8 @@ async fn test_read_and_parse_command_status_v2_command_ok_v2_0_option_lines() {
     async(feature = "async-network-client", tokio::test)
 )]
 async fn test_read_and_parse_command_status_v2_command_ok_v2_0_option_lines_newline() {
-    let input = b"ok refs/heads/main\n";
-    let mut reader = Fixture(input);
+    let input = pkt_lines(&["ok refs/heads/main\n"]);
+    let mut reader = Fixture(&input);
     let result = read_and_parse_command_statuses_v2::<nom::error::Error<_>>(&mut reader).await;
     assert_eq!(
         result,
@@ -241,76 +243,302 @@ async fn test_read_and_parse_command_status_v2_command_ok_v2_0_option_lines_newl
     )
 }
 
-#[ignore]
+#[maybe_async::test(
+    feature = "blocking-network-client",
+    async(feature = "async-network-client", tokio::test)
+)]
+async fn test_read_and_parse_command_status_v2_flush_terminated() {
+    let input = pkt_lines(&[
+        "ok refs/heads/main\n",
+        "ng refs/heads/debug non-fast-forward\n",
+        FLUSH,
+    ]);
+    let mut reader = Fixture(&input);
+    let result = read_and_parse_command_statuses_v2::<nom::error::Error<_>>(&mut reader).await;
+    assert_eq!(
+        result,
+        Ok(vec![
+            CommandStatusV2::Ok(
+                RefName(BString::new(b"refs/heads/main".to_vec())),
+                Vec::new()
+            ),
+            CommandStatusV2::Fail(
+                RefName(BString::new(b"refs/heads/debug".to_vec())),
+                ErrorMsg(BString::new(b"non-fast-forward".to_vec()))
+            ),
+        ]),
+        "command-status-v2"
+    )
+}
+
+#[maybe_async::test(
+    feature = "blocking-network-client",
+    async(feature = "async-network-client", tokio::test)
+)]
+async fn test_read_and_parse_command_status_v2_flush_only() {
+    let input = pkt_lines(&[FLUSH, "ok refs/heads/main\n"]);
+    let mut reader = Fixture(&input);
+    let result = read_and_parse_command_statuses_v2::<nom::error::Error<_>>(&mut reader).await;
+    assert_eq!(
+        result,
+        Err(ParseError::ExpectedOneOrMoreCommandStatusV2),
+        "command-status-v2"
+    )
+}
+
+#[maybe_async::test(
+    feature = "blocking-network-client",
+    async(feature = "async-network-client", tokio::test)
+)]
+async fn test_parse_flush_before_unpack_status() {
+    let input = pkt_lines(&[FLUSH, "unpack ok\n", "ok refs/heads/main\n"]);
+    let mut reader = Fixture(&input);
+    let result = parse(&mut reader).await;
+    assert_eq!(result, Err(ParseError::UnexpectedFlush), "report-status-v2")
+}
+
 #[maybe_async::test(
     feature = "blocking-network-client",
     async(feature = "async-network-client", tokio::test)
 )]
 async fn test_read_and_parse_command_status_v2_command_ok_v2_1_option_lines() {
-    todo!()
+    let input = pkt_lines(&["ok refs/heads/main", "option refname refs/heads/main"]);
+    let mut reader = Fixture(&input);
+    let result = read_and_parse_command_statuses_v2::<nom::error::Error<_>>(&mut reader).await;
+    assert_eq!(
+        result,
+        Ok(vec![CommandStatusV2::Ok(
+            RefName(BString::new(b"refs/heads/main".to_vec())),
+            vec![OptionLine::OptionRefName(RefName(BString::new(
+                b"refs/heads/main".to_vec()
+            ))),],
+        )]),
+        "command-status-v2"
+    )
 }
 
-#[ignore]
 #[maybe_async::test(
     feature = "blocking-network-client",
     async(feature = "async-network-client", tokio::test)
 )]
 async fn test_read_and_parse_command_status_v2_command_ok_v2_1_option_lines_newline() {
-    todo!()
+    let input = pkt_lines(&["ok refs/heads/main\n", "option refname refs/heads/main\n"]);
+    let mut reader = Fixture(&input);
+    let result = read_and_parse_command_statuses_v2::<nom::error::Error<_>>(&mut reader).await;
+    assert_eq!(
+        result,
+        Ok(vec![CommandStatusV2::Ok(
+            RefName(BString::new(b"refs/heads/main".to_vec())),
+            vec![OptionLine::OptionRefName(RefName(BString::new(
+                b"refs/heads/main".to_vec()
+            ))),],
+        )]),
+        "command-status-v2"
+    )
 }
 
-#[ignore]
 #[maybe_async::test(
     feature = "blocking-network-client",
     async(feature = "async-network-client", tokio::test)
 )]
 async fn test_read_and_parse_command_status_v2_command_ok_v2_2_option_lines() {
-    todo!()
+    let input = pkt_lines(&[
+        "ok refs/heads/main",
+        "option refname refs/heads/main",
+        "option old-oid 91536083cdb16ef3c29638054642b50a34ea8c25",
+    ]);
+    let mut reader = Fixture(&input);
+    let result = read_and_parse_command_statuses_v2::<nom::error::Error<_>>(&mut reader).await;
+    assert_eq!(
+        result,
+        Ok(vec![CommandStatusV2::Ok(
+            RefName(BString::new(b"refs/heads/main".to_vec())),
+            vec![
+                OptionLine::OptionRefName(RefName(BString::new(b"refs/heads/main".to_vec()))),
+                OptionLine::OptionOldOid(oid("91536083cdb16ef3c29638054642b50a34ea8c25")),
+            ],
+        )]),
+        "command-status-v2"
+    )
 }
 
-#[ignore]
 #[maybe_async::test(
     feature = "blocking-network-client",
     async(feature = "async-network-client", tokio::test)
 )]
 async fn test_read_and_parse_command_status_v2_command_ok_v2_2_option_lines_newline() {
-    todo!()
+    let input = pkt_lines(&[
+        "ok refs/heads/main\n",
+        "option refname refs/heads/main\n",
+        "option old-oid 91536083cdb16ef3c29638054642b50a34ea8c25\n",
+    ]);
+    let mut reader = Fixture(&input);
+    let result = read_and_parse_command_statuses_v2::<nom::error::Error<_>>(&mut reader).await;
+    assert_eq!(
+        result,
+        Ok(vec![CommandStatusV2::Ok(
+            RefName(BString::new(b"refs/heads/main".to_vec())),
+            vec![
+                OptionLine::OptionRefName(RefName(BString::new(b"refs/heads/main".to_vec()))),
+                OptionLine::OptionOldOid(oid("91536083cdb16ef3c29638054642b50a34ea8c25")),
+            ],
+        )]),
+        "command-status-v2"
+    )
 }
 
-#[ignore]
 #[maybe_async::test(
     feature = "blocking-network-client",
     async(feature = "async-network-client", tokio::test)
 )]
 async fn test_read_and_parse_command_status_v2_command_ok_v2_3_option_lines() {
-    todo!()
+    let input = pkt_lines(&[
+        "ok refs/heads/main",
+        "option refname refs/heads/main",
+        "option old-oid 91536083cdb16ef3c29638054642b50a34ea8c25",
+        "option new-oid 0000000000000000000000000000000000000000",
+    ]);
+    let mut reader = Fixture(&input);
+    let result = read_and_parse_command_statuses_v2::<nom::error::Error<_>>(&mut reader).await;
+    assert_eq!(
+        result,
+        Ok(vec![CommandStatusV2::Ok(
+            RefName(BString::new(b"refs/heads/main".to_vec())),
+            vec![
+                OptionLine::OptionRefName(RefName(BString::new(b"refs/heads/main".to_vec()))),
+                OptionLine::OptionOldOid(oid("91536083cdb16ef3c29638054642b50a34ea8c25")),
+                OptionLine::OptionNewOid(oid("0000000000000000000000000000000000000000")),
+            ],
+        )]),
+        "command-status-v2"
+    )
 }
 
-#[ignore]
 #[maybe_async::test(
     feature = "blocking-network-client",
     async(feature = "async-network-client", tokio::test)
 )]
 async fn test_read_and_parse_command_status_v2_command_ok_v2_3_option_lines_newline() {
-    todo!()
+    let input = pkt_lines(&[
+        "ok refs/heads/main\n",
+        "option refname refs/heads/main\n",
+        "option old-oid 91536083cdb16ef3c29638054642b50a34ea8c25\n",
+        "option new-oid 0000000000000000000000000000000000000000\n",
+    ]);
+    let mut reader = Fixture(&input);
+    let result = read_and_parse_command_statuses_v2::<nom::error::Error<_>>(&mut reader).await;
+    assert_eq!(
+        result,
+        Ok(vec![CommandStatusV2::Ok(
+            RefName(BString::new(b"refs/heads/main".to_vec())),
+            vec![
+                OptionLine::OptionRefName(RefName(BString::new(b"refs/heads/main".to_vec()))),
+                OptionLine::OptionOldOid(oid("91536083cdb16ef3c29638054642b50a34ea8c25")),
+                OptionLine::OptionNewOid(oid("0000000000000000000000000000000000000000")),
+            ],
+        )]),
+        "command-status-v2"
+    )
 }
 
-#[ignore]
 #[maybe_async::test(
     feature = "blocking-network-client",
     async(feature = "async-network-client", tokio::test)
 )]
 async fn test_read_and_parse_command_status_v2_command_ok_v2_4_option_lines() {
-    todo!()
+    let input = pkt_lines(&[
+        "ok refs/heads/main",
+        "option refname refs/heads/main",
+        "option old-oid 91536083cdb16ef3c29638054642b50a34ea8c25",
+        "option new-oid 0000000000000000000000000000000000000000",
+        "option forced-update",
+    ]);
+    let mut reader = Fixture(&input);
+    let result = read_and_parse_command_statuses_v2::<nom::error::Error<_>>(&mut reader).await;
+    assert_eq!(
+        result,
+        Ok(vec![CommandStatusV2::Ok(
+            RefName(BString::new(b"refs/heads/main".to_vec())),
+            vec![
+                OptionLine::OptionRefName(RefName(BString::new(b"refs/heads/main".to_vec()))),
+                OptionLine::OptionOldOid(oid("91536083cdb16ef3c29638054642b50a34ea8c25")),
+                OptionLine::OptionNewOid(oid("0000000000000000000000000000000000000000")),
+                OptionLine::OptionForce,
+            ],
+        )]),
+        "command-status-v2"
+    )
 }
 
-#[ignore]
 #[maybe_async::test(
     feature = "blocking-network-client",
     async(feature = "async-network-client", tokio::test)
 )]
 async fn test_read_and_parse_command_status_v2_command_ok_v2_4_option_lines_newline() {
-    todo!()
+    let input = pkt_lines(&[
+        "ok refs/heads/main\n",
+        "option refname refs/heads/main\n",
+        "option old-oid 91536083cdb16ef3c29638054642b50a34ea8c25\n",
+        "option new-oid 0000000000000000000000000000000000000000\n",
+        "option forced-update\n",
+    ]);
+    let mut reader = Fixture(&input);
+    let result = read_and_parse_command_statuses_v2::<nom::error::Error<_>>(&mut reader).await;
+    assert_eq!(
+        result,
+        Ok(vec![CommandStatusV2::Ok(
+            RefName(BString::new(b"refs/heads/main".to_vec())),
+            vec![
+                OptionLine::OptionRefName(RefName(BString::new(b"refs/heads/main".to_vec()))),
+                OptionLine::OptionOldOid(oid("91536083cdb16ef3c29638054642b50a34ea8c25")),
+                OptionLine::OptionNewOid(oid("0000000000000000000000000000000000000000")),
+                OptionLine::OptionForce,
+            ],
+        )]),
+        "command-status-v2"
+    )
+}
+
+#[maybe_async::test(
+    feature = "blocking-network-client",
+    async(feature = "async-network-client", tokio::test)
+)]
+async fn test_read_and_parse_command_status_v2_option_line_without_command_ok() {
+    let input = pkt_lines(&["option forced-update\n", "ok refs/heads/main\n"]);
+    let mut reader = Fixture(&input);
+    let result = read_and_parse_command_statuses_v2::<nom::error::Error<_>>(&mut reader).await;
+    assert_eq!(
+        result,
+        Err(ParseError::UnexpectedOptionLine),
+        "command-status-v2"
+    )
+}
+
+#[maybe_async::test(
+    feature = "blocking-network-client",
+    async(feature = "async-network-client", tokio::test)
+)]
+async fn test_read_and_parse_command_status_v2_option_line_after_command_fail() {
+    let input = pkt_lines(&["ng refs/heads/main rejected\n", "option forced-update\n"]);
+    let mut reader = Fixture(&input);
+    let result = read_and_parse_command_statuses_v2::<nom::error::Error<_>>(&mut reader).await;
+    assert_eq!(
+        result,
+        Err(ParseError::UnexpectedOptionLine),
+        "command-status-v2"
+    )
+}
+
+#[test]
+fn test_parse_option_line_rejects_invalid_oid() {
+    let result = parse_option_line::<nom::error::Error<_>>(b"option old-oid not-a-hash");
+    assert!(result.is_err(), "option-line: {:?}", result)
+}
+
+#[test]
+fn test_parse_option_line_rejects_unknown_option() {
+    let result = parse_option_line::<nom::error::Error<_>>(b"option unknown");
+    assert!(result.is_err(), "option-line: {:?}", result)
 }
 
 #[maybe_async::test(
@@ -318,8 +546,8 @@ async fn test_read_and_parse_command_status_v2_command_ok_v2_4_option_lines_newl
     async(feature = "async-network-client", tokio::test)
 )]
 async fn test_read_and_parse_command_status_v2_command_fail() {
-    let input = b"ng refs/heads/main some error message";
-    let mut reader = Fixture(input);
+    let input = pkt_lines(&["ng refs/heads/main some error message"]);
+    let mut reader = Fixture(&input);
     let result = read_and_parse_command_statuses_v2::<nom::error::Error<_>>(&mut reader).await;
     assert_eq!(
         result,
@@ -336,8 +564,8 @@ async fn test_read_and_parse_command_status_v2_command_fail() {
     async(feature = "async-network-client", tokio::test)
 )]
 async fn test_read_and_parse_command_status_v2_command_fail_newline() {
-    let input = b"ng refs/heads/main some error message\n";
-    let mut reader = Fixture(input);
+    let input = pkt_lines(&["ng refs/heads/main some error message\n"]);
+    let mut reader = Fixture(&input);
     let result = read_and_parse_command_statuses_v2::<nom::error::Error<_>>(&mut reader).await;
     assert_eq!(
         result,
@@ -444,3 +672,178 @@ fn test_parse_error_msg_empty() {
         "error msg is empty"
     )
 }
+
+#[test]
+fn test_parse_refname_valid() {
+    let result = parse_refname::<nom::error::Error<_>>(b"refs/heads/feature/a.b-c_d");
+    assert_eq!(
+        result.map(|x| x.1),
+        Ok(RefName(BString::from("refs/heads/feature/a.b-c_d"))),
+        "refname"
+    )
+}
+
+fn is_rejected(input: &[u8]) -> bool {
+    parse_refname::<nom::error::Error<_>>(input).is_err()
+}
+
+#[test]
+fn test_parse_refname_rejects_double_dot() {
+    assert!(is_rejected(b"refs/heads/a..b"), "double dot")
+}
+
+#[test]
+fn test_parse_refname_rejects_lock_suffix() {
+    assert!(is_rejected(b"refs/heads/main.lock"), "ref");
+    assert!(is_rejected(b"refs/heads.lock/main"), "component")
+}
+
+#[test]
+fn test_parse_refname_rejects_leading_slash() {
+    assert!(is_rejected(b"/refs/heads/main"), "leading slash")
+}
+
+#[test]
+fn test_parse_refname_rejects_trailing_slash() {
+    assert!(is_rejected(b"refs/heads/main/"), "trailing slash")
+}
+
+#[test]
+fn test_parse_refname_rejects_consecutive_slashes() {
+    assert!(is_rejected(b"refs/heads//main"), "consecutive slashes")
+}
+
+#[test]
+fn test_parse_refname_rejects_at_brace() {
+    assert!(is_rejected(b"refs/heads/main@{1}"), "at brace")
+}
+
+#[test]
+fn test_parse_refname_rejects_at() {
+    assert!(is_rejected(b"@"), "at")
+}
+
+#[test]
+fn test_parse_refname_rejects_leading_dot() {
+    assert!(is_rejected(b"refs/heads/.hidden"), "leading dot")
+}
+
+#[test]
+fn test_parse_refname_rejects_trailing_dot() {
+    assert!(is_rejected(b"refs/heads/main."), "trailing dot")
+}
+
+#[test]
+fn test_parse_refname_rejects_backslash() {
+    assert!(is_rejected(b"refs/heads/a\\b"), "backslash")
+}
+
+#[test]
+fn test_as_bstr() {
+    assert_eq!(
+        RefName(BString::from("refs/heads/main")).as_bstr(),
+        "refs/heads/main",
+        "ref name"
+    );
+    assert_eq!(
+        ErrorMsg(BString::from("non-fast-forward")).as_bstr(),
+        "non-fast-forward",
+        "error msg"
+    );
+}
+
+#[test]
+fn test_error_msg_lossy() {
+    let error_msg = ErrorMsg(BString::from(b"r\xe9f\xe9rence verrouill\xe9e".to_vec()));
+    assert_eq!(
+        error_msg.to_string(),
+        "r\u{fffd}f\u{fffd}rence verrouill\u{fffd}e",
+        "display"
+    );
+    assert_eq!(
+        error_msg.as_bstr(),
+        b"r\xe9f\xe9rence verrouill\xe9e".as_bstr(),
+        "raw bytes"
+    );
+}
+
+#[test]
+fn test_parse_error_io_source() {
+    use std::error::Error as _;
+
+    let err = as_slice(Err(std::io::Error::new(
+        std::io::ErrorKind::ConnectionReset,
+        "connection reset",
+    )))
+    .expect_err("IO error");
+
+    assert_eq!(err.to_string(), "IO error: connection reset", "display");
+    assert_eq!(
+        err.source().map(|source| source.to_string()),
+        Some("connection reset".to_string()),
+        "source"
+    );
+}
+
+#[test]
+fn test_as_slice_unexpected_eof() {
+    let err = as_slice(Err(std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        "failed to fill whole buffer",
+    )))
+    .expect_err("end of input");
+
+    assert_eq!(err, ParseError::UnexpectedEof, "variant");
+}
+
+#[maybe_async::test(
+    feature = "blocking-network-client",
+    async(feature = "async-network-client", tokio::test)
+)]
+async fn test_read_and_parse_truncated_before_flush() {
+    let mut input = vec!["000eunpack ok", "0017ok refs/heads/main", ""]
+        .join("\n")
+        .into_bytes();
+    let reader = Fixture(&mut input);
+    let result = read_and_parse(reader).await;
+    assert_eq!(result, Err(ParseError::UnexpectedEof), "report-status-v2")
+}
+
+#[maybe_async::test(
+    feature = "blocking-network-client",
+    async(feature = "async-network-client", tokio::test)
+)]
+async fn test_read_and_parse_truncated_within_line() {
+    let mut input = vec!["000eunpack ok", "0017ok refs/he"]
+        .join("\n")
+        .into_bytes();
+    let reader = Fixture(&mut input);
+    let result = read_and_parse(reader).await;
+    assert_eq!(result, Err(ParseError::UnexpectedEof), "report-status-v2")
+}
+
+#[maybe_async::test(
+    feature = "blocking-network-client",
+    async(feature = "async-network-client", tokio::test)
+)]
+async fn test_read_and_parse_flush_before_unpack_status() {
+    let mut input = b"0000".to_vec();
+    let reader = Fixture(&mut input);
+    let result = read_and_parse(reader).await;
+    assert_eq!(
+        result,
+        Err(ParseError::FailedToReadUnpackStatus),
+        "report-status-v2"
+    )
+}
+
+#[test]
+fn test_parse_error_nom_source() {
+    use std::error::Error as _;
+
+    let err = parse_with(nom::bytes::complete::tag(&b"unpack "[..]), b"unexpected")
+        .expect_err("nom error");
+
+    assert!(matches!(err, ParseError::Nom(_)), "variant");
+    assert!(err.source().is_some(), "source");
+}
@@ -0,0 +1,111 @@
+use super::fixture::{pkt_lines, Fixture, FLUSH};
+use super::*;
+use git::bstr::BString;
+
+#[maybe_async::test(
+    feature = "blocking-network-client",
+    async(feature = "async-network-client", tokio::test)
+)]
+async fn test_read_and_parse_with_sidebands() {
+    let input = pkt_lines(&[
+        "\x01000dunpack ok",
+        "\x02Resolving deltas: 100% (2/2), done.",
+        "\x010016ok refs/heads/main0000",
+        FLUSH,
+    ]);
+    let reader = Fixture(&input);
+    let mut messages = Vec::new();
+    let result = read_and_parse_with_sidebands(reader, ReportStatusVersion::V2, |is_err, data| {
+        messages.push((is_err, BString::from(data)))
+    })
+    .await;
+    assert_eq!(
+        result,
+        Ok((
+            UnpackResult::Ok,
+            vec![CommandStatusV2::Ok(
+                RefName(BString::new(b"refs/heads/main".to_vec())),
+                Vec::new(),
+            )]
+        )),
+        "report-status-v2"
+    );
+    assert_eq!(
+        messages,
+        vec![(false, BString::from("Resolving deltas: 100% (2/2), done."))],
+        "progress"
+    )
+}
+
+#[maybe_async::test(
+    feature = "blocking-network-client",
+    async(feature = "async-network-client", tokio::test)
+)]
+async fn test_read_and_parse_with_sidebands_error_message() {
+    let input = pkt_lines(&[
+        "\x03fatal: the remote end hung up unexpectedly",
+        "\x01000dunpack ok0017ok refs/heads/debug0000",
+        FLUSH,
+    ]);
+    let reader = Fixture(&input);
+    let mut messages = Vec::new();
+    let result = read_and_parse_with_sidebands(reader, ReportStatusVersion::V1, |is_err, data| {
+        messages.push((is_err, BString::from(data)))
+    })
+    .await;
+    assert_eq!(
+        result,
+        Ok((
+            UnpackResult::Ok,
+            vec![CommandStatusV2::Ok(
+                RefName(BString::new(b"refs/heads/debug".to_vec())),
+                Vec::new(),
+            )]
+        )),
+        "report-status"
+    );
+    assert_eq!(
+        messages,
+        vec![(
+            true,
+            BString::from("fatal: the remote end hung up unexpectedly")
+        )],
+        "error messages"
+    )
+}
+
+#[maybe_async::test(
+    feature = "blocking-network-client",
+    async(feature = "async-network-client", tokio::test)
+)]
+async fn test_read_and_parse_with_sidebands_unexpected_band() {
+    let input = pkt_lines(&["\x01000dunpack ok", "\x04unknown"]);
+    let reader = Fixture(&input);
+    let result = read_and_parse_with_sidebands(reader, ReportStatusVersion::V2, |_, _| ()).await;
+    assert_eq!(result, Err(ParseError::UnexpectedSideband(4)), "sideband")
+}
+
+#[maybe_async::test(
+    feature = "blocking-network-client",
+    async(feature = "async-network-client", tokio::test)
+)]
+async fn test_read_and_parse_with_sidebands_ends_at_flush() {
+    let input = pkt_lines(&[
+        "\x01000dunpack ok0016ok refs/heads/main0000",
+        FLUSH,
+        "\x04unknown",
+    ]);
+    let reader = Fixture(&input);
+    let result = read_and_parse_with_sidebands(reader, ReportStatusVersion::V2, |_, _| ()).await;
+    assert_eq!(
+        result,
+        Ok((
+            UnpackResult::Ok,
+            vec![CommandStatusV2::Ok(
+                RefName(BString::new(b"refs/heads/main".to_vec())),
+                Vec::new(),
+            )]
+        )),
+        "report-status-v2"
+    );
+}
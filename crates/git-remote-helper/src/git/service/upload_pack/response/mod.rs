@@ -0,0 +1 @@
+pub mod shallow_info;
@@ -0,0 +1,73 @@
+use git_repository as git;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+#[cfg(test)]
+mod tests;
+
+/// An update to the shallow boundary from a fetch response, which protocol v2
+/// sends in its `shallow-info` section.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ShallowUpdate {
+    Shallow(git::hash::ObjectId),
+    Unshallow(git::hash::ObjectId),
+}
+
+/// The shallow commits listed in the shallow file at `path`, which a
+/// repository that isn't shallow doesn't have.
+pub fn read(path: &Path) -> std::io::Result<Vec<git::hash::ObjectId>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            git::hash::ObjectId::from_hex(line.as_bytes()).map_err(|err| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("invalid shallow commit `{}`: {}", line, err),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Applies `shallow_updates` to the shallow file at `path`, which lists the
+/// shallow commits one per line. The file is removed once no commits are
+/// shallow.
+pub fn apply(path: &Path, shallow_updates: &[ShallowUpdate]) -> std::io::Result<()> {
+    let mut shallow_commits = match std::fs::read_to_string(path) {
+        Ok(contents) => contents
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect::<BTreeSet<_>>(),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTreeSet::new(),
+        Err(err) => return Err(err),
+    };
+
+    for shallow_update in shallow_updates {
+        match shallow_update {
+            ShallowUpdate::Shallow(id) => shallow_commits.insert(id.to_hex().to_string()),
+            ShallowUpdate::Unshallow(id) => shallow_commits.remove(&id.to_hex().to_string()),
+        };
+    }
+
+    if shallow_commits.is_empty() {
+        match std::fs::remove_file(path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    } else {
+        let contents = shallow_commits
+            .into_iter()
+            .map(|id| id + "\n")
+            .collect::<String>();
+        std::fs::write(path, contents)
+    }
+}
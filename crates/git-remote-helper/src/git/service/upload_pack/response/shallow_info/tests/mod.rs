@@ -0,0 +1,78 @@
+use super::*;
+
+const SHALLOW: &str = "91536083cdb16ef3c29638054642b50a34ea8c25";
+const UNSHALLOW: &str = "1111111111111111111111111111111111111111";
+
+fn oid(hex: &str) -> git::hash::ObjectId {
+    git::hash::ObjectId::from_hex(hex.as_bytes()).expect("valid hash")
+}
+
+#[test]
+fn test_apply() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("shallow");
+    std::fs::write(&path, format!("{}\n", UNSHALLOW)).expect("write shallow file");
+
+    apply(
+        &path,
+        &[
+            ShallowUpdate::Shallow(oid(SHALLOW)),
+            ShallowUpdate::Unshallow(oid(UNSHALLOW)),
+        ],
+    )
+    .expect("apply to succeed");
+
+    assert_eq!(
+        std::fs::read_to_string(&path).expect("shallow file to exist"),
+        format!("{}\n", SHALLOW),
+        "shallow file"
+    )
+}
+
+#[test]
+fn test_apply_removes_empty_shallow_file() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("shallow");
+    std::fs::write(&path, format!("{}\n", SHALLOW)).expect("write shallow file");
+
+    apply(&path, &[ShallowUpdate::Unshallow(oid(SHALLOW))]).expect("apply to succeed");
+
+    assert!(!path.exists(), "shallow file should be removed")
+}
+
+#[test]
+fn test_read() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("shallow");
+    std::fs::write(&path, format!("{}\n{}\n", UNSHALLOW, SHALLOW)).expect("write shallow file");
+
+    assert_eq!(
+        read(&path).expect("read to succeed"),
+        vec![oid(UNSHALLOW), oid(SHALLOW)],
+        "shallow commits"
+    )
+}
+
+#[test]
+fn test_read_without_shallow_file() {
+    let dir = tempfile::tempdir().expect("tempdir");
+
+    assert_eq!(
+        read(&dir.path().join("shallow")).expect("read to succeed"),
+        Vec::new(),
+        "shallow commits"
+    )
+}
+
+#[test]
+fn test_read_invalid_shallow_file() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("shallow");
+    std::fs::write(&path, "not-a-hash\n").expect("write shallow file");
+
+    assert_eq!(
+        read(&path).expect_err("invalid commit").kind(),
+        std::io::ErrorKind::InvalidData,
+        "kind"
+    )
+}
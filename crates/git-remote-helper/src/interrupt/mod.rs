@@ -0,0 +1,20 @@
+//! Checks for Ctrl-C between round-trips to the remote.
+//!
+//! The handler installed with `git_repository::interrupt::init_handler` sets
+//! `git_repository::interrupt::IS_INTERRUPTED`, which is what's polled here.
+//! A second Ctrl-C removes tempfiles and exits without waiting.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(test)]
+mod tests;
+
+/// Fails once `should_interrupt` is set, so that nothing more is asked of the
+/// remote after Ctrl-C.
+pub fn check(should_interrupt: &AtomicBool) -> anyhow::Result<()> {
+    if should_interrupt.load(Ordering::Relaxed) {
+        anyhow::bail!("interrupted");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,18 @@
+use super::*;
+
+#[test]
+fn test_check() {
+    let should_interrupt = AtomicBool::new(false);
+
+    assert!(check(&should_interrupt).is_ok(), "not interrupted");
+
+    should_interrupt.store(true, Ordering::Relaxed);
+
+    assert_eq!(
+        check(&should_interrupt)
+            .expect_err("to be interrupted")
+            .to_string(),
+        "interrupted",
+        "error"
+    );
+}
@@ -2,30 +2,43 @@
 
 pub mod cli;
 pub mod commands;
+pub mod debug;
+pub mod error;
+pub mod event;
 pub mod git;
+pub mod interrupt;
+pub mod stats;
+pub mod verbosity;
 
-use anyhow::{anyhow, Context};
-use clap::{Command, FromArgMatches as _, Parser as _, Subcommand as _};
-use cli::Args;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+
+#[cfg(test)]
+mod tests;
+
+use anyhow::Context;
+use clap::Parser as _;
+use cli::{Args, DiagnoseArgs, ListJsonArgs};
 use commands::Commands;
+use error::{Kind, WithKind as _};
 use git_repository as gitoxide;
 use gitoxide::protocol::transport;
-use log::trace;
+use log::{trace, warn};
 use maybe_async::maybe_async;
 use std::collections::BTreeSet;
 use std::env;
-use std::path::Path;
-use strum::VariantNames as _;
+use std::io::{BufRead, BufWriter, Write};
 
 #[cfg(all(feature = "async-network-client", feature = "blocking-network-client"))]
 compile_error!("Cannot set both 'async-network-client' and 'blocking-network-client' features as they are mutually exclusive");
 
-const GIT_DIR: &str = "GIT_DIR";
+/// The environment variable Git sets to the repository it runs a helper for.
+pub const GIT_DIR: &str = "GIT_DIR";
 
 #[maybe_async]
 pub async fn main<C>(
     connect: impl Fn(String, transport::client::connect::Options) -> C,
-) -> anyhow::Result<()>
+) -> Result<(), error::Error>
 where
     C: std::future::Future<
         Output = Result<
@@ -34,54 +47,191 @@ where
         >,
     >,
 {
+    let credentials = git::credentials::Credentials::default();
+    let authenticate = |action| credentials.authenticate(action);
+
+    let git_protocol = git::protocol::from_env().with_kind(Kind::Config)?;
+
+    if let Ok(list_json_args) = ListJsonArgs::try_parse() {
+        trace!("list_json_args.url: {:?}", list_json_args.url);
+
+        let mut transport = connect(
+            list_json_args.url.clone(),
+            transport::client::connect::Options {
+                version: git_protocol.version,
+                #[cfg(feature = "blocking-network-client")]
+                ssh: Default::default(),
+            },
+        )
+        .await?;
+
+        return commands::list::execute_json(
+            &mut transport,
+            authenticate,
+            &git_protocol.extra_parameters,
+        )
+        .await
+        .map_err(git::protocol::surface_remote_error)
+        .with_context(|| {
+            credentials.context(
+                "failed to list refs from",
+                &list_json_args.url,
+                git_protocol.version,
+            )
+        })
+        .with_kind(Kind::List)
+        .map_err(|err| err.with_url(&list_json_args.url));
+    }
+
+    if let Ok(diagnose_args) = DiagnoseArgs::try_parse() {
+        trace!("diagnose_args.url: {:?}", diagnose_args.url);
+
+        let transport = connect(
+            diagnose_args.url.clone(),
+            transport::client::connect::Options {
+                version: git_protocol.version,
+                #[cfg(feature = "blocking-network-client")]
+                ssh: Default::default(),
+            },
+        )
+        .await?;
+
+        return commands::diagnose::execute(
+            transport,
+            authenticate,
+            &git_protocol.extra_parameters,
+            &mut std::io::stdout(),
+        )
+        .await
+        .map_err(git::protocol::surface_remote_error)
+        .with_context(|| {
+            credentials.context(
+                "failed to connect to",
+                &diagnose_args.url,
+                git_protocol.version,
+            )
+        })
+        .with_kind(Kind::Connect)
+        .map_err(|err| err.with_url(&diagnose_args.url));
+    }
+
     let args = Args::parse();
     trace!("args.repository: {:?}", args.repository);
     trace!("args.url: {:?}", args.url);
 
+    // The handler sets `IS_INTERRUPTED` before calling this, which is all
+    // that's polled between round-trips.
     gitoxide::interrupt::init_handler(move || {})?;
 
-    let git_dir = env::var(GIT_DIR).context("failed to get GIT_DIR")?;
-    trace!("GIT_DIR: {}", git_dir);
+    let repo = match env::var_os(GIT_DIR) {
+        Some(git_dir) => {
+            trace!("GIT_DIR: {:?}", git_dir);
+            git::repository::open(&git_dir).with_kind(Kind::Config)?
+        }
+        None => {
+            trace!("GIT_DIR is not set");
+            git::repository::discover(".").with_kind(Kind::Config)?
+        }
+    };
+
+    run(
+        connect,
+        &args,
+        &repo,
+        &mut std::io::stdin().lock(),
+        &mut std::io::stdout(),
+    )
+    .await
+    .map_err(|err| err.with_url(&args.url))
+}
+
+/// Speaks the remote helper protocol with Git, reading commands from `input`
+/// and writing responses to `out`, using `connect` to reach the remote.
+#[maybe_async]
+pub async fn run<C>(
+    connect: impl Fn(String, transport::client::connect::Options) -> C,
+    args: &Args,
+    repo: &gitoxide::Repository,
+    input: &mut impl BufRead,
+    out: &mut impl Write,
+) -> Result<(), error::Error>
+where
+    C: std::future::Future<
+        Output = Result<
+            Box<(dyn transport::client::Transport + Send)>,
+            transport::client::connect::Error,
+        >,
+    >,
+{
+    let credentials = git::credentials::Credentials::default();
+    let authenticate = |action| credentials.authenticate(action);
 
-    let repo_dir = Path::new(&git_dir)
-        .parent()
-        .ok_or_else(|| anyhow!("failed to get repository directory"))?;
+    let mut git_protocol = git::protocol::from_env().with_kind(Kind::Config)?;
 
-    let repo = gitoxide::open(repo_dir)?;
+    // The protocol version the remote responds with, once it's been probed.
+    let mut remote_version = None;
 
-    // TODO: implementer provides this
-    let authenticate =
-        |action| panic!("unexpected call to authenticate with action: {:#?}", action);
+    // The connection from the last protocol v2 handshake, so that Git's
+    // usual `capabilities` then `list` don't each need one.
+    let mut connection = None;
 
+    let mut options = commands::option::Options::default();
     let mut fetch: commands::fetch::Batch = BTreeSet::new();
     let mut push: commands::push::Batch = BTreeSet::new();
 
     loop {
         trace!("loop");
 
-        // TODO: BString?
-        let mut input = String::new();
-
-        std::io::stdin()
-            .read_line(&mut input)
-            .context("failed to read from stdin")?;
-
-        let input = input.trim();
+        let line = read_line(input)?;
+        let eof = line.is_none();
+        let input = line.unwrap_or_default();
 
         if input.is_empty() {
-            trace!("terminated with a blank line");
+            if eof {
+                trace!("input closed");
+            } else {
+                trace!("terminated with a blank line");
+            }
 
-            let fetch_transport = connect(
-                args.url.clone(),
-                transport::client::connect::Options {
-                    version: transport::Protocol::V2,
-                    #[cfg(feature = "blocking-network-client")]
-                    ssh: Default::default(),
-                },
+            // Git usually fetches right after listing, so the connection
+            // from that is reused too.
+            let fetch_connection = match connection.take() {
+                Some(connection) => connection,
+                None => git::protocol::Connection::new(
+                    connect(
+                        args.url.clone(),
+                        transport::client::connect::Options {
+                            version: git_protocol.version,
+                            #[cfg(feature = "blocking-network-client")]
+                            ssh: Default::default(),
+                        },
+                    )
+                    .await?,
+                ),
+            };
+
+            let fetch_stats = commands::fetch::process(
+                fetch_connection,
+                repo,
+                &args.url,
+                authenticate,
+                &options,
+                &git_protocol.extra_parameters,
+                &gitoxide::interrupt::IS_INTERRUPTED,
+                None,
+                &mut fetch,
+                out,
             )
-            .await?;
+            .await
+            .map_err(git::protocol::surface_remote_error)
+            .with_context(|| {
+                credentials.context("failed to fetch from", &args.url, git_protocol.version)
+            })
+            .with_kind(Kind::Fetch)?;
 
-            commands::fetch::process(fetch_transport, &repo, &args.url, &mut fetch).await?;
+            if eof {
+                check_unterminated(&push).with_kind(Kind::Input)?;
+            }
 
             // NOTE: push still uses the v1 protocol so we use that here.
             let mut push_transport = connect(
@@ -94,38 +244,187 @@ where
             )
             .await?;
 
-            commands::push::process(&mut push_transport, &repo, authenticate, &mut push).await?;
+            let push_stats = commands::push::process(
+                &mut push_transport,
+                repo,
+                authenticate,
+                &options,
+                &git_protocol.extra_parameters,
+                &mut push,
+                out,
+            )
+            .await
+            .with_context(|| {
+                credentials.context("failed to push to", &args.url, transport::Protocol::V1)
+            })
+            .with_kind(Kind::Push)?;
+
+            stats::write(&stats::Stats::new(fetch_stats, push_stats))?;
 
             // continue; // Useful to inspect .git directory before it disappears
             break Ok(());
         }
 
-        let input = input.split(' ').collect::<Vec<_>>();
-
         trace!("input: {:#?}", input);
 
-        let input_command = Command::new("git-remote-icp")
-            .multicall(true)
-            .subcommand_required(true);
+        interrupt::check(&gitoxide::interrupt::IS_INTERRUPTED).with_kind(Kind::Interrupted)?;
 
-        let input_command = Commands::augment_subcommands(input_command);
-        let matches = input_command.try_get_matches_from(input)?;
-        let command = Commands::from_arg_matches(&matches)?;
+        let command = Commands::from_line(&input).with_kind(Kind::Input)?;
 
         match command {
             Commands::Capabilities => {
-                // TODO: buffer and flush
-                Commands::VARIANTS
-                    .iter()
-                    .filter(|command| **command != "capabilities" && **command != "list")
-                    .for_each(|command| println!("{}", command));
-                println!();
+                // Git only uses what depends on the remote's version with
+                // protocol v2, so there's nothing to probe for otherwise.
+                if remote_version.is_none() && git_protocol.version == transport::Protocol::V2 {
+                    let mut probed = git::protocol::Connection::new(
+                        connect(
+                            args.url.clone(),
+                            transport::client::connect::Options {
+                                version: git_protocol.version,
+                                #[cfg(feature = "blocking-network-client")]
+                                ssh: Default::default(),
+                            },
+                        )
+                        .await?,
+                    );
+
+                    remote_version = match git::protocol::probe(
+                        &mut probed,
+                        authenticate,
+                        &git_protocol.extra_parameters,
+                    )
+                    .await
+                    {
+                        Ok(version) => {
+                            if probed.is_reusable() {
+                                connection = Some(probed);
+                            }
+                            Some(version)
+                        }
+                        Err(err) => match git::protocol::fallback(git_protocol.version, &err) {
+                            Some(version) => {
+                                warn!("using protocol {:?}: {:#}", version, err);
+                                git_protocol.version = version;
+                                Some(version)
+                            }
+                            None => {
+                                warn!("failed to probe the remote's protocol version: {:#}", err);
+                                None
+                            }
+                        },
+                    };
+                }
+
+                let remote_supports_v2 = remote_version == Some(transport::Protocol::V2);
+
+                let mut capabilities = BufWriter::new(&mut *out);
+
+                for capability in commands::capabilities(remote_supports_v2, args.readonly) {
+                    writeln!(capabilities, "{}", capability)?;
+                }
+
+                writeln!(capabilities)?;
+                capabilities.flush()?;
             }
             Commands::Fetch { hash, name } => {
                 trace!("batch fetch {} {}", hash, name);
                 let _ = fetch.insert((hash, name));
             }
             Commands::List { variant } => {
+                let ref_prefixes = commands::list::ref_prefixes(repo, &args.repository, &variant);
+
+                loop {
+                    // A connection the interrupt closed isn't worth retrying.
+                    interrupt::check(&gitoxide::interrupt::IS_INTERRUPTED)
+                        .with_kind(Kind::Interrupted)?;
+
+                    let reused = connection.is_some();
+
+                    let mut list_connection = match connection.take() {
+                        Some(connection) => connection,
+                        None => git::protocol::Connection::new(
+                            connect(
+                                args.url.clone(),
+                                transport::client::connect::Options {
+                                    version: git_protocol.version,
+                                    #[cfg(feature = "blocking-network-client")]
+                                    ssh: Default::default(),
+                                },
+                            )
+                            .await?,
+                        ),
+                    };
+
+                    let result = commands::list::execute(
+                        &mut list_connection,
+                        authenticate,
+                        &variant,
+                        &git_protocol.extra_parameters,
+                        &ref_prefixes,
+                        options.object_format,
+                        out,
+                    )
+                    .await
+                    .map_err(git::protocol::surface_remote_error)
+                    .with_context(|| {
+                        credentials.context(
+                            "failed to list refs from",
+                            &args.url,
+                            git_protocol.version,
+                        )
+                    });
+
+                    // Later connections use the same version, so that a
+                    // fetch after listing doesn't fail the same way.
+                    match result {
+                        // The remote may have closed the connection since,
+                        // so it's dropped and another is made.
+                        Err(err) if reused => {
+                            warn!("retrying with a new connection: {:#}", err);
+                        }
+                        Err(err) => match git::protocol::fallback(git_protocol.version, &err) {
+                            Some(version) => {
+                                warn!("retrying with protocol {:?}: {:#}", version, err);
+                                git_protocol.version = version;
+                            }
+                            None => return Err(error::Error::new(Kind::List, err)),
+                        },
+                        Ok(()) => {
+                            if list_connection.is_reusable() {
+                                connection = Some(list_connection);
+                            }
+                            break;
+                        }
+                    }
+                }
+
+                trace!("protocol version: {:?}", git_protocol.version);
+            }
+            Commands::Option { name, value } => {
+                commands::option::execute(&mut options, &name, &value, out)?
+            }
+            Commands::Push { src_dst } => {
+                // Git doesn't push without the capability, but this makes
+                // sure nothing is sent if it does.
+                if args.readonly {
+                    return Err(error::Error::new(
+                        Kind::Push,
+                        anyhow::anyhow!(
+                            "refusing to push to {} since {} is set",
+                            args.url,
+                            commands::push::READONLY
+                        ),
+                    ));
+                }
+
+                // Parsed now so that a malformed refspec fails before any of
+                // the batch is pushed.
+                let refspec = commands::push::RefSpec::parse(&src_dst).with_kind(Kind::Input)?;
+
+                trace!("batch push {}", src_dst);
+                let _ = push.insert(refspec);
+            }
+            Commands::StatelessConnect { service } => {
                 let mut transport = connect(
                     args.url.clone(),
                     transport::client::connect::Options {
@@ -136,12 +435,54 @@ where
                 )
                 .await?;
 
-                commands::list::execute(&mut transport, authenticate, &variant).await?
-            }
-            Commands::Push { src_dst } => {
-                trace!("batch push {}", src_dst);
-                let _ = push.insert(src_dst);
+                let connected = commands::stateless_connect::execute(
+                    &mut transport,
+                    authenticate,
+                    &service,
+                    &git_protocol.extra_parameters,
+                    input,
+                    out,
+                )
+                .await
+                .with_context(|| {
+                    credentials.context("failed to connect to", &args.url, transport::Protocol::V2)
+                })
+                .with_kind(Kind::Fetch)?;
+
+                if connected {
+                    break Ok(());
+                }
             }
         }
     }
 }
+
+/// Reads a line of input without its trailing whitespace, or `None` once Git
+/// has closed stdin.
+// TODO: BString?
+fn read_line(reader: &mut impl BufRead) -> anyhow::Result<Option<String>> {
+    let mut input = String::new();
+
+    let len = reader
+        .read_line(&mut input)
+        .context("failed to read from stdin")?;
+
+    if len == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(input.trim().to_string()))
+}
+
+/// Git closing stdin before the blank line that terminates a batch, for
+/// example because it crashed, means it may not have sent every command.
+/// Pending fetches are still processed since they only add objects, but half
+/// a push could leave the remote inconsistent.
+fn check_unterminated(push: &commands::push::Batch) -> anyhow::Result<()> {
+    if !push.is_empty() {
+        trace!("discarding unterminated push: {:#?}", push);
+        return Err(anyhow::anyhow!("connection closed before batch terminator"));
+    }
+
+    Ok(())
+}
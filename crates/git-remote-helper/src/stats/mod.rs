@@ -0,0 +1,133 @@
+//! Statistics about a helper invocation, for monitoring clone and push
+//! performance.
+//!
+//! When `GIT_REMOTE_ICP_STATS_JSON=<path>` is set and the `serde` feature is
+//! enabled, the statistics are written to `<path>` as JSON once a batch has
+//! been processed.
+
+use log::trace;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+#[cfg(test)]
+mod tests;
+
+pub(crate) const STATS_JSON: &str = "GIT_REMOTE_ICP_STATS_JSON";
+
+static CANISTER_CALLS: AtomicU64 = AtomicU64::new(0);
+static RETRIES: AtomicU64 = AtomicU64::new(0);
+
+/// Records that a call was made to a canister.
+pub fn record_canister_call() {
+    CANISTER_CALLS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records that a request was retried.
+pub fn record_retry() {
+    RETRIES.fetch_add(1, Ordering::Relaxed);
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Stats {
+    pub fetch: Option<FetchStats>,
+    pub push: Option<PushStats>,
+    pub canister_calls: u64,
+    pub retries: u64,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FetchStats {
+    /// Time spent on the handshake and listing refs.
+    pub prepare_ms: u64,
+    /// Time spent negotiating, receiving, and indexing the pack.
+    pub receive_ms: u64,
+    pub objects: u64,
+    pub bytes: u64,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PushStats {
+    pub handshake_ms: u64,
+    /// Time spent counting objects and writing the pack.
+    pub pack_ms: u64,
+    /// Time spent waiting for and parsing the status report.
+    pub report_ms: u64,
+    pub objects: u64,
+    pub bytes: u64,
+}
+
+impl Stats {
+    pub fn new(fetch: Option<FetchStats>, push: Option<PushStats>) -> Self {
+        Self {
+            fetch,
+            push,
+            canister_calls: CANISTER_CALLS.load(Ordering::Relaxed),
+            retries: RETRIES.load(Ordering::Relaxed),
+        }
+    }
+}
+
+pub fn millis(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+/// Writes `stats` to the path in `GIT_REMOTE_ICP_STATS_JSON`, if set.
+pub fn write(stats: &Stats) -> anyhow::Result<()> {
+    trace!("stats: {:#?}", stats);
+
+    match path() {
+        Some(path) => write_json(stats, &path),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(test))]
+fn path() -> Option<PathBuf> {
+    std::env::var_os(STATS_JSON).map(PathBuf::from)
+}
+
+// Tests set the path for their own thread, since setting the variable would
+// change it for every test running alongside.
+#[cfg(test)]
+thread_local! {
+    static TEST_PATH: std::cell::RefCell<Option<PathBuf>> = std::cell::RefCell::new(None);
+}
+
+#[cfg(test)]
+fn path() -> Option<PathBuf> {
+    TEST_PATH
+        .with(|path| path.borrow().clone())
+        .or_else(|| std::env::var_os(STATS_JSON).map(PathBuf::from))
+}
+
+/// Runs `f` as if `GIT_REMOTE_ICP_STATS_JSON` were set to `path` for the
+/// current thread alone.
+#[cfg(all(test, feature = "serde"))]
+pub(crate) fn with_path<T>(path: &std::path::Path, f: impl FnOnce() -> T) -> T {
+    TEST_PATH.with(|test_path| *test_path.borrow_mut() = Some(path.to_path_buf()));
+    let result = f();
+    TEST_PATH.with(|test_path| *test_path.borrow_mut() = None);
+    result
+}
+
+#[cfg(feature = "serde")]
+fn write_json(stats: &Stats, path: &std::path::Path) -> anyhow::Result<()> {
+    use anyhow::Context as _;
+
+    let json = serde_json::to_vec(stats)?;
+    std::fs::write(path, json)
+        .with_context(|| format!("failed to write stats to {}", path.display()))
+}
+
+#[cfg(not(feature = "serde"))]
+fn write_json(_stats: &Stats, _path: &std::path::Path) -> anyhow::Result<()> {
+    log::warn!(
+        "{} is set but git-remote-helper was built without the `serde` feature",
+        STATS_JSON
+    );
+    Ok(())
+}
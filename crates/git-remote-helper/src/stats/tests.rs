@@ -0,0 +1,7 @@
+use super::*;
+
+#[test]
+fn test_millis() {
+    assert_eq!(millis(Duration::from_micros(1500)), 1);
+    assert_eq!(millis(Duration::from_secs(2)), 2000);
+}
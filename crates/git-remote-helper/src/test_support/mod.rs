@@ -0,0 +1,24 @@
+use std::path::Path;
+
+/// Runs `git` in `dir` with a test identity and `main` as the default
+/// branch, returning its trimmed stdout.
+pub fn git<'a>(dir: &Path, args: impl IntoIterator<Item = &'a str>) -> String {
+    let output = std::process::Command::new("git")
+        .current_dir(dir)
+        .args([
+            "-c",
+            "user.name=Test",
+            "-c",
+            "user.email=0+test.users.noreply@codebase.org",
+            "-c",
+            "init.defaultBranch=main",
+        ])
+        .args(args)
+        .output()
+        .expect("git to run");
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout)
+        .expect("valid utf8")
+        .trim()
+        .to_string()
+}
@@ -0,0 +1,662 @@
+use super::*;
+
+#[test]
+fn test_read_line() {
+    let mut input: &[u8] = b"fetch 0123 refs/heads/main\n\nfetch 4567 refs/heads/topic";
+
+    assert_eq!(
+        read_line(&mut input).expect("line to be read"),
+        Some("fetch 0123 refs/heads/main".to_string()),
+        "command"
+    );
+    assert_eq!(
+        read_line(&mut input).expect("line to be read"),
+        Some("".to_string()),
+        "blank line"
+    );
+    assert_eq!(
+        read_line(&mut input).expect("line to be read"),
+        Some("fetch 4567 refs/heads/topic".to_string()),
+        "command without a newline"
+    );
+    assert_eq!(read_line(&mut input).expect("line to be read"), None, "eof");
+}
+
+#[test]
+fn test_pending_fetch_at_eof() {
+    // Only pushes are discarded, so with no push pending the fetch batch
+    // goes on to be processed.
+    let push = commands::push::Batch::new();
+
+    assert!(check_unterminated(&push).is_ok(), "fetch is processed");
+}
+
+#[test]
+fn test_pending_push_at_eof() {
+    let push = commands::push::Batch::from([commands::push::RefSpec::parse(
+        "refs/heads/main:refs/heads/main",
+    )
+    .expect("valid refspec")]);
+
+    assert_eq!(
+        check_unterminated(&push)
+            .expect_err("push to be discarded")
+            .to_string(),
+        "connection closed before batch terminator",
+        "error"
+    );
+}
+
+/// Runs the helper against `url` from `local`, feeding it `input` the way Git
+/// would and returning everything it wrote back.
+#[cfg(feature = "blocking-network-client")]
+fn run_helper(local: &std::path::Path, url: &str, input: &str) -> String {
+    let (output, result) = try_run_helper(local, url, input);
+    result.expect("helper to succeed");
+    output
+}
+
+/// Like `run_helper`, but also returns whether the helper succeeded.
+#[cfg(feature = "blocking-network-client")]
+fn try_run_helper(
+    local: &std::path::Path,
+    url: &str,
+    input: &str,
+) -> (String, Result<(), error::Error>) {
+    let args = Args {
+        repository: "origin".to_string(),
+        url: url.to_string(),
+        readonly: false,
+    };
+    try_run_helper_with(local, &args, input)
+}
+
+/// Like `try_run_helper`, but with the given `args`.
+#[cfg(feature = "blocking-network-client")]
+fn try_run_helper_with(
+    local: &std::path::Path,
+    args: &Args,
+    input: &str,
+) -> (String, Result<(), error::Error>) {
+    let repo = git::repository::open(local.join(".git")).expect("repository to open");
+    let mut output = Vec::new();
+
+    let result = run(
+        |url, options| transport::connect::<_, std::convert::Infallible>(url.as_str(), options),
+        args,
+        &repo,
+        &mut input.as_bytes(),
+        &mut output,
+    );
+
+    (String::from_utf8(output).expect("valid utf8"), result)
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_capabilities_list_and_fetch() {
+    use test_support::git;
+
+    let dir = tempfile::tempdir().expect("temp dir");
+    let remote = dir.path().join("remote.git");
+    let work = dir.path().join("work");
+    let local = dir.path().join("local");
+
+    git(dir.path(), ["init", "--bare", remote.to_str().unwrap()]);
+    git(dir.path(), ["init", work.to_str().unwrap()]);
+    git(&work, ["commit", "--allow-empty", "-m", "initial"]);
+    git(&work, ["push", remote.to_str().unwrap(), "main"]);
+    git(dir.path(), ["init", local.to_str().unwrap()]);
+
+    let head = git(&work, ["rev-parse", "HEAD"]);
+    let url = format!("file://{}", remote.display());
+
+    let output = run_helper(
+        &local,
+        &url,
+        &format!(
+            "capabilities\noption object-format true\nlist\nfetch {} refs/heads/main\n\n",
+            head
+        ),
+    );
+
+    assert_eq!(
+        output,
+        format!(
+            "fetch\noption\npush\nobject-format\ncheck-connectivity\nstateless-connect\n\n\
+             ok\n\
+             :object-format sha1\n@refs/heads/main HEAD\n{head} refs/heads/main\n\n\
+             \n",
+            head = head
+        ),
+        "output"
+    );
+    assert_eq!(
+        git(&local, ["cat-file", "-t", &head]),
+        "commit",
+        "fetched object"
+    );
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_fetch_with_check_connectivity() {
+    use test_support::git;
+
+    let dir = tempfile::tempdir().expect("temp dir");
+    let remote = dir.path().join("remote.git");
+    let work = dir.path().join("work");
+    let local = dir.path().join("local");
+
+    git(dir.path(), ["init", "--bare", remote.to_str().unwrap()]);
+    git(dir.path(), ["init", work.to_str().unwrap()]);
+    git(&work, ["commit", "--allow-empty", "-m", "initial"]);
+    git(&work, ["commit", "--allow-empty", "-m", "second"]);
+    git(&work, ["push", remote.to_str().unwrap(), "main"]);
+    git(dir.path(), ["init", local.to_str().unwrap()]);
+
+    let head = git(&work, ["rev-parse", "HEAD"]);
+    let url = format!("file://{}", remote.display());
+
+    let output = run_helper(
+        &local,
+        &url,
+        &format!(
+            "capabilities\noption check-connectivity true\nfetch {} refs/heads/main\n\n",
+            head
+        ),
+    );
+
+    assert_eq!(
+        output,
+        "fetch\noption\npush\nobject-format\ncheck-connectivity\nstateless-connect\n\n\
+         ok\n\
+         connectivity-ok\n\n",
+        "output"
+    );
+    assert_eq!(
+        git(&local, ["cat-file", "-t", &format!("{}~1", head)]),
+        "commit",
+        "fetched parent"
+    );
+}
+
+#[cfg(all(feature = "blocking-network-client", feature = "serde"))]
+#[test]
+fn test_stats_json_keys_after_fetch() {
+    use test_support::git;
+
+    let dir = tempfile::tempdir().expect("temp dir");
+    let remote = dir.path().join("remote.git");
+    let work = dir.path().join("work");
+    let local = dir.path().join("local");
+    let stats_json = dir.path().join("stats.json");
+
+    git(dir.path(), ["init", "--bare", remote.to_str().unwrap()]);
+    git(dir.path(), ["init", work.to_str().unwrap()]);
+    git(&work, ["commit", "--allow-empty", "-m", "initial"]);
+    git(&work, ["push", remote.to_str().unwrap(), "main"]);
+    git(dir.path(), ["init", local.to_str().unwrap()]);
+
+    let head = git(&work, ["rev-parse", "HEAD"]);
+    let url = format!("file://{}", remote.display());
+
+    stats::with_path(&stats_json, || {
+        run_helper(&local, &url, &format!("fetch {} refs/heads/main\n\n", head))
+    });
+
+    let json: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&stats_json).expect("stats to be written"))
+            .expect("valid json");
+
+    let keys = |value: &serde_json::Value| {
+        let mut keys = value
+            .as_object()
+            .expect("an object")
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys
+    };
+
+    assert_eq!(
+        keys(&json),
+        vec!["canister_calls", "fetch", "push", "retries"],
+        "keys"
+    );
+    assert_eq!(
+        keys(&json["fetch"]),
+        vec!["bytes", "objects", "prepare_ms", "receive_ms"],
+        "fetch keys"
+    );
+    // The commit and its empty tree
+    assert_eq!(json["fetch"]["objects"], 2, "objects");
+    assert!(json["push"].is_null(), "no push");
+}
+
+/// Git prunes remote-tracking refs with `git fetch --prune` by comparing them
+/// against what `list` reports, so a ref deleted on the remote only has to be
+/// left out.
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_list_omits_deleted_refs() {
+    use test_support::git;
+
+    let dir = tempfile::tempdir().expect("temp dir");
+    let remote = dir.path().join("remote.git");
+    let work = dir.path().join("work");
+    let local = dir.path().join("local");
+
+    git(dir.path(), ["init", "--bare", remote.to_str().unwrap()]);
+    git(dir.path(), ["init", work.to_str().unwrap()]);
+    git(&work, ["commit", "--allow-empty", "-m", "initial"]);
+    git(&work, ["branch", "topic"]);
+    git(&work, ["push", remote.to_str().unwrap(), "main", "topic"]);
+    git(dir.path(), ["init", local.to_str().unwrap()]);
+
+    let head = git(&work, ["rev-parse", "HEAD"]);
+    let url = format!("file://{}", remote.display());
+
+    let before = run_helper(&local, &url, "list\n");
+    assert!(
+        before.contains(&format!("{} refs/heads/topic\n", head)),
+        "listed before deleting: {}",
+        before
+    );
+
+    git(
+        &work,
+        ["push", remote.to_str().unwrap(), "--delete", "topic"],
+    );
+
+    let after = run_helper(&local, &url, "list\n");
+    assert!(
+        !after.contains("refs/heads/topic"),
+        "listed after deleting: {}",
+        after
+    );
+    assert!(
+        after.contains(&format!("{} refs/heads/main\n", head)),
+        "main listed after deleting: {}",
+        after
+    );
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_fetch_when_input_closes_without_blank_line() {
+    use test_support::git;
+
+    let dir = tempfile::tempdir().expect("temp dir");
+    let remote = dir.path().join("remote.git");
+    let work = dir.path().join("work");
+    let local = dir.path().join("local");
+
+    git(dir.path(), ["init", "--bare", remote.to_str().unwrap()]);
+    git(dir.path(), ["init", work.to_str().unwrap()]);
+    git(&work, ["commit", "--allow-empty", "-m", "initial"]);
+    git(&work, ["push", remote.to_str().unwrap(), "main"]);
+    git(dir.path(), ["init", local.to_str().unwrap()]);
+
+    let head = git(&work, ["rev-parse", "HEAD"]);
+    let url = format!("file://{}", remote.display());
+
+    // The pending fetch is still processed, then the helper exits.
+    let output = run_helper(&local, &url, &format!("fetch {} refs/heads/main\n", head));
+
+    assert_eq!(output, "\n", "output");
+    assert_eq!(
+        git(&local, ["cat-file", "-t", &head]),
+        "commit",
+        "fetched object"
+    );
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_push_when_input_closes_without_blank_line() {
+    use test_support::git;
+
+    let dir = tempfile::tempdir().expect("temp dir");
+    let remote = dir.path().join("remote.git");
+    let local = dir.path().join("local");
+
+    git(dir.path(), ["init", "--bare", remote.to_str().unwrap()]);
+    git(dir.path(), ["init", local.to_str().unwrap()]);
+    git(&local, ["commit", "--allow-empty", "-m", "local"]);
+
+    let url = format!("file://{}", remote.display());
+
+    let (output, result) = try_run_helper(&local, &url, "push refs/heads/main:refs/heads/main");
+
+    assert_eq!(output, "", "output");
+    let err = result.expect_err("push to be discarded");
+    assert_eq!(
+        err.to_string(),
+        "connection closed before batch terminator",
+        "error"
+    );
+    assert_eq!(err.kind, error::Kind::Input, "kind");
+    assert_eq!(
+        git(&remote, ["for-each-ref"]),
+        "",
+        "the remote is unchanged"
+    );
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_dry_run_push_leaves_remote_unchanged() {
+    use test_support::git;
+
+    let dir = tempfile::tempdir().expect("temp dir");
+    let remote = dir.path().join("remote.git");
+    let local = dir.path().join("local");
+
+    git(dir.path(), ["init", "--bare", remote.to_str().unwrap()]);
+    git(dir.path(), ["init", local.to_str().unwrap()]);
+    git(&local, ["commit", "--allow-empty", "-m", "initial"]);
+
+    let url = format!("file://{}", remote.display());
+
+    let output = run_helper(
+        &local,
+        &url,
+        "option dry-run true\npush refs/heads/main:refs/heads/main\n\n",
+    );
+
+    assert_eq!(output, "ok\nok refs/heads/main\n\n", "output");
+    assert_eq!(git(&remote, ["for-each-ref"]), "", "remote refs");
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_dry_run_fast_forward_push_is_accepted() {
+    use test_support::git;
+
+    let dir = tempfile::tempdir().expect("temp dir");
+    let remote = dir.path().join("remote.git");
+    let local = dir.path().join("local");
+
+    git(dir.path(), ["init", "--bare", remote.to_str().unwrap()]);
+    git(dir.path(), ["init", local.to_str().unwrap()]);
+    git(&local, ["commit", "--allow-empty", "-m", "initial"]);
+    git(&local, ["push", remote.to_str().unwrap(), "main"]);
+    git(&local, ["commit", "--allow-empty", "-m", "second"]);
+
+    let remote_head = git(&remote, ["rev-parse", "main"]);
+    let url = format!("file://{}", remote.display());
+
+    let output = run_helper(
+        &local,
+        &url,
+        "option dry-run true\npush refs/heads/main:refs/heads/main\n\n",
+    );
+
+    assert_eq!(output, "ok\nok refs/heads/main\n\n", "output");
+    assert_eq!(
+        git(&remote, ["rev-parse", "main"]),
+        remote_head,
+        "remote ref"
+    );
+}
+
+/// Without this, `git push --dry-run` would report a push the remote is
+/// going to reject as one that would succeed.
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_dry_run_non_fast_forward_push_is_rejected() {
+    use test_support::git;
+
+    let dir = tempfile::tempdir().expect("temp dir");
+    let remote = dir.path().join("remote.git");
+    let work = dir.path().join("work");
+    let local = dir.path().join("local");
+
+    git(dir.path(), ["init", "--bare", remote.to_str().unwrap()]);
+    git(dir.path(), ["init", work.to_str().unwrap()]);
+    git(&work, ["commit", "--allow-empty", "-m", "remote"]);
+    git(&work, ["push", remote.to_str().unwrap(), "main"]);
+    git(dir.path(), ["init", local.to_str().unwrap()]);
+    git(&local, ["commit", "--allow-empty", "-m", "local"]);
+
+    let url = format!("file://{}", remote.display());
+
+    let (output, result) = try_run_helper(
+        &local,
+        &url,
+        "option dry-run true\npush refs/heads/main:refs/heads/main\n\n",
+    );
+
+    assert_eq!(
+        output, "ok\nerror refs/heads/main non-fast-forward\n\n",
+        "output"
+    );
+    assert!(result.is_err(), "push fails");
+
+    let output = run_helper(
+        &local,
+        &url,
+        "option dry-run true\npush +refs/heads/main:refs/heads/main\n\n",
+    );
+
+    assert_eq!(output, "ok\nok refs/heads/main\n\n", "forced output");
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_non_fast_forward_push_is_rejected() {
+    use test_support::git;
+
+    let dir = tempfile::tempdir().expect("temp dir");
+    let remote = dir.path().join("remote.git");
+    let work = dir.path().join("work");
+    let local = dir.path().join("local");
+
+    git(dir.path(), ["init", "--bare", remote.to_str().unwrap()]);
+    // Git checks this itself before pushing, so the remote has to.
+    git(&remote, ["config", "receive.denyNonFastForwards", "true"]);
+    git(dir.path(), ["init", work.to_str().unwrap()]);
+    git(&work, ["commit", "--allow-empty", "-m", "remote"]);
+    git(&work, ["push", remote.to_str().unwrap(), "main"]);
+    git(dir.path(), ["init", local.to_str().unwrap()]);
+    git(&local, ["commit", "--allow-empty", "-m", "local"]);
+
+    let remote_head = git(&remote, ["rev-parse", "main"]);
+    let url = format!("file://{}", remote.display());
+
+    let (output, result) = try_run_helper(&local, &url, "push refs/heads/main:refs/heads/main\n\n");
+
+    // Git shows its usual hint about fetching first for exactly this reason.
+    assert_eq!(
+        output, "error refs/heads/main non-fast-forward\n\n",
+        "output"
+    );
+    assert!(result.is_err(), "push fails");
+    assert_eq!(
+        git(&remote, ["rev-parse", "main"]),
+        remote_head,
+        "remote ref"
+    );
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_push_is_rejected_when_readonly() {
+    use test_support::git;
+
+    let dir = tempfile::tempdir().expect("temp dir");
+    let remote = dir.path().join("remote.git");
+    let local = dir.path().join("local");
+
+    git(dir.path(), ["init", "--bare", remote.to_str().unwrap()]);
+    git(dir.path(), ["init", local.to_str().unwrap()]);
+    git(&local, ["commit", "--allow-empty", "-m", "local"]);
+
+    let url = format!("file://{}", remote.display());
+    let args = Args {
+        repository: "origin".to_string(),
+        url: url.clone(),
+        readonly: true,
+    };
+
+    let (output, result) = try_run_helper_with(
+        &local,
+        &args,
+        "capabilities\npush refs/heads/main:refs/heads/main\n\n",
+    );
+
+    assert!(
+        !output.contains("push"),
+        "push isn't advertised: {}",
+        output
+    );
+    let err = result.expect_err("push to be refused");
+    assert_eq!(
+        err.to_string(),
+        format!("refusing to push to {} since ICP_READONLY is set", url),
+        "error"
+    );
+    assert_eq!(err.kind, error::Kind::Push, "kind");
+    assert_eq!(
+        git(&remote, ["for-each-ref"]),
+        "",
+        "the remote is unchanged"
+    );
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_push_with_stale_lease_is_rejected() {
+    use test_support::git;
+
+    let dir = tempfile::tempdir().expect("temp dir");
+    let remote = dir.path().join("remote.git");
+    let local = dir.path().join("local");
+
+    git(dir.path(), ["init", "--bare", remote.to_str().unwrap()]);
+    git(dir.path(), ["init", local.to_str().unwrap()]);
+    git(&local, ["commit", "--allow-empty", "-m", "initial"]);
+    git(&local, ["push", remote.to_str().unwrap(), "main"]);
+    git(&local, ["commit", "--allow-empty", "-m", "second"]);
+
+    let remote_head = git(&remote, ["rev-parse", "main"]);
+    let url = format!("file://{}", remote.display());
+
+    // The lease expects `main` not to exist.
+    let (output, result) = try_run_helper(
+        &local,
+        &url,
+        &format!(
+            "option cas refs/heads/main:{}\npush +refs/heads/main:refs/heads/main\n\n",
+            gitoxide::hash::Kind::Sha1.null()
+        ),
+    );
+
+    assert_eq!(output, "ok\nerror refs/heads/main stale info\n\n", "output");
+    assert!(result.is_err(), "push fails");
+    assert_eq!(
+        git(&remote, ["rev-parse", "main"]),
+        remote_head,
+        "remote ref"
+    );
+}
+
+/// A transport to a server that fails the handshake when asked for protocol
+/// v2, rather than ignoring `version=2` as it's expected to.
+#[cfg(feature = "blocking-network-client")]
+struct RejectsV2;
+
+#[cfg(feature = "blocking-network-client")]
+impl transport::client::TransportWithoutIO for RejectsV2 {
+    fn request(
+        &mut self,
+        _write_mode: transport::client::WriteMode,
+        _on_into_read: transport::client::MessageKind,
+    ) -> Result<transport::client::RequestWriter<'_>, transport::client::Error> {
+        unreachable!("the handshake fails first")
+    }
+
+    fn to_url(&self) -> std::borrow::Cow<'_, gitoxide::bstr::BStr> {
+        std::borrow::Cow::Borrowed(gitoxide::bstr::BStr::new("file:///rejects-v2"))
+    }
+
+    fn connection_persistent_by_default(&self) -> bool {
+        false
+    }
+
+    fn configure(
+        &mut self,
+        _config: &dyn std::any::Any,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "blocking-network-client")]
+impl transport::client::Transport for RejectsV2 {
+    fn handshake<'a>(
+        &mut self,
+        _service: transport::Service,
+        _extra_parameters: &'a [(&'a str, Option<&'a str>)],
+    ) -> Result<transport::client::SetServiceResponse<'_>, transport::client::Error> {
+        Err(std::io::Error::new(std::io::ErrorKind::Other, "unsupported protocol version 2").into())
+    }
+}
+
+#[cfg(feature = "blocking-network-client")]
+#[test]
+fn test_list_retries_with_v1_when_v2_is_rejected() {
+    use test_support::git;
+
+    let dir = tempfile::tempdir().expect("temp dir");
+    let remote = dir.path().join("remote.git");
+    let local = dir.path().join("local");
+
+    git(dir.path(), ["init", "--bare", remote.to_str().unwrap()]);
+    git(dir.path(), ["init", local.to_str().unwrap()]);
+    git(&local, ["commit", "--allow-empty", "-m", "initial"]);
+    git(&local, ["push", remote.to_str().unwrap(), "main"]);
+
+    let head = git(&local, ["rev-parse", "HEAD"]);
+    let url = format!("file://{}", remote.display());
+    let args = Args {
+        repository: "origin".to_string(),
+        url,
+        readonly: false,
+    };
+    let repo = git::repository::open(local.join(".git")).expect("repository to open");
+    let versions = std::cell::RefCell::new(Vec::new());
+    let mut output = Vec::new();
+
+    run(
+        |url, options| {
+            versions.borrow_mut().push(options.version);
+
+            if options.version == transport::Protocol::V2 {
+                let transport: Box<dyn transport::client::Transport + Send> = Box::new(RejectsV2);
+                Ok(transport)
+            } else {
+                transport::connect::<_, std::convert::Infallible>(url.as_str(), options)
+            }
+        },
+        &args,
+        &repo,
+        &mut "list\n".as_bytes(),
+        &mut output,
+    )
+    .expect("list to succeed");
+
+    assert_eq!(
+        versions.borrow()[..2],
+        [transport::Protocol::V2, transport::Protocol::V1],
+        "versions"
+    );
+    let output = String::from_utf8(output).expect("valid utf8");
+    assert!(
+        output.contains(&format!("{} refs/heads/main\n", head)),
+        "listed with v1: {}",
+        output
+    );
+}
@@ -0,0 +1,48 @@
+//! Logging that follows Git's `-q` and `-v` flags.
+//!
+//! Git passes its verbosity to the helper with `option verbosity <n>`, where
+//! `0` is quiet and higher is more verbose. Unless `RUST_LOG` is set, which
+//! always takes precedence, that verbosity sets the log level.
+
+use log::LevelFilter;
+
+#[cfg(test)]
+mod tests;
+
+pub(crate) const RUST_LOG: &str = "RUST_LOG";
+
+/// The verbosity Git uses when neither `-q` nor `-v` is given.
+pub const DEFAULT: usize = 1;
+
+/// Initializes logging to stderr, at the level for the default verbosity
+/// unless `RUST_LOG` is set.
+pub fn init_logger() {
+    if std::env::var_os(RUST_LOG).is_some() {
+        env_logger::init();
+    } else {
+        // The logger lets everything through so that the maximum level alone
+        // decides what is logged, and can be raised later.
+        env_logger::Builder::new()
+            .filter_level(LevelFilter::Trace)
+            .init();
+        log::set_max_level(level(DEFAULT));
+    }
+}
+
+/// Sets the log level for `verbosity`, unless `RUST_LOG` is set.
+pub fn set(verbosity: usize) {
+    if std::env::var_os(RUST_LOG).is_none() {
+        log::set_max_level(level(verbosity));
+    }
+}
+
+/// The log level for `verbosity`.
+pub fn level(verbosity: usize) -> LevelFilter {
+    match verbosity {
+        0 => LevelFilter::Error,
+        1 => LevelFilter::Warn,
+        2 => LevelFilter::Info,
+        3 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
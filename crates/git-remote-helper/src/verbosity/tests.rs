@@ -0,0 +1,11 @@
+use super::*;
+
+#[test]
+fn test_level() {
+    assert_eq!(level(0), LevelFilter::Error, "quiet");
+    assert_eq!(level(DEFAULT), LevelFilter::Warn, "default");
+    assert_eq!(level(2), LevelFilter::Info, "-v");
+    assert_eq!(level(3), LevelFilter::Debug, "-vv");
+    assert_eq!(level(4), LevelFilter::Trace, "-vvv");
+    assert_eq!(level(10), LevelFilter::Trace, "more");
+}
@@ -2,8 +2,18 @@ mod connect;
 
 use connect::connect;
 use git_remote_helper;
+use git_remote_helper::cli::Usage;
+
+const USAGE: Usage = Usage {
+    name: env!("CARGO_PKG_NAME"),
+    version: env!("CARGO_PKG_VERSION"),
+    about: env!("CARGO_PKG_DESCRIPTION"),
+    env: &[],
+};
 
 pub fn main() -> anyhow::Result<()> {
-    env_logger::init();
-    git_remote_helper::main(connect)
+    USAGE.exit_if_requested();
+
+    git_remote_helper::verbosity::init_logger();
+    git_remote_helper::error::report(git_remote_helper::main(connect))
 }
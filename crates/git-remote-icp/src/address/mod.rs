@@ -0,0 +1,119 @@
+use git::bstr::{BString, ByteSlice as _};
+use git::url::Scheme;
+use git_repository as git;
+use ic_agent::export::Principal;
+
+#[cfg(test)]
+mod tests;
+
+/// A canister and the replica to reach it through, parsed from a URL of the
+/// form `icp://<canister-id>.<replica-host>/<path>` or
+/// `icp://<replica-host>/<canister-id>/<path>`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Address {
+    pub canister_id: Principal,
+    pub replica_url: String,
+    /// The URL to request from the canister.
+    pub url: git::Url,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid canister id `{text}` in URL: {source}")]
+    InvalidCanisterId {
+        text: String,
+        #[source]
+        source: candid::types::principal::PrincipalError,
+    },
+    #[error("URL has no host")]
+    MissingHost,
+}
+
+/// Parses the canister id and replica URL out of `url`, which is expected
+/// to have had its scheme resolved to `http` or `https`.
+///
+/// Returns `Ok(None)` if `url` doesn't contain anything resembling a canister
+/// id, in which case the configured canister id should be used.
+pub fn parse(url: &git::Url) -> Result<Option<Address>, Error> {
+    let host = url.host().ok_or(Error::MissingHost)?;
+
+    let scheme = match url.scheme {
+        Scheme::Http => "http",
+        _ => "https",
+    };
+
+    let port = url
+        .port
+        .map(|port| format!(":{}", port))
+        .unwrap_or_default();
+
+    // icp://<canister-id>.<replica-host>/<path>
+    if let Some((label, replica_host)) = host.split_once('.') {
+        if looks_like_principal(label) {
+            let canister_id = parse_principal(label)?;
+
+            // Boundary nodes serve canisters on `<canister-id>.raw.<domain>`
+            // without certification but agents talk to `<domain>` directly.
+            let replica_host = replica_host.strip_prefix("raw.").unwrap_or(replica_host);
+
+            return Ok(Some(Address {
+                canister_id,
+                replica_url: format!("{}://{}{}", scheme, replica_host, port),
+                url: url.clone(),
+            }));
+        }
+    }
+
+    // icp://<replica-host>/<canister-id>/<path>
+    let path = url.path.to_str_lossy();
+    let path = path.trim_start_matches('/');
+    let (segment, rest) = path.split_once('/').unwrap_or((path, ""));
+
+    if looks_like_principal(segment) {
+        let canister_id = parse_principal(segment)?;
+
+        let mut url = url.clone();
+        url.path = BString::from(format!("/{}", rest));
+
+        return Ok(Some(Address {
+            canister_id,
+            replica_url: format!("{}://{}{}", scheme, host, port),
+            url,
+        }));
+    }
+
+    Ok(None)
+}
+
+fn parse_principal(text: &str) -> Result<Principal, Error> {
+    Principal::from_text(text).map_err(|source| Error::InvalidCanisterId {
+        text: text.to_string(),
+        source,
+    })
+}
+
+// The textual representation of a principal is base32 (lowercase, without
+// padding) split into groups of 5 characters with `-`. The final group may be
+// shorter. Matching on this shape lets us report a checksum error for
+// something that was clearly intended as a canister id, rather than silently
+// ignoring it.
+fn looks_like_principal(text: &str) -> bool {
+    let groups = text.split('-').collect::<Vec<_>>();
+
+    let is_base32 = |group: &str| {
+        group
+            .chars()
+            .all(|chr| chr.is_ascii_lowercase() || ('2'..='7').contains(&chr))
+    };
+
+    match groups.split_last() {
+        Some((last, init)) if !init.is_empty() => {
+            init.iter()
+                .all(|group| group.len() == 5 && is_base32(group))
+                && !last.is_empty()
+                && last.len() <= 5
+                && is_base32(last)
+        }
+        _ => false,
+    }
+}
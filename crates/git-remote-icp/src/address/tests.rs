@@ -0,0 +1,91 @@
+use super::*;
+
+const CANISTER_ID: &str = "w7uni-tiaaa-aaaam-qaydq-cai";
+
+fn url(input: &str) -> git::Url {
+    git::url::parse(input.into()).expect("valid URL")
+}
+
+#[test]
+fn test_parse_canister_id_subdomain() {
+    let result = parse(&url(
+        "https://w7uni-tiaaa-aaaam-qaydq-cai.raw.ic0.app/@paul/hello-world.git",
+    ))
+    .expect("valid address");
+
+    assert_eq!(
+        result,
+        Some(Address {
+            canister_id: Principal::from_text(CANISTER_ID).unwrap(),
+            replica_url: "https://ic0.app".to_string(),
+            url: url("https://w7uni-tiaaa-aaaam-qaydq-cai.raw.ic0.app/@paul/hello-world.git"),
+        })
+    )
+}
+
+#[test]
+fn test_parse_canister_id_subdomain_with_port() {
+    let result = parse(&url(
+        "http://rwlgt-iiaaa-aaaaa-aaaaa-cai.raw.ic0.localhost:8453/@paul/hello-world.git",
+    ))
+    .expect("valid address");
+
+    assert_eq!(
+        result.map(|address| (address.canister_id, address.replica_url)),
+        Some((
+            Principal::from_text("rwlgt-iiaaa-aaaaa-aaaaa-cai").unwrap(),
+            "http://ic0.localhost:8453".to_string(),
+        ))
+    )
+}
+
+#[test]
+fn test_parse_canister_id_path_segment() {
+    let result = parse(&url(
+        "https://ic0.app/w7uni-tiaaa-aaaam-qaydq-cai/@paul/hello-world.git",
+    ))
+    .expect("valid address");
+
+    assert_eq!(
+        result,
+        Some(Address {
+            canister_id: Principal::from_text(CANISTER_ID).unwrap(),
+            replica_url: "https://ic0.app".to_string(),
+            url: url("https://ic0.app/@paul/hello-world.git"),
+        })
+    )
+}
+
+#[test]
+fn test_parse_no_canister_id() {
+    let result = parse(&url(
+        "http://git.codebase.ic0.localhost:8453/@paul/hello-world.git",
+    ))
+    .expect("valid address");
+
+    assert_eq!(result, None)
+}
+
+#[test]
+fn test_parse_invalid_canister_id() {
+    let result = parse(&url(
+        "https://aaaaa-tiaaa-aaaam-qaydq-cai.raw.ic0.app/@paul/hello-world.git",
+    ));
+
+    match result {
+        Err(Error::InvalidCanisterId { text, .. }) => {
+            assert_eq!(text, "aaaaa-tiaaa-aaaam-qaydq-cai")
+        }
+        result => panic!("expected an invalid canister id, got {:?}", result),
+    }
+}
+
+#[test]
+fn test_looks_like_principal() {
+    assert!(looks_like_principal(CANISTER_ID));
+    assert!(looks_like_principal("aaaaa-aa"));
+    assert!(!looks_like_principal("git"));
+    assert!(!looks_like_principal("my-host"));
+    assert!(!looks_like_principal("@paul"));
+    assert!(!looks_like_principal(""));
+}
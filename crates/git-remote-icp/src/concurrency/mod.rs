@@ -0,0 +1,79 @@
+use crate::config;
+use log::trace;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, PoisonError};
+
+#[cfg(test)]
+mod tests;
+
+static CANISTER_CALLS: Semaphore = Semaphore::new(0);
+
+/// Makes a canister call once fewer than the maximum number of calls are in
+/// flight, so that concurrent requests don't overwhelm a boundary node.
+pub fn canister_call<T>(call: impl FnOnce() -> T) -> T {
+    let _permit = CANISTER_CALLS.acquire();
+    call()
+}
+
+pub struct Semaphore {
+    /// Zero until resolved from config on first use.
+    max: AtomicUsize,
+    in_flight: Mutex<usize>,
+    released: Condvar,
+}
+
+impl Semaphore {
+    /// A `max` of zero is read from config on first use.
+    pub const fn new(max: usize) -> Self {
+        Self {
+            max: AtomicUsize::new(max),
+            in_flight: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    pub fn acquire(&self) -> Permit<'_> {
+        let max = self.max();
+        let mut in_flight = self
+            .in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        while *in_flight >= max {
+            trace!("waiting for one of {} canister calls to finish", in_flight);
+            in_flight = self
+                .released
+                .wait(in_flight)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+
+        *in_flight += 1;
+        Permit(self)
+    }
+
+    fn max(&self) -> usize {
+        match self.max.load(Ordering::Relaxed) {
+            0 => {
+                let max = config::max_concurrency();
+                self.max.store(max, Ordering::Relaxed);
+                max
+            }
+            max => max,
+        }
+    }
+}
+
+/// Releases its place in the semaphore when dropped.
+pub struct Permit<'a>(&'a Semaphore);
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let mut in_flight = self
+            .0
+            .in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *in_flight -= 1;
+        self.0.released.notify_one();
+    }
+}
@@ -0,0 +1,51 @@
+use super::*;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Default)]
+struct Counter {
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+}
+
+impl Counter {
+    fn call(&self) {
+        let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(10));
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn test_semaphore_bounds_calls_in_flight() {
+    let semaphore = Arc::new(Semaphore::new(3));
+    let counter = Arc::new(Counter::default());
+
+    let handles = (0..16)
+        .map(|_| {
+            let semaphore = semaphore.clone();
+            let counter = counter.clone();
+            std::thread::spawn(move || {
+                let _permit = semaphore.acquire();
+                counter.call();
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for handle in handles {
+        handle.join().expect("no panic");
+    }
+
+    let max_in_flight = counter.max_in_flight.load(Ordering::SeqCst);
+    assert!(
+        max_in_flight <= 3,
+        "expected no more than 3 calls in flight, got {}",
+        max_in_flight
+    );
+    assert_eq!(
+        *semaphore.in_flight.lock().expect("lock"),
+        0,
+        "permits released"
+    )
+}
@@ -1,6 +1,10 @@
 use anyhow::anyhow;
 use git_remote_helper::git;
 use ic_agent::export::Principal;
+use std::time::Duration;
+
+#[cfg(test)]
+mod tests;
 
 const CANISTER_ID_KEY: &str = "icp.canisterId";
 const DEFAULT_CANISTER_ID: &str = "w7uni-tiaaa-aaaam-qaydq-cai";
@@ -21,12 +25,56 @@ pub fn fetch_root_key() -> bool {
         .unwrap_or(DEFAULT_FETCH_ROOT_KEY)
 }
 
+pub const ANONYMOUS_ENV_VAR: &str = "ICP_ANONYMOUS";
+
+/// Whether to make calls with the anonymous identity even when a private key
+/// is configured, read from `ICP_ANONYMOUS`.
+pub fn anonymous() -> bool {
+    env_enabled(ANONYMOUS_ENV_VAR)
+}
+
+/// Whether the environment variable `name` is set to enable something.
+pub(crate) fn env_enabled(name: &str) -> bool {
+    std::env::var(name).map_or(false, |value| is_enabled(&value))
+}
+
+fn is_enabled(value: &str) -> bool {
+    matches!(value.to_ascii_lowercase().as_str(), "1" | "true" | "yes")
+}
+
+pub const IDENTITY_PEM_ENV_VAR: &str = "ICP_IDENTITY_PEM";
 const PRIVATE_KEY_KEY: &str = "icp.privateKey";
 
+/// The path to a PEM file, read from `ICP_IDENTITY_PEM` or else from the
+/// `icp.privateKey` git config.
 pub fn private_key() -> anyhow::Result<String> {
-    git::config::get(PRIVATE_KEY_KEY).map_err(|_| {
-        anyhow!("failed to read icp.privateKey from git config. Set `icp.privateKey = <path to private key>`")
-    })
+    match std::env::var(IDENTITY_PEM_ENV_VAR) {
+        Ok(path) if !path.is_empty() => Ok(path),
+        _ => git::config::get(PRIVATE_KEY_KEY).map_err(|_| {
+            anyhow!("failed to read {} or icp.privateKey from git config. Set `{}=<path to private key>` or `icp.privateKey = <path to private key>`", IDENTITY_PEM_ENV_VAR, IDENTITY_PEM_ENV_VAR)
+        }),
+    }
+}
+
+pub const DELEGATION_ENV_VAR: &str = "ICP_DELEGATION";
+
+/// Fails when `ICP_DELEGATION` is set. The version of ic-agent the helper is
+/// built with can't make calls with a delegation chain, and ignoring it would
+/// make them as another principal than the one it delegates from.
+pub fn check_delegation() -> anyhow::Result<()> {
+    parse_delegation(std::env::var(DELEGATION_ENV_VAR).ok())
+}
+
+fn parse_delegation(value: Option<String>) -> anyhow::Result<()> {
+    match value {
+        Some(path) if !path.is_empty() => Err(anyhow!(
+            "{} is set to `{}`, but delegation chains aren't supported. Unset it to sign with {} or icp.privateKey",
+            DELEGATION_ENV_VAR,
+            path,
+            IDENTITY_PEM_ENV_VAR
+        )),
+        _ => Ok(()),
+    }
 }
 
 const REPLICA_URL_KEY: &str = "icp.replicaUrl";
@@ -35,3 +83,218 @@ const DEFAULT_REPLICA_URL: &str = "https://ic0.app";
 pub fn replica_url() -> String {
     git::config::get(REPLICA_URL_KEY).unwrap_or_else(|_| DEFAULT_REPLICA_URL.to_string())
 }
+
+pub const REPLICA_URL_ENV_VAR: &str = "ICP_REPLICA_URL";
+
+/// A replica URL from `ICP_REPLICA_URL` to use instead of the one in the
+/// remote's URL or `icp.replicaUrl`, for routing through a local replica or a
+/// particular boundary node.
+pub fn replica_url_override() -> anyhow::Result<Option<String>> {
+    match std::env::var(REPLICA_URL_ENV_VAR) {
+        Ok(value) if !value.is_empty() => parse_replica_url(&value).map(Some),
+        _ => Ok(None),
+    }
+}
+
+fn parse_replica_url(value: &str) -> anyhow::Result<String> {
+    let url = reqwest::Url::parse(value)
+        .map_err(|err| anyhow!("invalid {} `{}`: {}", REPLICA_URL_ENV_VAR, value, err))?;
+
+    if !matches!(url.scheme(), "http" | "https") || !url.has_host() {
+        return Err(anyhow!(
+            "invalid {} `{}`: expected an http or https URL with a host",
+            REPLICA_URL_ENV_VAR,
+            value
+        ));
+    }
+
+    Ok(value.to_string())
+}
+
+pub const USER_AGENT_ENV_VAR: &str = "ICP_USER_AGENT";
+
+/// A `User-Agent` from `ICP_USER_AGENT` to send instead of the default.
+pub fn user_agent() -> Option<String> {
+    std::env::var(USER_AGENT_ENV_VAR)
+        .ok()
+        .filter(|user_agent| !user_agent.is_empty())
+}
+
+pub const REQUEST_TIMEOUT_ENV_VAR: &str = "ICP_REQUEST_TIMEOUT";
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 60;
+
+/// How long to wait for a canister call, read in seconds from
+/// `ICP_REQUEST_TIMEOUT`.
+pub fn request_timeout() -> Duration {
+    let secs = std::env::var(REQUEST_TIMEOUT_ENV_VAR)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(DEFAULT_REQUEST_TIMEOUT_SECS);
+    Duration::from_secs(secs)
+}
+
+pub const MAX_CONCURRENCY_ENV_VAR: &str = "GIT_REMOTE_ICP_MAX_CONCURRENCY";
+const DEFAULT_MAX_CONCURRENCY: usize = 8;
+
+/// The maximum number of canister calls in flight at once, read from
+/// `GIT_REMOTE_ICP_MAX_CONCURRENCY`.
+pub fn max_concurrency() -> usize {
+    std::env::var(MAX_CONCURRENCY_ENV_VAR)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|max| *max > 0)
+        .unwrap_or(DEFAULT_MAX_CONCURRENCY)
+}
+
+pub const UPLOAD_CHUNK_SIZE_ENV_VAR: &str = "GIT_REMOTE_ICP_UPLOAD_CHUNK_SIZE";
+// Well under the 2MiB limit on ingress messages, which includes the envelope
+// and the rest of the request.
+const DEFAULT_UPLOAD_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// The maximum number of bytes of a request body to send per canister call,
+/// read from `GIT_REMOTE_ICP_UPLOAD_CHUNK_SIZE`.
+pub fn upload_chunk_size() -> usize {
+    std::env::var(UPLOAD_CHUNK_SIZE_ENV_VAR)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|size| *size > 0)
+        .unwrap_or(DEFAULT_UPLOAD_CHUNK_SIZE)
+}
+
+pub const FETCH_CONCURRENCY_ENV_VAR: &str = "ICP_FETCH_CONCURRENCY";
+const DEFAULT_FETCH_CONCURRENCY: usize = 4;
+// Each chunk is a query of its own, and also counts towards
+// `GIT_REMOTE_ICP_MAX_CONCURRENCY`.
+const MAX_FETCH_CONCURRENCY: usize = 16;
+
+/// How many chunks of a streamed response body to request at once, read from
+/// `ICP_FETCH_CONCURRENCY`.
+pub fn fetch_concurrency() -> usize {
+    std::env::var(FETCH_CONCURRENCY_ENV_VAR)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|concurrency| *concurrency > 0)
+        .unwrap_or(DEFAULT_FETCH_CONCURRENCY)
+        .min(MAX_FETCH_CONCURRENCY)
+}
+
+pub const MAX_RESPONSE_BYTES_ENV_VAR: &str = "ICP_MAX_RESPONSE_BYTES";
+// Far more than the packs canisters serve, while still bounding what a
+// misbehaving one can make the helper write.
+const DEFAULT_MAX_RESPONSE_BYTES: u64 = 4 * 1024 * 1024 * 1024;
+
+/// The most bytes of a decoded response body to accept, read from
+/// `ICP_MAX_RESPONSE_BYTES`.
+pub fn max_response_bytes() -> u64 {
+    std::env::var(MAX_RESPONSE_BYTES_ENV_VAR)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|max| *max > 0)
+        .unwrap_or(DEFAULT_MAX_RESPONSE_BYTES)
+}
+
+pub const MAX_RETRIES_ENV_VAR: &str = "GIT_REMOTE_ICP_MAX_RETRIES";
+const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// How many times to retry a canister call that failed with a transient
+/// error, read from `GIT_REMOTE_ICP_MAX_RETRIES`.
+pub fn max_retries() -> u32 {
+    std::env::var(MAX_RETRIES_ENV_VAR)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_RETRIES)
+}
+
+pub const MAX_REDIRECTS_ENV_VAR: &str = "GIT_REMOTE_ICP_MAX_REDIRECTS";
+const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+/// How many redirects from a boundary node to follow for a canister call,
+/// read from `GIT_REMOTE_ICP_MAX_REDIRECTS`.
+pub fn max_redirects() -> usize {
+    std::env::var(MAX_REDIRECTS_ENV_VAR)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_REDIRECTS)
+}
+
+pub const REFS_CACHE_TTL_ENV_VAR: &str = "GIT_REMOTE_ICP_REFS_CACHE_TTL";
+// Disabled unless asked for, since cached refs may have moved on the remote.
+const DEFAULT_REFS_CACHE_TTL_SECS: u64 = 0;
+
+/// How long listed refs are cached for between runs, read in seconds from
+/// `GIT_REMOTE_ICP_REFS_CACHE_TTL`, where 0 disables the cache.
+pub fn refs_cache_ttl() -> Duration {
+    let secs = std::env::var(REFS_CACHE_TTL_ENV_VAR)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_REFS_CACHE_TTL_SECS);
+    Duration::from_secs(secs)
+}
+
+pub const QUERY_METHOD_ENV_VAR: &str = "GIT_REMOTE_ICP_QUERY_METHOD";
+const DEFAULT_QUERY_METHOD: &str = "http_request";
+
+pub const UPDATE_METHOD_ENV_VAR: &str = "GIT_REMOTE_ICP_UPDATE_METHOD";
+const DEFAULT_UPDATE_METHOD: &str = "http_request_update";
+
+pub const STORE_CHUNK_METHOD_ENV_VAR: &str = "GIT_REMOTE_ICP_STORE_CHUNK_METHOD";
+const DEFAULT_STORE_CHUNK_METHOD: &str = "store_chunk";
+
+pub const COMMIT_CHUNKS_METHOD_ENV_VAR: &str = "GIT_REMOTE_ICP_COMMIT_CHUNKS_METHOD";
+const DEFAULT_COMMIT_CHUNKS_METHOD: &str = "commit_chunks";
+
+/// The canister methods that Git's requests are made with, so that backends
+/// other than asset-canister-style ones can be reached.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Methods {
+    /// Queried for requests without a body, like the ref advertisement.
+    pub query: String,
+    /// Called as an update for requests with a body, like fetches and pushes.
+    pub update: String,
+    /// Called as an update with each chunk of a body too large for `update`.
+    pub store_chunk: String,
+    /// Called as an update to make a request with the chunks that were
+    /// stored as its body.
+    pub commit_chunks: String,
+}
+
+/// The canister methods to make requests with, read from
+/// `GIT_REMOTE_ICP_QUERY_METHOD`, `GIT_REMOTE_ICP_UPDATE_METHOD`,
+/// `GIT_REMOTE_ICP_STORE_CHUNK_METHOD`, and
+/// `GIT_REMOTE_ICP_COMMIT_CHUNKS_METHOD`.
+pub fn methods() -> anyhow::Result<Methods> {
+    Ok(Methods {
+        query: parse_method(
+            QUERY_METHOD_ENV_VAR,
+            std::env::var(QUERY_METHOD_ENV_VAR).ok(),
+            DEFAULT_QUERY_METHOD,
+        )?,
+        update: parse_method(
+            UPDATE_METHOD_ENV_VAR,
+            std::env::var(UPDATE_METHOD_ENV_VAR).ok(),
+            DEFAULT_UPDATE_METHOD,
+        )?,
+        store_chunk: parse_method(
+            STORE_CHUNK_METHOD_ENV_VAR,
+            std::env::var(STORE_CHUNK_METHOD_ENV_VAR).ok(),
+            DEFAULT_STORE_CHUNK_METHOD,
+        )?,
+        commit_chunks: parse_method(
+            COMMIT_CHUNKS_METHOD_ENV_VAR,
+            std::env::var(COMMIT_CHUNKS_METHOD_ENV_VAR).ok(),
+            DEFAULT_COMMIT_CHUNKS_METHOD,
+        )?,
+    })
+}
+
+fn parse_method(env_var: &str, value: Option<String>, default: &str) -> anyhow::Result<String> {
+    match value {
+        None => Ok(default.to_string()),
+        Some(value) if value.trim().is_empty() => Err(anyhow!(
+            "invalid {}: expected a canister method name, got an empty one",
+            env_var
+        )),
+        Some(value) => Ok(value.trim().to_string()),
+    }
+}
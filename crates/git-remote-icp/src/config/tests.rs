@@ -0,0 +1,94 @@
+use super::*;
+
+#[test]
+fn test_parse_replica_url() {
+    assert_eq!(
+        parse_replica_url("http://localhost:4943").expect("valid URL"),
+        "http://localhost:4943",
+        "local replica"
+    );
+    assert_eq!(
+        parse_replica_url("https://icp-api.io").expect("valid URL"),
+        "https://icp-api.io",
+        "boundary node"
+    );
+}
+
+#[test]
+fn test_parse_replica_url_invalid() {
+    assert_eq!(
+        parse_replica_url("localhost:4943")
+            .expect_err("missing scheme")
+            .to_string(),
+        "invalid ICP_REPLICA_URL `localhost:4943`: expected an http or https URL with a host",
+        "missing scheme"
+    );
+    assert!(parse_replica_url("not a url").is_err(), "not a URL");
+}
+
+#[test]
+fn test_parse_delegation() {
+    assert!(parse_delegation(None).is_ok(), "unset");
+    assert!(parse_delegation(Some(String::new())).is_ok(), "empty");
+    assert_eq!(
+        parse_delegation(Some("delegation.json".to_string()))
+            .expect_err("delegation to be refused")
+            .to_string(),
+        "ICP_DELEGATION is set to `delegation.json`, but delegation chains aren't supported. Unset it to sign with ICP_IDENTITY_PEM or icp.privateKey",
+        "set"
+    );
+}
+
+#[test]
+fn test_is_enabled() {
+    assert!(is_enabled("1"), "1");
+    assert!(is_enabled("true"), "true");
+    assert!(is_enabled("YES"), "YES");
+    assert!(!is_enabled("0"), "0");
+    assert!(!is_enabled("false"), "false");
+    assert!(!is_enabled(""), "empty");
+}
+
+#[test]
+fn test_parse_method() {
+    assert_eq!(
+        parse_method(QUERY_METHOD_ENV_VAR, None, DEFAULT_QUERY_METHOD).expect("default"),
+        "http_request",
+        "unset"
+    );
+    assert_eq!(
+        parse_method(
+            QUERY_METHOD_ENV_VAR,
+            Some("git_upload_pack".to_string()),
+            DEFAULT_QUERY_METHOD
+        )
+        .expect("valid method"),
+        "git_upload_pack",
+        "set"
+    );
+    assert_eq!(
+        parse_method(
+            UPDATE_METHOD_ENV_VAR,
+            Some(" git_receive_pack\n".to_string()),
+            DEFAULT_UPDATE_METHOD
+        )
+        .expect("valid method"),
+        "git_receive_pack",
+        "surrounding whitespace"
+    );
+}
+
+#[test]
+fn test_parse_method_empty() {
+    assert_eq!(
+        parse_method(
+            UPDATE_METHOD_ENV_VAR,
+            Some(" ".to_string()),
+            DEFAULT_UPDATE_METHOD
+        )
+        .expect_err("empty method")
+        .to_string(),
+        "invalid GIT_REMOTE_ICP_UPDATE_METHOD: expected a canister method name, got an empty one",
+        "empty"
+    );
+}
@@ -1,4 +1,7 @@
+use crate::address::{self, Address};
+use crate::config::Methods;
 use crate::http::Remote;
+use crate::{config, proxy, redirect, tls};
 
 use git::protocol::transport;
 use git::url::Scheme;
@@ -6,28 +9,140 @@ use git_repository as git;
 use ic_agent::agent::http_transport::ReqwestHttpReplicaV2Transport;
 use ic_agent::export::Principal;
 use ic_agent::{Agent, Identity};
-use log::trace;
+use log::{info, trace};
+use std::cell::{Cell, RefCell};
+use std::convert::Infallible;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::runtime::Runtime;
 use transport::client::connect::Error;
 
+#[cfg(test)]
+mod tests;
+
+/// The transport a URL is reached over.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Route {
+    /// Requests go to a canister through the agent, addressed by the URL or
+    /// else by the configured canister id.
+    Canister(Option<Address>),
+    /// Requests go to an ordinary smart HTTP server, such as an HTTP gateway
+    /// in front of a canister.
+    Http,
+    /// The repository is on the local filesystem, for mirrors and testing.
+    File,
+}
+
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(60);
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+// Replicas reject messages that expire more than five minutes from now.
+const MAX_INGRESS_EXPIRY: Duration = Duration::from_secs(5 * 60);
+
+/// The schemes of URLs the helper can be given, which Git passes on without
+/// the `icp::` prefix of URLs like `icp::https://`.
+const SUPPORTED_SCHEMES: &[&str] = &["icp", "ic", "https", "http", "file"];
+
+#[derive(Debug, thiserror::Error)]
+#[error("unsupported URL scheme `{scheme}`, expected one of: {}", SUPPORTED_SCHEMES.join(", "))]
+pub struct UnsupportedScheme {
+    pub scheme: String,
+}
+
+/// Selects the transport for `url`, resolving its scheme to `http` or
+/// `https`.
+///
+/// `ic://` and `icp://` URLs always reach a canister. `http://` and
+/// `https://` URLs, as given with `icp::http://`, reach a canister when they
+/// name one and use plain HTTP otherwise. `file://` URLs, as given with
+/// `icp::file://`, are read locally.
+pub fn route(url: &mut git::Url) -> Result<Route, Error> {
+    let canister = match &url.scheme {
+        Scheme::Ext(scheme) if scheme == "ic" || scheme == "icp" => true,
+        Scheme::Https | Scheme::Http => false,
+        Scheme::File => return Ok(Route::File),
+        scheme => {
+            return Err(Error::Connection(Box::new(UnsupportedScheme {
+                scheme: scheme.to_string(),
+            })))
+        }
+    };
+
+    if canister {
+        url.scheme = Scheme::Https;
+    }
+
+    match address::parse(url).map_err(|err| Error::Connection(Box::new(err)))? {
+        Some(address) => Ok(Route::Canister(Some(address))),
+        None if canister => Ok(Route::Canister(None)),
+        None => Ok(Route::Http),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn connect<'a, Url, E>(
     identity: Arc<dyn Identity>,
     fetch_root_key: bool,
     replica_url: String,
+    replica_url_override: Option<String>,
     canister_id: Principal,
+    request_timeout: Duration,
+    user_agent: Option<String>,
+    methods: Methods,
 ) -> impl Fn(Url, transport::connect::Options) -> Result<Box<dyn transport::client::Transport + Send + 'a>, Error>
 where
     Url: TryInto<git::url::Url, Error = E>,
     git::url::parse::Error: From<E>,
 {
-    trace!("identity: {:#?}", identity);
     trace!("fetch_root_key: {:#?}", fetch_root_key);
     trace!("replica_url: {}", replica_url);
+    trace!("replica_url_override: {:#?}", replica_url_override);
     trace!("canister_id: {}", canister_id);
+    trace!("request_timeout: {:#?}", request_timeout);
+    trace!("user_agent: {:#?}", user_agent);
+    trace!("methods: {:#?}", methods);
+
+    // Only the principal is logged, since the identity holds a private key.
+    let caller = identity
+        .sender()
+        .ok()
+        .filter(|principal| *principal != Principal::anonymous());
+    let sender = caller.unwrap_or_else(Principal::anonymous);
+    trace!("sender: {}", sender);
+
+    let caller = caller.map_or_else(|| "anonymous".to_string(), |principal| principal.to_text());
+
+    // Logged for the first canister connection only, since Git's commands
+    // usually each make one to the same canister.
+    let logged = Cell::new(false);
+
+    // Built on first use and shared by every connection, so that canister
+    // calls reuse pooled connections rather than each doing its own TCP and
+    // TLS handshakes.
+    let client = RefCell::new(None);
 
     move |url: Url, options| {
-        let mut url = url.try_into().map_err(git::url::parse::Error::from)?;
+        let mut url: git::Url = url.try_into().map_err(git::url::parse::Error::from)?;
+
+        trace!("Provided URL scheme: {:#?}", url.scheme);
+
+        let route = route(&mut url)?;
+
+        trace!("Resolved URL scheme: {:#?}", url.scheme);
+        trace!("Selected transport: {:#?}", route);
+
+        let (canister_id, replica_url) = match route {
+            route @ (Route::Http | Route::File) => {
+                trace!("Using the {:?} transport for {}", route, url.to_bstring());
+                let transport = transport::connect::<_, Infallible>(url, options)?;
+                return Ok(transport);
+            }
+            Route::Canister(Some(address)) => {
+                url = address.url;
+                (address.canister_id, address.replica_url)
+            }
+            Route::Canister(None) => (canister_id, replica_url.clone()),
+        };
 
         if url.user().is_some() {
             return Err(Error::UnsupportedUrlTokens {
@@ -36,22 +151,36 @@ where
             });
         }
 
-        trace!("Provided URL scheme: {:#?}", url.scheme);
+        // The canister id still comes from the URL, but it can be reached
+        // through a different replica.
+        let replica_url = replica_url_override.clone().unwrap_or(replica_url);
 
-        url.scheme = match url.scheme {
-            Scheme::Ext(scheme) if &scheme == "icp" => Ok(Scheme::Https),
-            scheme @ (Scheme::Https | Scheme::Http) => Ok(scheme),
-            _ => Err(Error::UnsupportedScheme(url.scheme)),
-        }?;
+        trace!("Resolved canister_id: {}", canister_id);
+        trace!("Resolved replica_url: {}", replica_url);
+        trace!("Using the canister transport for {}", url.to_bstring());
 
-        trace!("Resolved URL scheme: {:#?}", url.scheme);
+        if !logged.replace(true) {
+            info!(
+                "Connecting to canister {} through {} as {}",
+                canister_id, replica_url, caller
+            );
+        }
 
-        let replica_transport = ReqwestHttpReplicaV2Transport::create(&replica_url)
-            .map_err(|err| Error::Connection(Box::new(err)))?;
+        let client = shared_client(
+            &client,
+            request_timeout,
+            user_agent.as_deref(),
+            tls::no_verify(&replica_url),
+        )?;
+
+        let replica_transport =
+            ReqwestHttpReplicaV2Transport::create_with_client(&replica_url, client)
+                .map_err(|err| Error::Connection(Box::new(err)))?;
 
         let agent = Agent::builder()
             .with_transport(replica_transport)
             .with_arc_identity(identity.clone())
+            .with_ingress_expiry(Some(ingress_expiry(request_timeout)))
             .build()
             .map_err(|err| Error::Connection(Box::new(err)))?;
 
@@ -63,7 +192,18 @@ where
                 .map_err(|err| Error::Connection(Box::new(err)))?;
         }
 
-        let remote = Remote::new(agent, canister_id);
+        // A local replica's root key isn't the mainnet one we'd verify
+        // certificates against, and local asset canisters may not certify
+        // every response.
+        let remote = Remote::new(
+            agent,
+            canister_id,
+            request_timeout,
+            fetch_root_key,
+            user_agent.clone(),
+            sender,
+            methods.clone(),
+        );
 
         let transport = transport::client::http::connect_http(
             remote,
@@ -74,3 +214,61 @@ where
         Ok(Box::new(transport))
     }
 }
+
+/// How long a message stays valid: the request timeout, up to the most a
+/// replica accepts.
+fn ingress_expiry(request_timeout: Duration) -> Duration {
+    request_timeout.min(MAX_INGRESS_EXPIRY)
+}
+
+/// The client in `cache`, built first if there isn't one yet. Clones share
+/// the same pool of connections. A client that doesn't verify certificates
+/// is never shared with a replica that should have them verified, or the
+/// other way around.
+fn shared_client(
+    cache: &RefCell<Option<(bool, reqwest::Client)>>,
+    request_timeout: Duration,
+    user_agent: Option<&str>,
+    tls_no_verify: bool,
+) -> Result<reqwest::Client, Error> {
+    if let Some((_, client)) = cache
+        .borrow()
+        .as_ref()
+        .filter(|(no_verify, _)| *no_verify == tls_no_verify)
+    {
+        trace!("Reusing the HTTP client");
+        return Ok(client.clone());
+    }
+
+    let mut client = reqwest::Client::builder()
+        .timeout(request_timeout)
+        // Keeps idle pooled connections from being closed by NATs and load
+        // balancers between calls.
+        .tcp_keepalive(KEEPALIVE_INTERVAL)
+        .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+        .redirect(redirect::policy(config::max_redirects()));
+
+    // Boundary nodes see the agent's requests, and the canister sees the
+    // requests from Git.
+    if let Some(user_agent) = user_agent {
+        client = client.user_agent(user_agent);
+    }
+
+    // Configured explicitly rather than left to reqwest so that malformed
+    // proxy URLs are reported instead of ignored.
+    if let Some(proxy) = proxy::from_env().map_err(|err| Error::Connection(err.into()))? {
+        client = client.proxy(proxy);
+    }
+
+    if tls_no_verify {
+        client = client.danger_accept_invalid_certs(true);
+    }
+
+    let client = client
+        .build()
+        .map_err(|err| Error::Connection(Box::new(err)))?;
+
+    *cache.borrow_mut() = Some((tls_no_verify, client.clone()));
+
+    Ok(client)
+}
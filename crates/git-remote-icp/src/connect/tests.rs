@@ -0,0 +1,223 @@
+use super::*;
+
+fn route_of(input: &str) -> (Route, String) {
+    let mut url = git::url::parse(input.into()).expect("valid URL");
+    let route = route(&mut url).expect("supported URL");
+    (route, url.to_bstring().to_string())
+}
+
+#[test]
+fn test_route_icp_with_canister_id() {
+    let (route, url) =
+        route_of("icp://w7uni-tiaaa-aaaam-qaydq-cai.raw.ic0.app/@paul/hello-world.git");
+
+    assert!(matches!(route, Route::Canister(Some(_))), "route");
+    assert_eq!(
+        url, "https://w7uni-tiaaa-aaaam-qaydq-cai.raw.ic0.app/@paul/hello-world.git",
+        "url"
+    );
+}
+
+#[test]
+fn test_route_ic_without_canister_id() {
+    let (route, url) = route_of("ic://git.codebase.org/@paul/hello-world.git");
+
+    assert_eq!(route, Route::Canister(None), "route");
+    assert_eq!(url, "https://git.codebase.org/@paul/hello-world.git", "url");
+}
+
+#[test]
+fn test_route_ic_with_canister_id_path_segment() {
+    let (route, _url) =
+        route_of("ic://icp0.io/w7uni-tiaaa-aaaam-qaydq-cai/shards/paul/hello-world.git");
+
+    match route {
+        Route::Canister(Some(address)) => {
+            assert_eq!(
+                address.canister_id.to_text(),
+                "w7uni-tiaaa-aaaam-qaydq-cai",
+                "canister id"
+            );
+            assert_eq!(address.replica_url, "https://icp0.io", "replica url");
+            assert_eq!(
+                address.url.to_bstring(),
+                "https://icp0.io/shards/paul/hello-world.git",
+                "repository url"
+            );
+        }
+        route => panic!("expected a canister address, got {:?}", route),
+    }
+}
+
+#[test]
+fn test_route_ic_with_invalid_canister_id_path_segment() {
+    let mut url = git::url::parse(
+        "ic://icp0.io/aaaaa-tiaaa-aaaam-qaydq-cai/shards/paul/hello-world.git".into(),
+    )
+    .expect("valid URL");
+
+    match route(&mut url) {
+        Err(Error::Connection(err)) => match err.downcast_ref::<address::Error>() {
+            Some(address::Error::InvalidCanisterId { text, .. }) => {
+                assert_eq!(text, "aaaaa-tiaaa-aaaam-qaydq-cai", "canister id")
+            }
+            err => panic!("expected an invalid canister id, got {:?}", err),
+        },
+        result => panic!("expected a connection error, got {:?}", result),
+    }
+}
+
+#[test]
+fn test_route_http_with_canister_id() {
+    let (route, _) =
+        route_of("http://rwlgt-iiaaa-aaaaa-aaaaa-cai.raw.ic0.localhost:8453/@paul/hello-world.git");
+
+    assert!(matches!(route, Route::Canister(Some(_))), "route");
+}
+
+#[test]
+fn test_route_https_without_canister_id() {
+    let (route, url) = route_of("https://github.com/codebase-labs/git-remote-icp.git");
+
+    assert_eq!(route, Route::Http, "route");
+    assert_eq!(
+        url, "https://github.com/codebase-labs/git-remote-icp.git",
+        "url"
+    );
+}
+
+#[test]
+fn test_route_http_without_canister_id() {
+    let (route, url) = route_of("http://localhost:8000/@paul/hello-world.git");
+
+    assert_eq!(route, Route::Http, "route");
+    assert_eq!(url, "http://localhost:8000/@paul/hello-world.git", "url");
+}
+
+#[test]
+fn test_route_file() {
+    let (route, url) = route_of("file:///tmp/hello-world.git");
+
+    assert_eq!(route, Route::File, "route");
+    assert_eq!(url, "file:///tmp/hello-world.git", "url");
+}
+
+#[test]
+fn test_route_unsupported_scheme() {
+    let mut url = git::url::parse("ssh://git@github.com/codebase-labs/git-remote-icp.git".into())
+        .expect("valid URL");
+
+    let err = route(&mut url).expect_err("unsupported scheme");
+    let messages = std::iter::successors(Some(&err as &dyn std::error::Error), |err| err.source())
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+
+    assert!(
+        messages.iter().any(|message| message
+            == "unsupported URL scheme `ssh`, expected one of: icp, ic, https, http, file"),
+        "error: {:?}",
+        messages
+    );
+}
+
+#[test]
+fn test_ingress_expiry_is_request_timeout() {
+    assert_eq!(
+        ingress_expiry(Duration::from_secs(30)),
+        Duration::from_secs(30),
+        "ingress expiry"
+    );
+}
+
+#[test]
+fn test_ingress_expiry_is_clamped() {
+    assert_eq!(
+        ingress_expiry(Duration::from_secs(60 * 60)),
+        MAX_INGRESS_EXPIRY,
+        "ingress expiry"
+    );
+}
+
+#[test]
+fn test_shared_client_is_built_once() {
+    let cache = RefCell::new(None);
+
+    shared_client(&cache, Duration::from_secs(30), None, false).expect("client");
+    assert!(cache.borrow().is_some(), "cached");
+
+    shared_client(&cache, Duration::from_secs(30), None, false).expect("cached client");
+    assert!(cache.borrow().is_some(), "still cached");
+}
+
+#[test]
+fn test_shared_client_without_tls_verification_is_not_reused() {
+    let cache = RefCell::new(None);
+
+    shared_client(&cache, Duration::from_secs(30), None, true).expect("client");
+    assert_eq!(
+        cache.borrow().as_ref().map(|(no_verify, _)| *no_verify),
+        Some(true),
+        "cached without verification"
+    );
+
+    shared_client(&cache, Duration::from_secs(30), None, false).expect("client");
+    assert_eq!(
+        cache.borrow().as_ref().map(|(no_verify, _)| *no_verify),
+        Some(false),
+        "rebuilt with verification"
+    );
+}
+
+#[test]
+fn test_connect_file_lists_refs() {
+    use git_remote_helper::test_support::git;
+
+    let dir = tempfile::tempdir().expect("temp dir");
+    let remote = dir.path().join("remote.git");
+    let work = dir.path().join("work");
+
+    git(dir.path(), ["init", "--bare", remote.to_str().unwrap()]);
+    git(dir.path(), ["init", work.to_str().unwrap()]);
+    git(&work, ["commit", "--allow-empty", "-m", "initial"]);
+    git(&work, ["push", remote.to_str().unwrap(), "main"]);
+
+    let head = git(&work, ["rev-parse", "HEAD"]);
+
+    let connect_to = connect::<String, _>(
+        Arc::new(ic_agent::identity::AnonymousIdentity {}),
+        false,
+        "https://ic0.app".to_string(),
+        None,
+        Principal::anonymous(),
+        Duration::from_secs(30),
+        None,
+    );
+
+    let transport = connect_to(
+        format!("file://{}", remote.display()),
+        transport::client::connect::Options {
+            version: transport::Protocol::V2,
+            ssh: Default::default(),
+        },
+    )
+    .expect("file transport");
+
+    let mut out = Vec::new();
+
+    git_remote_helper::commands::list::execute(
+        &mut git_remote_helper::git::protocol::Connection::new(transport),
+        |_| Ok(None),
+        &None,
+        &[],
+        &[],
+        false,
+        &mut out,
+    )
+    .expect("list");
+
+    assert_eq!(
+        String::from_utf8(out).expect("valid utf8"),
+        format!("@refs/heads/main HEAD\n{} refs/heads/main\n\n", head),
+        "list"
+    );
+}
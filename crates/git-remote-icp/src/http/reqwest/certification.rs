@@ -0,0 +1,216 @@
+use ic_agent::export::Principal;
+use ic_agent::hash_tree::{HashTree, Label, LookupResult};
+use ic_agent::{Agent, AgentError, Certificate};
+use ic_certified_assets::types::HeaderField;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(test)]
+mod tests;
+
+const IC_CERTIFICATE: &str = "IC-Certificate";
+
+/// How far the time in a certificate may be from ours before the response is
+/// considered a replay.
+const MAX_TIME_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// Why a response couldn't be shown to come from the canister.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("response is missing an {} header", IC_CERTIFICATE)]
+    Missing,
+    #[error("malformed {} header: {0}", IC_CERTIFICATE)]
+    Malformed(String),
+    #[error("invalid certificate: {0}")]
+    Certificate(#[source] AgentError),
+    #[error("certificate time is more than {}s from the current time", MAX_TIME_SKEW.as_secs())]
+    Expired,
+    #[error("certified data doesn't match the tree in the response")]
+    CertifiedData,
+    #[error("{0} isn't certified by the tree in the response")]
+    Uncertified(String),
+    #[error("body of {0} doesn't match its certified hash")]
+    Body(String),
+}
+
+impl From<Error> for std::io::Error {
+    fn from(err: Error) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// The hash the asset canister certified for a response's body, so that the
+/// body can be checked once it has been streamed.
+#[derive(Debug)]
+pub struct Certified {
+    path: String,
+    hash: Vec<u8>,
+}
+
+impl Certified {
+    pub fn check(&self, body_hash: &[u8]) -> Result<(), Error> {
+        if body_hash == self.hash.as_slice() {
+            Ok(())
+        } else {
+            Err(Error::Body(self.path.clone()))
+        }
+    }
+}
+
+/// Verifies that the asset canister `canister_id` certified a body for `url`,
+/// so that a boundary node can't tamper with it. Only responses to queries
+/// need this, as responses to update calls are certified by the agent.
+/// Responses without an `IC-Certificate` header are rejected unless
+/// `allow_uncertified` is set.
+pub fn verify(
+    agent: &Agent,
+    canister_id: Principal,
+    url: &str,
+    headers: &[HeaderField],
+    allow_uncertified: bool,
+) -> Result<Option<Certified>, Error> {
+    let header = match find(headers, IC_CERTIFICATE) {
+        Some(header) => header,
+        None if allow_uncertified => return Ok(None),
+        None => return Err(Error::Missing),
+    };
+
+    let (certificate, tree) = parse_header(header)?;
+
+    let certificate: Certificate<'_> = serde_cbor::from_slice(&certificate)
+        .map_err(|err| Error::Malformed(format!("certificate: {}", err)))?;
+    let tree: HashTree<'_> =
+        serde_cbor::from_slice(&tree).map_err(|err| Error::Malformed(format!("tree: {}", err)))?;
+
+    agent
+        .verify(&certificate, canister_id)
+        .map_err(Error::Certificate)?;
+
+    check_time(&certificate.tree, SystemTime::now())?;
+    check_certified_data(&certificate.tree, canister_id, &tree)?;
+    certified_hash(&tree, &certified_path(url)).map(Some)
+}
+
+/// The certificate and tree from the `IC-Certificate` header, which looks like
+/// `certificate=:<base64>:, tree=:<base64>:`.
+fn parse_header(header: &str) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let mut certificate = None;
+    let mut tree = None;
+
+    for field in header.split(',') {
+        let (name, value) = field
+            .trim()
+            .split_once('=')
+            .ok_or_else(|| Error::Malformed(format!("expected name=value, got {}", field)))?;
+
+        let value = value
+            .strip_prefix(':')
+            .and_then(|value| value.strip_suffix(':'))
+            .ok_or_else(|| {
+                Error::Malformed(format!("expected {} to be wrapped in colons", name))
+            })?;
+
+        let value =
+            base64::decode(value).map_err(|err| Error::Malformed(format!("{}: {}", name, err)))?;
+
+        match name {
+            "certificate" => certificate = Some(value),
+            "tree" => tree = Some(value),
+            _ => {}
+        }
+    }
+
+    match (certificate, tree) {
+        (Some(certificate), Some(tree)) => Ok((certificate, tree)),
+        (None, _) => Err(Error::Malformed("missing certificate".to_string())),
+        (_, None) => Err(Error::Malformed("missing tree".to_string())),
+    }
+}
+
+fn check_time(certificate_tree: &HashTree<'_>, now: SystemTime) -> Result<(), Error> {
+    let time = lookup(certificate_tree, &[Label::from("time")])
+        .and_then(decode_leb128)
+        .ok_or_else(|| Error::Malformed("certificate is missing its time".to_string()))?;
+
+    let time = UNIX_EPOCH + Duration::from_nanos(time);
+    let skew = now
+        .duration_since(time)
+        .or_else(|_| time.duration_since(now))
+        .unwrap_or_default();
+
+    if skew > MAX_TIME_SKEW {
+        return Err(Error::Expired);
+    }
+
+    Ok(())
+}
+
+/// The certificate vouches for the response's tree through the certified
+/// data the canister set to the tree's root hash.
+fn check_certified_data(
+    certificate_tree: &HashTree<'_>,
+    canister_id: Principal,
+    tree: &HashTree<'_>,
+) -> Result<(), Error> {
+    let path = [
+        Label::from("canister"),
+        Label::from(canister_id.as_slice()),
+        Label::from("certified_data"),
+    ];
+
+    match lookup(certificate_tree, &path) {
+        Some(certified_data) if certified_data == tree.digest().as_slice() => Ok(()),
+        _ => Err(Error::CertifiedData),
+    }
+}
+
+/// Asset canisters certify the SHA-256 of each asset's body under
+/// `http_assets` by its path.
+fn certified_hash(tree: &HashTree<'_>, path: &str) -> Result<Certified, Error> {
+    let labels = [Label::from("http_assets"), Label::from(path)];
+
+    match lookup(tree, &labels) {
+        Some(hash) => Ok(Certified {
+            path: path.to_string(),
+            hash: hash.to_vec(),
+        }),
+        None => Err(Error::Uncertified(path.to_string())),
+    }
+}
+
+fn lookup<'a>(tree: &'a HashTree<'_>, path: &[Label]) -> Option<&'a [u8]> {
+    match tree.lookup_path(path) {
+        LookupResult::Found(value) => Some(value),
+        _ => None,
+    }
+}
+
+/// The path an asset is certified under, which excludes the query string.
+fn certified_path(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(url) => url.path().to_string(),
+        Err(_) => url.split('?').next().unwrap_or_default().to_string(),
+    }
+}
+
+/// Times in certificates are nanoseconds encoded as unsigned LEB128.
+fn decode_leb128(bytes: &[u8]) -> Option<u64> {
+    let mut value: u64 = 0;
+
+    for (i, byte) in bytes.iter().enumerate() {
+        let shift = u32::try_from(i * 7).ok().filter(|shift| *shift < 64)?;
+        value |= u64::from(byte & 0x7f).checked_shl(shift)?;
+
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+fn find<'a>(headers: &'a [HeaderField], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
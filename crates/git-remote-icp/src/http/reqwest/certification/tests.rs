@@ -0,0 +1,117 @@
+use super::*;
+use ic_agent::hash_tree::{fork, label, leaf};
+use sha2::{Digest as _, Sha256};
+
+fn asset<'a>(path: &'a str, body: &[u8]) -> HashTree<'a> {
+    label(path, leaf(Sha256::digest(body).to_vec()))
+}
+
+#[test]
+fn test_parse_header() {
+    let header = format!(
+        "certificate=:{}:, tree=:{}:",
+        base64::encode(b"certificate"),
+        base64::encode(b"tree")
+    );
+
+    assert_eq!(
+        parse_header(&header).expect("valid header"),
+        (b"certificate".to_vec(), b"tree".to_vec()),
+        "certificate and tree"
+    );
+}
+
+#[test]
+fn test_parse_header_missing_tree() {
+    let header = format!("certificate=:{}:", base64::encode(b"certificate"));
+
+    assert_eq!(
+        parse_header(&header).expect_err("missing tree").to_string(),
+        "malformed IC-Certificate header: missing tree",
+        "error"
+    );
+}
+
+#[test]
+fn test_missing_header() {
+    let headers = vec![("Content-Type".to_string(), "text/plain".to_string())];
+
+    assert!(find(&headers, IC_CERTIFICATE).is_none(), "missing");
+}
+
+#[test]
+fn test_certified_path() {
+    assert_eq!(
+        certified_path(
+            "https://w7uni-tiaaa-aaaam-qaydq-cai.raw.ic0.app/info/refs?service=git-upload-pack"
+        ),
+        "/info/refs",
+        "absolute URL"
+    );
+    assert_eq!(
+        certified_path("/info/refs?service=git-upload-pack"),
+        "/info/refs",
+        "path"
+    );
+}
+
+#[test]
+fn test_certified_hash() {
+    let tree = label(
+        "http_assets",
+        fork(asset("/HEAD", b"head"), asset("/info/refs", b"refs")),
+    );
+
+    let certified = certified_hash(&tree, "/info/refs").expect("certified path");
+
+    assert!(
+        certified.check(&Sha256::digest(b"refs")).is_ok(),
+        "certified"
+    );
+
+    assert_eq!(
+        certified
+            .check(&Sha256::digest(b"tampered"))
+            .expect_err("tampered body")
+            .to_string(),
+        "body of /info/refs doesn't match its certified hash",
+        "tampered"
+    );
+
+    assert_eq!(
+        certified_hash(&tree, "/objects/info/packs")
+            .expect_err("uncertified path")
+            .to_string(),
+        "/objects/info/packs isn't certified by the tree in the response",
+        "uncertified"
+    );
+}
+
+#[test]
+fn test_check_time() {
+    let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    // 1_000_000s in nanoseconds as LEB128
+    let time = leaf(vec![0x80, 0x80, 0x9a, 0xa6, 0xea, 0xaf, 0xe3, 0x01]);
+    let tree = label("time", time);
+
+    assert!(check_time(&tree, now).is_ok(), "current");
+
+    assert!(
+        matches!(
+            check_time(&tree, now + Duration::from_secs(60 * 60)),
+            Err(Error::Expired)
+        ),
+        "expired"
+    );
+}
+
+#[test]
+fn test_decode_leb128() {
+    assert_eq!(decode_leb128(&[0x00]), Some(0), "zero");
+    assert_eq!(
+        decode_leb128(&[0xe5, 0x8e, 0x26]),
+        Some(624_485),
+        "multi-byte"
+    );
+    assert_eq!(decode_leb128(&[0x80]), None, "truncated");
+}
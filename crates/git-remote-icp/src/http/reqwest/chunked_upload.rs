@@ -0,0 +1,85 @@
+use crate::config::Methods;
+use candid::{CandidType, Encode};
+use git_remote_helper::event;
+use ic_agent::AgentError;
+use ic_certified_assets::types::HttpRequest;
+use log::trace;
+use serde_bytes::ByteBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(CandidType)]
+struct StoreChunkArg {
+    upload_id: u64,
+    index: u64,
+    content: ByteBuf,
+}
+
+#[derive(CandidType)]
+struct CommitChunksArg {
+    upload_id: u64,
+    request: HttpRequest,
+}
+
+/// An id for an upload, which the canister scopes to the caller.
+pub fn upload_id() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_nanos() as u64)
+}
+
+/// Sends a request whose body is too large for a single ingress message. The
+/// body is stored in order with `store_chunk` calls of at most `chunk_size`
+/// bytes, then `commit_chunks` makes the request with the stored chunks as its
+/// body, returning the candid-encoded response. Both are called by the names
+/// in `methods`.
+pub fn upload(
+    upload_id: u64,
+    mut request: HttpRequest,
+    chunk_size: usize,
+    methods: &Methods,
+    mut update: impl FnMut(&str, Vec<u8>) -> Result<Vec<u8>, AgentError>,
+) -> Result<Vec<u8>, AgentError> {
+    let body = std::mem::take(&mut request.body);
+
+    for (index, content) in body.chunks(chunk_size).enumerate() {
+        trace!(
+            "storing chunk {} of upload {} ({} bytes)",
+            index,
+            upload_id,
+            content.len()
+        );
+
+        let arg = Encode!(&StoreChunkArg {
+            upload_id,
+            index: index as u64,
+            content: ByteBuf::from(content),
+        })
+        .map_err(candid_error)?;
+
+        update(&methods.store_chunk, arg)?;
+    }
+
+    trace!("committing upload {}", upload_id);
+
+    let arg = Encode!(&CommitChunksArg { upload_id, request }).map_err(candid_error)?;
+
+    let response = update(&methods.commit_chunks, arg)?;
+
+    event::log(
+        "upload",
+        &[
+            ("upload_id", &upload_id),
+            ("chunks", &body.chunks(chunk_size).len()),
+            ("bytes", &body.len()),
+        ],
+    );
+
+    Ok(response)
+}
+
+fn candid_error(err: candid::Error) -> AgentError {
+    AgentError::CandidError(Box::new(err))
+}
@@ -0,0 +1,93 @@
+use super::*;
+
+/// Canister ingress messages are limited to 2MiB.
+const INGRESS_LIMIT: usize = 2 * 1024 * 1024;
+
+fn request(body: Vec<u8>) -> HttpRequest {
+    HttpRequest {
+        method: "POST".to_string(),
+        url: "/@paul/hello-world.git/git-receive-pack".to_string(),
+        headers: vec![],
+        body: ByteBuf::from(body),
+    }
+}
+
+const STORE_CHUNK: &str = "git_store_chunk";
+const COMMIT_CHUNKS: &str = "git_commit_chunks";
+
+/// Methods with chunk methods named unlike the defaults, as a backend other
+/// than an asset canister might name them.
+fn methods() -> Methods {
+    Methods {
+        query: "http_request".to_string(),
+        update: "http_request_update".to_string(),
+        store_chunk: STORE_CHUNK.to_string(),
+        commit_chunks: COMMIT_CHUNKS.to_string(),
+    }
+}
+
+#[test]
+fn test_upload_splits_pack_into_chunks() {
+    let pack = vec![0; 5 * 1024 * 1024];
+    let mut calls = Vec::new();
+
+    let res = upload(1, request(pack), 1024 * 1024, &methods(), |method, arg| {
+        calls.push((method.to_string(), arg.len()));
+        Ok(b"response".to_vec())
+    })
+    .expect("upload to succeed");
+
+    assert_eq!(res, b"response".to_vec(), "commit response");
+
+    let methods = calls
+        .iter()
+        .map(|(method, _)| method.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        methods,
+        vec![
+            STORE_CHUNK,
+            STORE_CHUNK,
+            STORE_CHUNK,
+            STORE_CHUNK,
+            STORE_CHUNK,
+            COMMIT_CHUNKS
+        ],
+        "methods"
+    );
+
+    assert!(
+        calls.iter().all(|(_, len)| *len < INGRESS_LIMIT),
+        "every call is under the ingress limit: {:?}",
+        calls
+    );
+}
+
+#[test]
+fn test_upload_partial_last_chunk() {
+    let pack = vec![0; 5 * 1024 * 1024 + 1];
+    let mut store_chunk_calls = 0;
+
+    upload(1, request(pack), 1024 * 1024, &methods(), |method, _| {
+        if method == STORE_CHUNK {
+            store_chunk_calls += 1;
+        }
+        Ok(vec![])
+    })
+    .expect("upload to succeed");
+
+    assert_eq!(store_chunk_calls, 6, "store_chunk calls");
+}
+
+#[test]
+fn test_upload_stops_on_error() {
+    let mut calls = 0;
+
+    let res = upload(1, request(vec![0; 3]), 1, &methods(), |_, _| {
+        calls += 1;
+        Err(AgentError::MessageError("unavailable".to_string()))
+    });
+
+    assert!(res.is_err(), "error");
+    assert_eq!(calls, 1, "calls");
+}
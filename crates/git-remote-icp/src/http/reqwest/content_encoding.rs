@@ -0,0 +1,103 @@
+use flate2::write::GzDecoder;
+use ic_certified_assets::types::HeaderField;
+use std::io::Write;
+
+#[cfg(test)]
+mod tests;
+
+const ACCEPT_ENCODING: &str = "Accept-Encoding";
+const CONTENT_ENCODING: &str = "Content-Encoding";
+
+/// The content codings we're able to decode.
+const SUPPORTED: &[&str] = &["gzip", "identity"];
+
+/// How a response body is encoded.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    Identity,
+    Gzip,
+}
+
+/// Adds an `Accept-Encoding` header listing the supported content codings,
+/// unless one is already present.
+pub fn accept(headers: &mut Vec<HeaderField>) {
+    if find(headers, ACCEPT_ENCODING).is_none() {
+        headers.push((ACCEPT_ENCODING.to_string(), SUPPORTED.join(", ")));
+    }
+}
+
+/// The encoding of a response from its `Content-Encoding`, rejecting ones we
+/// aren't able to decode so that the packetline parser doesn't fail opaquely
+/// on encoded bytes.
+pub fn check(headers: &[HeaderField]) -> std::io::Result<Encoding> {
+    let encoding = match find(headers, CONTENT_ENCODING) {
+        Some(encoding) => encoding.trim(),
+        None => return Ok(Encoding::Identity),
+    };
+
+    // `x-gzip` is an alias that recipients are expected to treat as `gzip`.
+    if encoding.is_empty() || encoding.eq_ignore_ascii_case("identity") {
+        Ok(Encoding::Identity)
+    } else if encoding.eq_ignore_ascii_case("gzip") || encoding.eq_ignore_ascii_case("x-gzip") {
+        Ok(Encoding::Gzip)
+    } else {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "unsupported {}: {} from remote. The canister should honor `{}: {}`",
+                CONTENT_ENCODING,
+                encoding,
+                ACCEPT_ENCODING,
+                SUPPORTED.join(", ")
+            ),
+        ))
+    }
+}
+
+/// Decodes the chunks of a body written to it, writing what they decode to
+/// into the inner writer.
+pub enum Decoder<W: Write> {
+    Identity(W),
+    Gzip(GzDecoder<W>),
+}
+
+impl<W: Write> Decoder<W> {
+    pub fn new(encoding: Encoding, inner: W) -> Self {
+        match encoding {
+            Encoding::Identity => Decoder::Identity(inner),
+            Encoding::Gzip => Decoder::Gzip(GzDecoder::new(inner)),
+        }
+    }
+
+    /// Writes out the rest of the body, failing if it was cut short, and
+    /// returns the inner writer.
+    pub fn finish(self) -> std::io::Result<W> {
+        match self {
+            Decoder::Identity(inner) => Ok(inner),
+            Decoder::Gzip(decoder) => decoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Decoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Decoder::Identity(inner) => inner.write(buf),
+            Decoder::Gzip(decoder) => decoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Decoder::Identity(inner) => inner.flush(),
+            Decoder::Gzip(decoder) => decoder.flush(),
+        }
+    }
+}
+
+fn find<'a>(headers: &'a [HeaderField], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
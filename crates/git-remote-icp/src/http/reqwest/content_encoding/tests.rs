@@ -0,0 +1,118 @@
+use super::*;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+/// The body of a response to a fetch that has nothing to send.
+const RESPONSE: &[u8] = b"0008NAK\n0000";
+
+fn gzip(body: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(body).expect("write to succeed");
+    encoder.finish().expect("encoding to succeed")
+}
+
+fn decode(encoding: Encoding, chunks: &[&[u8]]) -> std::io::Result<Vec<u8>> {
+    let mut decoder = Decoder::new(encoding, Vec::new());
+    for chunk in chunks {
+        decoder.write_all(chunk)?;
+    }
+    decoder.finish()
+}
+
+fn headers(headers: &[(&str, &str)]) -> Vec<HeaderField> {
+    headers
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+#[test]
+fn test_check_no_content_encoding() {
+    let headers = headers(&[("Content-Type", "application/x-git-upload-pack-result")]);
+    assert_eq!(check(&headers).ok(), Some(Encoding::Identity))
+}
+
+#[test]
+fn test_check_identity() {
+    let headers = headers(&[("content-encoding", "identity")]);
+    assert_eq!(check(&headers).ok(), Some(Encoding::Identity))
+}
+
+#[test]
+fn test_check_gzip() {
+    let headers = headers(&[("Content-Encoding", "GZIP")]);
+    assert_eq!(check(&headers).ok(), Some(Encoding::Gzip), "gzip");
+
+    let headers = headers(&[("Content-Encoding", "x-gzip")]);
+    assert_eq!(check(&headers).ok(), Some(Encoding::Gzip), "x-gzip");
+}
+
+#[test]
+fn test_check_unsupported() {
+    let headers = headers(&[("Content-Encoding", "br")]);
+    let err = check(&headers).expect_err("br to be unsupported");
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(
+        err.to_string(),
+        "unsupported Content-Encoding: br from remote. The canister should honor `Accept-Encoding: gzip, identity`"
+    )
+}
+
+#[test]
+fn test_accept() {
+    let mut headers = headers(&[("Git-Protocol", "version=2")]);
+    accept(&mut headers);
+    assert_eq!(
+        find(&headers, "accept-encoding"),
+        Some("gzip, identity"),
+        "accept-encoding"
+    )
+}
+
+#[test]
+fn test_accept_existing() {
+    let mut headers = headers(&[("Accept-Encoding", "gzip")]);
+    accept(&mut headers);
+    assert_eq!(headers.len(), 1);
+    assert_eq!(find(&headers, ACCEPT_ENCODING), Some("gzip"))
+}
+
+#[test]
+fn test_decode_identity() {
+    assert_eq!(
+        decode(Encoding::Identity, &[RESPONSE]).expect("decoding to succeed"),
+        RESPONSE,
+        "body"
+    )
+}
+
+#[test]
+fn test_decode_gzip() {
+    let body = gzip(RESPONSE);
+    assert_ne!(body, RESPONSE, "encoded body");
+    assert_eq!(
+        decode(Encoding::Gzip, &[&body]).expect("decoding to succeed"),
+        RESPONSE,
+        "body"
+    )
+}
+
+#[test]
+fn test_decode_gzip_in_chunks() {
+    let body = gzip(RESPONSE);
+    let chunks = body.chunks(3).collect::<Vec<_>>();
+    assert_eq!(
+        decode(Encoding::Gzip, &chunks).expect("decoding to succeed"),
+        RESPONSE,
+        "body"
+    )
+}
+
+#[test]
+fn test_decode_gzip_truncated() {
+    let body = gzip(RESPONSE);
+    assert!(
+        decode(Encoding::Gzip, &[&body[..body.len() - 4]]).is_err(),
+        "truncated body"
+    )
+}
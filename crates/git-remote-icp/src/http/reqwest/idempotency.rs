@@ -0,0 +1,42 @@
+//! Idempotency keys for the update calls a push makes.
+//!
+//! Each push request carries an `Idempotency-Key` header with 32 lowercase
+//! hex digits, made from the time, the helper's process id, and a counter
+//! when the request is made. The request is encoded once, so every retry of
+//! the call, whether ours or a boundary node's, carries the same key, and a
+//! canister that has already applied a request with that key can respond as
+//! it did before rather than updating refs again. Requests too large for a
+//! single call carry it in the request that `commit_chunks` makes.
+
+use ic_certified_assets::types::HeaderField;
+use sha2::{Digest as _, Sha256};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(test)]
+mod tests;
+
+pub const IDEMPOTENCY_KEY: &str = "Idempotency-Key";
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A key that no other request has, including from other helpers running at
+/// the same time.
+pub fn key() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_nanos());
+
+    let mut hash = Sha256::new();
+    hash.update(nanos.to_be_bytes());
+    hash.update(std::process::id().to_be_bytes());
+    hash.update(COUNTER.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+
+    format!("{:x}", hash.finalize())[..32].to_string()
+}
+
+/// Sets the `Idempotency-Key` header to `key`, replacing any Git sent.
+pub fn set_key(headers: &mut Vec<HeaderField>, key: &str) {
+    headers.retain(|(name, _)| !name.eq_ignore_ascii_case(IDEMPOTENCY_KEY));
+    headers.push((IDEMPOTENCY_KEY.to_string(), key.to_string()));
+}
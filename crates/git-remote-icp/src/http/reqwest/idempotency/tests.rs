@@ -0,0 +1,97 @@
+use super::*;
+use crate::retry;
+use candid::{Decode, Encode};
+use ic_certified_assets::types::HttpRequest;
+use serde_bytes::ByteBuf;
+use std::time::Duration;
+
+fn find(headers: &[HeaderField]) -> Option<&str> {
+    headers
+        .iter()
+        .find(|(name, _)| name == IDEMPOTENCY_KEY)
+        .map(|(_, value)| value.as_str())
+}
+
+#[test]
+fn test_key() {
+    let key = key();
+    assert_eq!(key.len(), 32, "length");
+    assert!(
+        key.chars()
+            .all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase()),
+        "lowercase hex: {}",
+        key
+    );
+}
+
+#[test]
+fn test_keys_are_unique() {
+    assert_ne!(key(), key(), "keys");
+}
+
+#[test]
+fn test_set_key_replaces_existing() {
+    let mut headers = vec![
+        ("idempotency-key".to_string(), "from-git".to_string()),
+        (
+            "Content-Type".to_string(),
+            "application/x-git-receive-pack-request".to_string(),
+        ),
+    ];
+
+    set_key(&mut headers, "0123456789abcdef0123456789abcdef");
+
+    assert_eq!(
+        headers,
+        vec![
+            (
+                "Content-Type".to_string(),
+                "application/x-git-receive-pack-request".to_string()
+            ),
+            (
+                IDEMPOTENCY_KEY.to_string(),
+                "0123456789abcdef0123456789abcdef".to_string()
+            ),
+        ],
+        "headers"
+    );
+}
+
+#[test]
+fn test_retried_call_reuses_key() {
+    let mut headers = vec![];
+    set_key(&mut headers, &key());
+
+    let request = HttpRequest {
+        method: "POST".to_string(),
+        url: "/@paul/hello-world.git/git-receive-pack".to_string(),
+        headers,
+        body: ByteBuf::from(b"0000".to_vec()),
+    };
+
+    // The request is encoded once and the same bytes are sent on each
+    // attempt, as for a canister call.
+    let arg = Encode!(&request).expect("request to encode");
+    let mut keys = Vec::new();
+
+    let res = retry::with_backoff(
+        2,
+        Duration::ZERO,
+        |_err: &String| true,
+        || {
+            let request = Decode!(&arg, HttpRequest).expect("request to decode");
+            keys.push(find(&request.headers).map(str::to_string));
+
+            if keys.len() == 1 {
+                Err("service unavailable".to_string())
+            } else {
+                Ok(())
+            }
+        },
+    );
+
+    assert!(res.is_ok(), "retried call succeeds");
+    assert_eq!(keys.len(), 2, "attempts");
+    assert!(keys[0].is_some(), "first attempt has a key");
+    assert_eq!(keys[0], keys[1], "keys");
+}
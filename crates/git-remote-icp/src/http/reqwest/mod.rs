@@ -1,10 +1,24 @@
+use crate::config::Methods;
 use ic_agent::export::Principal;
 use ic_agent::Agent;
+use std::time::Duration;
 
 /// An implementation for HTTP requests via `reqwest`.
 pub struct Remote {
     agent: Agent,
     canister_id: Principal,
+    /// How long to wait for a canister call, for reporting timeouts.
+    timeout: Duration,
+    /// Whether to accept query responses without a certificate, as when
+    /// talking to a local replica.
+    allow_uncertified: bool,
+    /// A `User-Agent` to send to the canister instead of Git's.
+    user_agent: Option<String>,
+    /// The principal calls are made as, which when it's anonymous has pushes
+    /// refused.
+    sender: Principal,
+    /// The canister methods to make requests with.
+    methods: Methods,
     /// A worker thread which performs the actual request.
     handle: Option<std::thread::JoinHandle<Result<(), remote::Error>>>,
     /// A channel to send requests (work) to the worker thread.
@@ -13,5 +27,13 @@ pub struct Remote {
     response: std::sync::mpsc::Receiver<remote::Response>,
 }
 
+mod certification;
+mod chunked_upload;
+mod content_encoding;
+mod idempotency;
+mod refs_cache;
+mod response_limit;
+mod streaming;
+
 ///
 mod remote;
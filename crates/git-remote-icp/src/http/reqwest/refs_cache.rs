@@ -0,0 +1,225 @@
+use git_remote_helper::GIT_DIR;
+use ic_agent::export::Principal;
+use ic_certified_assets::types::HeaderField;
+use log::{trace, warn};
+use sha2::{Digest as _, Sha256};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[cfg(test)]
+mod tests;
+
+const GIT_PROTOCOL: &str = "Git-Protocol";
+const LS_REFS: &[u8] = b"command=ls-refs";
+
+/// Where cached advertisements are kept, relative to the repository.
+const CACHE_DIR: &str = "icp/refs-cache";
+
+/// Ref advertisements from earlier runs of the helper, so that Git listing
+/// refs again within the TTL doesn't download all of them again.
+///
+/// Only responses that list the refs are cached. Each canister's are dropped
+/// once anything is pushed to it or a request to it fails, since either can
+/// mean the refs have changed, and refs that have moved would make Git
+/// negotiate with ids the remote no longer has them at.
+#[derive(Debug)]
+pub struct RefsCache {
+    dir: PathBuf,
+    /// The principal calls are made as, since what a canister lists can
+    /// depend on who's asking.
+    sender: Principal,
+    ttl: Duration,
+}
+
+/// A response that was cached, with a body that's already been decoded and
+/// checked against its certificate.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Cached {
+    pub headers: Vec<HeaderField>,
+    pub body: Vec<u8>,
+}
+
+impl RefsCache {
+    pub fn new(repository: &Path, canister_id: &str, sender: Principal, ttl: Duration) -> Self {
+        Self {
+            dir: repository.join(CACHE_DIR).join(canister_id),
+            sender,
+            ttl,
+        }
+    }
+
+    /// The cache in the repository Git runs the helper for, or `None` if it's
+    /// disabled with a TTL of 0 or there's no repository to keep it in.
+    pub fn from_env(canister_id: &str, sender: Principal, ttl: Duration) -> Option<Self> {
+        if ttl.is_zero() {
+            return None;
+        }
+
+        let repository = std::env::var_os(GIT_DIR)?;
+        Some(Self::new(Path::new(&repository), canister_id, sender, ttl))
+    }
+
+    /// The key to cache the response to a request with, which is `None` for
+    /// requests that don't list refs. The protocol version is part of it since
+    /// Git sends it as a header and the advertisement differs between them,
+    /// and so is the sender, so that refs listed for one identity aren't
+    /// served to another.
+    pub fn key(&self, url: &str, headers: &[HeaderField], body: &[u8]) -> Option<String> {
+        if !lists_refs(url, body) {
+            return None;
+        }
+
+        let version = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(GIT_PROTOCOL))
+            .map_or("", |(_, value)| value.as_str());
+
+        let mut hash = Sha256::new();
+        for part in [
+            self.sender.as_slice(),
+            version.as_bytes(),
+            url.as_bytes(),
+            body,
+        ] {
+            hash.update((part.len() as u64).to_be_bytes());
+            hash.update(part);
+        }
+
+        Some(format!("{:x}", hash.finalize()))
+    }
+
+    /// The response cached for `key`, unless it's older than the TTL.
+    pub fn get(&self, key: &str) -> Option<Cached> {
+        let path = self.dir.join(key);
+
+        let age = std::fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok()?
+            .elapsed()
+            .unwrap_or_default();
+
+        if age > self.ttl {
+            trace!("refs cache for {} expired {:?} ago", key, age - self.ttl);
+            return None;
+        }
+
+        let cached = std::fs::read(&path).ok().and_then(|bytes| decode(&bytes));
+        trace!("refs cache hit for {}: {}", key, cached.is_some());
+        cached
+    }
+
+    /// Caches the response to the request with `key`. It's written to another
+    /// file first so that a helper reading it concurrently doesn't see half
+    /// of it.
+    pub fn put(&self, key: &str, headers: &[HeaderField], body: &[u8]) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+
+        let path = self.dir.join(key);
+        let partial = path.with_extension(format!("{}.tmp", std::process::id()));
+
+        let mut file = std::fs::File::create(&partial)?;
+        file.write_all(&encode(headers, body))?;
+        file.sync_all()?;
+
+        std::fs::rename(&partial, &path)
+    }
+
+    /// Drops everything cached for the canister.
+    pub fn invalidate(&self) {
+        match std::fs::remove_dir_all(&self.dir) {
+            Ok(()) => trace!("invalidated refs cache at {}", self.dir.display()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => warn!(
+                "failed to invalidate refs cache at {}: {}",
+                self.dir.display(),
+                err
+            ),
+        }
+    }
+}
+
+/// Whether the response to a request lists refs, which is the advertisement
+/// for fetching with protocol v0 or v1, and `ls-refs` with protocol v2.
+fn lists_refs(url: &str, body: &[u8]) -> bool {
+    if body.is_empty() {
+        url.contains("/info/refs") && url.contains("service=git-upload-pack")
+    } else {
+        url.ends_with("/git-upload-pack")
+            && body.windows(LS_REFS.len()).any(|window| window == LS_REFS)
+    }
+}
+
+/// A header per line, then a blank line, then the body.
+fn encode(headers: &[HeaderField], body: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for (name, value) in headers {
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.push(b':');
+        bytes.extend_from_slice(value.as_bytes());
+        bytes.push(b'\n');
+    }
+    bytes.push(b'\n');
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+fn decode(bytes: &[u8]) -> Option<Cached> {
+    let mut headers = Vec::new();
+    let mut rest = bytes;
+
+    loop {
+        let end = rest.iter().position(|byte| *byte == b'\n')?;
+        let line = std::str::from_utf8(&rest[..end]).ok()?;
+        rest = &rest[end + 1..];
+
+        if line.is_empty() {
+            break;
+        }
+
+        let (name, value) = line.split_once(':')?;
+        headers.push((name.to_string(), value.to_string()));
+    }
+
+    Some(Cached {
+        headers,
+        body: rest.to_vec(),
+    })
+}
+
+/// Passes what's written on to the inner writer, keeping a copy of it to be
+/// cached once it's all been written.
+pub struct Tee<W: Write> {
+    inner: W,
+    copy: Option<Vec<u8>>,
+}
+
+impl<W: Write> Tee<W> {
+    /// Keeps a copy only if `copy` is set, as when there's a key to cache the
+    /// response with.
+    pub fn new(inner: W, copy: bool) -> Self {
+        Self {
+            inner,
+            copy: copy.then(Vec::new),
+        }
+    }
+
+    /// What was written, if a copy was kept.
+    pub fn into_copy(self) -> Option<Vec<u8>> {
+        self.copy
+    }
+}
+
+impl<W: Write> Write for Tee<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(copy) = &mut self.copy {
+            copy.extend_from_slice(&buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,191 @@
+use super::*;
+
+const CANISTER_ID: &str = "w7uni-tiaaa-aaaam-qaydq-cai";
+const INFO_REFS_URL: &str =
+    "https://example.com/@paul/hello-world.git/info/refs?service=git-upload-pack";
+const UPLOAD_PACK_URL: &str = "https://example.com/@paul/hello-world.git/git-upload-pack";
+const LS_REFS_BODY: &[u8] = b"0014command=ls-refs\n0001000dpeel\n0000";
+const FETCH_BODY: &[u8] = b"0012command=fetch\n0001000ddone\n0000";
+
+fn headers(headers: &[(&str, &str)]) -> Vec<HeaderField> {
+    headers
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+/// The key a cache for the anonymous identity has for a request.
+fn key(url: &str, headers: &[HeaderField], body: &[u8]) -> Option<String> {
+    RefsCache::new(
+        Path::new("."),
+        CANISTER_ID,
+        Principal::anonymous(),
+        Duration::ZERO,
+    )
+    .key(url, headers, body)
+}
+
+fn cache(ttl: Duration) -> (tempfile::TempDir, RefsCache) {
+    let dir = tempfile::tempdir().expect("temp dir to be created");
+    let cache = RefsCache::new(dir.path(), CANISTER_ID, Principal::anonymous(), ttl);
+    (dir, cache)
+}
+
+#[test]
+fn test_key_info_refs() {
+    assert!(
+        key(INFO_REFS_URL, &[], &[]).is_some(),
+        "ref advertisement is cached"
+    )
+}
+
+#[test]
+fn test_key_ls_refs() {
+    assert!(
+        key(UPLOAD_PACK_URL, &[], LS_REFS_BODY).is_some(),
+        "ls-refs is cached"
+    )
+}
+
+#[test]
+fn test_key_not_listing_refs() {
+    assert_eq!(key(UPLOAD_PACK_URL, &[], FETCH_BODY), None, "fetch");
+    assert_eq!(
+        key(
+            "https://example.com/@paul/hello-world.git/info/refs?service=git-receive-pack",
+            &[],
+            &[]
+        ),
+        None,
+        "push advertisement"
+    )
+}
+
+#[test]
+fn test_key_protocol_version() {
+    let v1 = key(
+        INFO_REFS_URL,
+        &headers(&[("Git-Protocol", "version=1")]),
+        &[],
+    );
+    let v2 = key(
+        INFO_REFS_URL,
+        &headers(&[("git-protocol", "version=2")]),
+        &[],
+    );
+    assert_ne!(v1, v2, "keys");
+}
+
+#[test]
+fn test_key_ls_refs_arguments() {
+    let all = key(UPLOAD_PACK_URL, &[], LS_REFS_BODY);
+    let heads = key(
+        UPLOAD_PACK_URL,
+        &[],
+        b"0014command=ls-refs\n0001001bref-prefix refs/heads/\n0000",
+    );
+    assert_ne!(all, heads, "keys");
+}
+
+#[test]
+fn test_key_sender() {
+    let other = RefsCache::new(
+        Path::new("."),
+        CANISTER_ID,
+        Principal::management_canister(),
+        Duration::ZERO,
+    );
+    assert_ne!(
+        key(INFO_REFS_URL, &[], &[]),
+        other.key(INFO_REFS_URL, &[], &[]),
+        "keys"
+    );
+}
+
+#[test]
+fn test_get_missing() {
+    let (_dir, cache) = cache(Duration::from_secs(60));
+    assert_eq!(cache.get("missing"), None, "cached");
+}
+
+#[test]
+fn test_put_then_get() {
+    let (_dir, cache) = cache(Duration::from_secs(60));
+    let headers = headers(&[
+        (
+            "Content-Type",
+            "application/x-git-upload-pack-advertisement",
+        ),
+        ("Cache-Control", "no-cache"),
+    ]);
+    let body = b"001e# service=git-upload-pack\n0000\n\nbinary\0body";
+
+    cache.put("key", &headers, body).expect("put to succeed");
+
+    assert_eq!(
+        cache.get("key"),
+        Some(Cached {
+            headers,
+            body: body.to_vec(),
+        }),
+        "cached"
+    );
+}
+
+#[test]
+fn test_get_expired() {
+    let (_dir, cache) = cache(Duration::from_millis(1));
+    cache.put("key", &[], b"0000").expect("put to succeed");
+
+    std::thread::sleep(Duration::from_millis(10));
+
+    assert_eq!(cache.get("key"), None, "cached");
+}
+
+#[test]
+fn test_invalidate() {
+    let (_dir, cache) = cache(Duration::from_secs(60));
+    cache.put("key", &[], b"0000").expect("put to succeed");
+
+    cache.invalidate();
+
+    assert_eq!(cache.get("key"), None, "cached");
+}
+
+#[test]
+fn test_invalidate_other_canister() {
+    let (dir, cache) = cache(Duration::from_secs(60));
+    let other = RefsCache::new(
+        dir.path(),
+        "aaaaa-aa",
+        Principal::anonymous(),
+        Duration::from_secs(60),
+    );
+    cache.put("key", &[], b"0000").expect("put to succeed");
+
+    other.invalidate();
+
+    assert!(cache.get("key").is_some(), "cached");
+}
+
+#[test]
+fn test_from_env_disabled() {
+    assert!(
+        RefsCache::from_env(CANISTER_ID, Principal::anonymous(), Duration::ZERO).is_none(),
+        "cache"
+    )
+}
+
+#[test]
+fn test_tee() {
+    let mut tee = Tee::new(Vec::new(), true);
+    tee.write_all(b"0000").expect("write to succeed");
+    assert_eq!(tee.into_copy(), Some(b"0000".to_vec()), "copy");
+}
+
+#[test]
+fn test_tee_without_copy() {
+    let mut tee = Tee::new(Vec::new(), false);
+    tee.write_all(b"0000").expect("write to succeed");
+    assert_eq!(tee.into_copy(), None, "copy");
+}
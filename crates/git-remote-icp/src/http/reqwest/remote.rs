@@ -1,23 +1,36 @@
 // Based on
 // https://github.com/Byron/gitoxide/blob/e6b9906c486b11057936da16ed6e0ec450a0fb83/git-transport/src/client/blocking_io/http/reqwest/remote.rs
 
-use crate::{http, http::reqwest::Remote};
+use crate::http::reqwest::refs_cache::{self, RefsCache};
+use crate::http::reqwest::response_limit::Limited;
+use crate::http::reqwest::{
+    certification, chunked_upload, content_encoding, idempotency, streaming, Remote,
+};
+use crate::{concurrency, config, http, interrupt, retry};
 
 use candid::{Decode, Encode};
 use git_features::io::pipe;
+use git_remote_helper::debug;
 use git_repository as git;
 // https://github.com/Byron/gitoxide/pull/690
 use git::protocol::transport::client::http::PostBodyDataKind;
 use ic_agent::export::Principal;
-use ic_agent::Agent;
-use ic_certified_assets::types::{HeaderField, HttpRequest, HttpResponse};
-use log::trace;
+use ic_agent::{Agent, AgentError};
+use ic_certified_assets::types::{
+    HeaderField, HttpRequest, HttpResponse, StreamingCallbackHttpResponse, StreamingCallbackToken,
+};
+use log::{trace, warn};
 use serde_bytes::ByteBuf;
+use sha2::{Digest as _, Sha256};
 use std::any::Any;
 use std::io::{Read, Write};
 use std::ops::Deref;
+use std::time::Duration;
 use tokio::runtime::Runtime;
 
+#[cfg(test)]
+mod tests;
+
 /// The error returned by the 'remote' helper, a purely internal construct to perform http requests.
 #[derive(Debug, thiserror::Error)]
 #[allow(missing_docs)]
@@ -41,17 +54,32 @@ impl git::protocol::transport::IsSpuriousError for Error {
 }
 
 impl Remote {
-    pub fn new(agent: Agent, canister_id: Principal) -> Self {
+    pub fn new(
+        agent: Agent,
+        canister_id: Principal,
+        timeout: Duration,
+        allow_uncertified: bool,
+        user_agent: Option<String>,
+        sender: Principal,
+        methods: config::Methods,
+    ) -> Self {
         let (req_send, req_recv) = std::sync::mpsc::sync_channel(0);
         let (res_send, res_recv) = std::sync::mpsc::sync_channel(0);
         let runtime = Runtime::new().expect("failed to create runtime");
         let moved_agent = agent.clone();
+        let chunk_size = config::upload_chunk_size();
+        let fetch_concurrency = config::fetch_concurrency();
+        let max_response_bytes = config::max_response_bytes();
+        let moved_methods = methods.clone();
+        let refs_cache =
+            RefsCache::from_env(&canister_id.to_text(), sender, config::refs_cache_ttl());
+        let anonymous = sender == Principal::anonymous();
         let handle = std::thread::spawn(move || -> Result<(), Error> {
             // We may error while configuring, which is expected as part of the internal protocol. The error will be
             // received and the sender of the request might restart us.
             for Request {
                 url,
-                headers,
+                mut headers,
                 upload_body_kind,
             } in req_recv
             {
@@ -90,6 +118,54 @@ impl Remote {
                 }
                 .to_string();
 
+                let request_url = url.clone();
+
+                // The canister would refuse the push anyway, but with a less
+                // helpful error about permissions.
+                if anonymous && is_push(&request_url) {
+                    let kind = std::io::ErrorKind::PermissionDenied;
+                    let err = format!(
+                        "refusing to push anonymously; set {} to the path of the private key to push with",
+                        config::IDENTITY_PEM_ENV_VAR
+                    );
+                    headers_tx
+                        .channel
+                        .send(Err(std::io::Error::new(kind, err)))
+                        .ok();
+                    continue;
+                }
+
+                // Refs listed before a push may have moved since.
+                if is_push(&request_url) {
+                    if let Some(refs_cache) = &refs_cache {
+                        refs_cache.invalidate();
+                    }
+                }
+
+                let cache_key = refs_cache
+                    .as_ref()
+                    .and_then(|refs_cache| refs_cache.key(&request_url, &headers, &body));
+
+                let cached = refs_cache
+                    .as_ref()
+                    .zip(cache_key.as_ref())
+                    .and_then(|(refs_cache, key)| refs_cache.get(key));
+
+                if let Some(cached) = cached {
+                    trace!("using cached refs for {}", request_url);
+                    send_headers(headers_tx, &cached.headers).ok();
+                    if let Err(err) = response_body_tx.write_all(&cached.body) {
+                        response_body_tx.channel.send(Err(err)).ok();
+                    }
+                    continue;
+                }
+
+                // Made before the request is encoded, so that every retry of
+                // the call has the same key.
+                if upload_body_kind.is_some() && is_push(&request_url) {
+                    idempotency::set_key(&mut headers, &idempotency::key());
+                }
+
                 let http_request = HttpRequest {
                     method,
                     url,
@@ -98,36 +174,69 @@ impl Remote {
                 };
 
                 trace!("http_request: {:#?}", http_request);
+                debug::write("request-body.bin", http_request.body.as_slice());
 
-                let arg = match candid::Encode!(&http_request) {
-                    Ok(arg) => arg,
-                    Err(err) => {
-                        let kind = std::io::ErrorKind::Other;
-                        let err = Err(std::io::Error::new(kind, err));
-                        headers_tx.channel.send(err).ok();
-                        continue;
-                    }
-                };
-
-                let res = if let Some(_) = upload_body_kind {
-                    runtime.block_on(
-                        moved_agent
-                            .update(&canister_id, "http_request_update")
-                            .with_arg(&arg)
-                            .call_and_wait(),
+                let res = if upload_body_kind.is_some() && http_request.body.len() > chunk_size {
+                    chunked_upload::upload(
+                        chunked_upload::upload_id(),
+                        http_request,
+                        chunk_size,
+                        &moved_methods,
+                        |method, arg| {
+                            call(&runtime, &moved_agent, &canister_id, method, &arg, true)
+                        },
                     )
                 } else {
-                    runtime.block_on(
-                        moved_agent
-                            .query(&canister_id, "http_request")
-                            .with_arg(&arg)
-                            .call(),
-                    )
+                    let arg = match candid::Encode!(&http_request) {
+                        Ok(arg) => arg,
+                        Err(err) => {
+                            let kind = std::io::ErrorKind::Other;
+                            let err = Err(std::io::Error::new(kind, err));
+                            headers_tx.channel.send(err).ok();
+                            continue;
+                        }
+                    };
+
+                    if let Some(_) = upload_body_kind {
+                        call(
+                            &runtime,
+                            &moved_agent,
+                            &canister_id,
+                            &moved_methods.update,
+                            &arg,
+                            true,
+                        )
+                    } else {
+                        call(
+                            &runtime,
+                            &moved_agent,
+                            &canister_id,
+                            &moved_methods.query,
+                            &arg,
+                            false,
+                        )
+                    }
                 };
 
                 let res = res
                     .map_err(|agent_error| {
-                        std::io::Error::new(std::io::ErrorKind::Other, agent_error)
+                        if interrupt::is_interrupted(&agent_error) {
+                            let err = format!(
+                                "canister call to {} for {} was interrupted",
+                                canister_id, request_url
+                            );
+                            std::io::Error::new(std::io::ErrorKind::Other, err)
+                        } else if is_timeout(&agent_error) {
+                            let err = format!(
+                                "canister call to {} for {} timed out after {}s",
+                                canister_id,
+                                request_url,
+                                timeout.as_secs()
+                            );
+                            std::io::Error::new(std::io::ErrorKind::TimedOut, err)
+                        } else {
+                            std::io::Error::new(std::io::ErrorKind::Other, agent_error)
+                        }
                     })
                     .and_then(|res| {
                         Decode!(res.as_slice(), HttpResponse).map_err(|candid_error| {
@@ -154,41 +263,98 @@ impl Remote {
                                 Err(std::io::Error::new(kind, err))
                             }),
                         _ => Ok(res),
+                    })
+                    .and_then(|res| {
+                        let encoding = content_encoding::check(&res.headers)?;
+                        let certified = if upload_body_kind.is_none() {
+                            certification::verify(
+                                &moved_agent,
+                                canister_id,
+                                &request_url,
+                                &res.headers,
+                                allow_uncertified,
+                            )?
+                        } else {
+                            None
+                        };
+                        Ok((res, encoding, certified))
                     });
 
-                let res = match res {
+                let (res, encoding, certified) = match res {
                     Ok(res) => res,
                     Err(err) => {
+                        // A failure may be down to the refs having changed.
+                        if let Some(refs_cache) = &refs_cache {
+                            refs_cache.invalidate();
+                        }
                         headers_tx.channel.send(Err(err)).ok();
                         continue;
                     }
                 };
 
-                let send_headers = {
-                    move || -> std::io::Result<()> {
-                        for (name, value) in res.headers {
-                            headers_tx.write_all(name.as_str().as_bytes())?;
-                            headers_tx.write_all(b":")?;
-                            headers_tx.write_all(value.as_bytes())?;
-                            headers_tx.write_all(b"\n")?;
-                        }
-                        // Make sure this is an FnOnce closure to signal the remote reader we are done.
-                        drop(headers_tx);
-                        Ok(())
-                    }
-                };
+                debug::write("response-body.bin", res.body.as_slice());
+
+                let response_headers = cache_key.as_ref().map(|_| res.headers.clone());
 
                 // We don't have to care if anybody is receiving the header, as
                 // a matter of fact we cannot fail sending them. Thus an error
                 // means the receiver failed somehow, but might also have
                 // decided not to read headers at all. Fine with us.
-                send_headers().ok();
+                send_headers(headers_tx, &res.headers).ok();
 
                 // Reading the response body is streaming and may fail for many
                 // reasons. If so, we send the error over the response body
-                // channel and that's all we can do.
-                if let Err(err) = std::io::copy(&mut res.body.deref(), &mut response_body_tx) {
-                    response_body_tx.channel.send(Err(err)).ok();
+                // channel and that's all we can do. Since the body is checked
+                // against its certified hash only once all of it has been
+                // streamed, a mismatch is sent before the reader sees EOF.
+                // What's certified is the encoded body, so it's hashed before
+                // being decoded.
+                let mut body_hash = Sha256::new();
+                let mut body = content_encoding::Decoder::new(
+                    encoding,
+                    Limited::new(
+                        refs_cache::Tee::new(&mut response_body_tx, cache_key.is_some()),
+                        max_response_bytes,
+                    ),
+                );
+
+                let written = streaming::write_body(
+                    canister_id,
+                    res.body.deref(),
+                    res.streaming_strategy,
+                    fetch_concurrency,
+                    |method, token| next_chunk(&runtime, &moved_agent, &canister_id, method, token),
+                    |chunk| {
+                        body_hash.update(chunk);
+                        body.write_all(chunk)
+                    },
+                )
+                .and_then(|_| body.finish())
+                .map(Limited::into_inner)
+                .and_then(|tee| {
+                    if let Some(certified) = &certified {
+                        certified.check(&body_hash.finalize())?;
+                    }
+                    Ok(tee)
+                })
+                .map(refs_cache::Tee::into_copy);
+
+                match written {
+                    Ok(copy) => {
+                        if let (Some(refs_cache), Some(key), Some(headers), Some(copy)) =
+                            (&refs_cache, &cache_key, &response_headers, copy)
+                        {
+                            if let Err(err) = refs_cache.put(key, headers, &copy) {
+                                warn!("failed to cache refs for {}: {}", request_url, err);
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        if let Some(refs_cache) = &refs_cache {
+                            refs_cache.invalidate();
+                        }
+                        response_body_tx.channel.send(Err(err)).ok();
+                    }
                 }
             }
             Ok(())
@@ -197,6 +363,11 @@ impl Remote {
         Remote {
             agent,
             canister_id,
+            timeout,
+            allow_uncertified,
+            user_agent,
+            sender,
+            methods,
             handle: Some(handle),
             request: req_send,
             response: res_recv,
@@ -204,6 +375,108 @@ impl Remote {
     }
 }
 
+/// Writes `headers` for the reader, then drops the writer so that it knows
+/// there are no more.
+fn send_headers(mut headers_tx: pipe::Writer, headers: &[HeaderField]) -> std::io::Result<()> {
+    for (name, value) in headers {
+        headers_tx.write_all(name.as_str().as_bytes())?;
+        headers_tx.write_all(b":")?;
+        headers_tx.write_all(value.as_bytes())?;
+        headers_tx.write_all(b"\n")?;
+    }
+    drop(headers_tx);
+    Ok(())
+}
+
+/// Makes a canister call once there's room for it, retrying transient
+/// failures, which for update calls are only those before the call was sent.
+/// The call is abandoned on Ctrl-C, which also stops the uploads of any
+/// chunks after it.
+fn call(
+    runtime: &Runtime,
+    agent: &Agent,
+    canister_id: &Principal,
+    method: &str,
+    arg: &[u8],
+    update: bool,
+) -> Result<Vec<u8>, AgentError> {
+    let attempt = || {
+        git_remote_helper::stats::record_canister_call();
+
+        concurrency::canister_call(|| {
+            let should_interrupt = &git::interrupt::IS_INTERRUPTED;
+
+            if update {
+                interrupt::block_on(
+                    runtime,
+                    should_interrupt,
+                    agent
+                        .update(canister_id, method)
+                        .with_arg(arg)
+                        .call_and_wait(),
+                )
+            } else {
+                interrupt::block_on(
+                    runtime,
+                    should_interrupt,
+                    agent.query(canister_id, method).with_arg(arg).call(),
+                )
+            }
+        })
+    };
+
+    if update {
+        retry::canister_update(attempt)
+    } else {
+        retry::canister_call(attempt)
+    }
+}
+
+/// Queries the streaming callback for the chunk of a body after `token`.
+fn next_chunk(
+    runtime: &Runtime,
+    agent: &Agent,
+    canister_id: &Principal,
+    method: &str,
+    token: StreamingCallbackToken,
+) -> std::io::Result<StreamingCallbackHttpResponse> {
+    let arg = Encode!(&token)
+        .map_err(|candid_error| std::io::Error::new(std::io::ErrorKind::Other, candid_error))?;
+
+    let res = call(runtime, agent, canister_id, method, &arg, false)
+        .map_err(|agent_error| std::io::Error::new(std::io::ErrorKind::Other, agent_error))?;
+
+    Decode!(res.as_slice(), StreamingCallbackHttpResponse)
+        .map_err(|candid_error| std::io::Error::new(std::io::ErrorKind::Other, candid_error))
+}
+
+fn is_timeout(agent_error: &AgentError) -> bool {
+    match agent_error {
+        AgentError::TimeoutWaitingForResponse() => true,
+        AgentError::TransportError(err) => err
+            .downcast_ref::<reqwest::Error>()
+            .map_or(false, reqwest::Error::is_timeout),
+        _ => false,
+    }
+}
+
+/// Whether `url` is requested as part of a push, which Git makes to the
+/// `git-receive-pack` service.
+fn is_push(url: &str) -> bool {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+
+    path.ends_with("/git-receive-pack")
+        || query
+            .split('&')
+            .any(|parameter| parameter == "service=git-receive-pack")
+}
+
+fn set_user_agent(headers: &mut Vec<HeaderField>, user_agent: &str) {
+    headers.retain(|(name, _)| !name.eq_ignore_ascii_case("User-Agent"));
+    trace!("User-Agent: {}", user_agent);
+    headers.push(("User-Agent".to_string(), user_agent.to_string()));
+}
+
 /// utilities
 impl Remote {
     fn make_request(
@@ -223,6 +496,12 @@ impl Remote {
             let value = &header_line[colon_pos + 1..];
             header_values.push((header_name.trim().to_string(), value.trim().to_string()));
         }
+        content_encoding::accept(&mut header_values);
+
+        if let Some(user_agent) = &self.user_agent {
+            set_user_agent(&mut header_values, user_agent);
+        }
+
         self.request
             .send(Request {
                 url: url.to_owned(),
@@ -245,7 +524,15 @@ impl Remote {
                     .join()
                     .expect("no panic")
                     .expect_err("no receiver means thread is down with init error");
-                *self = Self::new(self.agent.clone(), self.canister_id);
+                *self = Self::new(
+                    self.agent.clone(),
+                    self.canister_id,
+                    self.timeout,
+                    self.allow_uncertified,
+                    self.user_agent.clone(),
+                    self.sender,
+                    self.methods.clone(),
+                );
                 return Err(http::Error::InitHttpClient {
                     source: Box::new(err),
                 });
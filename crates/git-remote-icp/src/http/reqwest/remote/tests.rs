@@ -0,0 +1,86 @@
+use super::*;
+use git::protocol::transport::client::http::Http as _;
+use ic_agent::agent::http_transport::ReqwestHttpReplicaV2Transport;
+use std::net::TcpListener;
+
+const URL: &str = "https://ic0.app/@paul/hello-world.git";
+
+/// A remote making calls with the anonymous identity to a replica at
+/// `replica_url`.
+fn anonymous_remote(replica_url: &str) -> Remote {
+    let transport = ReqwestHttpReplicaV2Transport::create(replica_url).expect("transport");
+    let agent = Agent::builder()
+        .with_transport(transport)
+        .build()
+        .expect("agent");
+
+    Remote::new(
+        agent,
+        Principal::anonymous(),
+        Duration::from_secs(30),
+        false,
+        None,
+        Principal::anonymous(),
+        config::Methods {
+            query: "http_request".to_string(),
+            update: "http_request_update".to_string(),
+            store_chunk: "store_chunk".to_string(),
+            commit_chunks: "commit_chunks".to_string(),
+        },
+    )
+}
+
+#[test]
+fn test_is_push() {
+    for url in [
+        format!("{}/info/refs?service=git-receive-pack", URL),
+        format!("{}/git-receive-pack", URL),
+        "/@paul/hello-world.git/git-receive-pack".to_string(),
+    ] {
+        assert!(is_push(&url), "push: {}", url);
+    }
+
+    for url in [
+        format!("{}/info/refs?service=git-upload-pack", URL),
+        format!("{}/git-upload-pack", URL),
+        format!("{}/info/refs?service=git-receive-pack-v2", URL),
+        "https://ic0.app/git-receive-pack.git/git-upload-pack".to_string(),
+        "https://ic0.app/@paul/service=git-receive-pack/info/refs".to_string(),
+    ] {
+        assert!(!is_push(&url), "not a push: {}", url);
+    }
+}
+
+#[test]
+fn test_anonymous_push_is_refused_before_calling_the_canister() {
+    let replica = TcpListener::bind("127.0.0.1:0").expect("listener");
+    replica.set_nonblocking(true).expect("nonblocking");
+    let replica_url = format!("http://{}", replica.local_addr().expect("address"));
+
+    let mut remote = anonymous_remote(&replica_url);
+
+    let url = format!("{}/info/refs?service=git-receive-pack", URL);
+    let mut response = remote
+        .get(&url, URL, Vec::<String>::new())
+        .expect("request to be sent");
+
+    let err = response
+        .headers
+        .read_to_end(&mut Vec::new())
+        .expect_err("push to be refused");
+
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied, "kind");
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "refusing to push anonymously; set {} to the path of the private key to push with",
+            config::IDENTITY_PEM_ENV_VAR
+        ),
+        "error"
+    );
+    assert_eq!(
+        replica.accept().map(|_| ()).map_err(|err| err.kind()),
+        Err(std::io::ErrorKind::WouldBlock),
+        "no connection to the replica"
+    );
+}
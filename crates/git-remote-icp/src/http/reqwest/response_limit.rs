@@ -0,0 +1,55 @@
+use crate::config;
+use std::io::Write;
+
+#[cfg(test)]
+mod tests;
+
+/// Passes what's written on to the inner writer until more than `max_bytes`
+/// have been written in total, then fails, so that a canister can't make the
+/// helper pass on or cache an unbounded response. It's written to once the
+/// body has been decoded, so a small compressed body that decodes to a huge
+/// one is caught as well.
+pub struct Limited<W: Write> {
+    inner: W,
+    written: u64,
+    max_bytes: u64,
+}
+
+impl<W: Write> Limited<W> {
+    pub fn new(inner: W, max_bytes: u64) -> Self {
+        Self {
+            inner,
+            written: 0,
+            max_bytes,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for Limited<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = u64::try_from(buf.len()).unwrap_or(u64::MAX);
+
+        if self.written.saturating_add(len) > self.max_bytes {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "the response is larger than {} bytes. Set {} to allow larger responses",
+                    self.max_bytes,
+                    config::MAX_RESPONSE_BYTES_ENV_VAR
+                ),
+            ));
+        }
+
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,51 @@
+use super::*;
+
+#[test]
+fn test_within_limit() {
+    let mut limited = Limited::new(Vec::new(), 8);
+    limited.write_all(b"0004").expect("write to succeed");
+    limited.write_all(b"0000").expect("write to succeed");
+    assert_eq!(limited.into_inner(), b"00040000", "written");
+}
+
+#[test]
+fn test_over_limit() {
+    let mut limited = Limited::new(Vec::new(), 8);
+    limited.write_all(b"0004").expect("write to succeed");
+
+    let err = limited
+        .write_all(b"00000")
+        .expect_err("limit to be exceeded");
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData, "kind");
+    assert_eq!(
+        err.to_string(),
+        "the response is larger than 8 bytes. Set ICP_MAX_RESPONSE_BYTES to allow larger responses",
+        "message"
+    );
+    assert_eq!(limited.into_inner(), b"0004", "nothing more is written");
+}
+
+#[test]
+fn test_over_limit_after_decoding() {
+    use crate::http::reqwest::content_encoding::{Decoder, Encoding};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    // A MiB of zeros compresses to about a KiB.
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(&vec![0; 1024 * 1024])
+        .expect("write to succeed");
+    let body = encoder.finish().expect("encoding to succeed");
+
+    let mut decoder = Decoder::new(Encoding::Gzip, Limited::new(Vec::new(), 64 * 1024));
+    let result = decoder
+        .write_all(&body)
+        .and_then(|_| decoder.finish().map(|_| ()));
+
+    assert_eq!(
+        result.expect_err("limit to be exceeded").kind(),
+        std::io::ErrorKind::InvalidData,
+        "kind"
+    );
+}
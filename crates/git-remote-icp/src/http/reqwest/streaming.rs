@@ -0,0 +1,132 @@
+use candid::Nat;
+use ic_agent::export::Principal;
+use ic_certified_assets::types::{
+    StreamingCallbackHttpResponse, StreamingCallbackToken, StreamingStrategy,
+};
+use log::trace;
+
+#[cfg(test)]
+mod tests;
+
+/// Writes a response body that the canister may stream, calling back for each
+/// chunk after the first.
+///
+/// Up to `concurrency` chunks are requested at once, with the tokens of the
+/// ones after the next predicted by incrementing its index, as asset canisters
+/// do. They're written in order however they arrive, so only that many chunks
+/// of a large pack are in memory at a time. Once a prediction turns out wrong,
+/// the rest of the chunks are requested one at a time.
+pub fn write_body(
+    canister_id: Principal,
+    body: &[u8],
+    streaming_strategy: Option<StreamingStrategy>,
+    concurrency: usize,
+    next_chunk: impl Fn(&str, StreamingCallbackToken) -> std::io::Result<StreamingCallbackHttpResponse>
+        + Sync,
+    mut write: impl FnMut(&[u8]) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    write(body)?;
+
+    let (method, mut token) = match streaming_strategy {
+        Some(StreamingStrategy::Callback { callback, token }) => {
+            // The certificate only vouches for the canister we called.
+            if callback.principal != canister_id {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "refusing to stream a response from {} via {}",
+                        canister_id, callback.principal
+                    ),
+                ));
+            }
+            (callback.method, token)
+        }
+        None => return Ok(()),
+    };
+
+    let mut concurrency = concurrency.max(1);
+
+    'rounds: loop {
+        trace!("streaming the next {} chunks of {}", concurrency, token.key);
+
+        let tokens = (0..concurrency as u64)
+            .map(|offset| with_offset(&token, offset))
+            .collect::<Vec<_>>();
+        let responses = next_chunks(&method, &tokens, &next_chunk);
+
+        // Chunks requested past the end, or with a wrong prediction, are
+        // discarded, including any errors from them.
+        for (index, res) in responses.into_iter().enumerate() {
+            let res = res?;
+            write(&res.body)?;
+
+            let next = match res.token {
+                Some(next) => next,
+                None => return Ok(()),
+            };
+
+            match tokens.get(index + 1) {
+                Some(predicted) if is_same(predicted, &next) => {}
+                Some(_) => {
+                    trace!("the canister's streaming tokens aren't sequential");
+                    concurrency = 1;
+                    token = next;
+                    continue 'rounds;
+                }
+                None => {
+                    token = next;
+                    continue 'rounds;
+                }
+            }
+        }
+    }
+}
+
+/// Requests the chunks for `tokens` at once, returning the responses in the
+/// order of `tokens`.
+fn next_chunks<F>(
+    method: &str,
+    tokens: &[StreamingCallbackToken],
+    next_chunk: &F,
+) -> Vec<std::io::Result<StreamingCallbackHttpResponse>>
+where
+    F: Fn(&str, StreamingCallbackToken) -> std::io::Result<StreamingCallbackHttpResponse> + Sync,
+{
+    if let [token] = tokens {
+        return vec![next_chunk(method, token.clone())];
+    }
+
+    std::thread::scope(|scope| {
+        let handles = tokens
+            .iter()
+            .map(|token| scope.spawn(move || next_chunk(method, token.clone())))
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle.join().unwrap_or_else(|_| {
+                    Err(std::io::Error::new(
+                        std::io::ErrorKind::Other,
+                        "the request for a chunk panicked",
+                    ))
+                })
+            })
+            .collect()
+    })
+}
+
+/// The token `offset` chunks after `token`.
+fn with_offset(token: &StreamingCallbackToken, offset: u64) -> StreamingCallbackToken {
+    StreamingCallbackToken {
+        index: Nat(token.index.0.clone() + offset),
+        ..token.clone()
+    }
+}
+
+fn is_same(a: &StreamingCallbackToken, b: &StreamingCallbackToken) -> bool {
+    a.key == b.key
+        && a.content_encoding == b.content_encoding
+        && a.index == b.index
+        && a.sha256 == b.sha256
+}
@@ -0,0 +1,226 @@
+use super::*;
+use candid::{Func, Nat};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const CANISTER_ID: &str = "w7uni-tiaaa-aaaam-qaydq-cai";
+
+fn canister_id() -> Principal {
+    Principal::from_text(CANISTER_ID).expect("valid principal")
+}
+
+fn token(index: u64) -> StreamingCallbackToken {
+    StreamingCallbackToken {
+        key: "/@paul/hello-world.git/git-upload-pack".to_string(),
+        content_encoding: "identity".to_string(),
+        index: Nat::from(index),
+        sha256: None,
+    }
+}
+
+fn callback(principal: Principal) -> StreamingStrategy {
+    StreamingStrategy::Callback {
+        callback: Func {
+            principal,
+            method: "http_request_streaming_callback".to_string(),
+        },
+        token: token(1),
+    }
+}
+
+#[test]
+fn test_write_body_without_streaming() {
+    let mut body = Vec::new();
+
+    write_body(
+        canister_id(),
+        b"PACK",
+        None,
+        4,
+        |_, _| panic!("unexpected call for the next chunk"),
+        |chunk| {
+            body.extend_from_slice(chunk);
+            Ok(())
+        },
+    )
+    .expect("body to be written");
+
+    assert_eq!(body, b"PACK".to_vec(), "body");
+}
+
+#[test]
+fn test_write_body_streams_chunks() {
+    let chunks = Mutex::new(VecDeque::from([
+        StreamingCallbackHttpResponse {
+            body: b"chunk 1 ".to_vec().into(),
+            token: Some(token(2)),
+        },
+        StreamingCallbackHttpResponse {
+            body: b"chunk 2".to_vec().into(),
+            token: None,
+        },
+    ]));
+    let methods = Mutex::new(Vec::new());
+    let mut writes = Vec::new();
+
+    write_body(
+        canister_id(),
+        b"chunk 0 ",
+        Some(callback(canister_id())),
+        1,
+        |method, _| {
+            methods.lock().expect("lock").push(method.to_string());
+            Ok(chunks
+                .lock()
+                .expect("lock")
+                .pop_front()
+                .expect("another chunk"))
+        },
+        |chunk| {
+            writes.push(chunk.to_vec());
+            Ok(())
+        },
+    )
+    .expect("body to be written");
+
+    assert_eq!(
+        methods.into_inner().expect("lock"),
+        vec!["http_request_streaming_callback"; 2],
+        "callback calls"
+    );
+    assert_eq!(
+        writes,
+        vec![
+            b"chunk 0 ".to_vec(),
+            b"chunk 1 ".to_vec(),
+            b"chunk 2".to_vec()
+        ],
+        "each chunk is written as it arrives"
+    );
+}
+
+#[test]
+fn test_write_body_rejects_other_canisters() {
+    let other = Principal::from_text("rwlgt-iiaaa-aaaaa-aaaaa-cai").expect("valid principal");
+
+    let err = write_body(
+        canister_id(),
+        b"chunk 0 ",
+        Some(callback(other)),
+        4,
+        |_, _| panic!("unexpected call for the next chunk"),
+        |_| Ok(()),
+    )
+    .expect_err("callback to another canister");
+
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData, "kind");
+}
+
+#[test]
+fn test_write_body_reassembles_out_of_order_chunks() {
+    let last = 6;
+    let indices = Mutex::new(Vec::new());
+    let mut writes = Vec::new();
+
+    write_body(
+        canister_id(),
+        b"chunk 0 ",
+        Some(callback(canister_id())),
+        4,
+        |_, requested| {
+            let index = u64::try_from(requested.index.0).expect("small index");
+            indices.lock().expect("lock").push(index);
+
+            if index > last {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "chunk index out of bounds",
+                ));
+            }
+
+            // Later chunks in each round arrive first.
+            std::thread::sleep(Duration::from_millis((last + 1 - index) * 20));
+
+            Ok(StreamingCallbackHttpResponse {
+                body: format!("chunk {} ", index).into_bytes().into(),
+                token: (index < last).then(|| token(index + 1)),
+            })
+        },
+        |chunk| {
+            writes.push(String::from_utf8(chunk.to_vec()).expect("utf8"));
+            Ok(())
+        },
+    )
+    .expect("body to be written");
+
+    assert_eq!(
+        writes.concat(),
+        "chunk 0 chunk 1 chunk 2 chunk 3 chunk 4 chunk 5 chunk 6 ",
+        "chunks are written in order"
+    );
+
+    let mut indices = indices.into_inner().expect("lock");
+    indices.sort_unstable();
+    assert_eq!(
+        indices,
+        vec![1, 2, 3, 4, 5, 6, 7, 8],
+        "two rounds of four, the last partly past the end"
+    );
+}
+
+fn keyed(key: &str) -> StreamingCallbackToken {
+    StreamingCallbackToken {
+        key: key.to_string(),
+        ..token(0)
+    }
+}
+
+#[test]
+fn test_write_body_without_sequential_tokens() {
+    let calls = Mutex::new(0);
+    let mut writes = Vec::new();
+
+    write_body(
+        canister_id(),
+        b"chunk 0 ",
+        Some(StreamingStrategy::Callback {
+            callback: Func {
+                principal: canister_id(),
+                method: "http_request_streaming_callback".to_string(),
+            },
+            token: keyed("a"),
+        }),
+        4,
+        |_, requested| {
+            *calls.lock().expect("lock") += 1;
+
+            match (requested.key.as_str(), requested.index == Nat::from(0u64)) {
+                ("a", true) => Ok(StreamingCallbackHttpResponse {
+                    body: b"chunk 1 ".to_vec().into(),
+                    token: Some(keyed("b")),
+                }),
+                ("b", true) => Ok(StreamingCallbackHttpResponse {
+                    body: b"chunk 2".to_vec().into(),
+                    token: None,
+                }),
+                _ => Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "unknown token",
+                )),
+            }
+        },
+        |chunk| {
+            writes.push(chunk.to_vec());
+            Ok(())
+        },
+    )
+    .expect("body to be written");
+
+    assert_eq!(writes.concat(), b"chunk 0 chunk 1 chunk 2".to_vec(), "body");
+    assert_eq!(
+        calls.into_inner().expect("lock"),
+        5,
+        "a round of four, then one at a time"
+    );
+}
@@ -0,0 +1,53 @@
+use ic_agent::AgentError;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+#[cfg(test)]
+mod tests;
+
+/// How often a canister call in flight checks whether it's been interrupted.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, thiserror::Error)]
+#[error("interrupted")]
+pub struct Interrupted;
+
+/// Runs `call` on `runtime` unless `should_interrupt` is set, abandoning it
+/// if it's set while the call is in flight rather than waiting for the
+/// canister to respond.
+pub fn block_on<T>(
+    runtime: &Runtime,
+    should_interrupt: &AtomicBool,
+    call: impl Future<Output = Result<T, AgentError>>,
+) -> Result<T, AgentError> {
+    if should_interrupt.load(Ordering::Relaxed) {
+        return Err(interrupted());
+    }
+
+    runtime.block_on(async {
+        tokio::select! {
+            res = call => res,
+            _ = wait(should_interrupt) => Err(interrupted()),
+        }
+    })
+}
+
+/// Whether `agent_error` is from a call `block_on` abandoned.
+pub fn is_interrupted(agent_error: &AgentError) -> bool {
+    match agent_error {
+        AgentError::TransportError(err) => err.is::<Interrupted>(),
+        _ => false,
+    }
+}
+
+fn interrupted() -> AgentError {
+    AgentError::TransportError(Box::new(Interrupted))
+}
+
+async fn wait(should_interrupt: &AtomicBool) {
+    while !should_interrupt.load(Ordering::Relaxed) {
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
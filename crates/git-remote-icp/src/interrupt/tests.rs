@@ -0,0 +1,61 @@
+use super::*;
+use std::sync::Arc;
+use std::time::Instant;
+
+#[test]
+fn test_block_on_completes() {
+    let runtime = Runtime::new().expect("runtime");
+    let should_interrupt = AtomicBool::new(false);
+
+    let result = block_on(&runtime, &should_interrupt, async { Ok(1) });
+
+    assert_eq!(result.expect("call to succeed"), 1, "result");
+}
+
+#[test]
+fn test_block_on_already_interrupted() {
+    let runtime = Runtime::new().expect("runtime");
+    let should_interrupt = AtomicBool::new(true);
+
+    let result: Result<(), _> = block_on(&runtime, &should_interrupt, std::future::pending());
+
+    assert!(
+        is_interrupted(&result.expect_err("call to be interrupted")),
+        "interrupted"
+    );
+}
+
+#[test]
+fn test_block_on_abandons_call_in_flight() {
+    let runtime = Runtime::new().expect("runtime");
+    let should_interrupt = Arc::new(AtomicBool::new(false));
+
+    let interrupt = {
+        let should_interrupt = should_interrupt.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            should_interrupt.store(true, Ordering::Relaxed);
+        })
+    };
+
+    let start = Instant::now();
+    let result: Result<(), _> = block_on(&runtime, &should_interrupt, std::future::pending());
+    interrupt.join().expect("no panic");
+
+    assert!(
+        is_interrupted(&result.expect_err("call to be interrupted")),
+        "interrupted"
+    );
+    assert!(
+        start.elapsed() < Duration::from_secs(1),
+        "abandoned within a second"
+    );
+}
+
+#[test]
+fn test_is_interrupted_other_error() {
+    assert!(
+        !is_interrupted(&AgentError::TimeoutWaitingForResponse()),
+        "timeout"
+    );
+}
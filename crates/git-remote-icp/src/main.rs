@@ -1,23 +1,144 @@
+mod address;
+mod concurrency;
 mod config;
 mod connect;
 mod http;
+mod identity;
+mod interrupt;
+mod proxy;
+mod redirect;
+mod retry;
+mod tls;
 
 use anyhow::anyhow;
-use ic_agent::identity::{AnonymousIdentity, Identity, Secp256k1Identity};
-use log::trace;
-use std::sync::Arc;
+use git_remote_helper::cli::Usage;
+use git_remote_helper::error::{self, Kind, WithKind as _};
+use ic_agent::export::Principal;
+use ic_agent::identity::Identity as _;
+use log::{info, trace};
+
+const USAGE: Usage = Usage {
+    name: env!("CARGO_PKG_NAME"),
+    version: env!("CARGO_PKG_VERSION"),
+    about: "A Git remote helper for the Internet Computer Protocol.
+
+Git runs this helper for URLs of the form icp://<address>, or
+icp::<transport>://<address> where <transport> is ic, https, http for local
+development, or file. The canister is taken from URLs of the form
+icp://<canister-id>.<replica-host>/<path> or
+icp://<replica-host>/<canister-id>/<path>, and otherwise from the
+icp.canisterId git config.",
+    env: &[
+        (
+            config::ANONYMOUS_ENV_VAR,
+            "Set to 1 to fetch anonymously even when a private key is set",
+        ),
+        (
+            config::IDENTITY_PEM_ENV_VAR,
+            "A PEM file to sign requests with, in place of icp.privateKey",
+        ),
+        (
+            config::DELEGATION_ENV_VAR,
+            "Unsupported: fails rather than ignoring a delegation chain",
+        ),
+        (
+            config::REPLICA_URL_ENV_VAR,
+            "A replica to reach canisters through, in place of the URL's",
+        ),
+        (
+            config::REQUEST_TIMEOUT_ENV_VAR,
+            "Seconds to wait for a canister call",
+        ),
+        (
+            config::USER_AGENT_ENV_VAR,
+            "A User-Agent to send in place of the default",
+        ),
+        (
+            proxy::PROXY_ENV_VAR,
+            "A proxy to reach replicas through, in place of HTTPS_PROXY",
+        ),
+        (
+            tls::NO_VERIFY_ENV_VAR,
+            "DANGEROUS: set to 1 to skip verifying a local replica's TLS",
+        ),
+        (
+            tls::NO_VERIFY_MAINNET_ENV_VAR,
+            "DANGEROUS: set to 1 to skip verifying a mainnet replica's TLS",
+        ),
+        (
+            config::MAX_CONCURRENCY_ENV_VAR,
+            "The most canister calls to have in flight at once",
+        ),
+        (
+            config::FETCH_CONCURRENCY_ENV_VAR,
+            "How many chunks of a streamed response to request at once",
+        ),
+        (
+            config::UPLOAD_CHUNK_SIZE_ENV_VAR,
+            "The most bytes of a request body to send per canister call",
+        ),
+        (
+            config::MAX_RESPONSE_BYTES_ENV_VAR,
+            "The most bytes of a response body to accept",
+        ),
+        (
+            config::MAX_RETRIES_ENV_VAR,
+            "How many times to retry a failed canister call",
+        ),
+        (
+            config::MAX_REDIRECTS_ENV_VAR,
+            "How many redirects to follow for a canister call",
+        ),
+        (
+            config::REFS_CACHE_TTL_ENV_VAR,
+            "Seconds to reuse listed refs for, or 0 to not cache them",
+        ),
+        (
+            config::QUERY_METHOD_ENV_VAR,
+            "The canister method to query, in place of http_request",
+        ),
+        (
+            config::UPDATE_METHOD_ENV_VAR,
+            "The canister method to update, in place of http_request_update",
+        ),
+        (
+            config::STORE_CHUNK_METHOD_ENV_VAR,
+            "The canister method to store chunks with, in place of store_chunk",
+        ),
+        (
+            config::COMMIT_CHUNKS_METHOD_ENV_VAR,
+            "The canister method to commit chunks with, in place of commit_chunks",
+        ),
+    ],
+};
 
 pub fn main() -> anyhow::Result<()> {
-    env_logger::init();
+    USAGE.exit_if_requested();
+
+    git_remote_helper::verbosity::init_logger();
+
+    error::report(run())
+}
+
+fn run() -> Result<(), error::Error> {
+    config::check_delegation().with_kind(Kind::Config)?;
 
-    let private_key_path = config::private_key();
-    trace!("private key path: {:#?}", private_key_path);
+    let provider = identity::provider();
+    info!("Using {}", provider.describe());
 
-    let identity = get_identity(private_key_path)?;
+    let identity = provider.identity().with_kind(Kind::Config)?;
 
-    let principal = identity.sender().map_err(|err| anyhow!(err))?;
+    let principal = identity
+        .sender()
+        .map_err(|err| anyhow!(err))
+        .with_kind(Kind::Config)?;
     trace!("principal: {}", principal);
-    eprintln!("Principal for caller: {}", principal);
+
+    if principal == Principal::anonymous() {
+        info!("Session is anonymous; pushing requires an identity");
+    } else {
+        info!("Session is authenticated as {}", principal);
+    }
 
     let fetch_root_key = config::fetch_root_key();
     trace!("fetch root key: {}", fetch_root_key);
@@ -25,27 +146,29 @@ pub fn main() -> anyhow::Result<()> {
     let replica_url = config::replica_url();
     trace!("replica url: {}", replica_url);
 
-    let canister_id = config::canister_id()?;
+    let replica_url_override = config::replica_url_override().with_kind(Kind::Config)?;
+    trace!("replica url override: {:#?}", replica_url_override);
+
+    let canister_id = config::canister_id().with_kind(Kind::Config)?;
     trace!("canister id: {}", canister_id);
 
+    let request_timeout = config::request_timeout();
+    trace!("request timeout: {:#?}", request_timeout);
+
+    let user_agent = config::user_agent();
+    trace!("user agent: {:#?}", user_agent);
+
+    let methods = config::methods().with_kind(Kind::Config)?;
+    trace!("methods: {:#?}", methods);
+
     git_remote_helper::main(connect::connect(
         identity,
         fetch_root_key,
         replica_url,
+        replica_url_override,
         canister_id,
+        request_timeout,
+        user_agent,
+        methods,
     ))
 }
-
-fn get_identity(private_key_path: anyhow::Result<String>) -> anyhow::Result<Arc<dyn Identity>> {
-    match private_key_path {
-        Ok(path) => {
-            eprintln!("Using identity for private key found in git config");
-            let identity = Secp256k1Identity::from_pem_file(path)?;
-            Ok(Arc::new(identity))
-        }
-        Err(_) => {
-            eprintln!("No private key found git config, using anonymous identity");
-            Ok(Arc::new(AnonymousIdentity {}))
-        }
-    }
-}
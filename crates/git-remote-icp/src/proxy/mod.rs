@@ -0,0 +1,94 @@
+use anyhow::anyhow;
+use log::trace;
+use reqwest::Url;
+
+#[cfg(test)]
+mod tests;
+
+pub const PROXY_ENV_VAR: &str = "ICP_PROXY";
+
+/// Checked in order, with `ICP_PROXY` taking precedence over the variables
+/// other tools use. Only HTTPS proxies apply since replicas are reached over
+/// HTTPS, except for local development.
+const PROXY_ENV_VARS: &[&str] = &[
+    PROXY_ENV_VAR,
+    "HTTPS_PROXY",
+    "https_proxy",
+    "ALL_PROXY",
+    "all_proxy",
+];
+const NO_PROXY_ENV_VARS: &[&str] = &["NO_PROXY", "no_proxy"];
+
+/// The proxy to reach replicas through, read from the environment.
+pub fn from_env() -> anyhow::Result<Option<reqwest::Proxy>> {
+    let (name, value) = match find_env(PROXY_ENV_VARS) {
+        Some(var) => var,
+        None => return Ok(None),
+    };
+
+    let url = parse(name, &value)?;
+    let no_proxy = NoProxy::parse(&find_env(NO_PROXY_ENV_VARS).unwrap_or_default().1);
+
+    trace!("proxy from {}: {}", name, url);
+    trace!("no proxy: {:#?}", no_proxy);
+
+    Ok(Some(reqwest::Proxy::custom(move |target| {
+        match target.host_str() {
+            Some(host) if no_proxy.matches(host) => None,
+            _ => Some(url.clone()),
+        }
+    })))
+}
+
+fn find_env(names: &[&'static str]) -> Option<(&'static str, String)> {
+    names.iter().find_map(|name| {
+        std::env::var(name)
+            .ok()
+            .filter(|value| !value.is_empty())
+            .map(|value| (*name, value))
+    })
+}
+
+fn parse(name: &str, value: &str) -> anyhow::Result<Url> {
+    let url = Url::parse(value).map_err(|err| anyhow!("invalid {} `{}`: {}", name, value, err))?;
+
+    if !matches!(url.scheme(), "http" | "https" | "socks5" | "socks5h") || !url.has_host() {
+        return Err(anyhow!(
+            "invalid {} `{}`: expected an http, https, socks5, or socks5h URL with a host",
+            name,
+            value
+        ));
+    }
+
+    Ok(url)
+}
+
+/// Hosts to connect to directly, from a comma-separated list of domains as in
+/// `NO_PROXY`. Each domain also matches its subdomains, and `*` matches every
+/// host.
+#[derive(Debug, Default, Eq, PartialEq)]
+struct NoProxy(Vec<String>);
+
+impl NoProxy {
+    fn parse(value: &str) -> Self {
+        Self(
+            value
+                .split(',')
+                .map(|domain| domain.trim().trim_start_matches('.').to_ascii_lowercase())
+                .filter(|domain| !domain.is_empty())
+                .collect(),
+        )
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+
+        self.0.iter().any(|domain| {
+            domain == "*"
+                || host == *domain
+                || host
+                    .strip_suffix(domain.as_str())
+                    .map_or(false, |subdomain| subdomain.ends_with('.'))
+        })
+    }
+}
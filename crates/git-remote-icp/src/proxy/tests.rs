@@ -0,0 +1,62 @@
+use super::*;
+
+#[test]
+fn test_parse() {
+    assert_eq!(
+        parse("HTTPS_PROXY", "http://proxy.example.com:3128")
+            .expect("valid proxy")
+            .as_str(),
+        "http://proxy.example.com:3128/",
+        "http"
+    );
+    assert_eq!(
+        parse("ALL_PROXY", "socks5h://localhost:1080")
+            .expect("valid proxy")
+            .as_str(),
+        "socks5h://localhost:1080",
+        "socks"
+    );
+}
+
+#[test]
+fn test_parse_malformed() {
+    assert_eq!(
+        parse("ICP_PROXY", "http://")
+            .expect_err("missing host")
+            .to_string(),
+        "invalid ICP_PROXY `http://`: empty host",
+        "missing host"
+    );
+    assert_eq!(
+        parse("ICP_PROXY", "ftp://proxy.example.com")
+            .expect_err("unsupported scheme")
+            .to_string(),
+        "invalid ICP_PROXY `ftp://proxy.example.com`: expected an http, https, socks5, or socks5h URL with a host",
+        "unsupported scheme"
+    );
+}
+
+#[test]
+fn test_no_proxy() {
+    let no_proxy = NoProxy::parse("localhost, .internal.example.com,,");
+
+    assert_eq!(
+        no_proxy,
+        NoProxy(vec![
+            "localhost".to_string(),
+            "internal.example.com".to_string()
+        ]),
+        "parsed"
+    );
+
+    assert!(no_proxy.matches("localhost"), "exact");
+    assert!(no_proxy.matches("git.internal.example.com"), "subdomain");
+    assert!(no_proxy.matches("LOCALHOST"), "case insensitive");
+    assert!(!no_proxy.matches("ic0.app"), "other host");
+    assert!(!no_proxy.matches("notinternal.example.com"), "suffix");
+}
+
+#[test]
+fn test_no_proxy_wildcard() {
+    assert!(NoProxy::parse("*").matches("ic0.app"), "wildcard");
+}
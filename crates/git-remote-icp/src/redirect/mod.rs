@@ -0,0 +1,66 @@
+use crate::config;
+use log::trace;
+use reqwest::redirect::Policy;
+use reqwest::{StatusCode, Url};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error(
+        "too many redirects from the replica, the last to {target}. Set {} to follow more",
+        config::MAX_REDIRECTS_ENV_VAR
+    )]
+    TooMany { target: String },
+    #[error("the replica redirected to {target} with status {status}, which can't be followed without changing the canister call. Set {} to the replica it redirects to", config::REPLICA_URL_ENV_VAR)]
+    Unsafe { status: u16, target: String },
+}
+
+/// Follows at most `max_redirects` redirects that keep the method and body
+/// of the request, as boundary nodes send when moving a canister to another
+/// domain. Other redirects would turn the `POST` of a canister call into a
+/// `GET`, so they fail with an error naming where they lead instead.
+pub fn policy(max_redirects: usize) -> Policy {
+    Policy::custom(move |attempt| {
+        match check(
+            attempt.status(),
+            attempt.url(),
+            attempt.previous().len(),
+            max_redirects,
+        ) {
+            Ok(()) => {
+                trace!("following redirect to {}", attempt.url());
+                attempt.follow()
+            }
+            Err(err) => attempt.error(err),
+        }
+    })
+}
+
+/// Whether the `redirects`th redirect in a row, with `status`, to `target`
+/// can be followed.
+fn check(
+    status: StatusCode,
+    target: &Url,
+    redirects: usize,
+    max_redirects: usize,
+) -> Result<(), Error> {
+    if !matches!(
+        status,
+        StatusCode::TEMPORARY_REDIRECT | StatusCode::PERMANENT_REDIRECT
+    ) {
+        return Err(Error::Unsafe {
+            status: status.as_u16(),
+            target: target.to_string(),
+        });
+    }
+
+    if redirects > max_redirects {
+        return Err(Error::TooMany {
+            target: target.to_string(),
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use super::*;
+
+fn url(value: &str) -> Url {
+    Url::parse(value).expect("valid URL")
+}
+
+#[test]
+fn test_check_preserving_redirects() {
+    let target = url("https://icp0.io/api/v2/canister/aaaaa-aa/call");
+    assert_eq!(
+        check(StatusCode::TEMPORARY_REDIRECT, &target, 1, 10),
+        Ok(()),
+        "307"
+    );
+    assert_eq!(
+        check(StatusCode::PERMANENT_REDIRECT, &target, 10, 10),
+        Ok(()),
+        "308 at the limit"
+    );
+}
+
+#[test]
+fn test_check_too_many() {
+    let target = url("https://icp0.io/api/v2/canister/aaaaa-aa/call");
+    assert_eq!(
+        check(StatusCode::PERMANENT_REDIRECT, &target, 11, 10),
+        Err(Error::TooMany {
+            target: target.to_string()
+        }),
+        "over the limit"
+    );
+    assert_eq!(
+        check(StatusCode::PERMANENT_REDIRECT, &target, 1, 0)
+            .expect_err("redirects to be disabled")
+            .to_string(),
+        "too many redirects from the replica, the last to https://icp0.io/api/v2/canister/aaaaa-aa/call. Set GIT_REMOTE_ICP_MAX_REDIRECTS to follow more",
+        "disabled"
+    );
+}
+
+#[test]
+fn test_check_unsafe() {
+    let target = url("https://icp0.io/api/v2/canister/aaaaa-aa/call");
+    for status in [
+        StatusCode::MOVED_PERMANENTLY,
+        StatusCode::FOUND,
+        StatusCode::SEE_OTHER,
+    ] {
+        assert_eq!(
+            check(status, &target, 1, 10),
+            Err(Error::Unsafe {
+                status: status.as_u16(),
+                target: target.to_string()
+            }),
+            "{}",
+            status
+        );
+    }
+    assert_eq!(
+        check(StatusCode::MOVED_PERMANENTLY, &target, 1, 10)
+            .expect_err("301 to be unsafe")
+            .to_string(),
+        "the replica redirected to https://icp0.io/api/v2/canister/aaaaa-aa/call with status 301, which can't be followed without changing the canister call. Set ICP_REPLICA_URL to the replica it redirects to",
+        "message"
+    );
+}
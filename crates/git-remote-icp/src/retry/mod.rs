@@ -0,0 +1,79 @@
+use crate::config;
+use ic_agent::AgentError;
+use log::trace;
+use std::time::Duration;
+
+#[cfg(test)]
+mod tests;
+
+const BASE_DELAY: Duration = Duration::from_millis(250);
+
+/// Makes a query call, retrying with exponential backoff on errors that are
+/// likely to succeed if repeated, like a boundary node rate limiting us.
+pub fn canister_call<T>(call: impl FnMut() -> Result<T, AgentError>) -> Result<T, AgentError> {
+    with_backoff(config::max_retries(), BASE_DELAY, is_transient, call)
+}
+
+/// Makes an update call, retrying only on errors from before the request was
+/// sent. A boundary node can respond with an error after the replica has
+/// accepted the call, and repeating it then would apply it twice.
+pub fn canister_update<T>(call: impl FnMut() -> Result<T, AgentError>) -> Result<T, AgentError> {
+    with_backoff(config::max_retries(), BASE_DELAY, is_unsent, call)
+}
+
+/// Calls `call` until it succeeds, fails with an error `is_transient` rejects,
+/// or has been retried `max_retries` times, doubling the delay from `base_delay`
+/// after each attempt.
+pub fn with_backoff<T, E>(
+    max_retries: u32,
+    base_delay: Duration,
+    is_transient: impl Fn(&E) -> bool,
+    mut call: impl FnMut() -> Result<T, E>,
+) -> Result<T, E>
+where
+    E: std::fmt::Display,
+{
+    let mut retries = 0;
+
+    loop {
+        match call() {
+            Err(err) if retries < max_retries && is_transient(&err) => {
+                let delay = base_delay.saturating_mul(2u32.saturating_pow(retries));
+                retries += 1;
+
+                trace!(
+                    "retrying in {}ms ({} of {}): {}",
+                    delay.as_millis(),
+                    retries,
+                    max_retries,
+                    err
+                );
+                git_remote_helper::stats::record_retry();
+
+                std::thread::sleep(delay);
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Errors from boundary nodes and the network that don't depend on the
+/// request. Anything the replica or canister responded to, including
+/// rejections, would fail the same way again. Timeouts aren't retried, since
+/// an update call that timed out may still have been executed.
+fn is_transient(agent_error: &AgentError) -> bool {
+    match agent_error {
+        AgentError::HttpError(payload) => matches!(payload.status, 429 | 502 | 503 | 504),
+        _ => is_unsent(agent_error),
+    }
+}
+
+/// Errors connecting to the replica, which leave the request unsent.
+fn is_unsent(agent_error: &AgentError) -> bool {
+    match agent_error {
+        AgentError::TransportError(err) => err
+            .downcast_ref::<reqwest::Error>()
+            .map_or(false, reqwest::Error::is_connect),
+        _ => false,
+    }
+}
@@ -0,0 +1,99 @@
+use super::*;
+use std::cell::Cell;
+
+#[derive(Debug, PartialEq)]
+enum MockError {
+    Unavailable,
+    Rejected,
+}
+
+impl std::fmt::Display for MockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// A transport that fails with `error` for the first `failures` calls.
+struct MockTransport {
+    failures: u32,
+    error: fn() -> MockError,
+    calls: Cell<u32>,
+}
+
+impl MockTransport {
+    fn new(failures: u32, error: fn() -> MockError) -> Self {
+        Self {
+            failures,
+            error,
+            calls: Cell::new(0),
+        }
+    }
+
+    fn call(&self) -> Result<&'static str, MockError> {
+        let calls = self.calls.get() + 1;
+        self.calls.set(calls);
+
+        if calls > self.failures {
+            Ok("response")
+        } else {
+            Err((self.error)())
+        }
+    }
+}
+
+fn is_unavailable(err: &MockError) -> bool {
+    *err == MockError::Unavailable
+}
+
+#[test]
+fn test_retries_transient_errors() {
+    let transport = MockTransport::new(2, || MockError::Unavailable);
+
+    let res = with_backoff(3, Duration::ZERO, is_unavailable, || transport.call());
+
+    assert_eq!(res, Ok("response"), "response");
+    assert_eq!(transport.calls.get(), 3, "calls");
+}
+
+#[test]
+fn test_gives_up_after_max_retries() {
+    let transport = MockTransport::new(5, || MockError::Unavailable);
+
+    let res = with_backoff(3, Duration::ZERO, is_unavailable, || transport.call());
+
+    assert_eq!(res, Err(MockError::Unavailable), "error");
+    assert_eq!(transport.calls.get(), 4, "calls");
+}
+
+#[test]
+fn test_does_not_retry_rejections() {
+    let transport = MockTransport::new(2, || MockError::Rejected);
+
+    let res = with_backoff(3, Duration::ZERO, is_unavailable, || transport.call());
+
+    assert_eq!(res, Err(MockError::Rejected), "error");
+    assert_eq!(transport.calls.get(), 1, "calls");
+}
+
+fn http_error(status: u16) -> AgentError {
+    AgentError::HttpError(ic_agent::agent::agent_error::HttpErrorPayload {
+        status,
+        content_type: None,
+        content: vec![],
+    })
+}
+
+#[test]
+fn test_is_transient() {
+    assert!(is_transient(&http_error(503)), "503");
+    assert!(is_transient(&http_error(429)), "429");
+    assert!(!is_transient(&http_error(400)), "400");
+}
+
+#[test]
+fn test_updates_not_retried_after_responses() {
+    // A boundary node may have passed the call on before failing it.
+    for status in [429, 502, 503, 504] {
+        assert!(!is_unsent(&http_error(status)), "{}", status);
+    }
+}
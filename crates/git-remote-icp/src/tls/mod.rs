@@ -0,0 +1,68 @@
+use crate::config;
+use log::{trace, warn};
+use reqwest::Url;
+
+#[cfg(test)]
+mod tests;
+
+pub const NO_VERIFY_ENV_VAR: &str = "ICP_TLS_NO_VERIFY";
+pub const NO_VERIFY_MAINNET_ENV_VAR: &str = "ICP_TLS_NO_VERIFY_MAINNET";
+
+/// The domains of mainnet boundary nodes, including their subdomains.
+/// Certificates are only left unverified for these with the separate
+/// `ICP_TLS_NO_VERIFY_MAINNET` override, since anything reached through them
+/// is real.
+const MAINNET_DOMAINS: &[&str] = &["ic0.app", "icp0.io", "icp-api.io", "icp.io"];
+
+/// Whether to accept invalid TLS certificates from `replica_url`, read from
+/// `ICP_TLS_NO_VERIFY`. This is dangerous, and meant only for local replicas
+/// and boundary nodes with self-signed certificates.
+pub fn no_verify(replica_url: &str) -> bool {
+    let no_verify = config::env_enabled(NO_VERIFY_ENV_VAR);
+    let allow_mainnet = config::env_enabled(NO_VERIFY_MAINNET_ENV_VAR);
+
+    let applies = applies(replica_url, no_verify, allow_mainnet);
+    trace!("tls no verify for {}: {}", replica_url, applies);
+
+    if applies {
+        warn!(
+            "DANGER: {} is set, so TLS certificates from {} aren't verified and anyone on the network can impersonate it",
+            NO_VERIFY_ENV_VAR, replica_url
+        );
+    } else if no_verify {
+        warn!(
+            "{} is set but isn't applied to {}, which is a mainnet boundary node. Set {} as well to apply it",
+            NO_VERIFY_ENV_VAR, replica_url, NO_VERIFY_MAINNET_ENV_VAR
+        );
+    }
+
+    applies
+}
+
+/// Whether skipping verification applies to `replica_url`, which for
+/// mainnet, or a URL that can't be parsed, takes `allow_mainnet` as well.
+fn applies(replica_url: &str, no_verify: bool, allow_mainnet: bool) -> bool {
+    if !no_verify {
+        return false;
+    }
+
+    allow_mainnet || !is_mainnet(replica_url)
+}
+
+fn is_mainnet(replica_url: &str) -> bool {
+    let host = match Url::parse(replica_url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_ascii_lowercase))
+    {
+        Some(host) => host,
+        None => return true,
+    };
+    let host = host.trim_end_matches('.');
+
+    MAINNET_DOMAINS.iter().any(|domain| {
+        host == *domain
+            || host
+                .strip_suffix(domain)
+                .map_or(false, |rest| rest.ends_with('.'))
+    })
+}
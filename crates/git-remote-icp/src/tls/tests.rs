@@ -0,0 +1,51 @@
+use super::*;
+
+#[test]
+fn test_applies_to_local_replica() {
+    assert!(
+        applies("http://localhost:4943", true, false),
+        "local replica"
+    );
+    assert!(
+        applies("https://boundary.internal.example.com", true, false),
+        "self-signed boundary node"
+    );
+}
+
+#[test]
+fn test_applies_only_when_enabled() {
+    assert!(
+        !applies("http://localhost:4943", false, false),
+        "local replica"
+    );
+    assert!(
+        !applies("https://ic0.app", false, true),
+        "mainnet override alone"
+    );
+}
+
+#[test]
+fn test_never_applies_to_mainnet_without_override() {
+    for replica_url in [
+        "https://ic0.app",
+        "https://IC0.APP.",
+        "https://w7uni-tiaaa-aaaam-qaydq-cai.raw.ic0.app",
+        "https://icp0.io",
+        "https://icp-api.io",
+        "https://boundary.icp.io:443",
+        "not a url",
+    ] {
+        assert!(!applies(replica_url, true, false), "{}", replica_url);
+    }
+}
+
+#[test]
+fn test_applies_to_mainnet_with_override() {
+    assert!(applies("https://ic0.app", true, true), "mainnet");
+}
+
+#[test]
+fn test_is_mainnet_suffix() {
+    assert!(!is_mainnet("https://notic0.app"), "suffix without a dot");
+    assert!(!is_mainnet("https://ic0.app.example.com"), "prefix");
+}
@@ -1,10 +1,20 @@
 mod connect;
 
 use git_remote_helper;
+use git_remote_helper::cli::Usage;
 use connect::connect;
 
+const USAGE: Usage = Usage {
+    name: env!("CARGO_PKG_NAME"),
+    version: env!("CARGO_PKG_VERSION"),
+    about: env!("CARGO_PKG_DESCRIPTION"),
+    env: &[],
+};
+
 #[tokio::main]
 pub async fn main() -> anyhow::Result<()> {
-    env_logger::init();
-    git_remote_helper::main(connect).await
+    USAGE.exit_if_requested();
+
+    git_remote_helper::verbosity::init_logger();
+    git_remote_helper::error::report(git_remote_helper::main(connect).await)
 }
@@ -106,7 +106,7 @@
           };
 
           git-remote-helper-async = git-remote-helper "async-network-client";
-          git-remote-helper-blocking = git-remote-helper "blocking-network-client";
+          git-remote-helper-blocking = git-remote-helper "blocking-network-client,serde";
 
           git-remote-http-reqwest = pkgs.callPackage ./nix/git-remote-helper.nix rec {
             inherit craneLib src;